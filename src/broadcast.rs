@@ -0,0 +1,431 @@
+//! Broadcasting the live output of `Player` as an Ogg Vorbis stream, either served over HTTP or sent to an Icecast
+//! server, so that generative music can be listened to from a browser or a media player without routing the audio
+//! through external tools.
+
+use crate::{
+    errors::AudioError,
+    vorbis::{OggWriter, StreamEncoder},
+};
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Number of frames that are handed to the encoder at once.
+const BLOCK_FRAMES: usize = 1024;
+
+/// Number of blocks that can be waiting to be encoded before new blocks are dropped, which is a little under a second
+/// at 44.1 kHz. This is also the number of blocks that are set aside up front, so that the audio thread never
+/// allocates one.
+const QUEUED_BLOCKS: usize = 32;
+
+/// Number of packets that can be waiting to be written to a listener or a server before it is disconnected, which is
+/// about three seconds at 44.1 kHz.
+const QUEUED_PACKETS: usize = 128;
+
+/// Number of packets on every page after the first, which is about a fifth of a second at 44.1 kHz. Pages are closed
+/// after this many packets rather than once they fill up, since quiet packets take only a few bytes and a full page
+/// of them would keep listeners waiting.
+const PAGE_PACKETS: usize = 8;
+
+/// How often background threads check whether the broadcast has stopped when nothing comes in.
+const STOP_POLL: Duration = Duration::from_millis(50);
+
+/// How long a listener or a server can take to accept a page before it is disconnected.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An audio packet of the stream along with its granule position, which is shared by every output.
+type Packet = (Arc<Vec<u8>>, u64);
+
+/// Serves the output of a `Player` as an endless Ogg Vorbis stream over HTTP, with chunked transfer encoding. Any path
+/// on the server plays the stream, so it can be opened with something like `http://localhost:8000/`. Pass `tap` to
+/// `Player::set_output_tap` to start feeding the broadcast. Every listener is written to by a thread of its own, so a
+/// slow listener falls behind and is disconnected without holding up the others. The server stops listening once the
+/// broadcast is dropped.
+pub struct HttpBroadcast {
+    address: SocketAddr,
+    encoding: Encoding,
+    error: Arc<Mutex<Option<AudioError>>>,
+}
+
+impl HttpBroadcast {
+    /// Starts listening for listeners on `address`, for a stream of `channels` channels at `sample_rate`, which
+    /// should match the output of the player (see `Player::get_sample_rate` and `Player::get_output_channels`).
+    pub fn bind<A>(address: A, sample_rate: f64, channels: usize) -> Result<Self, AudioError>
+    where
+        A: ToSocketAddrs,
+    {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let encoding = Encoding::start(sample_rate, channels);
+        let error = Arc::new(Mutex::new(None));
+        {
+            let outputs = encoding.outputs.clone();
+            let error = Arc::clone(&error);
+            thread::spawn(move || accept(listener, outputs, error));
+        }
+        Ok(Self {
+            address,
+            encoding,
+            error,
+        })
+    }
+
+    /// Gets the address that the server is listening on, which is useful when binding to port 0.
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Gets the number of listeners that are connected.
+    pub fn get_listeners(&self) -> usize {
+        self.encoding.outputs.count.load(Ordering::Relaxed)
+    }
+
+    /// Takes the last error that the server ran into while accepting listeners, if any. The server keeps listening
+    /// after an error. Listeners that disconnect or cannot keep up are dropped without an error.
+    pub fn take_error(&self) -> Option<AudioError> {
+        self.error.lock().unwrap().take()
+    }
+
+    /// Creates a callback that feeds every frame that it is given into the broadcast. Frames are encoded on a
+    /// background thread, and frames are dropped rather than waited on if the encoder falls behind, so the callback
+    /// never blocks or allocates on the audio thread. Blocks are shared by every tap of the broadcast, so only one
+    /// should be fed at a time.
+    pub fn tap(&self) -> impl FnMut(&[f64]) + Send + 'static {
+        self.encoding.tap()
+    }
+}
+
+/// Sends the output of a `Player` to an Icecast server as an Ogg Vorbis stream, which the server relays to its own
+/// listeners. The stream is sent with an HTTP `PUT` request, which Icecast 2.4 and later accept, and is logged in as
+/// the `source` user. Pass `tap` to `Player::set_output_tap` to start feeding the stream. The connection is closed
+/// once the source is dropped.
+pub struct IcecastSource {
+    encoding: Encoding,
+    connected: Arc<AtomicBool>,
+    error: Arc<Mutex<Option<AudioError>>>,
+}
+
+impl IcecastSource {
+    /// Connects to the Icecast server at `address` as the source of `mount` (such as `/live.ogg`), with the source
+    /// password of the server, for a stream of `channels` channels at `sample_rate`. This fails with
+    /// `AudioError::IcecastRefused` if the server turns the stream down, such as for a wrong password or a mount that
+    /// already has a source.
+    pub fn connect<A>(
+        address: A,
+        mount: &str,
+        password: &str,
+        sample_rate: f64,
+        channels: usize,
+    ) -> Result<Self, AudioError>
+    where
+        A: ToSocketAddrs,
+    {
+        let mut stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(WRITE_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let host = stream.peer_addr()?;
+        let slash = if mount.starts_with('/') { "" } else { "/" };
+        write!(
+            stream,
+            "PUT {slash}{mount} HTTP/1.1\r\nHost: {host}\r\nAuthorization: Basic {}\r\nUser-Agent: soundtoys/{}\r\n\
+             Content-Type: application/ogg\r\nIce-Public: 0\r\n\r\n",
+            base64(format!("source:{password}").as_bytes()),
+            env!("CARGO_PKG_VERSION"),
+        )?;
+        let response = read_head(&mut stream)?;
+        let status = response.lines().next().unwrap_or_default();
+        if status.split(' ').nth(1) != Some("200") {
+            return Err(AudioError::IcecastRefused(status.to_string()));
+        }
+        let encoding = Encoding::start(sample_rate, channels);
+        let connected = Arc::new(AtomicBool::new(true));
+        let error = Arc::new(Mutex::new(None));
+        {
+            let outputs = encoding.outputs.clone();
+            let connected = Arc::clone(&connected);
+            let error = Arc::clone(&error);
+            thread::spawn(move || {
+                if let Err(err) = outputs.write(stream) {
+                    *error.lock().unwrap() = Some(err.into());
+                }
+                connected.store(false, Ordering::Relaxed);
+            });
+        }
+        Ok(Self {
+            encoding,
+            connected,
+            error,
+        })
+    }
+
+    /// Checks whether the stream is still being sent. The connection is closed if the server hangs up or cannot keep
+    /// up, and the reason is kept for `take_error`.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Takes the error that closed the connection, if any.
+    pub fn take_error(&self) -> Option<AudioError> {
+        self.error.lock().unwrap().take()
+    }
+
+    /// Creates a callback that feeds every frame that it is given into the stream, in the same way as
+    /// `HttpBroadcast::tap`.
+    pub fn tap(&self) -> impl FnMut(&[f64]) + Send + 'static {
+        self.encoding.tap()
+    }
+}
+
+/// The thread that encodes a broadcast, along with the blocks that taps fill for it.
+struct Encoding {
+    sender: SyncSender<Vec<f32>>,
+    /// Blocks that have been encoded, which are handed back to be filled again.
+    free: Arc<Mutex<Receiver<Vec<f32>>>>,
+    outputs: Outputs,
+    channels: usize,
+}
+
+impl Encoding {
+    fn start(sample_rate: f64, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let encoder = StreamEncoder::new(sample_rate, channels);
+        let outputs = Outputs {
+            headers: Arc::new(encoder.headers().clone()),
+            added: Arc::new(Mutex::new(Vec::new())),
+            count: Arc::new(AtomicUsize::new(0)),
+            stopped: Arc::new(AtomicBool::new(false)),
+        };
+        let (sender, receiver) = mpsc::sync_channel(QUEUED_BLOCKS);
+        // One more block than can be queued, for the block that a tap is filling.
+        let (free_sender, free) = mpsc::sync_channel(QUEUED_BLOCKS + 1);
+        for _ in 0..=QUEUED_BLOCKS {
+            let _ = free_sender.send(Vec::with_capacity(BLOCK_FRAMES * channels));
+        }
+        {
+            let outputs = outputs.clone();
+            thread::spawn(move || encode(receiver, free_sender, encoder, outputs));
+        }
+        Self {
+            sender,
+            free: Arc::new(Mutex::new(free)),
+            outputs,
+            channels,
+        }
+    }
+
+    fn tap(&self) -> impl FnMut(&[f64]) + Send + 'static {
+        let sender = self.sender.clone();
+        let free = Arc::clone(&self.free);
+        let channels = self.channels;
+        let mut block: Option<Vec<f32>> = None;
+        move |frame| {
+            if block.is_none() {
+                block = free.try_lock().ok().and_then(|free| free.try_recv().ok());
+            }
+            // Every block is still waiting to be encoded, so the frame is dropped.
+            let Some(filling) = &mut block else {
+                return;
+            };
+            filling.extend(
+                (0..channels).map(|channel| {
+                    frame.get(channel).copied().unwrap_or(0.0).clamp(-1.0, 1.0) as f32
+                }),
+            );
+            if filling.len() >= BLOCK_FRAMES * channels {
+                if let Some(full) = block.take() {
+                    if let Err(
+                        TrySendError::Full(mut full) | TrySendError::Disconnected(mut full),
+                    ) = sender.try_send(full)
+                    {
+                        full.clear();
+                        block = Some(full);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Encoding {
+    fn drop(&mut self) {
+        self.outputs.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The places that the packets of a broadcast are written to, such as listeners or a server.
+#[derive(Clone)]
+struct Outputs {
+    headers: Arc<[Vec<u8>; 3]>,
+    /// Outputs that have joined since the last packet, which the encoding thread takes over.
+    added: Arc<Mutex<Vec<SyncSender<Packet>>>>,
+    /// The number of outputs that packets are being sent to.
+    count: Arc<AtomicUsize>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Outputs {
+    /// Writes the stream to `writer` from the next packet on, until the broadcast stops or `writer` falls behind.
+    /// Each output gets pages of its own, which start with the headers, so that its decoder can start partway through
+    /// the broadcast.
+    fn write<W>(&self, writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let (sender, packets) = mpsc::sync_channel(QUEUED_PACKETS);
+        self.added.lock().unwrap().push(sender);
+        let mut ogg = OggWriter::new(writer);
+        ogg.headers(&self.headers)?;
+        let mut written = 0;
+        while !self.stopped.load(Ordering::Relaxed) {
+            let (packet, granule) = match packets.recv_timeout(STOP_POLL) {
+                Ok(packet) => packet,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) if self.stopped.load(Ordering::Relaxed) => {
+                    break
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "could not keep up with the broadcast",
+                    ))
+                }
+            };
+            ogg.packet(&packet, granule)?;
+            written += 1;
+            // Some decoders count samples from the granule position of the page that the first audio packet is on, so
+            // it is given a page of its own, as in an exported file.
+            if written == 1 || written % PAGE_PACKETS == 0 {
+                ogg.flush(false)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encodes every block and hands its packets to every output, dropping outputs whose queues are full, and hands the
+/// blocks back to be filled again. Outputs are written to on threads of their own, so this never waits on a socket.
+fn encode(
+    receiver: Receiver<Vec<f32>>,
+    free: SyncSender<Vec<f32>>,
+    mut encoder: StreamEncoder,
+    outputs: Outputs,
+) {
+    let mut senders: Vec<SyncSender<Packet>> = Vec::new();
+    // Every output stops once the broadcast is over, which is when `senders` is dropped.
+    while !outputs.stopped.load(Ordering::Relaxed) {
+        let mut block = match receiver.recv_timeout(STOP_POLL) {
+            Ok(block) => block,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        senders.append(&mut outputs.added.lock().unwrap());
+        encoder.push(&block, |packet, granule| {
+            let packet = (Arc::new(packet), granule);
+            senders.retain(|sender| sender.try_send(packet.clone()).is_ok());
+        });
+        block.clear();
+        let _ = free.try_send(block);
+        outputs.count.store(senders.len(), Ordering::Relaxed);
+    }
+    outputs.added.lock().unwrap().clear();
+}
+
+/// Accepts new listeners until the broadcast is stopped, giving each one a thread that greets it and then writes the
+/// stream to it.
+fn accept(listener: TcpListener, outputs: Outputs, error: Arc<Mutex<Option<AudioError>>>) {
+    while !outputs.stopped.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let outputs = outputs.clone();
+                // Listeners that disconnect or fall behind are simply dropped.
+                thread::spawn(move || {
+                    if let Ok(stream) = greet(stream) {
+                        let _ = outputs.write(Chunked(stream));
+                    }
+                });
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(STOP_POLL);
+            }
+            Err(err) => {
+                *error.lock().unwrap() = Some(err.into());
+                // Errors such as running out of file handles would otherwise repeat straight away.
+                thread::sleep(STOP_POLL);
+            }
+        }
+    }
+}
+
+/// Reads the request of a new listener and answers with the headers of the response.
+fn greet(mut stream: TcpStream) -> io::Result<TcpStream> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(WRITE_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    read_head(&mut stream)?;
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: audio/ogg\r\nTransfer-Encoding: chunked\r\nCache-Control: no-cache\r\n\r\n",
+    )?;
+    Ok(stream)
+}
+
+/// Reads the request or status line and the headers of an HTTP message, up to the blank line that ends them.
+fn read_head(stream: &mut TcpStream) -> io::Result<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.ends_with(b"\r\n\r\n") && head.len() < 8192 {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Writes everything that it is given as a chunk of a response with chunked transfer encoding.
+struct Chunked(TcpStream);
+
+impl Write for Chunked {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the response.
+        if bytes.is_empty() {
+            return Ok(0);
+        }
+        write!(self.0, "{:x}\r\n", bytes.len())?;
+        self.0.write_all(bytes)?;
+        self.0.write_all(b"\r\n")?;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Encodes bytes as Base64, for the credentials of a request.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let value = group.iter().enumerate().fold(0_u32, |value, (i, byte)| {
+            value | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(ALPHABET[(value >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
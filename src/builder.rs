@@ -0,0 +1,251 @@
+//! Compose instruments out of layers of oscillators, instead of writing out a new type that implements `Instrument`.
+
+use crate::{
+    instruments::{Instrument, Tuning, Variation},
+    note::{scale, tune},
+    oscillators::{osc_with_modulations, Envelope, EnvelopeADSR, Modulation, Oscillator},
+    player::ChannelId,
+};
+use alloc::vec::Vec;
+use rand::rngs::SmallRng;
+
+/// Changes how a single layer of an instrument is played.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayerOption {
+    /// Transposes the layer by a number of semitones.
+    Semitones(i32),
+    /// Detunes the layer by a number of cents.
+    Detune(f64),
+    /// Scales the volume of the layer.
+    Gain(f64),
+}
+
+/// Transposes a layer by a number of octaves, such as 1 for an octave above or -1 for an octave below.
+pub fn octave(octaves: i32) -> LayerOption {
+    LayerOption::Semitones(octaves * 12)
+}
+
+/// Transposes a layer by a number of semitones.
+pub fn semitones(semitones: i32) -> LayerOption {
+    LayerOption::Semitones(semitones)
+}
+
+/// Detunes a layer by a number of cents, which thickens the sound when layered on top of the same oscillator.
+pub fn detune(cents: f64) -> LayerOption {
+    LayerOption::Detune(cents)
+}
+
+/// Scales the volume of a layer.
+pub fn gain(gain: f64) -> LayerOption {
+    LayerOption::Gain(gain)
+}
+
+/// A single oscillator of a `LayeredInstrument`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Layer {
+    pub oscillator: Oscillator,
+    pub semitones: i32,
+    pub detune: f64,
+    pub gain: f64,
+}
+
+impl Layer {
+    /// Creates a new layer from an oscillator and a list of options.
+    pub fn new<O>(oscillator: Oscillator, options: O) -> Self
+    where
+        O: IntoIterator<Item = LayerOption>,
+    {
+        let mut layer = Self {
+            oscillator,
+            semitones: 0,
+            detune: 0.0,
+            gain: 1.0,
+        };
+        for option in options {
+            match option {
+                LayerOption::Semitones(semitones) => layer.semitones += semitones,
+                LayerOption::Detune(cents) => layer.detune += cents,
+                LayerOption::Gain(gain) => layer.gain *= gain,
+            }
+        }
+        layer
+    }
+}
+
+/// Builds a `LayeredInstrument` by stacking layers of oscillators, such as
+/// `InstrumentBuilder::new().layer(Oscillator::Sine, [octave(1), gain(0.5)]).layer(Oscillator::Noise, [gain(0.05)])`.
+#[derive(Clone)]
+pub struct InstrumentBuilder<E = EnvelopeADSR> {
+    instrument: LayeredInstrument<E>,
+}
+
+impl InstrumentBuilder {
+    /// Constructs a new `InstrumentBuilder` with no layers and the default envelope.
+    pub fn new() -> Self {
+        Self {
+            instrument: LayeredInstrument {
+                layers: Vec::new(),
+                env: EnvelopeADSR::default(),
+                volume: 1.0,
+                modulations: Vec::new(),
+                tuning: Tuning::default(),
+                variation: Variation::default(),
+                channel: ChannelId::unique(),
+            },
+        }
+    }
+}
+
+impl Default for InstrumentBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Envelope> InstrumentBuilder<E> {
+    /// Adds a layer that plays `oscillator`, changed by `options`, on top of the previous layers.
+    pub fn layer<O>(mut self, oscillator: Oscillator, options: O) -> Self
+    where
+        O: IntoIterator<Item = LayerOption>,
+    {
+        self.instrument.layers.push(Layer::new(oscillator, options));
+        self
+    }
+
+    /// Sets the envelope shared by every layer.
+    pub fn envelope<F: Envelope>(self, env: F) -> InstrumentBuilder<F> {
+        let LayeredInstrument {
+            layers,
+            volume,
+            modulations,
+            tuning,
+            variation,
+            channel,
+            ..
+        } = self.instrument;
+        InstrumentBuilder {
+            instrument: LayeredInstrument {
+                layers,
+                env,
+                volume,
+                modulations,
+                tuning,
+                variation,
+                channel,
+            },
+        }
+    }
+
+    pub fn volume(mut self, volume: f64) -> Self {
+        self.instrument.volume = volume;
+        self
+    }
+
+    /// Adds a modulation that is applied to every layer.
+    pub fn modulation(mut self, modulation: Modulation) -> Self {
+        self.instrument.modulations.push(modulation);
+        self
+    }
+
+    pub fn tuning(mut self, tuning: f64) -> Self {
+        self.instrument.tuning.tuning = tuning;
+        self
+    }
+
+    pub fn variation(mut self, variation: Variation) -> Self {
+        self.instrument.variation = variation;
+        self
+    }
+
+    /// Builds the instrument, which is given a channel of its own (see `LayeredInstrument`).
+    pub fn build(mut self) -> LayeredInstrument<E> {
+        self.instrument.channel = ChannelId::unique();
+        self.instrument
+    }
+}
+
+/// An instrument made up of layers of oscillators that share an envelope, which is built with `InstrumentBuilder`.
+/// Every `LayeredInstrument` has the same type no matter its layers, so instead of sharing the channel of its type
+/// (see `ChannelId::of`), every instrument that is built gets a channel of its own, which its clones share. This keeps
+/// two different patches from releasing each other's notes. To mix it, pass `get_channel` to the `set_channel_*`
+/// methods of `Player` rather than using the methods that take an instrument type.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayeredInstrument<E = EnvelopeADSR> {
+    pub layers: Vec<Layer>,
+    pub env: E,
+    pub volume: f64,
+    pub modulations: Vec<Modulation>,
+    pub tuning: Tuning,
+    pub variation: Variation,
+    #[cfg_attr(feature = "serde", serde(skip, default = "ChannelId::unique"))]
+    channel: ChannelId,
+}
+
+impl<E: Envelope + 'static> Instrument for LayeredInstrument<E> {
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
+        let amplitude = self.env.amplitude(time, time_on, time_off);
+        if amplitude <= 0.0 {
+            *note_finished = true;
+        }
+        let sound: f64 = self
+            .layers
+            .iter()
+            .map(|layer| {
+                layer.gain
+                    * osc_with_modulations(
+                        phase_time,
+                        tune(
+                            scale(note_id as i32 + layer.semitones),
+                            tuning + layer.detune,
+                        ),
+                        layer.oscillator.clone(),
+                        &self.modulations,
+                    )
+            })
+            .sum();
+        amplitude * sound * self.volume
+    }
+
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        self.variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        self.layers.len() as f64
+    }
+
+    fn get_channel(&self) -> Option<ChannelId> {
+        Some(self.channel)
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Layered Instrument"
+    }
+}
@@ -0,0 +1,597 @@
+//! Signal processing building blocks for shaping the output of oscillators.
+
+use crate::effects::Effect;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+/// Represents the various transfer functions that `waveshape` can apply.
+#[non_exhaustive]
+#[derive(Clone, Debug)]
+pub enum Curve {
+    /// Smooth saturation that gets harsher the higher the `drive` is (should be above 0.0)
+    Tanh { drive: f64 },
+    /// Cuts off anything louder than `threshold`
+    HardClip { threshold: f64 },
+    /// Chebyshev polynomial of the first kind of the given order, which turns a full scale sine wave into its harmonic
+    /// of that order
+    Chebyshev(u32),
+}
+
+/// Passes a sample through a transfer function to add harmonics and grit. Samples between -1.0 and 1.0 stay between
+/// -1.0 and 1.0.
+pub fn waveshape(sample: f64, curve: Curve) -> f64 {
+    match curve {
+        Curve::Tanh { drive } => (drive * sample).tanh() / drive.tanh(),
+        Curve::HardClip { threshold } => sample.clamp(-threshold, threshold),
+        Curve::Chebyshev(order) => {
+            let x = sample.clamp(-1.0, 1.0);
+            let mut previous = 1.0;
+            let mut current = x;
+            if order == 0 {
+                return previous;
+            }
+            for _ in 1..order {
+                let next = 2.0 * x * current - previous;
+                previous = current;
+                current = next;
+            }
+            current
+        }
+    }
+}
+
+/// A resonant low-pass filter, modelled on a state variable filter so that it stays stable while its cutoff is swept.
+/// Unlike the other building blocks, a filter remembers the samples that pass through it, so every note needs its own
+/// filter (see `Instrument::process`).
+#[derive(Clone, Debug, Default)]
+pub struct LowPassFilter {
+    state: SvfCore,
+}
+
+impl LowPassFilter {
+    /// Creates a new filter.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Passes the next sample through the filter. The `cutoff` (in Hz) is kept below the Nyquist frequency, and the
+    /// `resonance` ranges from 0.0 for no resonance to 1.0 for self-oscillation.
+    pub fn process(&mut self, sample: f64, cutoff: f64, resonance: f64, sample_rate: f64) -> f64 {
+        let k = 2.0 - 2.0 * resonance.clamp(0.0, 1.0);
+        self.state.process(sample, cutoff, k, sample_rate).0
+    }
+}
+
+/// A band-pass filter, which only lets through the frequencies around its center. Like `LowPassFilter`, every note
+/// needs its own filter.
+#[derive(Clone, Debug, Default)]
+pub struct BandPassFilter {
+    state: SvfCore,
+}
+
+impl BandPassFilter {
+    /// Creates a new filter.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Passes the next sample through the filter. The `center` frequency (in Hz) is kept below the Nyquist frequency,
+    /// and a higher `q` narrows the band that is let through, such as 1.4 for a band an octave wide. Frequencies at
+    /// the center pass through at their original volume.
+    pub fn process(&mut self, sample: f64, center: f64, q: f64, sample_rate: f64) -> f64 {
+        let k = 1.0 / q.max(0.1);
+        k * self.state.process(sample, center, k, sample_rate).1
+    }
+}
+
+/// The response of a `StateVariableFilter`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SvfMode {
+    /// Lets through the frequencies below the cutoff.
+    #[default]
+    LowPass,
+    /// Lets through the frequencies around the cutoff.
+    BandPass,
+    /// Lets through the frequencies above the cutoff.
+    HighPass,
+    /// Cuts out the frequencies around the cutoff.
+    Notch,
+}
+
+/// A resonant state variable filter (often called an SVF) with a choice of responses. Its coefficients are cheap to
+/// work out and it stays stable however fast its cutoff moves, so the cutoff can be changed on every sample, such as
+/// to sweep it with an envelope for a filter sweep or with an LFO for a wah. Like the other filters, every note needs
+/// its own filter.
+#[derive(Clone, Debug, Default)]
+pub struct StateVariableFilter {
+    pub mode: SvfMode,
+    state: SvfCore,
+}
+
+impl StateVariableFilter {
+    /// Creates a new filter.
+    pub fn new(mode: SvfMode) -> Self {
+        Self {
+            mode,
+            state: SvfCore::default(),
+        }
+    }
+
+    /// Passes the next sample through the filter. The `cutoff` (in Hz) is kept below the Nyquist frequency, and the
+    /// `resonance` ranges from 0.0 for no resonance to 1.0 for self-oscillation.
+    pub fn process(&mut self, sample: f64, cutoff: f64, resonance: f64, sample_rate: f64) -> f64 {
+        let k = 2.0 - 2.0 * resonance.clamp(0.0, 1.0);
+        let (low, band) = self.state.process(sample, cutoff, k, sample_rate);
+        let high = sample - k * band - low;
+        match self.mode {
+            SvfMode::LowPass => low,
+            SvfMode::BandPass => band,
+            SvfMode::HighPass => high,
+            SvfMode::Notch => low + high,
+        }
+    }
+}
+
+/// The state shared by the filters, which is a state variable filter with a damping of `k`, solved with the
+/// trapezoidal integrators of Andrew Simper's design.
+#[derive(Clone, Debug, Default)]
+struct SvfCore {
+    ic1eq: f64,
+    ic2eq: f64,
+}
+
+impl SvfCore {
+    /// Passes the next sample through the filter, returning its low-pass and band-pass outputs.
+    fn process(&mut self, sample: f64, cutoff: f64, k: f64, sample_rate: f64) -> (f64, f64) {
+        let cutoff = cutoff.clamp(10.0, sample_rate * 0.49);
+        let g = (core::f64::consts::PI * cutoff / sample_rate).tan();
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+        let v3 = sample - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+        (v2, v1)
+    }
+}
+
+/// The response of a `Biquad` filter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BiquadType {
+    /// Lets through the frequencies below the cutoff.
+    LowPass,
+    /// Lets through the frequencies above the cutoff.
+    HighPass,
+    /// Lets through the frequencies around the cutoff, at their original volume at the cutoff itself.
+    BandPass,
+    /// Cuts out the frequencies around the cutoff.
+    Notch,
+    /// Boosts or cuts the frequencies below the cutoff by `gain` decibels.
+    LowShelf,
+    /// Boosts or cuts the frequencies above the cutoff by `gain` decibels.
+    HighShelf,
+    /// Boosts or cuts the frequencies around the cutoff by `gain` decibels.
+    Peak,
+}
+
+/// A second-order filter with the coefficients of Robert Bristow-Johnson's Audio EQ Cookbook. Unlike the state
+/// variable filters, its response can be a notch or a shelf, but it can click if its cutoff is swept quickly. The
+/// cutoff, Q and gain can be changed between samples, and the coefficients are only worked out again when they do.
+/// Like the other filters, every note needs its own biquad. A biquad can also be added to the effects of `Player`, in
+/// which case it runs at the sample rate that it is prepared with.
+#[derive(Clone, Debug)]
+pub struct Biquad {
+    pub kind: BiquadType,
+    /// The cutoff or center frequency (in Hz), which is kept below the Nyquist frequency.
+    pub cutoff: f64,
+    /// How sharp the filter is around the cutoff, where 0.707 gives the flattest response. For shelves, this sets how
+    /// steep the slope of the shelf is.
+    pub q: f64,
+    /// How much a shelf or peak boosts or cuts by (in decibels), which the other types ignore.
+    pub gain: f64,
+    sample_rate: f64,
+    coefficients: BiquadCoefficients,
+    /// The settings that the coefficients were worked out for.
+    settings: Option<(BiquadType, f64, f64, f64, f64)>,
+    state: [f64; 2],
+}
+
+/// The coefficients of a biquad, normalized so that `a0` is 1.0.
+#[derive(Clone, Copy, Debug, Default)]
+struct BiquadCoefficients {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    /// Creates a new filter with no gain.
+    pub fn new(kind: BiquadType, cutoff: f64, q: f64) -> Self {
+        Self {
+            kind,
+            cutoff,
+            q,
+            gain: 0.0,
+            sample_rate: 44100.0,
+            coefficients: BiquadCoefficients::default(),
+            settings: None,
+            state: [0.0; 2],
+        }
+    }
+
+    /// Creates a new shelf or peak filter that boosts or cuts by `gain` decibels.
+    pub fn shelf(kind: BiquadType, cutoff: f64, gain: f64) -> Self {
+        Self {
+            gain,
+            ..Self::new(kind, cutoff, core::f64::consts::FRAC_1_SQRT_2)
+        }
+    }
+
+    /// Passes the next sample through the filter.
+    pub fn process(&mut self, sample: f64, sample_rate: f64) -> f64 {
+        let settings = (self.kind, self.cutoff, self.q, self.gain, sample_rate);
+        if self.settings != Some(settings) {
+            self.coefficients = self.coefficients(sample_rate);
+            self.settings = Some(settings);
+        }
+        // Transposed direct form II, which keeps the least state.
+        let BiquadCoefficients { b0, b1, b2, a1, a2 } = self.coefficients;
+        let output = b0 * sample + self.state[0];
+        self.state[0] = b1 * sample - a1 * output + self.state[1];
+        self.state[1] = b2 * sample - a2 * output;
+        output
+    }
+
+    /// Works out the coefficients of the filter for its current settings.
+    fn coefficients(&self, sample_rate: f64) -> BiquadCoefficients {
+        let cutoff = self.cutoff.clamp(10.0, sample_rate * 0.49);
+        let w0 = 2.0 * core::f64::consts::PI * cutoff / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * self.q.max(0.1));
+        let a = 10.0_f64.powf(self.gain / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+        let (b0, b1, b2, a0, a1, a2) = match self.kind {
+            BiquadType::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            BiquadType::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            BiquadType::BandPass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            BiquadType::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            BiquadType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            BiquadType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+            BiquadType::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+        };
+        BiquadCoefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+impl Effect for Biquad {
+    fn process(&mut self, sample: f64) -> f64 {
+        Biquad::process(self, sample, self.sample_rate)
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.state = [0.0; 2];
+    }
+
+    fn get_name() -> &'static str {
+        "Biquad Filter"
+    }
+}
+
+/// Shifts every frequency of a signal by the same number of Hz, unlike transposing which multiplies them. Since the
+/// harmonics of a note no longer line up after a shift, this gives inharmonic, metallic tones, and sweeping the shift
+/// slowly gives a barberpole effect. Like the filters, every note needs its own shifter. A shifter can also be added to
+/// the effects of `Player`, in which case it shifts by `shift` at the sample rate that it is prepared with.
+#[derive(Clone, Debug)]
+pub struct FrequencyShifter {
+    /// How far every frequency is shifted (in Hz) when the shifter is used as an effect.
+    pub shift: f64,
+    hilbert: HilbertTransform,
+    phase: f64,
+    sample_rate: f64,
+}
+
+impl Default for FrequencyShifter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrequencyShifter {
+    /// Creates a new shifter.
+    pub fn new() -> Self {
+        Self {
+            shift: 0.0,
+            hilbert: HilbertTransform::default(),
+            phase: 0.0,
+            sample_rate: 44100.0,
+        }
+    }
+
+    /// Passes the next sample through the shifter, moving every frequency up by `shift` Hz, or down if `shift` is
+    /// negative. Frequencies that are shifted below 0 Hz fold back over.
+    pub fn process(&mut self, sample: f64, shift: f64, sample_rate: f64) -> f64 {
+        let (real, imaginary) = self.hilbert.process(sample);
+        let output = real * self.phase.cos() + imaginary * self.phase.sin();
+        self.phase = (self.phase + core::f64::consts::TAU * shift / sample_rate)
+            .rem_euclid(core::f64::consts::TAU);
+        output
+    }
+}
+
+impl Effect for FrequencyShifter {
+    fn process(&mut self, sample: f64) -> f64 {
+        FrequencyShifter::process(self, sample, self.shift, self.sample_rate)
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.hilbert = HilbertTransform::default();
+        self.phase = 0.0;
+    }
+
+    fn get_name() -> &'static str {
+        "Frequency Shifter"
+    }
+}
+
+/// Splits a signal into two signals that are 90 degrees apart over most of the audible range, using two chains of
+/// all-pass filters.
+#[derive(Clone, Debug, Default)]
+struct HilbertTransform {
+    real: [AllPass; 4],
+    imaginary: [AllPass; 4],
+    delay: f64,
+}
+
+impl HilbertTransform {
+    /// The coefficients of the all-pass filters of each chain, as published by Olli Niemitalo. Each stage squares its
+    /// coefficient (see `AllPass`).
+    const REAL: [f64; 4] = [0.6923878, 0.9360654322959, 0.9882295226860, 0.9987488452737];
+    const IMAGINARY: [f64; 4] = [
+        0.4021921162426,
+        0.8561710882420,
+        0.9722909545651,
+        0.9952884791278,
+    ];
+
+    /// Passes the next sample through both chains, returning the in-phase and quadrature outputs.
+    fn process(&mut self, sample: f64) -> (f64, f64) {
+        let real = self
+            .real
+            .iter_mut()
+            .zip(Self::REAL)
+            .fold(sample, |x, (stage, a)| stage.process(x, a));
+        let imaginary = self
+            .imaginary
+            .iter_mut()
+            .zip(Self::IMAGINARY)
+            .fold(sample, |x, (stage, a)| stage.process(x, a));
+        // The in-phase chain is a sample ahead of the quadrature chain.
+        let real = core::mem::replace(&mut self.delay, real);
+        (real, imaginary)
+    }
+}
+
+/// A second order all-pass filter with only even powers, (a² - z⁻²) / (1 - a²z⁻²), as used by `HilbertTransform`.
+#[derive(Clone, Debug, Default)]
+struct AllPass {
+    inputs: [f64; 2],
+    outputs: [f64; 2],
+}
+
+impl AllPass {
+    fn process(&mut self, sample: f64, a: f64) -> f64 {
+        let output = a * a * (sample + self.outputs[1]) - self.inputs[1];
+        self.inputs = [sample, self.inputs[0]];
+        self.outputs = [output, self.outputs[0]];
+        output
+    }
+}
+
+/// Tracks the level of a signal, rising quickly as the signal gets louder and falling slowly as it fades, which is
+/// useful for effects that react to how hard a note is played.
+#[derive(Clone, Debug)]
+pub struct EnvelopeFollower {
+    /// How long the level takes to rise (in seconds)
+    pub attack: f64,
+    /// How long the level takes to fall (in seconds)
+    pub release: f64,
+    level: f64,
+}
+
+impl Default for EnvelopeFollower {
+    fn default() -> Self {
+        Self::new(0.005, 0.1)
+    }
+}
+
+impl EnvelopeFollower {
+    /// Creates a new envelope follower.
+    pub fn new(attack: f64, release: f64) -> Self {
+        Self {
+            attack,
+            release,
+            level: 0.0,
+        }
+    }
+
+    /// Follows the next sample, returning the current level of the signal.
+    pub fn process(&mut self, sample: f64, sample_rate: f64) -> f64 {
+        let input = sample.abs();
+        let time = if input > self.level {
+            self.attack
+        } else {
+            self.release
+        };
+        let coefficient = (-1.0 / (time * sample_rate).max(1.0)).exp();
+        self.level = input + coefficient * (self.level - input);
+        self.level
+    }
+}
+
+/// Sweeps a resonant band-pass filter up as the signal gets louder and back down as it fades, so that every note
+/// opens with a "wah". This suits funky bass and clavinet sounds. Like the filters, every note needs its own auto-wah.
+#[derive(Clone, Debug)]
+pub struct AutoWah {
+    /// The center frequency of the filter when the signal is silent (in Hz)
+    pub min_center: f64,
+    /// The center frequency of the filter when the signal is at full scale (in Hz)
+    pub max_center: f64,
+    /// How narrow the band is, where higher values give a more pronounced wah
+    pub q: f64,
+    /// Scales the level of the signal before it moves the filter
+    pub sensitivity: f64,
+    /// How much of the filtered signal is mixed in, from 0.0 to 1.0
+    pub mix: f64,
+    pub follower: EnvelopeFollower,
+    filter: BandPassFilter,
+}
+
+impl Default for AutoWah {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AutoWah {
+    /// Creates a new auto-wah.
+    pub fn new() -> Self {
+        Self {
+            min_center: 300.0,
+            max_center: 2500.0,
+            q: 5.0,
+            sensitivity: 2.0,
+            mix: 1.0,
+            follower: EnvelopeFollower::new(0.002, 0.15),
+            filter: BandPassFilter::new(),
+        }
+    }
+
+    /// Passes the next sample through the auto-wah.
+    pub fn process(&mut self, sample: f64, sample_rate: f64) -> f64 {
+        let level = (self.follower.process(sample, sample_rate) * self.sensitivity).min(1.0);
+        // Sweep exponentially so that the wah moves evenly through the octaves.
+        let center = self.min_center * (self.max_center / self.min_center).powf(level);
+        let wet = self.filter.process(sample, center, self.q, sample_rate);
+        sample + self.mix * (wet - sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use core::f64::consts::TAU;
+
+    const SAMPLE_RATE: f64 = 44100.0;
+
+    /// Gets the amplitude and phase (in degrees) of the component of `signal` at `hertz`.
+    fn component(signal: &[f64], hertz: f64) -> (f64, f64) {
+        let (re, im) = signal
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (i, x)| {
+                let angle = TAU * hertz * i as f64 / SAMPLE_RATE;
+                (re + x * angle.cos(), im - x * angle.sin())
+            });
+        let n = signal.len() as f64;
+        (
+            2.0 * (re * re + im * im).sqrt() / n,
+            im.atan2(re).to_degrees(),
+        )
+    }
+
+    #[test]
+    fn hilbert_outputs_are_in_quadrature() {
+        for hertz in [100.0, 1000.0, 5000.0, 15000.0] {
+            let mut hilbert = HilbertTransform::default();
+            let (mut real, mut imaginary) = (Vec::new(), Vec::new());
+            for i in 0..44100 * 2 {
+                let (re, im) = hilbert.process((TAU * hertz * i as f64 / SAMPLE_RATE).sin());
+                if i >= 44100 {
+                    real.push(re);
+                    imaginary.push(im);
+                }
+            }
+            let (real_level, real_phase) = component(&real, hertz);
+            let (imaginary_level, imaginary_phase) = component(&imaginary, hertz);
+            // The quadrature output lags the in-phase output by 90 degrees.
+            let difference = (imaginary_phase - real_phase).rem_euclid(360.0);
+            assert!(
+                (difference - 90.0).abs() < 1.0,
+                "phase difference of {difference} degrees at {hertz} Hz"
+            );
+            assert!((real_level - imaginary_level).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn frequency_shifter_leaves_out_the_image() {
+        let mut shifter = FrequencyShifter::new();
+        let output: Vec<f64> = (0..44100 * 2)
+            .map(|i| {
+                let sample = (TAU * 1000.0 * i as f64 / SAMPLE_RATE).sin();
+                shifter.process(sample, 200.0, SAMPLE_RATE)
+            })
+            .skip(44100)
+            .collect();
+        let (shifted, _) = component(&output, 1200.0);
+        let (image, _) = component(&output, 800.0);
+        assert!(shifted > 0.9, "shifted level of {shifted}");
+        assert!(image < 0.01, "image level of {image}");
+    }
+}
@@ -0,0 +1,1470 @@
+//! Effects that process the mixed output of `Player`, such as delays, to make it sound produced rather than raw.
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::{
+    dsp::{waveshape, Biquad, BiquadType, Curve},
+    errors::AudioError,
+    note::NoteValue,
+    parameters::Parameters,
+    primitives::Parameter,
+    sampler::Sample,
+};
+#[cfg(feature = "std")]
+use crate::{
+    fft::{fft, Complex},
+    primitives::Transport,
+};
+use alloc::{boxed::Box, string::String, string::ToString, sync::Arc, vec, vec::Vec};
+#[cfg(feature = "std")]
+use atomic_float::AtomicF64;
+use core::f64::consts::{PI, TAU};
+#[cfg(feature = "std")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+use dyn_clone::DynClone;
+#[cfg(feature = "wav")]
+use std::path::Path;
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+/// Processes a signal one sample at a time. Since an effect remembers the samples that pass through it, `Player`
+/// gives every output channel its own copy of each effect.
+///
+/// This trait is the extension point for effects from other crates, and hosts (`Player`, `apply_chain`, and the
+/// effects themselves) only ever call the methods below in this order: `prepare` once, then any number of calls to
+/// `process` or `process_block`, with `reset` in between whenever playback jumps. An effect that does this can be
+/// passed anywhere a built-in effect can, and registered by name in an `EffectRegistry`:
+///
+/// ```ignore
+/// #[derive(Clone)]
+/// struct Gain(f64);
+///
+/// impl Effect for Gain {
+///     fn process(&mut self, sample: f64) -> f64 {
+///         sample * self.0
+///     }
+///
+///     fn get_name() -> &'static str {
+///         "Gain"
+///     }
+/// }
+///
+/// let mut registry = EffectRegistry::with_builtins();
+/// registry.register(Gain(0.5));
+/// ```
+pub trait Effect: Send + DynClone {
+    /// Passes the next sample through the effect.
+    fn process(&mut self, sample: f64) -> f64;
+
+    /// Passes a block of samples through the effect in place. By default, this calls `process` on every sample,
+    /// which effects that work on whole blocks (such as in the frequency domain) can override.
+    fn process_block(&mut self, block: &mut [f64]) {
+        for sample in block.iter_mut() {
+            *sample = self.process(*sample);
+        }
+    }
+
+    /// Called once with the sample rate that the effect is about to process at and the largest block that will be
+    /// passed to `process_block`, before the first call to `process`. `Player` processes one frame at a time, so it
+    /// passes a block size of 1. Does nothing by default.
+    fn prepare(&mut self, _sample_rate: f64, _max_block_size: usize) {}
+
+    /// Clears everything that the effect remembers of the signal (such as the contents of a delay line), without
+    /// changing its settings, so that it sounds the same as when it was prepared. Does nothing by default.
+    fn reset(&mut self) {}
+
+    /// Gets the name that the effect is registered under in an `EffectRegistry`.
+    fn get_name() -> &'static str
+    where
+        Self: Sized;
+}
+
+dyn_clone::clone_trait_object!(Effect);
+
+/// Passes a sample through `effect` offline, with every channel going through its own copy of the effect.
+pub fn apply<E>(effect: &E, sample: &Sample) -> Sample
+where
+    E: Effect + 'static,
+{
+    apply_chain(&[Box::new(dyn_clone::clone(effect))], sample)
+}
+
+/// Number of frames that `apply_chain` passes to `Effect::process_block` at once.
+const OFFLINE_BLOCK: usize = 1024;
+
+/// Passes a sample through a chain of effects offline, in order, in the same manner as `apply`.
+pub fn apply_chain(effects: &[Box<dyn Effect>], sample: &Sample) -> Sample {
+    let channels = sample.channels.max(1);
+    let mut data = sample.data.clone();
+    let mut block = Vec::with_capacity(OFFLINE_BLOCK);
+    for channel in 0..channels {
+        let mut chain = effects.to_vec();
+        for effect in chain.iter_mut() {
+            effect.prepare(sample.sample_rate, OFFLINE_BLOCK);
+        }
+        for frames in data.chunks_mut(OFFLINE_BLOCK * channels) {
+            block.clear();
+            block.extend(frames.iter().skip(channel).step_by(channels));
+            for effect in chain.iter_mut() {
+                effect.process_block(&mut block);
+            }
+            for (sample, processed) in frames
+                .iter_mut()
+                .skip(channel)
+                .step_by(channels)
+                .zip(&block)
+            {
+                *sample = *processed;
+            }
+        }
+    }
+    Sample {
+        data,
+        sample_rate: sample.sample_rate,
+        channels: sample.channels,
+    }
+}
+
+/// A copy of a chain of effects for every channel that it processes.
+#[cfg(any(feature = "playback", feature = "serde"))]
+#[derive(Clone, Default)]
+pub(crate) struct EffectChain {
+    channels: Vec<Vec<Box<dyn Effect>>>,
+}
+
+#[cfg(any(feature = "playback", feature = "serde"))]
+impl EffectChain {
+    /// Sets up a copy of `effects` for each of `channels` channels, prepared to process a frame at a time at
+    /// `sample_rate`. This is done before the chain is handed to the audio thread, since preparing an effect may
+    /// allocate or take a while.
+    pub(crate) fn new(effects: Vec<Box<dyn Effect>>, channels: usize, sample_rate: f64) -> Self {
+        if effects.is_empty() {
+            return Self::default();
+        }
+        Self {
+            channels: (0..channels)
+                .map(|_| {
+                    let mut chain = effects.clone();
+                    for effect in chain.iter_mut() {
+                        effect.prepare(sample_rate, 1);
+                    }
+                    chain
+                })
+                .collect(),
+        }
+    }
+
+    /// Passes a frame through the chain.
+    pub(crate) fn process(&mut self, frame: &mut [f64]) {
+        for (sample, chain) in frame.iter_mut().zip(self.channels.iter_mut()) {
+            for effect in chain.iter_mut() {
+                *sample = effect.process(*sample);
+            }
+        }
+    }
+
+    /// Resets every copy of the chain (see `Effect::reset`).
+    #[cfg(feature = "playback")]
+    pub(crate) fn reset(&mut self) {
+        for effect in self.channels.iter_mut().flatten() {
+            effect.reset();
+        }
+    }
+}
+
+/// Drives the values of an effect from `Parameter`s, so that effects can be performed rather than left as static
+/// inserts, such as by sweeping the feedback of a delay or the cutoff of a filter with `Player::modulate_parameter`.
+/// Every parameter is bound to a value of the effect by its name in `Parameters`, and is read before every sample
+/// (or every block in `process_block`), so changes and modulations of the parameter reach every copy of the effect
+/// on the audio thread. The effect is registered under the name of the effect that it wraps.
+///
+/// ```ignore
+/// let feedback = Arc::new(Parameter::new(0.4));
+/// let delay = Automated::new(Delay::synced(120.0, NoteValue::DottedEighth)).bind("feedback", &feedback)?;
+/// player.set_effects(vec![Box::new(delay)]);
+/// let lfo = LowFrequencyOscillator::following(&transport, NoteValue::Whole, 0.3, Oscillator::Sine);
+/// player.modulate_parameter(&feedback, lfo);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Automated<E> {
+    effect: E,
+    /// The name of every bound value, along with the parameter that it follows and the value it was last set to.
+    bindings: Vec<(String, Arc<Parameter>, f64)>,
+}
+
+impl<E: Effect + Parameters> Automated<E> {
+    /// Wraps an effect, which has no values bound to parameters yet.
+    pub fn new(effect: E) -> Self {
+        Self {
+            effect,
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Binds the value of the effect called `name` to `parameter`, replacing any parameter that it was bound to
+    /// before. Returns an error if the effect has no value with that name.
+    pub fn bind(mut self, name: &str, parameter: &Arc<Parameter>) -> Result<Self, AudioError> {
+        let value = self
+            .effect
+            .get_parameter(name)
+            .ok_or_else(|| AudioError::UnknownParameter(name.to_string()))?;
+        self.bindings.retain(|(bound, _, _)| bound != name);
+        self.bindings
+            .push((name.to_string(), Arc::clone(parameter), value));
+        Ok(self)
+    }
+
+    /// Gets the effect that is being driven.
+    pub fn get_effect(&self) -> &E {
+        &self.effect
+    }
+
+    /// Sets every bound value of the effect whose parameter has changed.
+    fn update(&mut self) {
+        for (name, parameter, last) in self.bindings.iter_mut() {
+            let value = parameter.get();
+            if value != *last {
+                *last = value;
+                // The name was checked when it was bound.
+                let _ = self.effect.set_parameter(name, value);
+            }
+        }
+    }
+}
+
+impl<E: Effect + Parameters + Clone + 'static> Effect for Automated<E> {
+    fn process(&mut self, sample: f64) -> f64 {
+        self.update();
+        self.effect.process(sample)
+    }
+
+    fn process_block(&mut self, block: &mut [f64]) {
+        self.update();
+        self.effect.process_block(block);
+    }
+
+    fn prepare(&mut self, sample_rate: f64, max_block_size: usize) {
+        self.effect.prepare(sample_rate, max_block_size);
+    }
+
+    fn reset(&mut self) {
+        self.effect.reset();
+    }
+
+    fn get_name() -> &'static str {
+        E::get_name()
+    }
+}
+
+/// The longest time (in seconds) that `Delay` can be set to, which is how long a line it sets aside in `prepare` so that
+/// its time can be changed while it plays without allocating.
+pub const MAX_DELAY_TIME: f64 = 10.0;
+
+/// Repeats the signal after `time` seconds (up to `MAX_DELAY_TIME`), feeding every repeat back into the delay so that
+/// it echoes away.
+#[derive(Clone, Debug)]
+pub struct Delay {
+    /// The time between repeats (in seconds).
+    pub time: f64,
+    /// How much of every repeat is fed back into the delay, from 0.0 for a single repeat to just under 1.0 for
+    /// repeats that barely fade.
+    pub feedback: f64,
+    /// How much of the delayed signal is mixed in, from 0.0 (dry) to 1.0 (only the repeats).
+    pub mix: f64,
+    buffer: Vec<f64>,
+    position: usize,
+    sample_rate: f64,
+}
+
+impl Delay {
+    /// Creates a new delay with `time` given in milliseconds.
+    pub fn from_millis(time: f64) -> Self {
+        let mut delay = Self {
+            time: time / 1000.0,
+            feedback: 0.4,
+            mix: 0.3,
+            buffer: Vec::new(),
+            position: 0,
+            sample_rate: 0.0,
+        };
+        delay.prepare(44100.0, 1);
+        delay
+    }
+
+    /// Creates a new delay that is synced to the tempo, repeating every `note`, such as `NoteValue::DottedEighth`.
+    pub fn synced(tempo: f64, note: NoteValue) -> Self {
+        Self::from_millis(note.as_secs(tempo) * 1000.0)
+    }
+
+    /// Gets the number of samples that the delay is currently set to, which is no longer than the line.
+    fn delay_samples(&self) -> usize {
+        ((self.time * self.sample_rate).round() as usize).clamp(1, self.buffer.len() - 1)
+    }
+}
+
+impl Effect for Delay {
+    fn process(&mut self, sample: f64) -> f64 {
+        let length = self.buffer.len();
+        // Changing the time only moves the point that the line is read from.
+        let read = (self.position + length - self.delay_samples()) % length;
+        let delayed = self.buffer[read];
+        self.buffer[self.position] = sample + delayed * self.feedback.clamp(0.0, 0.999);
+        self.position = (self.position + 1) % length;
+        sample + self.mix * (delayed - sample)
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.buffer = vec![0.0; (MAX_DELAY_TIME * sample_rate).ceil() as usize + 1];
+        self.position = 0;
+    }
+
+    fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.position = 0;
+    }
+
+    fn get_name() -> &'static str {
+        "Delay"
+    }
+}
+
+/// The longest delay (in seconds) that the modulation effects can sweep to.
+const MAX_MODULATED_DELAY: f64 = 0.1;
+
+/// A delay line that is read at a point swept back and forth by a sine LFO, which is the core shared by `Chorus`,
+/// `Flanger` and `Phaser`.
+#[derive(Clone, Debug, Default)]
+struct ModulatedDelay {
+    buffer: Vec<f64>,
+    position: usize,
+    phase: f64,
+    sample_rate: f64,
+}
+
+impl ModulatedDelay {
+    fn new() -> Self {
+        let mut line = Self::default();
+        line.prepare(44100.0);
+        line
+    }
+
+    fn prepare(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.buffer = vec![0.0; (MAX_MODULATED_DELAY * sample_rate).ceil() as usize + 2];
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.position = 0;
+        self.phase = 0.0;
+    }
+
+    /// Advances the LFO by one sample at `rate` hertz, returning its output between 0.0 and 1.0.
+    fn sweep(&mut self, rate: f64) -> f64 {
+        self.phase = (self.phase + rate / self.sample_rate).fract();
+        0.5 - 0.5 * (TAU * self.phase).cos()
+    }
+
+    /// Reads the line `delay` seconds back, interpolating between the two nearest samples.
+    fn read(&self, delay: f64) -> f64 {
+        let length = self.buffer.len();
+        let delay = (delay * self.sample_rate).clamp(1.0, (length - 2) as f64);
+        let point = self.position as f64 + length as f64 - delay;
+        let fraction = point.fract();
+        let index = point as usize % length;
+        let next = (index + 1) % length;
+        self.buffer[index] + fraction * (self.buffer[next] - self.buffer[index])
+    }
+
+    fn write(&mut self, sample: f64) {
+        self.buffer[self.position] = sample;
+        self.position = (self.position + 1) % self.buffer.len();
+    }
+
+    /// Passes the next sample through the line, with the read point swept between `delay` and `delay + depth` seconds
+    /// back, returning the delayed signal.
+    fn process(&mut self, sample: f64, rate: f64, delay: f64, depth: f64, feedback: f64) -> f64 {
+        let sweep = self.sweep(rate);
+        let delayed = self.read(delay + depth * sweep);
+        self.write(sample + delayed * feedback);
+        delayed
+    }
+}
+
+/// Thickens the signal by mixing in a copy of it that is delayed by a slowly drifting amount, so that it sounds like
+/// several slightly detuned instruments playing together.
+#[derive(Clone, Debug)]
+pub struct Chorus {
+    /// How many times a second the delay drifts back and forth (in hertz).
+    pub rate: f64,
+    /// How far the delay drifts (in seconds).
+    pub depth: f64,
+    /// The shortest delay (in seconds).
+    pub delay: f64,
+    /// How much of the delayed signal is fed back into the delay.
+    pub feedback: f64,
+    /// How much of the delayed signal is mixed in, from 0.0 (dry) to 1.0 (only the delayed signal).
+    pub mix: f64,
+    line: ModulatedDelay,
+}
+
+impl Chorus {
+    /// Creates a new chorus.
+    pub fn new() -> Self {
+        Self {
+            rate: 0.8,
+            depth: 0.004,
+            delay: 0.015,
+            feedback: 0.0,
+            mix: 0.5,
+            line: ModulatedDelay::new(),
+        }
+    }
+}
+
+impl Default for Chorus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Effect for Chorus {
+    fn process(&mut self, sample: f64) -> f64 {
+        let delayed = self.line.process(
+            sample,
+            self.rate,
+            self.delay,
+            self.depth,
+            self.feedback.clamp(0.0, 0.95),
+        );
+        sample + self.mix * (delayed - sample)
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        self.line.prepare(sample_rate);
+    }
+
+    fn reset(&mut self) {
+        self.line.reset();
+    }
+
+    fn get_name() -> &'static str {
+        "Chorus"
+    }
+}
+
+/// Sweeps a comb of notches up and down the spectrum by mixing in a copy of the signal with a very short, swept delay,
+/// which gives the jet-like whoosh of two tapes played slightly out of sync.
+#[derive(Clone, Debug)]
+pub struct Flanger {
+    /// How many times a second the delay sweeps back and forth (in hertz).
+    pub rate: f64,
+    /// How far the delay sweeps (in seconds).
+    pub depth: f64,
+    /// The shortest delay (in seconds).
+    pub delay: f64,
+    /// How much of the delayed signal is fed back into the delay, which sharpens the notches. Negative feedback
+    /// inverts the repeats, which gives a hollower sound.
+    pub feedback: f64,
+    /// How much of the delayed signal is mixed in, from 0.0 (dry) to 1.0 (only the delayed signal).
+    pub mix: f64,
+    line: ModulatedDelay,
+}
+
+impl Flanger {
+    /// Creates a new flanger.
+    pub fn new() -> Self {
+        Self {
+            rate: 0.25,
+            depth: 0.003,
+            delay: 0.001,
+            feedback: 0.6,
+            mix: 0.5,
+            line: ModulatedDelay::new(),
+        }
+    }
+}
+
+impl Default for Flanger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Effect for Flanger {
+    fn process(&mut self, sample: f64) -> f64 {
+        let delayed = self.line.process(
+            sample,
+            self.rate,
+            self.delay,
+            self.depth,
+            self.feedback.clamp(-0.95, 0.95),
+        );
+        sample + self.mix * (delayed - sample)
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        self.line.prepare(sample_rate);
+    }
+
+    fn reset(&mut self) {
+        self.line.reset();
+    }
+
+    fn get_name() -> &'static str {
+        "Flanger"
+    }
+}
+
+/// Sweeps a few notches up and down the spectrum by mixing in a copy of the signal that is passed through a chain of
+/// all-pass filters, which shift the phase of the signal without changing its level. The filters are swept by the LFO
+/// of the shared delay line, and the output of the chain is fed back through the line.
+#[derive(Clone, Debug)]
+pub struct Phaser {
+    /// How many times a second the notches sweep up and down (in hertz).
+    pub rate: f64,
+    /// The lowest frequency that the filters sweep down to (in hertz).
+    pub min_frequency: f64,
+    /// The highest frequency that the filters sweep up to (in hertz).
+    pub max_frequency: f64,
+    /// How much of the filtered signal is fed back into the chain, which deepens the notches.
+    pub feedback: f64,
+    /// How much of the filtered signal is mixed in, where 0.5 gives the deepest notches.
+    pub mix: f64,
+    /// The number of all-pass filters in the chain. Every two filters add a notch.
+    pub stages: usize,
+    line: ModulatedDelay,
+    filters: Vec<f64>,
+}
+
+impl Phaser {
+    /// Creates a new phaser with four stages.
+    pub fn new() -> Self {
+        Self {
+            rate: 0.5,
+            min_frequency: 200.0,
+            max_frequency: 2000.0,
+            feedback: 0.5,
+            mix: 0.5,
+            stages: 4,
+            line: ModulatedDelay::new(),
+            filters: Vec::new(),
+        }
+    }
+}
+
+impl Default for Phaser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Effect for Phaser {
+    fn process(&mut self, sample: f64) -> f64 {
+        self.filters.resize(self.stages, 0.0);
+        let sweep = self.line.sweep(self.rate);
+        let min = self.min_frequency.max(1.0);
+        let max = self.max_frequency.max(min);
+        let frequency = min * (max / min).powf(sweep);
+        let tan = (PI * frequency / self.line.sample_rate).min(1.5).tan();
+        let a = (tan - 1.0) / (tan + 1.0);
+        let feedback = self.line.read(0.0) * self.feedback.clamp(0.0, 0.95);
+        let filtered = self.filters.iter_mut().fold(sample + feedback, |x, state| {
+            // A first-order all-pass filter, which is itself a one-sample delay line.
+            let y = a * x + *state;
+            *state = x - a * y;
+            y
+        });
+        self.line.write(filtered);
+        sample + self.mix * (filtered - sample)
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        self.line.prepare(sample_rate);
+        self.filters = vec![0.0; self.stages];
+    }
+
+    fn reset(&mut self) {
+        self.line.reset();
+        self.filters = vec![0.0; self.stages];
+    }
+
+    fn get_name() -> &'static str {
+        "Phaser"
+    }
+}
+
+/// Smooths the gain reduction (in decibels) of a dynamics processor, moving towards more reduction at the `attack`
+/// time and back at the `release` time, which are both in seconds.
+#[derive(Clone, Debug, Default)]
+struct GainSmoother {
+    reduction: f64,
+    sample_rate: f64,
+}
+
+impl GainSmoother {
+    fn prepare(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.reduction = 0.0;
+    }
+
+    fn process(&mut self, target: f64, attack: f64, release: f64) -> f64 {
+        let time = if target < self.reduction {
+            attack
+        } else {
+            release
+        };
+        let coefficient = (-1.0 / (time.max(1e-6) * self.sample_rate)).exp();
+        self.reduction = coefficient * self.reduction + (1.0 - coefficient) * target;
+        self.reduction
+    }
+}
+
+/// Gets how many decibels a signal at `level` decibels is turned down by, for a compressor with a soft knee that is
+/// `knee` decibels wide around the `threshold`.
+fn gain_reduction(level: f64, threshold: f64, ratio: f64, knee: f64) -> f64 {
+    let slope = 1.0 / ratio.max(1.0) - 1.0;
+    let over = level - threshold;
+    if 2.0 * over <= -knee {
+        0.0
+    } else if knee > 0.0 && 2.0 * over.abs() < knee {
+        slope * (over + knee / 2.0).powi(2) / (2.0 * knee)
+    } else {
+        slope * over
+    }
+}
+
+/// Converts an amplitude into decibels, treating silence as -200 dB.
+fn to_decibels(amplitude: f64) -> f64 {
+    20.0 * amplitude.abs().max(1e-10).log10()
+}
+
+/// Converts decibels into an amplitude.
+fn from_decibels(decibels: f64) -> f64 {
+    10.0_f64.powf(decibels / 20.0)
+}
+
+/// Keeps the signal below a `ceiling` by turning it down smoothly as it nears the ceiling, instead of clipping it.
+/// Peaks that get past the attack are rounded off just below the ceiling, so the output never goes past the ceiling
+/// in either direction.
+#[derive(Clone, Debug)]
+pub struct Limiter {
+    /// The highest amplitude that the output reaches.
+    pub ceiling: f64,
+    /// How far below the ceiling the limiter starts to turn the signal down (in decibels). Steady signals settle
+    /// halfway through the knee, which leaves headroom for peaks that get past the attack.
+    pub knee: f64,
+    /// How long the limiter takes to turn the signal down (in seconds).
+    pub attack: f64,
+    /// How long the limiter takes to let go once the signal drops (in seconds).
+    pub release: f64,
+    smoother: GainSmoother,
+}
+
+impl Limiter {
+    /// Creates a new limiter that keeps the signal below `ceiling`.
+    pub fn new(ceiling: f64) -> Self {
+        let mut limiter = Self {
+            ceiling,
+            knee: 6.0,
+            attack: 0.001,
+            release: 0.1,
+            smoother: GainSmoother::default(),
+        };
+        limiter.prepare(44100.0, 1);
+        limiter
+    }
+
+    /// Limits every channel of a frame by the same amount, following the loudest of them, so that peaks on one side
+    /// do not shift the stereo image. `Player` limits its output this way, while copies of the limiter in a chain of
+    /// effects each follow their own channel.
+    pub fn process_frame(&mut self, frame: &mut [f64]) {
+        let peak = frame
+            .iter()
+            .fold(0.0, |peak: f64, sample| peak.max(sample.abs()));
+        let gain = self.gain(peak);
+        for sample in frame.iter_mut() {
+            *sample = self.clip(*sample * gain);
+        }
+    }
+
+    /// Gets the gain that the signal is turned down by, given its current level.
+    fn gain(&mut self, level: f64) -> f64 {
+        let target = gain_reduction(
+            to_decibels(level),
+            to_decibels(self.ceiling.max(1e-6)) - self.knee / 2.0,
+            f64::INFINITY,
+            self.knee,
+        );
+        from_decibels(self.smoother.process(target, self.attack, self.release))
+    }
+
+    /// Bends anything that gets past the attack smoothly towards the ceiling.
+    fn clip(&self, sample: f64) -> f64 {
+        let ceiling = self.ceiling.max(1e-6);
+        let knee = 0.9 * ceiling;
+        if sample.abs() <= knee {
+            sample
+        } else {
+            let excess = (sample.abs() - knee) / (ceiling - knee);
+            sample.signum() * (knee + (ceiling - knee) * excess.tanh())
+        }
+    }
+}
+
+impl Effect for Limiter {
+    fn process(&mut self, sample: f64) -> f64 {
+        let gain = self.gain(sample);
+        self.clip(sample * gain)
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        self.smoother.prepare(sample_rate);
+    }
+
+    fn reset(&mut self) {
+        self.smoother.reset();
+    }
+
+    fn get_name() -> &'static str {
+        "Limiter"
+    }
+}
+
+/// Evens out the volume of the signal by turning down everything louder than the `threshold` by the `ratio`, so that
+/// busy passages do not drown out quiet ones. The `makeup` gain turns the whole signal back up afterwards.
+#[derive(Clone, Debug)]
+pub struct Compressor {
+    /// The level that the compressor starts to turn the signal down at (in decibels).
+    pub threshold: f64,
+    /// How much the signal over the threshold is turned down, such as 4.0 to turn 4 dB over the threshold into 1 dB
+    /// over.
+    pub ratio: f64,
+    /// How wide the knee around the threshold is (in decibels), over which the compression eases in.
+    pub knee: f64,
+    /// How long the compressor takes to turn the signal down (in seconds).
+    pub attack: f64,
+    /// How long the compressor takes to let go once the signal drops (in seconds).
+    pub release: f64,
+    /// How much the signal is turned up after it is compressed (in decibels).
+    pub makeup: f64,
+    smoother: GainSmoother,
+}
+
+impl Compressor {
+    /// Creates a new compressor with a gentle ratio of 4:1.
+    pub fn new(threshold: f64) -> Self {
+        let mut compressor = Self {
+            threshold,
+            ratio: 4.0,
+            knee: 6.0,
+            attack: 0.01,
+            release: 0.15,
+            makeup: 0.0,
+            smoother: GainSmoother::default(),
+        };
+        compressor.prepare(44100.0, 1);
+        compressor
+    }
+}
+
+impl Effect for Compressor {
+    fn process(&mut self, sample: f64) -> f64 {
+        let target = gain_reduction(to_decibels(sample), self.threshold, self.ratio, self.knee);
+        let reduction = self.smoother.process(target, self.attack, self.release);
+        sample * from_decibels(reduction + self.makeup)
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        self.smoother.prepare(sample_rate);
+    }
+
+    fn reset(&mut self) {
+        self.smoother.reset();
+    }
+
+    fn get_name() -> &'static str {
+        "Compressor"
+    }
+}
+
+/// A three-band equalizer, with a low shelf, a peak in the middle and a high shelf, for taming harsh instruments (such
+/// as by cutting the highs of square waves) or adding weight to thin ones.
+#[derive(Clone, Debug)]
+pub struct EQ3 {
+    /// The frequency that the low shelf starts at (in Hz).
+    pub low_frequency: f64,
+    /// How much the low shelf boosts or cuts by (in decibels).
+    pub low_gain: f64,
+    /// The center frequency of the middle band (in Hz).
+    pub mid_frequency: f64,
+    /// How much the middle band boosts or cuts by (in decibels).
+    pub mid_gain: f64,
+    /// How narrow the middle band is, such as 0.7 for a band roughly two octaves wide.
+    pub mid_q: f64,
+    /// The frequency that the high shelf starts at (in Hz).
+    pub high_frequency: f64,
+    /// How much the high shelf boosts or cuts by (in decibels).
+    pub high_gain: f64,
+    bands: [Biquad; 3],
+    sample_rate: f64,
+}
+
+impl EQ3 {
+    /// Creates a new equalizer that leaves the signal unchanged until its gains are set.
+    pub fn new() -> Self {
+        Self {
+            low_frequency: 200.0,
+            low_gain: 0.0,
+            mid_frequency: 1000.0,
+            mid_gain: 0.0,
+            mid_q: 0.7,
+            high_frequency: 5000.0,
+            high_gain: 0.0,
+            bands: [
+                Biquad::shelf(BiquadType::LowShelf, 200.0, 0.0),
+                Biquad::shelf(BiquadType::Peak, 1000.0, 0.0),
+                Biquad::shelf(BiquadType::HighShelf, 5000.0, 0.0),
+            ],
+            sample_rate: 44100.0,
+        }
+    }
+}
+
+impl Default for EQ3 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Effect for EQ3 {
+    fn process(&mut self, sample: f64) -> f64 {
+        let [low, mid, high] = &mut self.bands;
+        (low.cutoff, low.gain) = (self.low_frequency, self.low_gain);
+        (mid.cutoff, mid.gain, mid.q) = (self.mid_frequency, self.mid_gain, self.mid_q);
+        (high.cutoff, high.gain) = (self.high_frequency, self.high_gain);
+        self.bands
+            .iter_mut()
+            .fold(sample, |x, band| band.process(x, self.sample_rate))
+    }
+
+    fn prepare(&mut self, sample_rate: f64, max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        for band in self.bands.iter_mut() {
+            Effect::prepare(band, sample_rate, max_block_size);
+        }
+    }
+
+    fn reset(&mut self) {
+        for band in self.bands.iter_mut() {
+            band.reset();
+        }
+    }
+
+    fn get_name() -> &'static str {
+        "3-Band EQ"
+    }
+}
+
+/// Tilts the tone of the signal between dark and bright with a single knob, using a low shelf and a high shelf that
+/// meet at `frequency` and move in opposite directions, so that the overall loudness stays about the same. This is a
+/// quicker way to adjust the overall tone of a mix than setting up an `EQ3`.
+#[derive(Clone, Debug)]
+pub struct Tilt {
+    /// How far the tone is tilted (in decibels), where positive values brighten the signal by turning up the highs and
+    /// turning down the lows, negative values darken it, and 0.0 leaves it unchanged. Each shelf moves by half of it.
+    pub tilt: f64,
+    /// The frequency that the tilt pivots around (in Hz).
+    pub frequency: f64,
+    shelves: [Biquad; 2],
+    sample_rate: f64,
+}
+
+impl Tilt {
+    /// Creates a new tilt that pivots around 1 kHz and leaves the signal unchanged until it is tilted.
+    pub fn new() -> Self {
+        Self {
+            tilt: 0.0,
+            frequency: 1000.0,
+            shelves: [
+                Biquad::shelf(BiquadType::LowShelf, 1000.0, 0.0),
+                Biquad::shelf(BiquadType::HighShelf, 1000.0, 0.0),
+            ],
+            sample_rate: 44100.0,
+        }
+    }
+}
+
+impl Default for Tilt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Effect for Tilt {
+    fn process(&mut self, sample: f64) -> f64 {
+        let [low, high] = &mut self.shelves;
+        (low.cutoff, low.gain) = (self.frequency, -self.tilt / 2.0);
+        (high.cutoff, high.gain) = (self.frequency, self.tilt / 2.0);
+        self.shelves
+            .iter_mut()
+            .fold(sample, |x, shelf| shelf.process(x, self.sample_rate))
+    }
+
+    fn prepare(&mut self, sample_rate: f64, max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        for shelf in self.shelves.iter_mut() {
+            Effect::prepare(shelf, sample_rate, max_block_size);
+        }
+    }
+
+    fn reset(&mut self) {
+        for shelf in self.shelves.iter_mut() {
+            shelf.reset();
+        }
+    }
+
+    fn get_name() -> &'static str {
+        "Tilt"
+    }
+}
+
+/// Saturates the signal with a `tanh` curve, which adds harmonics and grit that grow harsher the more it is driven.
+#[derive(Clone, Debug)]
+pub struct Distortion {
+    /// How hard the signal is driven into the curve (should be above 0.0), where around 1.0 is subtle and 10.0 or
+    /// more turns most sounds into a buzz.
+    pub drive: f64,
+    /// How much of the distorted signal is mixed in, from 0.0 (dry) to 1.0 (only the distorted signal).
+    pub mix: f64,
+}
+
+impl Distortion {
+    /// Creates a new distortion with a `drive` given in the same manner as the field.
+    pub fn new(drive: f64) -> Self {
+        Self { drive, mix: 1.0 }
+    }
+}
+
+impl Effect for Distortion {
+    fn process(&mut self, sample: f64) -> f64 {
+        let drive = self.drive.max(1e-3);
+        let distorted = waveshape(sample, Curve::Tanh { drive });
+        sample + self.mix * (distorted - sample)
+    }
+
+    fn get_name() -> &'static str {
+        "Distortion"
+    }
+}
+
+/// Saturates the positive and negative halves of the signal by different amounts, like a tube amplifier pushed
+/// into overdrive. Unlike `Distortion`, this adds even harmonics, which sound warmer.
+#[derive(Clone, Debug)]
+pub struct Overdrive {
+    /// How hard the signal is driven (should be above 0.0).
+    pub drive: f64,
+    /// How lopsided the clipping is, from 0.0 for symmetric clipping to 1.0 for clipping that mostly bends one half.
+    pub asymmetry: f64,
+    /// How much of the overdriven signal is mixed in, from 0.0 (dry) to 1.0 (only the overdriven signal).
+    pub mix: f64,
+    /// The last input and output of the filter that removes the offset that lopsided clipping leaves behind.
+    dc_blocker: (f64, f64),
+    sample_rate: f64,
+}
+
+impl Overdrive {
+    /// Creates a new overdrive with a `drive` given in the same manner as the field.
+    pub fn new(drive: f64) -> Self {
+        Self {
+            drive,
+            asymmetry: 0.3,
+            mix: 1.0,
+            dc_blocker: (0.0, 0.0),
+            sample_rate: 44100.0,
+        }
+    }
+}
+
+impl Effect for Overdrive {
+    fn process(&mut self, sample: f64) -> f64 {
+        let drive = self.drive.max(1e-3);
+        // Shifting the curve makes one half of the signal clip sooner than the other.
+        let bias = self.asymmetry.clamp(0.0, 1.0);
+        let shaped = ((drive * sample + bias).tanh() - bias.tanh()) / drive.tanh();
+        // A one-pole high-pass filter at around 10 Hz.
+        let pole = 1.0 - 2.0 * PI * 10.0 / self.sample_rate;
+        let (previous_input, previous_output) = self.dc_blocker;
+        let overdriven = shaped - previous_input + pole * previous_output;
+        self.dc_blocker = (shaped, overdriven);
+        sample + self.mix * (overdriven - sample)
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.dc_blocker = (0.0, 0.0);
+    }
+
+    fn get_name() -> &'static str {
+        "Overdrive"
+    }
+}
+
+/// Lowers the resolution of the signal, both in how many levels it can take (its bit depth) and in how often it
+/// changes (its sample rate), for the lo-fi crunch of old samplers and game consoles. The lowered sample rate is
+/// not filtered, so frequencies above half of it fold back down as inharmonic aliasing, which is part of the sound.
+#[derive(Clone, Debug)]
+pub struct Bitcrusher {
+    /// The number of bits that every sample is rounded to, such as 8.0 for the sound of an 8-bit console. Fractional
+    /// values smoothly step between bit depths.
+    pub bits: f64,
+    /// The sample rate that the signal is held at (in Hz).
+    pub rate: f64,
+    /// How much of the crushed signal is mixed in, from 0.0 (dry) to 1.0 (only the crushed signal).
+    pub mix: f64,
+    held: f64,
+    phase: f64,
+    sample_rate: f64,
+}
+
+impl Bitcrusher {
+    /// Creates a new bitcrusher that rounds to `bits` bits and holds the signal at `rate` Hz.
+    pub fn new(bits: f64, rate: f64) -> Self {
+        Self {
+            bits,
+            rate,
+            mix: 1.0,
+            held: 0.0,
+            phase: 1.0,
+            sample_rate: 44100.0,
+        }
+    }
+}
+
+impl Effect for Bitcrusher {
+    fn process(&mut self, sample: f64) -> f64 {
+        self.phase += self.rate / self.sample_rate;
+        if self.phase >= 1.0 {
+            self.phase = self.phase.fract();
+            let levels = 2.0_f64.powf(self.bits.clamp(1.0, 32.0) - 1.0);
+            self.held = (sample.clamp(-1.0, 1.0) * levels).round() / levels;
+        }
+        sample + self.mix * (self.held - sample)
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.held = 0.0;
+        self.phase = 1.0;
+    }
+
+    fn get_name() -> &'static str {
+        "Bitcrusher"
+    }
+}
+
+/// How quiet a fading repeat of a `BeatRepeat` gets (-60 dB) before it gives way to the signal again.
+#[cfg(feature = "std")]
+const SILENT_REPEAT: f64 = 0.001;
+
+/// Captures the last few beats of the signal and, on command, loops a slice of them over and over in place of the
+/// signal, for the stutters and glitchy fills of live performance. Put it in the chain of effects of `Player` to mangle
+/// the whole output, or in the insert effects of an instrument to mangle a single channel. Since `Player` gives every
+/// output channel its own copy of the effect, repeats are triggered through a `BeatRepeatControl` that is shared by
+/// every copy, so get one with `get_control` before handing the effect over. Beats are those of a `Transport`, so
+/// that the slices follow its tempo and start on its grid. For the same reason, this effect is not registered in
+/// `EffectRegistry::with_builtins`.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug)]
+pub struct BeatRepeat {
+    /// The number of beats of the signal that are captured, which is the longest slice that can be repeated. Room is
+    /// made for them at the tempo of the transport when the effect is prepared, so slices are shortened to fit if the
+    /// tempo slows down afterwards.
+    pub beats: f64,
+    /// The grid (in beats) that repeats start on, such as 1.0 to wait for the next beat of the transport, or 0.0 to
+    /// start straight away.
+    pub quantize: f64,
+    /// How much every repeat of a slice is turned down compared to the one before it, from 0.0 for a single repeat to
+    /// 1.0 for repeats that do not fade. Repeats that fade away give way to the signal again.
+    pub decay: f64,
+    transport: Transport,
+    control: BeatRepeatControl,
+    buffer: Vec<f64>,
+    position: usize,
+    /// A slice that starts once the given number of samples have passed, so that it lands on the grid.
+    pending: Option<(usize, usize)>,
+    repeat: Option<Repeat>,
+    generation: usize,
+    sample_rate: f64,
+}
+
+/// The slice of the captured signal that a `BeatRepeat` is looping.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug)]
+struct Repeat {
+    start: usize,
+    length: usize,
+    offset: usize,
+    gain: f64,
+}
+
+#[cfg(feature = "std")]
+impl BeatRepeat {
+    /// Creates a new beat repeat that captures the last `length` of `transport`, such as `NoteValue::Whole` for a bar
+    /// of 4/4, with repeats starting on the next sixteenth note.
+    pub fn new(transport: &Transport, length: NoteValue) -> Self {
+        let mut beat_repeat = Self {
+            beats: length.beats(),
+            quantize: NoteValue::Sixteenth.beats(),
+            decay: 1.0,
+            transport: transport.clone(),
+            control: BeatRepeatControl::default(),
+            buffer: Vec::new(),
+            position: 0,
+            pending: None,
+            repeat: None,
+            generation: 0,
+            sample_rate: 0.0,
+        };
+        beat_repeat.prepare(44100.0, 1);
+        beat_repeat
+    }
+
+    /// Gets the control that triggers repeats in this effect and in every copy of it.
+    pub fn get_control(&self) -> BeatRepeatControl {
+        self.control.clone()
+    }
+
+    /// Converts a number of beats at `tempo` into a number of samples.
+    fn beats_to_samples(&self, beats: f64, tempo: f64) -> usize {
+        (beats * 60.0 / tempo * self.sample_rate).round() as usize
+    }
+}
+
+#[cfg(feature = "std")]
+impl Effect for BeatRepeat {
+    fn process(&mut self, sample: f64) -> f64 {
+        let length = self.buffer.len();
+        let generation = self.control.inner.generation.load(Ordering::Acquire);
+        if generation != self.generation {
+            self.generation = generation;
+            let slice = self.control.inner.slice.load(Ordering::Relaxed);
+            self.pending = None;
+            if slice > 0.0 {
+                let (tempo, beat) = self.transport.get_tempo_and_beat();
+                let wait = if self.quantize > 0.0 {
+                    (beat / self.quantize).ceil() * self.quantize - beat
+                } else {
+                    0.0
+                };
+                let slice_length = self.beats_to_samples(slice, tempo).clamp(1, length);
+                self.pending = Some((self.beats_to_samples(wait, tempo), slice_length));
+            } else {
+                self.repeat = None;
+            }
+        }
+        if let Some((wait, slice_length)) = &mut self.pending {
+            if *wait == 0 {
+                // The slice is the audio that has just been heard, which is why it sounds like a stutter.
+                self.repeat = Some(Repeat {
+                    start: (self.position + length - *slice_length) % length,
+                    length: *slice_length,
+                    offset: 0,
+                    gain: 1.0,
+                });
+                self.pending = None;
+            } else {
+                *wait -= 1;
+            }
+        }
+        match &mut self.repeat {
+            Some(repeat) => {
+                // Capturing is held off while repeating, so that the slice is not overwritten.
+                let repeated = self.buffer[(repeat.start + repeat.offset) % length] * repeat.gain;
+                repeat.offset += 1;
+                if repeat.offset == repeat.length {
+                    repeat.offset = 0;
+                    repeat.gain *= self.decay.clamp(0.0, 1.0);
+                    if repeat.gain < SILENT_REPEAT {
+                        self.repeat = None;
+                    }
+                }
+                repeated
+            }
+            None => {
+                self.buffer[self.position] = sample;
+                self.position = (self.position + 1) % length;
+                sample
+            }
+        }
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        let length = self.beats_to_samples(self.beats, self.transport.get_tempo());
+        self.buffer = vec![0.0; length.max(1)];
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.position = 0;
+        self.pending = None;
+        self.repeat = None;
+    }
+
+    fn get_name() -> &'static str {
+        "BeatRepeat"
+    }
+}
+
+/// Triggers repeats in a `BeatRepeat` and every copy of it from another thread, such as from a button while the
+/// effect is playing in `Player`.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default)]
+pub struct BeatRepeatControl {
+    inner: Arc<BeatRepeatState>,
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+struct BeatRepeatState {
+    slice: AtomicF64,
+    generation: AtomicUsize,
+}
+
+#[cfg(feature = "std")]
+impl BeatRepeatControl {
+    /// Starts looping the last `slice` of the signal on the next beat of the grid, such as `NoteValue::Sixteenth` for a
+    /// stutter, which replaces any slice that is already looping. Slices longer than the captured beats are shortened
+    /// to fit.
+    pub fn repeat(&self, slice: NoteValue) {
+        self.set_slice(slice.beats());
+    }
+
+    /// Stops looping, letting the signal through again.
+    pub fn stop(&self) {
+        self.set_slice(0.0);
+    }
+
+    /// Checks whether a slice has been asked to loop and has not been stopped, even if it has since faded away.
+    pub fn is_repeating(&self) -> bool {
+        self.inner.slice.load(Ordering::Relaxed) > 0.0
+    }
+
+    /// Hands a slice (in beats) to the effect, where a slice of 0.0 stops looping.
+    fn set_slice(&self, slice: f64) {
+        self.inner.slice.store(slice, Ordering::Relaxed);
+        self.inner.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Number of frames in every partition of the impulse response of a `ConvolutionReverb`. The reverb is delayed by two
+/// partitions, since each block of input is convolved a little at a time while the next block comes in.
+#[cfg(feature = "std")]
+const CONVOLUTION_BLOCK: usize = 512;
+
+/// Impulse response partitions computed at a sample rate.
+#[cfg(feature = "std")]
+type Partitions = (f64, Arc<Vec<Vec<Complex>>>);
+
+/// Places the signal in a real space by convolving it with an impulse response, which is a recording of how the space
+/// responds to a single click (or a sweep that has been turned into one). Stereo impulse responses are mixed down to
+/// mono, and impulse responses are scaled to a consistent loudness, so that louder recordings do not give louder
+/// reverbs. The impulse response is split into blocks that are convolved separately in the frequency domain, which
+/// keeps long responses affordable, but delays the reverb by 1024 frames.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub struct ConvolutionReverb {
+    /// How much of the reverb is mixed in, from 0.0 (dry) to 1.0 (only the reverb).
+    pub mix: f64,
+    impulse: Arc<Sample>,
+    /// The partitions that were last computed, which are shared by every copy of the reverb, so that the copies for
+    /// each channel of a chain do not compute them again.
+    cache: Arc<Mutex<Partitions>>,
+    /// The spectrum of every block of the impulse response.
+    partitions: Arc<Vec<Vec<Complex>>>,
+    /// The spectra of the most recent blocks of input, where `newest` is the index of the newest one and older ones
+    /// follow it.
+    history: Vec<Vec<Complex>>,
+    newest: usize,
+    input: Vec<f64>,
+    /// The spectrum of the block of reverb that is being worked out.
+    sum: Vec<Complex>,
+    /// The block of reverb that is playing.
+    output: Vec<f64>,
+    /// The block of reverb that plays next.
+    ready: Vec<f64>,
+    position: usize,
+    /// How far along the convolution of the latest block of input is, from forward transform, through every
+    /// partition, to inverse transform.
+    step: usize,
+    steps_per_frame: usize,
+}
+
+#[cfg(feature = "std")]
+impl ConvolutionReverb {
+    /// Creates a new reverb from an impulse response.
+    pub fn new(impulse: Sample) -> Self {
+        let mut reverb = Self {
+            mix: 0.3,
+            impulse: Arc::new(impulse),
+            cache: Arc::new(Mutex::new((0.0, Arc::new(Vec::new())))),
+            partitions: Arc::new(Vec::new()),
+            history: Vec::new(),
+            newest: 0,
+            input: Vec::new(),
+            sum: Vec::new(),
+            output: Vec::new(),
+            ready: Vec::new(),
+            position: 0,
+            step: 0,
+            steps_per_frame: 1,
+        };
+        reverb.prepare(44100.0, 1);
+        reverb
+    }
+
+    /// Creates a new reverb from an impulse response saved as a WAV file.
+    #[cfg(feature = "wav")]
+    pub fn from_wav<P>(path: P) -> Result<Self, AudioError>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::new(Sample::from_wav(path)?))
+    }
+
+    /// Resamples the impulse response to `sample_rate` and transforms each of its blocks.
+    fn partition(&self, sample_rate: f64) -> Vec<Vec<Complex>> {
+        let frames = (self.impulse.get_length() * sample_rate).ceil() as usize;
+        let mut response: Vec<f64> = (0..frames)
+            .map(|i| self.impulse.amplitude(i as f64 / sample_rate))
+            .collect();
+        let energy = response.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if energy > 0.0 {
+            response.iter_mut().for_each(|x| *x /= energy);
+        }
+        if response.is_empty() {
+            response.push(0.0);
+        }
+        response
+            .chunks(CONVOLUTION_BLOCK)
+            .map(|block| {
+                let mut spectrum = vec![Complex::default(); 2 * CONVOLUTION_BLOCK];
+                for (s, x) in spectrum.iter_mut().zip(block) {
+                    s.re = *x;
+                }
+                fft(&mut spectrum, false);
+                spectrum
+            })
+            .collect()
+    }
+
+    /// Takes the next step of convolving the latest block of input.
+    fn convolve_step(&mut self) {
+        let partitions = self.partitions.len();
+        match self.step {
+            0 => fft(&mut self.history[self.newest], false),
+            step if step <= partitions => {
+                let index = step - 1;
+                let input = &self.history[(self.newest + index) % partitions];
+                for ((total, x), h) in self.sum.iter_mut().zip(input).zip(&self.partitions[index]) {
+                    let product = x.mul(*h);
+                    total.re += product.re;
+                    total.im += product.im;
+                }
+            }
+            step if step == partitions + 1 => {
+                fft(&mut self.sum, true);
+                // Overlap-save, where only the second half of the block is free of wrapped around samples.
+                let size = self.sum.len() as f64;
+                for (output, x) in self.ready.iter_mut().zip(&self.sum[CONVOLUTION_BLOCK..]) {
+                    *output = x.re / size;
+                }
+            }
+            _ => return,
+        }
+        self.step += 1;
+    }
+}
+
+#[cfg(feature = "std")]
+impl Effect for ConvolutionReverb {
+    fn process(&mut self, sample: f64) -> f64 {
+        self.input[CONVOLUTION_BLOCK + self.position] = sample;
+        let wet = self.output[self.position];
+        self.position += 1;
+        for _ in 0..self.steps_per_frame {
+            self.convolve_step();
+        }
+        if self.position == CONVOLUTION_BLOCK {
+            // The work is spread so that it is done by now, but make sure of it before the block is played.
+            while self.step <= self.partitions.len() + 1 {
+                self.convolve_step();
+            }
+            core::mem::swap(&mut self.output, &mut self.ready);
+            // Start on the block of input that has just come in, which is transformed along with the one before it.
+            self.newest = (self.newest + self.history.len() - 1) % self.history.len();
+            for (s, x) in self.history[self.newest].iter_mut().zip(&self.input) {
+                *s = Complex::new(*x, 0.0);
+            }
+            self.sum.fill(Complex::default());
+            self.step = 0;
+            self.input.copy_within(CONVOLUTION_BLOCK.., 0);
+            self.position = 0;
+        }
+        sample + self.mix * (wet - sample)
+    }
+
+    fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+        let size = 2 * CONVOLUTION_BLOCK;
+        // The impulse response is resampled to the sample rate that the reverb runs at.
+        let mut cache = self.cache.lock().unwrap();
+        if cache.0 != sample_rate {
+            *cache = (sample_rate, Arc::new(self.partition(sample_rate)));
+        }
+        self.partitions = Arc::clone(&cache.1);
+        drop(cache);
+        let partitions = self.partitions.len();
+        self.history = vec![vec![Complex::default(); size]; partitions];
+        self.newest = 0;
+        self.input = vec![0.0; size];
+        self.sum = vec![Complex::default(); size];
+        self.output = vec![0.0; CONVOLUTION_BLOCK];
+        self.ready = vec![0.0; CONVOLUTION_BLOCK];
+        self.position = 0;
+        // Nothing to convolve until the first block of input has come in.
+        self.step = partitions + 2;
+        self.steps_per_frame = (partitions + 2).div_ceil(CONVOLUTION_BLOCK);
+    }
+
+    fn reset(&mut self) {
+        for spectrum in self.history.iter_mut() {
+            spectrum.fill(Complex::default());
+        }
+        self.input.fill(0.0);
+        self.sum.fill(Complex::default());
+        self.output.fill(0.0);
+        self.ready.fill(0.0);
+        self.position = 0;
+        self.step = self.partitions.len() + 2;
+    }
+
+    fn get_name() -> &'static str {
+        "Convolution Reverb"
+    }
+}
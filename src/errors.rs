@@ -1,17 +1,124 @@
-use cpal::{BuildStreamError, DefaultStreamConfigError, PlayStreamError};
+use alloc::string::String;
+#[cfg(feature = "playback")]
+use cpal::{
+    BuildStreamError, DefaultStreamConfigError, DevicesError, HostUnavailable, PauseStreamError,
+    PlayStreamError, SupportedStreamConfigsError,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum AudioError {
+    #[cfg(feature = "playback")]
     #[error(transparent)]
     PlayStreamError(#[from] PlayStreamError),
 
+    #[cfg(feature = "playback")]
     #[error(transparent)]
     BuildStreamError(#[from] BuildStreamError),
 
+    #[cfg(feature = "playback")]
+    #[error(transparent)]
+    PauseStreamError(#[from] PauseStreamError),
+
     #[error("cannot find output device")]
     UnknownDevice,
 
+    #[error("cannot find output device: {0}")]
+    UnknownDeviceName(String),
+
+    #[cfg(feature = "playback")]
+    #[error(transparent)]
+    DevicesError(#[from] DevicesError),
+
+    #[cfg(feature = "playback")]
+    #[error(transparent)]
+    HostUnavailable(#[from] HostUnavailable),
+
+    #[cfg(feature = "playback")]
+    #[error(transparent)]
+    SupportedStreamConfigsError(#[from] SupportedStreamConfigsError),
+
+    #[error("audio stream thread has stopped")]
+    StreamThreadStopped,
+
+    #[error("output device does not support {0} channels")]
+    UnsupportedChannels(usize),
+
+    #[error("output device does not support {channels} channels at {sample_rate} Hz")]
+    UnsupportedConfig { sample_rate: u32, channels: usize },
+
+    #[cfg(feature = "playback")]
     #[error(transparent)]
     DefaultStreamConfigError(#[from] DefaultStreamConfigError),
+
+    #[cfg(feature = "wav")]
+    #[error(transparent)]
+    WavError(#[from] hound::Error),
+
+    #[cfg(feature = "hydrogen")]
+    #[error(transparent)]
+    XmlError(#[from] roxmltree::Error),
+
+    #[error("invalid pattern: {0}")]
+    InvalidPattern(String),
+
+    #[error("pattern has {found} steps, but the sequencer has {expected} steps")]
+    PatternLength { expected: usize, found: usize },
+
+    #[error("unknown parameter: {0}")]
+    UnknownParameter(String),
+
+    #[error("unknown instrument: {0}")]
+    UnknownInstrument(String),
+
+    #[error("unknown effect: {0}")]
+    UnknownEffect(String),
+
+    #[cfg(all(
+        feature = "jack",
+        any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd"
+        )
+    ))]
+    #[error(transparent)]
+    JackError(#[from] jack::Error),
+
+    #[cfg(feature = "broadcast")]
+    #[error("Icecast server refused the stream: {0}")]
+    IcecastRefused(String),
+
+    #[cfg(all(
+        feature = "mpris",
+        any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd"
+        )
+    ))]
+    #[error(transparent)]
+    DbusError(#[from] zbus::Error),
+
+    #[cfg(all(feature = "smtc", windows))]
+    #[error(transparent)]
+    WindowsError(#[from] windows::core::Error),
+
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    #[cfg(feature = "serde")]
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[cfg(feature = "serde")]
+    #[error(transparent)]
+    TomlSerializeError(#[from] toml::ser::Error),
+
+    #[cfg(feature = "serde")]
+    #[error(transparent)]
+    TomlDeserializeError(#[from] toml::de::Error),
 }
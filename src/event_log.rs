@@ -0,0 +1,763 @@
+//! A log of the events that happen during a performance, such as notes being played and released, written out as
+//! JSON lines so that timing issues can be debugged, and replaying logged performances offline.
+
+use crate::{
+    effects::{Effect, EffectChain},
+    errors::AudioError,
+    instruments::Instrument,
+    player::Normalization,
+    primitives::Parameter,
+    sampler::Sample,
+};
+use atomic_float::AtomicF64;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    iter::Peekable,
+    path::Path,
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+    vec::IntoIter,
+};
+
+/// Something that happened during a performance.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A note was played.
+    NoteOn {
+        instrument: String,
+        note_id: u8,
+        velocity: f64,
+    },
+    /// A note was released.
+    NoteOff { instrument: String, note_id: u8 },
+    /// A parameter was changed.
+    ParameterChange { name: String, value: f64 },
+    /// A `PatternChain` moved on to another section.
+    PatternSwitch { section: usize },
+}
+
+/// An event along with the time that it happened at, which makes up a single line of the log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub time: f64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// A log that writes every event it is given as a line of JSON. Clones of an `EventLog` write to the same log, so
+/// that the player and the sequencers can share one log. Events are written on a background thread, so that logging
+/// never waits on the writer, and everything logged is written out once the last clone is dropped.
+#[derive(Clone)]
+pub struct EventLog {
+    writer: Arc<LogWriter>,
+    clock: Arc<Mutex<Option<Arc<AtomicF64>>>>,
+    start: Instant,
+}
+
+/// Something for the thread that writes an `EventLog` to do.
+enum LogMessage {
+    Entry(LogEntry),
+    /// Flushes the writer, replying once everything before this has been written.
+    Flush(Sender<()>),
+}
+
+/// The thread that writes an `EventLog`, which is stopped once the last clone of the log is dropped.
+struct LogWriter {
+    sender: Option<Sender<LogMessage>>,
+    /// The last error that the thread ran into, which is kept until it is taken.
+    error: Arc<Mutex<Option<AudioError>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LogWriter {
+    fn send(&self, message: LogMessage) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(message);
+        }
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        // Closing the channel tells the thread to write out what is left and stop.
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl EventLog {
+    /// Creates a new log that writes to `writer`.
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let error = Arc::new(Mutex::new(None));
+        let thread = {
+            let error = Arc::clone(&error);
+            let mut writer = writer;
+            thread::spawn(move || {
+                let report = |result: Result<(), AudioError>| {
+                    if let Err(err) = result {
+                        *error.lock().unwrap() = Some(err);
+                    }
+                };
+                for message in receiver {
+                    match message {
+                        LogMessage::Entry(entry) => report(write_entry(&mut writer, &entry)),
+                        LogMessage::Flush(done) => {
+                            report(writer.flush().map_err(AudioError::from));
+                            let _ = done.send(());
+                        }
+                    }
+                }
+                report(writer.flush().map_err(AudioError::from));
+            })
+        };
+        Self {
+            writer: Arc::new(LogWriter {
+                sender: Some(sender),
+                error,
+                thread: Some(thread),
+            }),
+            clock: Arc::new(Mutex::new(None)),
+            start: Instant::now(),
+        }
+    }
+
+    /// Creates a new log that writes to a file at `path`, replacing the file if it already exists.
+    pub fn create<P>(path: P) -> Result<Self, AudioError>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Gets the time that events are logged at. Once the log is given to `Player::set_event_log`, this is the time of
+    /// the player, otherwise it is the wall time elapsed since the log was created.
+    pub fn get_time(&self) -> f64 {
+        match &*self.clock.lock().unwrap() {
+            Some(clock) => clock.load(Ordering::Relaxed),
+            None => self.start.elapsed().as_secs_f64(),
+        }
+    }
+
+    /// Writes an event to the log, timestamped with `get_time`. Since events are written in the background, this
+    /// returns the error of an earlier write that failed, if any (see `take_error`).
+    pub fn log(&self, event: Event) -> Result<(), AudioError> {
+        self.log_at(self.get_time(), event)
+    }
+
+    /// Writes an event to the log with the given timestamp, in the same manner as `log`.
+    pub fn log_at(&self, time: f64, event: Event) -> Result<(), AudioError> {
+        self.queue_at(time, event);
+        self.take_error().map_or(Ok(()), Err)
+    }
+
+    /// Waits for every event logged so far to be written, and writes out any events that are buffered by the
+    /// underlying writer. Returns the error of any write that failed since the last error was taken.
+    pub fn flush(&self) -> Result<(), AudioError> {
+        let (done, flushed) = mpsc::channel();
+        self.writer.send(LogMessage::Flush(done));
+        let _ = flushed.recv();
+        self.take_error().map_or(Ok(()), Err)
+    }
+
+    /// Takes the last error that writing the log ran into, if any. Events logged by the player and the sequencers
+    /// never return errors, so that a broken log never interrupts a performance, which leaves this to check on them.
+    pub fn take_error(&self) -> Option<AudioError> {
+        self.writer.error.lock().unwrap().take()
+    }
+
+    #[cfg(feature = "playback")]
+    pub(crate) fn set_clock(&self, clock: Arc<AtomicF64>) {
+        *self.clock.lock().unwrap() = Some(clock);
+    }
+
+    /// Hands an event to the thread that writes the log, leaving any error to `take_error`.
+    pub(crate) fn queue(&self, event: Event) {
+        self.queue_at(self.get_time(), event);
+    }
+
+    /// Same as `queue`, but with the given timestamp.
+    pub(crate) fn queue_at(&self, time: f64, event: Event) {
+        self.writer
+            .send(LogMessage::Entry(LogEntry { time, event }));
+    }
+}
+
+/// Writes an entry as a line of JSON.
+fn write_entry<W>(writer: &mut W, entry: &LogEntry) -> Result<(), AudioError>
+where
+    W: Write,
+{
+    serde_json::to_writer(&mut *writer, entry)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// Reads back every entry of a log that was written by `EventLog`. Blank lines are skipped.
+pub fn read_event_log<R>(reader: R) -> Result<Vec<LogEntry>, AudioError>
+where
+    R: BufRead,
+{
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(serde_json::from_str(&line)?);
+    }
+    Ok(entries)
+}
+
+/// Reads back every entry of a log file that was written by `EventLog`.
+pub fn open_event_log<P>(path: P) -> Result<Vec<LogEntry>, AudioError>
+where
+    P: AsRef<Path>,
+{
+    read_event_log(BufReader::new(File::open(path)?))
+}
+
+/// Re-renders a logged performance offline into a stereo sample, with every event landing on the exact sample that it
+/// was logged at. This produces a clean recording of a performance even if the live run had glitches. Instruments
+/// are looked up by name, so every instrument that was played has to be added with `add`. Since the parameters of
+/// instruments are not logged, instruments that vary on every note (see `Instrument::vary`) are played without
+/// variation, and the mixer settings of the player are not applied apart from the effects given with `with_effects`.
+#[derive(Clone)]
+pub struct Replay {
+    instruments: HashMap<&'static str, Box<dyn Instrument>>,
+    parameters: HashMap<String, Arc<Parameter>>,
+    effects: Vec<Box<dyn Effect>>,
+    sample_rate: f64,
+    tail: f64,
+    normalization: Normalization,
+    region: Option<RenderRegion>,
+}
+
+impl Replay {
+    /// Creates a new replay that renders at `sample_rate`.
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            instruments: HashMap::new(),
+            parameters: HashMap::new(),
+            effects: Vec::new(),
+            sample_rate,
+            tail: 5.0,
+            normalization: Normalization::default(),
+            region: None,
+        }
+    }
+
+    /// Adds an instrument that is used to play every logged note of the instrument with the same name.
+    pub fn add<I>(&mut self, instrument: I)
+    where
+        I: Instrument + 'static,
+    {
+        self.instruments.insert(I::get_name(), Box::new(instrument));
+    }
+
+    /// Adds a parameter that logged parameter changes under `name` are applied to while rendering.
+    pub fn add_parameter(&mut self, name: &str, parameter: Arc<Parameter>) {
+        self.parameters.insert(name.to_string(), parameter);
+    }
+
+    /// Sets how long notes are left to ring out after the last event (in seconds), which is 5 seconds by default, so
+    /// that the decay of reverbs and delays is not cut off. Rendering stops earlier if every note finishes before then,
+    /// unless a region or effects are set.
+    pub fn with_tail(mut self, tail: f64) -> Self {
+        self.tail = tail;
+        self
+    }
+
+    /// Passes the mix through a chain of effects, in order, in the same manner as `Player::set_effects`. Give it the
+    /// same effects as the player that the performance was logged from, so that the tails of delays and reverbs match
+    /// the live mix. Every stem is passed through a copy of its own, so the stems still add up to the mix as long as
+    /// the effects are linear, such as delays and reverbs but not compressors.
+    pub fn with_effects(mut self, effects: Vec<Box<dyn Effect>>) -> Self {
+        self.effects = effects;
+        self
+    }
+
+    /// Only renders a region of the performance, instead of everything from the first entry to the last.
+    pub fn with_region(mut self, region: RenderRegion) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// Sets how the volume of the mix is normalized, which should match the normalization of the player that the
+    /// performance was logged from.
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Renders the performance in `entries`, starting from the first entry. Notes of instruments that were not added
+    /// are skipped.
+    pub fn render(&self, entries: &[LogEntry]) -> Sample {
+        self.render_pass(entries, false).0
+    }
+
+    /// Renders the performance in `entries` in the same manner as `render`, along with a stem for every instrument
+    /// that was played, keyed by the name of the instrument. Every stem starts at the same time and has the same
+    /// length as the mix, so that the stems line up when they are imported into a DAW, and the stems add up to the
+    /// mix.
+    pub fn render_stems(&self, entries: &[LogEntry]) -> (Sample, BTreeMap<&'static str, Sample>) {
+        self.render_pass(entries, true)
+    }
+
+    /// Renders the performance in `entries` with `render_stems`, and writes every stem to a WAV file in `directory`,
+    /// named after its instrument (such as `8_bit_bell.wav` for "8-Bit Bell"). The directory is created if it does not
+    /// exist, and existing files are replaced. Returns the mix.
+    #[cfg(feature = "wav")]
+    pub fn write_stems<P>(&self, entries: &[LogEntry], directory: P) -> Result<Sample, AudioError>
+    where
+        P: AsRef<Path>,
+    {
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)?;
+        let (mix, stems) = self.render_stems(entries);
+        for (name, stem) in stems {
+            stem.to_wav(directory.join(stem_file_name(name)))?;
+        }
+        Ok(mix)
+    }
+
+    /// Renders the performance in `entries` one frame at a time, in the same manner as `render`, so that long
+    /// performances can be passed on to an encoder or a network stream without holding the whole render in memory.
+    /// The stream can be read either as an iterator of stereo frames or as raw bytes through `Read`.
+    pub fn stream(&self, entries: &[LogEntry]) -> ReplayStream {
+        let mut entries = entries.to_vec();
+        entries.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let first = entries.first().map_or(0.0, |e| e.time);
+        let (entries, start, end) = match self.region {
+            Some(region) => {
+                let (start, end) = region.bounds(first);
+                let length = (end - start) * region.loops as f64;
+                let parameters = self
+                    .parameters
+                    .iter()
+                    .map(|(name, parameter)| (name.clone(), parameter.get_base()))
+                    .collect();
+                (
+                    region.schedule(entries, first, parameters),
+                    start,
+                    start + length + self.tail,
+                )
+            }
+            None => {
+                let end = entries.last().map_or(0.0, |e| e.time) + self.tail;
+                (entries, first, end)
+            }
+        };
+        ReplayStream {
+            replay: self.clone(),
+            effects: EffectChain::new(self.effects.clone(), 2, self.sample_rate),
+            stem_effects: BTreeMap::new(),
+            notes: Vec::new(),
+            pending: entries.into_iter().peekable(),
+            start,
+            end,
+            frame: 0,
+            bytes: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Renders the performance in `entries` into a mix, and also into a stem for every instrument if `stems` is set.
+    fn render_pass(
+        &self,
+        entries: &[LogEntry],
+        stems: bool,
+    ) -> (Sample, BTreeMap<&'static str, Sample>) {
+        let mut stream = self.stream(entries);
+        let mut data = Vec::new();
+        let mut stem_data: BTreeMap<&'static str, Vec<f64>> = BTreeMap::new();
+        while let Some([left, right]) = stream.render_frame(stems.then_some(&mut stem_data)) {
+            data.push(left);
+            data.push(right);
+        }
+        let stems = stem_data
+            .into_iter()
+            .map(|(name, stem)| {
+                (
+                    name,
+                    Sample {
+                        data: stem,
+                        sample_rate: self.sample_rate,
+                        channels: 2,
+                    },
+                )
+            })
+            .collect();
+        let mix = Sample {
+            data,
+            sample_rate: self.sample_rate,
+            channels: 2,
+        };
+        (mix, stems)
+    }
+
+    /// Applies a logged event in the same manner as `Player`.
+    fn apply(&self, notes: &mut Vec<ReplayNote>, entry: LogEntry) {
+        match entry.event {
+            Event::NoteOn {
+                instrument,
+                note_id,
+                velocity,
+            } => {
+                let Some((name, channel)) = self.instruments.get_key_value(instrument.as_str())
+                else {
+                    return;
+                };
+                match notes
+                    .iter_mut()
+                    .find(|n| n.id == note_id && n.instrument_name == *name)
+                {
+                    Some(note) => {
+                        if note.off >= note.on {
+                            note.on = entry.time;
+                            note.velocity = velocity;
+                        }
+                    }
+                    None => {
+                        let mut instrument = dyn_clone::clone_box(&**channel);
+                        instrument.prepare(self.sample_rate);
+                        notes.push(ReplayNote {
+                            id: note_id,
+                            on: entry.time,
+                            off: 0.0,
+                            active: true,
+                            instrument,
+                            instrument_name: name,
+                            velocity,
+                        });
+                    }
+                }
+            }
+            Event::NoteOff {
+                instrument,
+                note_id,
+            } => {
+                let note = notes
+                    .iter_mut()
+                    .find(|n| n.id == note_id && n.instrument_name == instrument);
+                if let Some(note) = note {
+                    if note.off <= note.on {
+                        note.off = entry.time;
+                    }
+                }
+            }
+            Event::ParameterChange { name, value } => {
+                if let Some(parameter) = self.parameters.get(&name) {
+                    parameter.set(value);
+                }
+            }
+            Event::PatternSwitch { .. } => {}
+        }
+    }
+}
+
+/// A performance that is being rendered one frame at a time by `Replay::stream`. Every frame is a pair of left and
+/// right samples. Through `Read`, the frames are given as interleaved 32-bit floating point samples in little-endian
+/// byte order, which is the same format that `Sample::to_wav` writes.
+pub struct ReplayStream {
+    replay: Replay,
+    effects: EffectChain,
+    /// The copy of the effects for every stem, which is set up when the stem is first played.
+    stem_effects: BTreeMap<&'static str, EffectChain>,
+    notes: Vec<ReplayNote>,
+    pending: Peekable<IntoIter<LogEntry>>,
+    start: f64,
+    end: f64,
+    frame: usize,
+    /// The bytes of the last frame, which `read` may have only handed out in part.
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl ReplayStream {
+    /// Gets the sample rate of the stream.
+    pub fn get_sample_rate(&self) -> f64 {
+        self.replay.sample_rate
+    }
+
+    /// Renders the next frame, or returns `None` once the performance is over. If `stems` is given, the frame of every
+    /// instrument is added to its stem, where stems of instruments that are played for the first time start with
+    /// silence to line up with the mix.
+    fn render_frame(
+        &mut self,
+        stems: Option<&mut BTreeMap<&'static str, Vec<f64>>>,
+    ) -> Option<[f64; 2]> {
+        let replay = &self.replay;
+        let time = self.start + self.frame as f64 / replay.sample_rate;
+        let finished = replay.region.is_none()
+            && replay.effects.is_empty()
+            && self.pending.peek().is_none()
+            && self.notes.is_empty();
+        if time >= self.end || finished {
+            return None;
+        }
+        while let Some(entry) = self.pending.next_if(|e| e.time <= time) {
+            replay.apply(&mut self.notes, entry);
+        }
+        let voices = self.notes.len();
+        let mut instrument_voices: Vec<(&str, usize)> = Vec::new();
+        for note in &self.notes {
+            match instrument_voices
+                .iter_mut()
+                .find(|(name, _)| *name == note.instrument_name)
+            {
+                Some((_, count)) => *count += 1,
+                None => instrument_voices.push((note.instrument_name, 1)),
+            }
+        }
+        let mut stems = stems;
+        if let Some(stems) = &mut stems {
+            for note in &self.notes {
+                stems.entry(note.instrument_name).or_insert_with(|| {
+                    self.stem_effects.insert(
+                        note.instrument_name,
+                        EffectChain::new(replay.effects.clone(), 2, replay.sample_rate),
+                    );
+                    vec![0.0; self.frame * 2]
+                });
+            }
+            for stem in stems.values_mut() {
+                stem.extend([0.0, 0.0]);
+            }
+        }
+        let (mut left, mut right) = (0.0, 0.0);
+        for note in self.notes.iter_mut() {
+            let mut note_finished = false;
+            let (l, r) = note.instrument.process(
+                time,
+                note.on,
+                note.off,
+                note.id,
+                note.velocity,
+                &mut note_finished,
+            );
+            let same_instrument = instrument_voices
+                .iter()
+                .find(|(name, _)| *name == note.instrument_name)
+                .map_or(1, |(_, count)| *count);
+            let gain = replay.normalization.gain(voices, same_instrument);
+            left += l * gain;
+            right += r * gain;
+            if let Some(stem) = stems
+                .as_mut()
+                .and_then(|stems| stems.get_mut(note.instrument_name))
+            {
+                let frame = stem.len() - 2;
+                stem[frame] += l * gain;
+                stem[frame + 1] += r * gain;
+            }
+            // The amplitude of most envelopes is 0 when a note is first played, which should not end the note.
+            note.active = !note_finished || time <= note.on;
+        }
+        self.notes.retain(|n| n.active);
+        if let Some(stems) = stems {
+            for (name, stem) in stems.iter_mut() {
+                if let Some(effects) = self.stem_effects.get_mut(name) {
+                    let frame = stem.len() - 2;
+                    effects.process(&mut stem[frame..]);
+                }
+            }
+        }
+        let mut frame = [left, right];
+        self.effects.process(&mut frame);
+        self.frame += 1;
+        Some(frame)
+    }
+}
+
+impl Iterator for ReplayStream {
+    type Item = [f64; 2];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.render_frame(None)
+    }
+}
+
+impl Read for ReplayStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.position == self.bytes.len() {
+                let Some(frame) = self.render_frame(None) else {
+                    break;
+                };
+                self.bytes.clear();
+                for sample in frame {
+                    self.bytes.extend((sample as f32).to_le_bytes());
+                }
+                self.position = 0;
+            }
+            let count = (self.bytes.len() - self.position).min(buf.len() - written);
+            buf[written..written + count]
+                .copy_from_slice(&self.bytes[self.position..self.position + count]);
+            self.position += count;
+            written += count;
+        }
+        Ok(written)
+    }
+}
+
+/// A region of a performance that `Replay` renders, measured in bars from an origin, which is the first entry of the
+/// log by default. Only the notes that are played within the region are rendered, and notes that are still held at
+/// the end of the region are released there. Parameter changes from before the region are applied at its start, and
+/// every loop starts again from the values that the parameters had at the start of the region, where parameters that
+/// were not changed before the region start from the value they had when the replay was started. The region can be
+/// bounced a number of times back to back, such as to export a loop that repeats four times. The
+/// render always lasts for exactly the length of the region (times the number of loops) plus the tail of the
+/// `Replay`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderRegion {
+    pub tempo: f64,
+    pub beats_per_bar: u32,
+    /// The first bar of the region, where the first bar of the performance is bar 0.
+    pub start_bar: f64,
+    /// The bar that the region ends at, which is not included in the region.
+    pub end_bar: f64,
+    /// The number of times that the region is played.
+    pub loops: usize,
+    /// The time of the start of bar 0, or `None` to start at the first entry of the log.
+    pub origin: Option<f64>,
+}
+
+impl RenderRegion {
+    /// Creates a new region that spans from `start_bar` up to `end_bar` at `tempo`, which is played once.
+    pub fn new(tempo: f64, beats_per_bar: u32, start_bar: f64, end_bar: f64) -> Self {
+        Self {
+            tempo,
+            beats_per_bar,
+            start_bar,
+            end_bar,
+            loops: 1,
+            origin: None,
+        }
+    }
+
+    /// Plays the region `loops` times back to back.
+    pub fn looped(mut self, loops: usize) -> Self {
+        self.loops = loops.max(1);
+        self
+    }
+
+    /// Starts bar 0 at `time`, such as the time that a sequencer was started at.
+    pub fn with_origin(mut self, time: f64) -> Self {
+        self.origin = Some(time);
+        self
+    }
+
+    /// Gets the start and end times of the region, where `first` is the time of the first entry of the log.
+    fn bounds(&self, first: f64) -> (f64, f64) {
+        let origin = self.origin.unwrap_or(first);
+        let bar = 60.0 / self.tempo * self.beats_per_bar as f64;
+        let start = origin + self.start_bar * bar;
+        (start, origin + self.end_bar.max(self.start_bar) * bar)
+    }
+
+    /// Picks out the entries (sorted by time) that are played within the region, repeating them for every loop and
+    /// releasing held notes at the end of every loop. `parameters` are the values of the parameters before the first
+    /// entry, which are set again at the start of every loop along with the changes from before the region.
+    fn schedule(
+        &self,
+        entries: Vec<LogEntry>,
+        first: f64,
+        mut parameters: Vec<(String, f64)>,
+    ) -> Vec<LogEntry> {
+        let (start, end) = self.bounds(first);
+        let mut region = Vec::new();
+        for entry in entries {
+            if entry.time < start {
+                if let Event::ParameterChange { name, value } = entry.event {
+                    parameters.retain(|(n, _)| *n != name);
+                    parameters.push((name, value));
+                }
+            } else if entry.time < end {
+                region.push(entry);
+            }
+        }
+        let mut scheduled = Vec::new();
+        for repeat in 0..self.loops {
+            let offset = repeat as f64 * (end - start);
+            scheduled.extend(parameters.iter().map(|(name, value)| LogEntry {
+                time: start + offset,
+                event: Event::ParameterChange {
+                    name: name.clone(),
+                    value: *value,
+                },
+            }));
+            let mut held: Vec<(String, u8)> = Vec::new();
+            for entry in &region {
+                match &entry.event {
+                    Event::NoteOn {
+                        instrument,
+                        note_id,
+                        ..
+                    } => held.push((instrument.clone(), *note_id)),
+                    Event::NoteOff {
+                        instrument,
+                        note_id,
+                    } => held.retain(|(i, n)| i != instrument || n != note_id),
+                    _ => {}
+                }
+                scheduled.push(LogEntry {
+                    time: entry.time + offset,
+                    event: entry.event.clone(),
+                });
+            }
+            // Released just before the end of the loop, so that the notes of the next loop are played afresh.
+            let release = (end + offset - 1e-9).max(start + offset);
+            scheduled.extend(held.into_iter().map(|(instrument, note_id)| LogEntry {
+                time: release,
+                event: Event::NoteOff {
+                    instrument,
+                    note_id,
+                },
+            }));
+        }
+        scheduled
+    }
+}
+
+/// Turns the name of an instrument into the name of its stem file, such as `8_bit_bell.wav` for "8-Bit Bell".
+#[cfg(feature = "wav")]
+fn stem_file_name(name: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.wav", stem)
+}
+
+/// A note that is being played by `Replay`.
+struct ReplayNote {
+    id: u8,
+    on: f64,
+    off: f64,
+    active: bool,
+    instrument: Box<dyn Instrument>,
+    instrument_name: &'static str,
+    velocity: f64,
+}
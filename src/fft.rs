@@ -0,0 +1,62 @@
+//! A small radix-2 FFT for the effects and encoders that work in the frequency domain.
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use core::f64::consts::PI;
+
+/// A complex number.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub(crate) fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    pub(crate) fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+/// Transforms `buffer` in place, whose length must be a power of two. The inverse transform is not scaled, so its
+/// output has to be divided by the length of the buffer.
+pub(crate) fn fft(buffer: &mut [Complex], inverse: bool) {
+    let n = buffer.len();
+    debug_assert!(n.is_power_of_two());
+    // Reorder the buffer so that the butterflies can work in place.
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            buffer.swap(i, j);
+        }
+    }
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut length = 2;
+    while length <= n {
+        let angle = sign * 2.0 * PI / length as f64;
+        let step = Complex::new(angle.cos(), angle.sin());
+        for start in (0..n).step_by(length) {
+            let mut twiddle = Complex::new(1.0, 0.0);
+            for k in 0..length / 2 {
+                let even = buffer[start + k];
+                let odd = buffer[start + k + length / 2].mul(twiddle);
+                buffer[start + k] = Complex::new(even.re + odd.re, even.im + odd.im);
+                buffer[start + k + length / 2] = Complex::new(even.re - odd.re, even.im - odd.im);
+                twiddle = twiddle.mul(step);
+            }
+        }
+        length <<= 1;
+    }
+}
@@ -0,0 +1,375 @@
+//! Fixed-point versions of the oscillators and envelopes, for microcontrollers without a floating-point unit (such as
+//! Cortex-M boards driving an I2S DAC). Settings are still given as `f64` when an oscillator or envelope is created,
+//! so the same `Oscillator` and `EnvelopeADSR` definitions can be shared with the floating-point code, but every sample
+//! is then computed with integer arithmetic only. Time is counted in samples rather than seconds.
+//!
+//! Only the oscillators and envelopes have fixed-point versions. `Instrument`s still compute their samples as `f64`,
+//! so instruments cannot be run on such boards as they are, and have to be rebuilt from the types in this module.
+//! This module builds without the `std` feature, so it can be used on `no_std` targets along with the rest of the core.
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::oscillators::{EnvelopeADSR, EnvelopeCurve, Oscillator};
+use core::{
+    f64::consts::TAU,
+    ops::{Add, Mul, Neg, Sub},
+};
+
+macro_rules! fixed_point {
+    ($name:ident, $int:ty, $wide:ty, $bits:expr) => {
+        impl $name {
+            /// Silence.
+            pub const ZERO: Self = Self(0);
+            /// The largest value, which is just under 1.0.
+            pub const ONE: Self = Self(<$int>::MAX);
+            /// The smallest value, which is exactly -1.0.
+            pub const MINUS_ONE: Self = Self(<$int>::MIN);
+
+            /// Converts a number between -1.0 and 1.0, clamping numbers outside of that range.
+            pub fn from_f64(value: f64) -> Self {
+                let scaled = (value * (1_i64 << $bits) as f64).round();
+                Self(scaled.clamp(<$int>::MIN as f64, <$int>::MAX as f64) as $int)
+            }
+
+            /// Converts back to a floating-point number.
+            pub fn to_f64(self) -> f64 {
+                self.0 as f64 / (1_i64 << $bits) as f64
+            }
+
+            /// Clamps a wider intermediate result into range.
+            fn saturate(value: $wide) -> Self {
+                Self(value.clamp(<$int>::MIN as $wide, <$int>::MAX as $wide) as $int)
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+
+            /// Adds with saturation, so that a loud mix clips rather than wrapping around.
+            fn add(self, other: Self) -> Self {
+                Self(self.0.saturating_add(other.0))
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+
+            fn sub(self, other: Self) -> Self {
+                Self(self.0.saturating_sub(other.0))
+            }
+        }
+
+        impl Mul for $name {
+            type Output = Self;
+
+            /// Multiplies with rounding.
+            fn mul(self, other: Self) -> Self {
+                let product = self.0 as $wide * other.0 as $wide + (1 << ($bits - 1));
+                Self::saturate(product >> $bits)
+            }
+        }
+
+        impl Neg for $name {
+            type Output = Self;
+
+            fn neg(self) -> Self {
+                Self(self.0.saturating_neg())
+            }
+        }
+    };
+}
+
+/// A number between -1.0 and 1.0 with 15 fractional bits, which is the format of 16-bit DACs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Q15(pub i16);
+
+/// A number between -1.0 and 1.0 with 31 fractional bits, which is what the oscillators and envelopes compute in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Q31(pub i32);
+
+fixed_point!(Q15, i16, i32, 15);
+fixed_point!(Q31, i32, i64, 31);
+
+impl From<Q15> for Q31 {
+    fn from(value: Q15) -> Self {
+        Self((value.0 as i32) << 16)
+    }
+}
+
+impl From<Q31> for Q15 {
+    /// Rounds off the lowest 16 bits.
+    fn from(value: Q31) -> Self {
+        Self(((value.0 as i64 + (1 << 15)) >> 16).min(i16::MAX as i64) as i16)
+    }
+}
+
+/// Converts a frequency to the amount that a 32-bit phase advances by every sample, where a full cycle wraps around.
+fn phase_increment(hertz: f64, sample_rate: u32) -> u32 {
+    (hertz / sample_rate as f64 * (1_u64 << 32) as f64).round() as i64 as u32
+}
+
+/// Approximates a sine wave from a 32-bit phase, to within about 0.1%, with a parabola that is corrected towards the
+/// curve of a sine.
+fn sine(phase: u32) -> Q31 {
+    const ONE: i64 = 1 << 31;
+    // The phase as a fraction between -1.0 and 1.0 of half a cycle.
+    let x = phase as i32 as i64;
+    let y = (4 * x * (ONE - x.abs())) >> 31;
+    // The correction is 0.225 * (y * |y| - y).
+    let correction = ((((y * y.abs()) >> 31) - y) * 483_183_821) >> 31;
+    Q31::saturate(y + correction)
+}
+
+/// A small, fast generator of noise.
+#[derive(Clone, Debug)]
+struct XorShift(u32);
+
+impl XorShift {
+    fn next(&mut self) -> Q31 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        Q31(self.0 as i32)
+    }
+}
+
+/// An oscillator that runs on a phase accumulator, producing one sample of an `Oscillator` shape at a time.
+/// `Oscillator::SawAna` is rendered the same as `Oscillator::SawDig`, since summing its harmonics is too slow for a
+/// microcontroller, and pitch LFOs are left out.
+#[derive(Clone, Debug)]
+pub struct FixedOscillator {
+    shape: Oscillator,
+    phase: u32,
+    increment: u32,
+    modulator_phase: u32,
+    modulator_increment: u32,
+    /// The modulation index of FM in phase units, or the depth of AM.
+    amount: Q31,
+    held: Q31,
+    noise: XorShift,
+    sample_rate: u32,
+}
+
+impl FixedOscillator {
+    /// Creates a new oscillator that plays `shape` at `hertz`.
+    pub fn new(shape: Oscillator, hertz: f64, sample_rate: u32) -> Self {
+        let mut oscillator = Self {
+            shape,
+            phase: 0,
+            increment: 0,
+            modulator_phase: 0,
+            modulator_increment: 0,
+            amount: Q31::ZERO,
+            held: Q31::ZERO,
+            noise: XorShift(0x9E37_79B9),
+            sample_rate,
+        };
+        oscillator.set_frequency(hertz);
+        oscillator
+    }
+
+    /// Changes the frequency without resetting the phase, so that glides do not click.
+    pub fn set_frequency(&mut self, hertz: f64) {
+        self.increment = phase_increment(hertz, self.sample_rate);
+        match self.shape {
+            Oscillator::FM {
+                mod_ratio,
+                mod_index,
+            } => {
+                self.modulator_increment = phase_increment(hertz * mod_ratio, self.sample_rate);
+                // The index is stored as a fraction of a cycle, scaled down by 2^8 so that large indices still fit.
+                self.amount = Q31::from_f64(mod_index / TAU / 256.0);
+            }
+            Oscillator::RingMod { mod_ratio } => {
+                self.modulator_increment = phase_increment(hertz * mod_ratio, self.sample_rate);
+            }
+            Oscillator::AM { mod_ratio, depth } => {
+                self.modulator_increment = phase_increment(hertz * mod_ratio, self.sample_rate);
+                self.amount = Q31::from_f64(depth);
+            }
+            _ => {}
+        }
+    }
+
+    /// Seeds the generator used by `Oscillator::Noise` and `Oscillator::SampleAndHold`.
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.noise = XorShift(seed.max(1));
+        self
+    }
+
+    /// Starts the oscillator over from the beginning of a cycle.
+    pub fn reset(&mut self) {
+        self.phase = 0;
+        self.modulator_phase = 0;
+    }
+
+    /// Outputs the next sample.
+    pub fn next_q31(&mut self) -> Q31 {
+        let phase = self.phase;
+        let modulator = sine(self.modulator_phase);
+        let (next_phase, wrapped) = self.phase.overflowing_add(self.increment);
+        self.phase = next_phase;
+        self.modulator_phase = self.modulator_phase.wrapping_add(self.modulator_increment);
+        match self.shape {
+            Oscillator::Sine => sine(phase),
+            Oscillator::Square => {
+                if phase < 1 << 31 {
+                    Q31::ONE
+                } else {
+                    Q31::MINUS_ONE
+                }
+            }
+            Oscillator::Triangle => {
+                let saw = phase.wrapping_add(1 << 30) as i32 as i64;
+                Q31::saturate(2 * saw.abs() - (1 << 31))
+            }
+            Oscillator::SawAna(_) | Oscillator::SawDig => Q31((phase ^ (1 << 31)) as i32),
+            Oscillator::FM { .. } => {
+                let offset = (self.amount.0 as i64 * modulator.0 as i64) >> 22;
+                sine(phase.wrapping_add(offset as u32))
+            }
+            Oscillator::RingMod { .. } => sine(phase) * modulator,
+            Oscillator::AM { .. } => {
+                // The gain is 1.0 - depth / 2 + depth * b / 2, which swings between 1.0 - depth and 1.0.
+                let half = Q31(1 << 30);
+                let gain = Q31::ONE - self.amount * half + self.amount * (modulator * half);
+                sine(phase) * gain
+            }
+            Oscillator::SampleAndHold => {
+                if wrapped || phase == 0 {
+                    self.held = self.noise.next();
+                }
+                self.held
+            }
+            Oscillator::Noise => self.noise.next(),
+        }
+    }
+
+    /// Outputs the next sample with 16 bits.
+    pub fn next_q15(&mut self) -> Q15 {
+        self.next_q31().into()
+    }
+}
+
+/// Number of points that the curve of every envelope stage is sampled at.
+const CURVE_POINTS: usize = 64;
+
+/// The shortest number of samples that an envelope takes to release, in the same manner as the floating-point
+/// envelopes, at 44.1 kHz.
+const MIN_RELEASE_SAMPLES: u32 = 220;
+
+/// An `EnvelopeCurve` sampled into a table that is interpolated linearly.
+#[derive(Clone, Debug)]
+struct CurveTable([i32; CURVE_POINTS + 1]);
+
+impl CurveTable {
+    fn new(curve: EnvelopeCurve) -> Self {
+        let mut table = [0; CURVE_POINTS + 1];
+        for (i, point) in table.iter_mut().enumerate() {
+            *point = Q31::from_f64(curve.apply(i as f64 / CURVE_POINTS as f64)).0;
+        }
+        Self(table)
+    }
+
+    /// Maps the progress through a stage, where `position` samples out of `length` have passed, to the progress of
+    /// the amplitude through that stage.
+    fn apply(&self, position: u32, length: u32) -> i64 {
+        if position >= length {
+            return self.0[CURVE_POINTS] as i64;
+        }
+        // The progress with 16 fractional bits.
+        let progress = ((position as u64) << 16) * CURVE_POINTS as u64 / length as u64;
+        let index = (progress >> 16) as usize;
+        let fraction = (progress & 0xFFFF) as i64;
+        let (a, b) = (self.0[index] as i64, self.0[index + 1] as i64);
+        a + (((b - a) * fraction) >> 16)
+    }
+}
+
+/// A fixed-point `EnvelopeADSR`, with every time counted in samples.
+#[derive(Clone, Debug)]
+pub struct FixedEnvelopeADSR {
+    attack: u32,
+    decay: u32,
+    release: u32,
+    sustain_amplitude: i64,
+    start_amplitude: i64,
+    attack_curve: CurveTable,
+    decay_curve: CurveTable,
+    release_curve: CurveTable,
+    looping: bool,
+}
+
+impl FixedEnvelopeADSR {
+    /// Converts an envelope to run at `sample_rate`.
+    pub fn new(envelope: &EnvelopeADSR, sample_rate: u32) -> Self {
+        let samples = |seconds: f64| (seconds * sample_rate as f64).round().max(0.0) as u32;
+        Self {
+            attack: samples(envelope.attack_time),
+            decay: samples(envelope.decay_time),
+            release: samples(envelope.release_time).max(MIN_RELEASE_SAMPLES * sample_rate / 44100),
+            sustain_amplitude: Q31::from_f64(envelope.sustain_amplitude).0 as i64,
+            start_amplitude: Q31::from_f64(envelope.start_amplitude).0 as i64,
+            attack_curve: CurveTable::new(envelope.attack_curve),
+            decay_curve: CurveTable::new(envelope.decay_curve),
+            release_curve: CurveTable::new(envelope.release_curve),
+            looping: envelope.looping,
+        }
+    }
+
+    /// Gets the amplitude of the envelope at sample `time`, for a note activated at `time_on` and deactivated at
+    /// `time_off` (which is at or before `time_on` while the note is held), in the same manner as
+    /// `Envelope::amplitude`.
+    pub fn amplitude(&self, time: u32, time_on: u32, time_off: u32) -> Q31 {
+        if time_on >= time_off {
+            return Q31::saturate(self.held_amplitude(time.saturating_sub(time_on)));
+        }
+        let release_time = time.saturating_sub(time_off);
+        if release_time >= self.release {
+            return Q31::ZERO;
+        }
+        let released = self.held_amplitude(time_off - time_on);
+        let progress = self.release_curve.apply(release_time, self.release);
+        Q31::saturate(released - ((released * progress) >> 31))
+    }
+
+    /// Like `amplitude`, but scaled by how hard the note was played.
+    pub fn amplitude_with_velocity(
+        &self,
+        time: u32,
+        time_on: u32,
+        time_off: u32,
+        velocity: Q31,
+    ) -> Q31 {
+        self.amplitude(time, time_on, time_off) * velocity
+    }
+
+    /// Gets the amplitude while the note is held, `life_time` samples after it was activated.
+    fn held_amplitude(&self, life_time: u32) -> i64 {
+        let cycle = self.attack + self.decay;
+        if self.looping && cycle > 0 && life_time > cycle {
+            // Repeats of the attack start from the sustain amplitude instead of silence.
+            let cycle_life_time = (life_time - cycle) % cycle;
+            if cycle_life_time <= self.attack {
+                let progress = self.attack_curve.apply(cycle_life_time, self.attack);
+                return self.sustain_amplitude
+                    + (((self.start_amplitude - self.sustain_amplitude) * progress) >> 31);
+            }
+            return self.held_amplitude_once(cycle_life_time);
+        }
+        self.held_amplitude_once(life_time)
+    }
+
+    /// Same as `held_amplitude`, but without looping.
+    fn held_amplitude_once(&self, life_time: u32) -> i64 {
+        if life_time < self.attack {
+            (self.start_amplitude * self.attack_curve.apply(life_time, self.attack)) >> 31
+        } else if life_time < self.attack + self.decay {
+            let progress = self.decay_curve.apply(life_time - self.attack, self.decay);
+            self.start_amplitude
+                + (((self.sustain_amplitude - self.start_amplitude) * progress) >> 31)
+        } else {
+            self.sustain_amplitude
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! A small FLAC encoder, which compresses losslessly with the fixed predictors of the format rather than searching
+//! for the best linear predictor. This gets most of the way to the size of a full encoder on synthesized audio without
+//! pulling in a native library.
+
+use crate::sampler::Sample;
+use std::io::{self, Write};
+
+/// Number of frames in every block, which is the default of the reference encoder.
+const BLOCK_SIZE: usize = 4096;
+
+/// The largest Rice parameter, since 15 is reserved as the escape code.
+const MAX_RICE_PARAMETER: u32 = 14;
+
+/// Encodes a sample as a FLAC stream with `bits_per_sample` bits per sample, where samples are clamped between -1.0
+/// and 1.0.
+pub(crate) fn encode<W>(mut writer: W, sample: &Sample, bits_per_sample: u32) -> io::Result<()>
+where
+    W: Write,
+{
+    let channels = sample.channels.max(1);
+    let max = ((1_i64 << (bits_per_sample - 1)) - 1) as f64;
+    let samples: Vec<i64> = sample
+        .data
+        .iter()
+        .map(|s| (s.clamp(-1.0, 1.0) * max).round() as i64)
+        .collect();
+    let total_frames = samples.len() / channels;
+    writer.write_all(b"fLaC")?;
+    writer.write_all(&stream_info(
+        sample,
+        channels,
+        bits_per_sample,
+        total_frames,
+    ))?;
+    for (number, block) in samples.chunks(BLOCK_SIZE * channels).enumerate() {
+        writer.write_all(&frame(block, number as u64, channels, bits_per_sample))?;
+    }
+    writer.flush()
+}
+
+/// Builds the STREAMINFO metadata block, which is the only metadata block that is written. The frame sizes and the
+/// MD5 signature are left as unknown, which the format allows.
+fn stream_info(sample: &Sample, channels: usize, bits_per_sample: u32, frames: usize) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    // Marks this as the last metadata block, of type 0 (STREAMINFO) and 34 bytes long.
+    bits.write(1, 1);
+    bits.write(0, 7);
+    bits.write(34, 24);
+    bits.write(BLOCK_SIZE as u64, 16);
+    bits.write(BLOCK_SIZE as u64, 16);
+    bits.write(0, 24);
+    bits.write(0, 24);
+    bits.write(sample.sample_rate as u64, 20);
+    bits.write(channels as u64 - 1, 3);
+    bits.write(bits_per_sample as u64 - 1, 5);
+    bits.write(frames as u64, 36);
+    bits.write(0, 64);
+    bits.write(0, 64);
+    bits.into_bytes()
+}
+
+/// Encodes a block of interleaved samples as a frame, with every channel coded independently.
+fn frame(block: &[i64], number: u64, channels: usize, bits_per_sample: u32) -> Vec<u8> {
+    let frames = block.len() / channels;
+    let mut bits = BitWriter::default();
+    // The sync code, followed by a fixed block size.
+    bits.write(0b1111_1111_1111_1000, 16);
+    // The block size is stored as a 16-bit value at the end of the header, and the sample rate is in STREAMINFO.
+    bits.write(0b0111, 4);
+    bits.write(0b0000, 4);
+    bits.write(channels as u64 - 1, 4);
+    bits.write(sample_size_code(bits_per_sample), 3);
+    bits.write(0, 1);
+    bits.write_utf8(number);
+    bits.write(frames as u64 - 1, 16);
+    let crc = crc8(&bits.bytes);
+    bits.write(crc as u64, 8);
+    for channel in 0..channels {
+        let samples: Vec<i64> = block
+            .iter()
+            .skip(channel)
+            .step_by(channels)
+            .copied()
+            .collect();
+        subframe(&mut bits, &samples, bits_per_sample);
+    }
+    bits.align();
+    let crc = crc16(&bits.bytes);
+    bits.write(crc as u64, 16);
+    bits.into_bytes()
+}
+
+/// Gets the code that the header of a frame uses for a sample size, or 0 to take it from STREAMINFO.
+fn sample_size_code(bits_per_sample: u32) -> u64 {
+    match bits_per_sample {
+        8 => 0b001,
+        12 => 0b010,
+        16 => 0b100,
+        20 => 0b101,
+        24 => 0b110,
+        _ => 0b000,
+    }
+}
+
+/// Encodes the samples of a channel as a subframe, picking whichever of the fixed predictors codes them in the fewest
+/// bits, or storing them verbatim if none of the predictors help.
+fn subframe(bits: &mut BitWriter, samples: &[i64], bits_per_sample: u32) {
+    if samples.iter().all(|s| *s == samples[0]) {
+        bits.subframe_header(0b000000);
+        bits.write_signed(samples[0], bits_per_sample);
+        return;
+    }
+    let verbatim = samples.len() as u64 * bits_per_sample as u64;
+    let best = (0..=4.min(samples.len() - 1))
+        .map(|order| {
+            let residual = fixed_residual(samples, order);
+            let (parameter, size) = rice_parameter(&residual);
+            (
+                order,
+                residual,
+                parameter,
+                size + order as u64 * bits_per_sample as u64,
+            )
+        })
+        .min_by_key(|(.., size)| *size);
+    match best {
+        Some((order, residual, parameter, size)) if size < verbatim => {
+            bits.subframe_header(0b001000 | order as u64);
+            for warmup in &samples[..order] {
+                bits.write_signed(*warmup, bits_per_sample);
+            }
+            // Rice coding with 4-bit parameters, in a single partition.
+            bits.write(0b00, 2);
+            bits.write(0, 4);
+            bits.write(parameter as u64, 4);
+            for r in residual {
+                let folded = ((r << 1) ^ (r >> 63)) as u64;
+                bits.write_unary(folded >> parameter);
+                bits.write(folded & ((1 << parameter) - 1), parameter);
+            }
+        }
+        _ => {
+            bits.subframe_header(0b000001);
+            for sample in samples {
+                bits.write_signed(*sample, bits_per_sample);
+            }
+        }
+    }
+}
+
+/// Gets what is left of the samples after they are predicted by the fixed predictor of `order`.
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |back: usize| samples[i - back];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Picks the Rice parameter that codes the residual in the fewest bits, returning it along with the number of bits.
+fn rice_parameter(residual: &[i64]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|parameter| {
+            let size: u64 = residual
+                .iter()
+                .map(|r| (((r << 1) ^ (r >> 63)) as u64 >> parameter) + 1 + parameter as u64)
+                .sum();
+            (parameter, size)
+        })
+        .min_by_key(|(_, size)| *size)
+        .unwrap_or((0, 0))
+}
+
+/// Writes values most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    used: u32,
+}
+
+impl BitWriter {
+    /// Writes the lowest `count` bits of `value`.
+    fn write(&mut self, value: u64, count: u32) {
+        for bit in (0..count).rev() {
+            self.current = (self.current << 1) | ((value >> bit) & 1) as u8;
+            self.used += 1;
+            if self.used == 8 {
+                self.bytes.push(self.current);
+                self.current = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    /// Writes a signed value in two's complement with `count` bits.
+    fn write_signed(&mut self, value: i64, count: u32) {
+        self.write(value as u64 & ((1 << count) - 1), count);
+    }
+
+    /// Writes the header of a subframe of type `kind`, with no wasted bits.
+    fn subframe_header(&mut self, kind: u64) {
+        self.write(0, 1);
+        self.write(kind, 6);
+        self.write(0, 1);
+    }
+
+    /// Writes `value` zeros followed by a one.
+    fn write_unary(&mut self, value: u64) {
+        for _ in 0..value {
+            self.write(0, 1);
+        }
+        self.write(1, 1);
+    }
+
+    /// Writes a number in the extended UTF-8 coding that FLAC uses for frame numbers.
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+        let continuation_bytes = (1..=6).find(|n| value < 1 << (6 - n + 6 * n)).unwrap_or(6);
+        let first_bits = 6 - continuation_bytes;
+        let prefix = (0xFF_u64 << (7 - continuation_bytes)) & 0xFF;
+        self.write(prefix | (value >> (6 * continuation_bytes)), 8);
+        debug_assert!(value >> (6 * continuation_bytes) < 1 << first_bits);
+        for byte in (0..continuation_bytes).rev() {
+            self.write(0x80 | ((value >> (6 * byte)) & 0x3F), 8);
+        }
+    }
+
+    /// Pads the last byte with zeros.
+    fn align(&mut self) {
+        if self.used > 0 {
+            self.write(0, 8 - self.used);
+        }
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+/// The CRC-8 that protects the header of a frame, with the polynomial x^8 + x^2 + x + 1.
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// The CRC-16 that protects a whole frame, with the polynomial x^16 + x^15 + x^2 + 1.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
@@ -0,0 +1,39 @@
+//! A harmonizer that adds voices at diatonic intervals to the notes being played.
+
+use crate::{note::Key, player::Voice};
+use alloc::vec::Vec;
+
+/// Adds voices at diatonic intervals (such as thirds and fifths in the current key) to every note that passes through
+/// it. It sits between your input and `Player`, so pass voices through `process` before adding or removing them.
+#[derive(Clone, Debug)]
+pub struct Harmonizer {
+    pub key: Key,
+    /// The intervals to add, in degrees of the scale, such as 2 for a third above or 4 for a fifth above.
+    pub intervals: Vec<i32>,
+}
+
+impl Harmonizer {
+    /// Creates a new harmonizer.
+    pub fn new(key: Key, intervals: Vec<i32>) -> Self {
+        Self { key, intervals }
+    }
+
+    /// Outputs every voice along with its harmonies. Harmonies that fall outside of the range of notes are dropped.
+    /// Since the harmonies of a voice are always the same, pass the same voices through this method again when
+    /// removing them from `Player` to remove their harmonies as well.
+    pub fn process(&self, voices: Vec<Voice>) -> Vec<Voice> {
+        let mut result = Vec::with_capacity(voices.len() * (self.intervals.len() + 1));
+        for voice in voices {
+            for interval in &self.intervals {
+                let note_id = self
+                    .key
+                    .transpose_diatonic(voice.get_note_id() as i32, *interval);
+                if let Ok(note_id) = u8::try_from(note_id) {
+                    result.push(voice.with_note_id(note_id));
+                }
+            }
+            result.push(voice);
+        }
+        result
+    }
+}
@@ -0,0 +1,109 @@
+//! Importing drum patterns from Hydrogen (http://hydrogen-music.org), so that existing beat libraries can be reused
+//! with `PercussionSequencer`.
+
+use crate::{
+    errors::AudioError,
+    instruments::Instrument,
+    sequencer::{InstrumentObj, PercussionSequencerBuilder, PercussiveState, TrackId},
+};
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+
+/// Number of ticks in a quarter note in Hydrogen.
+const TICKS_PER_QUARTER: usize = 48;
+
+/// A table mapping the instrument names of a drum kit onto soundtoys instruments.
+#[derive(Clone, Default)]
+pub struct DrumMap {
+    instruments: HashMap<String, InstrumentObj>,
+}
+
+impl DrumMap {
+    /// Creates an empty drum map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps an instrument name of a drum kit (such as "Kick") onto an instrument.
+    pub fn add<I>(&mut self, name: &str, instrument: I)
+    where
+        I: Instrument + 'static,
+    {
+        self.instruments
+            .insert(name.to_owned(), InstrumentObj::new(instrument));
+    }
+}
+
+impl<const BEATS: usize> PercussionSequencerBuilder<BEATS> {
+    /// Adds the tracks of a pattern from a Hydrogen song (`.h2song`) or pattern (`.h2pattern`) file. The first pattern
+    /// in the file is imported, unless `pattern_name` is given. The instruments of the pattern are looked up in `map`
+    /// by name, or by their id if the file does not list their names, and instruments that are missing from `map`
+    /// are skipped. The pattern is spread out over the `BEATS` steps of the sequencer, and notes that fall between
+    /// steps are moved to the step before them. Every instrument of the kit is added as a track on a channel of its own,
+    /// even when several of them are mapped onto the same instrument type, so that a kick and a tom played by two
+    /// `Drumkick`s do not cut each other off. Returns the ids of the added tracks by instrument name, such as to look
+    /// up their channels with `get_track_channel`.
+    pub fn import_hydrogen(
+        &mut self,
+        xml: &str,
+        pattern_name: Option<&str>,
+        map: &DrumMap,
+    ) -> Result<HashMap<String, TrackId>, AudioError> {
+        let document = Document::parse(xml)?;
+        let names: HashMap<&str, &str> = document
+            .descendants()
+            .filter(|n| n.has_tag_name("instrument"))
+            .filter_map(|n| Some((child_text(n, "id")?, child_text(n, "name")?)))
+            .collect();
+        let pattern = document
+            .descendants()
+            .filter(|n| n.has_tag_name("pattern"))
+            .find(|n| {
+                let name = child_text(*n, "name").or_else(|| child_text(*n, "pattern_name"));
+                pattern_name.is_none() || name == pattern_name
+            })
+            .ok_or_else(|| AudioError::InvalidPattern("pattern not found".to_owned()))?;
+        let size = match child_text(pattern, "size") {
+            Some(size) => size
+                .parse::<usize>()
+                .map_err(|_| AudioError::InvalidPattern(format!("invalid size {size}")))?,
+            None => TICKS_PER_QUARTER * 4,
+        };
+        let mut tracks: HashMap<&str, [PercussiveState; BEATS]> = HashMap::new();
+        for note in pattern.descendants().filter(|n| n.has_tag_name("note")) {
+            let (Some(position), Some(id)) =
+                (child_text(note, "position"), child_text(note, "instrument"))
+            else {
+                continue;
+            };
+            let position = position
+                .parse::<usize>()
+                .map_err(|_| AudioError::InvalidPattern(format!("invalid position {position}")))?;
+            let step = position.checked_mul(BEATS).ok_or_else(|| {
+                AudioError::InvalidPattern(format!("invalid position {position}"))
+            })? / size.max(1);
+            if step >= BEATS {
+                continue;
+            }
+            let name = names.get(id).copied().unwrap_or(id);
+            tracks.entry(name).or_insert([PercussiveState::Rest; BEATS])[step] =
+                PercussiveState::Beat;
+        }
+        let mut ids = HashMap::new();
+        for (name, notes) in tracks {
+            if let Some(instrument) = map.instruments.get(name) {
+                let id = self.add_track_obj(instrument.clone(), notes);
+                ids.insert(name.to_owned(), id);
+            }
+        }
+        Ok(ids)
+    }
+}
+
+/// Gets the text of the first child element with the given tag name.
+fn child_text<'a>(node: Node<'a, '_>, tag_name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name(tag_name))
+        .and_then(|n| n.text())
+        .map(str::trim)
+}
@@ -2,11 +2,31 @@
 //! to guide you on implementing your own virtual instrument. Do not be afraid to do your own
 //! experimentation, this is a result of trial and error.
 
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
 use crate::{
-    note::scale,
-    oscillators::{osc, EnvelopeADSR, LowFrequencyOscillator, Oscillator},
+    dsp::{BandPassFilter, LowPassFilter},
+    note::{scale, tune, w},
+    oscillators::{
+        additive, fm, osc, osc_with_modulations, pitch_sweep, saw_harmonics, BendPhase, Envelope,
+        EnvelopeADSR, EnvelopeCurve, LowFrequencyOscillator, Modulation, ModulationTarget,
+        NoiseGen, Oscillator,
+    },
+    player::ChannelId,
 };
+use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
 use dyn_clone::DynClone;
+use rand::{rngs::SmallRng, Rng};
+
+/// The sample rate (in Hz) that instruments assume until `Instrument::prepare` is called, including after they are
+/// deserialized.
+const DEFAULT_SAMPLE_RATE: f64 = 44100.0;
+
+/// Gets `DEFAULT_SAMPLE_RATE`, for the sample rates of instruments that are skipped when serializing.
+#[cfg(feature = "serde")]
+fn default_sample_rate() -> f64 {
+    DEFAULT_SAMPLE_RATE
+}
 
 pub trait Instrument: Send + DynClone {
     fn sound(
@@ -18,169 +38,319 @@ pub trait Instrument: Send + DynClone {
         note_finished: &mut bool,
     ) -> f64;
 
+    /// Like `sound`, but also accounts for how hard the note was played, where `velocity` typically ranges from 0.0
+    /// to 1.0. By default, the output of `sound` is scaled by `velocity`.
+    fn sound_with_velocity(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> f64 {
+        self.sound(time, time_on, time_off, note_id, note_finished) * velocity
+    }
+
+    /// Like `sound_with_velocity`, but outputs the left and right channels separately for instruments with a stereo
+    /// image. By default, both channels are the output of `sound_with_velocity`.
+    fn sound_stereo(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> (f64, f64) {
+        let sound =
+            self.sound_with_velocity(time, time_on, time_off, note_id, velocity, note_finished);
+        (sound, sound)
+    }
+
     fn get_name() -> &'static str
     where
         Self: Sized;
-}
 
-dyn_clone::clone_trait_object!(Instrument);
+    /// Randomly varies the parameters of the instrument, which is called every time `Player` plays a new note so that
+    /// repeated notes sound more natural. Does nothing by default.
+    fn vary(&mut self, _rng: &mut SmallRng) {}
 
-/// This bell is akin to a glockenspiel. Since a bell is a crisp and clean instrument, we do not want to add
-/// any noise. Since a bell has almost no sustain, we need to adjust the envelope to suit that as well. This
-/// involves shortening the attack time, and increasing the decay time for the trailing off, and setting the
-/// sustain amplitude to 0. We also double the input frequency to make the bell sound higher pitched.
-#[derive(Clone)]
-pub struct Bell {
-    pub env: EnvelopeADSR,
-    pub volume: f64,
-}
+    /// An approximate cost of playing a single note, relative to the cost of a single sine oscillator.
+    fn cost(&self) -> f64 {
+        1.0
+    }
 
-impl Bell {
-    pub fn new() -> Self {
-        let mut env = EnvelopeADSR::default();
-        env.attack_time = 0.01;
-        env.decay_time = 1.0;
-        env.sustain_amplitude = 0.0;
-        env.release_time = 1.0;
-        let volume = 1.0;
-        Self { env, volume }
+    /// Asks the instrument to trade sound quality for speed (such as by lowering the resolution of its oscillators)
+    /// when `reduced` is `true`, and to go back to full quality when it is `false`. Does nothing by default.
+    fn set_reduced_quality(&mut self, _reduced: bool) {}
+
+    /// Bends the pitch of the instrument by a number of semitones, such as from the pitch wheel of a controller.
+    /// `Player::set_pitch_bend` calls this on notes that are already playing, so instruments should keep the phase of
+    /// their oscillators continuous when the bend changes, such as with `BendPhase`. Instruments without a pitch ignore
+    /// this, which is the default.
+    fn set_pitch_bend(&mut self, _semitones: f64) {}
+
+    /// Detunes the instrument by a number of cents on top of its own tuning, such as to thicken a sound by layering
+    /// slightly detuned voices. Does nothing by default.
+    fn set_fine_tune(&mut self, _cents: f64) {}
+
+    /// Gets the channel of `Player` that `Voice::new` plays the notes of the instrument on, or `None` for the channel
+    /// of its type (see `ChannelId::of`), which is the default. Instruments whose type says little about how they
+    /// sound, such as `LayeredInstrument`, give every instance a channel of its own instead.
+    fn get_channel(&self) -> Option<ChannelId> {
+        None
     }
-}
 
-impl Instrument for Bell {
-    fn sound(
-        &self,
+    /// Called once with the sample rate that a note is about to be rendered at, before the first call to `process`.
+    /// Does nothing by default.
+    fn prepare(&mut self, _sample_rate: f64) {}
+
+    /// Renders the next frame of a note, which is what `Player` calls to play the note. Every note that is played owns
+    /// its own copy of the instrument, and `process` is called once for every frame in order, so instruments with
+    /// evolving DSP state (such as filters, delay lines, and physical models) can keep that state in `self` and advance
+    /// it by one sample (see `prepare`) on every call. By default, this is `sound_stereo`, which keeps no state.
+    fn process(
+        &mut self,
         time: f64,
         time_on: f64,
         time_off: f64,
         note_id: u8,
+        velocity: f64,
         note_finished: &mut bool,
-    ) -> f64 {
-        let amplitude = self.env.amplitude(time, time_on, time_off);
-        if amplitude <= 0.0 {
-            *note_finished = true;
-        }
-        let sound =
-            1.00 * osc(
-                time - time_on,
-                scale(note_id as i32 + 12),
-                Oscillator::Sine,
-                Some(LowFrequencyOscillator {
-                    hertz: 5.0,
-                    amplitude: 0.0,
-                }),
-            ) + 0.50
-                * osc(
-                    time - time_on,
-                    scale(note_id as i32 + 24),
-                    Oscillator::Sine,
-                    None,
-                )
-                + 0.25
-                    * osc(
-                        time - time_on,
-                        scale(note_id as i32 + 36),
-                        Oscillator::Sine,
-                        None,
-                    );
-        amplitude * sound * self.volume
+    ) -> (f64, f64) {
+        self.sound_stereo(time, time_on, time_off, note_id, velocity, note_finished)
     }
 
-    fn get_name() -> &'static str
-    where
-        Self: Sized,
-    {
-        "Bell"
+    /// Called when a note is played again while it is still sounding, just before it restarts at `time`, given when the
+    /// note was activated (`time_on`) and deactivated (`time_off`) before the restart. Instruments with an envelope
+    /// pass this on to `Envelope::retrigger` so that the new attack starts from the level that the note is at instead
+    /// of from silence. Does nothing by default, so the note starts over as if it were played for the first time.
+    fn retrigger(&mut self, _time: f64, _time_on: f64, _time_off: f64, _note_id: u8) {}
+
+    /// Advances a note by one frame without it being heard, which is what `Player` calls instead of `process` for
+    /// notes that are virtual (see `Player::set_voice_budget`), so that instruments that keep state stay in step and
+    /// notes still finish. Instruments that keep no state can override this with something cheaper, such as only
+    /// checking whether the envelope has finished. By default, this is `process` with the output thrown away.
+    fn advance(
+        &mut self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) {
+        self.process(time, time_on, time_off, note_id, velocity, note_finished);
     }
 }
 
-/// 8-bit bell.
-#[derive(Clone)]
-pub struct Bell8 {
-    pub env: EnvelopeADSR,
+dyn_clone::clone_trait_object!(Instrument);
+
+/// The center frequencies (in Hz) of the octave bands that `NoiseLayer` uses by default.
+pub const OCTAVE_BANDS: [f64; 9] = [
+    62.5, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+/// How much the parameters of an instrument vary every time a note is played. Each parameter varies by a random amount
+/// of up to the given value in either direction.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Variation {
+    /// Fraction of the decay time of the envelope, such as 0.05 for ±5%.
+    pub decay: f64,
+    /// Cents of pitch, such as 10.0 for ±10 cents.
+    pub pitch: f64,
+    /// Fraction of the volume, such as 0.1 for ±10%.
     pub volume: f64,
 }
 
-impl Bell8 {
-    pub fn new() -> Self {
-        let mut env = EnvelopeADSR::default();
-        env.attack_time = 0.01;
-        env.decay_time = 0.5;
-        env.sustain_amplitude = 0.8;
-        env.release_time = 1.0;
-        let volume = 1.0;
-        Self { env, volume }
+impl Variation {
+    /// Picks random amounts to scale the decay time by, to add to the tuning (in cents), and to scale the volume by.
+    pub fn sample(&self, rng: &mut SmallRng) -> (f64, f64, f64) {
+        let decay = 1.0 + rng.gen_range(-self.decay.abs()..=self.decay.abs());
+        let pitch = rng.gen_range(-self.pitch.abs()..=self.pitch.abs());
+        let volume = 1.0 + rng.gen_range(-self.volume.abs()..=self.volume.abs());
+        (decay, pitch, volume)
     }
-}
 
-impl Instrument for Bell8 {
-    fn sound(
+    /// Varies the settings of an instrument in the way that most instruments do in `Instrument::vary`, by scaling the
+    /// decay of `env`, adding to the tuning of `tuning` (in cents), and scaling `volume` by random amounts picked with
+    /// `sample`.
+    /// Returns the amount that the decay was scaled by, for instruments with more envelopes to scale.
+    pub fn apply<E: Envelope>(
         &self,
-        time: f64,
-        time_on: f64,
-        time_off: f64,
-        note_id: u8,
-        note_finished: &mut bool,
+        rng: &mut SmallRng,
+        env: &mut E,
+        tuning: &mut Tuning,
+        volume: &mut f64,
     ) -> f64 {
-        let amplitude = self.env.amplitude(time, time_on, time_off);
-        if amplitude <= 0.0 {
-            *note_finished = true;
-        }
-        let sound =
-            1.00 * osc(
-                time - time_on,
-                scale(note_id as i32),
-                Oscillator::Square,
-                Some(LowFrequencyOscillator {
-                    hertz: 5.0,
-                    amplitude: 0.0,
-                }),
-            ) + 0.50
-                * osc(
-                    time - time_on,
-                    scale(note_id as i32 + 12),
-                    Oscillator::Sine,
-                    None,
-                )
-                + 0.25
-                    * osc(
-                        time - time_on,
-                        scale(note_id as i32 + 24),
-                        Oscillator::Sine,
-                        None,
-                    );
-        amplitude * sound * self.volume
+        let (decay, pitch, gain) = self.sample(rng);
+        env.scale_decay(decay);
+        tuning.tuning += pitch;
+        *volume *= gain;
+        decay
     }
+}
 
-    fn get_name() -> &'static str
-    where
-        Self: Sized,
-    {
-        "8-Bit Bell"
+/// The pitch of an instrument, which is shared by every instrument with a pitch: its own tuning, along with the pitch
+/// bend and fine tuning that `Player` and `Voice` set on top of it (see `Instrument::set_pitch_bend` and
+/// `Instrument::set_fine_tune`). It also keeps the phase of the oscillators of the instrument continuous while the
+/// pitch bend changes, with a `BendPhase`.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tuning {
+    /// The tuning of the instrument (in cents), which `Variation` varies for every note.
+    pub tuning: f64,
+    pub pitch_bend_semitones: f64,
+    pub fine_tune_cents: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    bend: BendPhase,
+}
+
+impl Tuning {
+    /// Gets the tuning of the instrument (in cents), including the pitch bend and fine tuning, which is what the
+    /// frequencies of its oscillators are tuned by (see `tune`).
+    pub fn cents(&self) -> f64 {
+        self.tuning + 100.0 * self.pitch_bend_semitones + self.fine_tune_cents
+    }
+
+    /// Gets the time to run the oscillators of a note played at `time_on` at, so that their phase stays continuous
+    /// when the pitch bend changes (see `BendPhase::elapsed`).
+    pub fn phase_time(&self, time: f64, time_on: f64) -> f64 {
+        self.bend.elapsed(self.pitch_bend_semitones, time, time_on)
+    }
+
+    /// Bends the pitch by a number of semitones, which is how instruments implement `Instrument::set_pitch_bend`.
+    pub fn set_pitch_bend(&mut self, semitones: f64) {
+        self.pitch_bend_semitones = semitones;
+    }
+
+    /// Detunes the pitch by a number of cents, which is how instruments implement `Instrument::set_fine_tune`.
+    pub fn set_fine_tune(&mut self, cents: f64) {
+        self.fine_tune_cents = cents;
+    }
+}
+
+define_instrument! {
+    /// This bell is akin to a glockenspiel. Since a bell is a crisp and clean instrument, we do not want to add
+    /// any noise. Since a bell has almost no sustain, we need to adjust the envelope to suit that as well. This
+    /// involves shortening the attack time, and increasing the decay time for the trailing off, and setting the
+    /// sustain amplitude to 0. We also double the input frequency to make the bell sound higher pitched.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Bell: "Bell" {
+        envelope: EnvelopeADSR::builder()
+            .attack(0.01)
+            .decay(1.0)
+            .sustain(0.0)
+            .release(1.0)
+            .build(),
+        volume: 1.0,
+        oscillators: [
+            1.00 => (Oscillator::Sine, 12),
+            0.50 => (Oscillator::Sine, 24),
+            0.25 => (Oscillator::Sine, 36),
+        ],
+    }
+}
+
+define_instrument! {
+    /// 8-bit bell.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Bell8: "8-Bit Bell" {
+        envelope: EnvelopeADSR::builder()
+            .attack(0.01)
+            .decay(0.5)
+            .sustain(0.8)
+            .release(1.0)
+            .build(),
+        volume: 1.0,
+        oscillators: [
+            1.00 => (Oscillator::Square, 0),
+            0.50 => (Oscillator::Sine, 12),
+            0.25 => (Oscillator::Sine, 24),
+        ],
     }
 }
 
 /// Since a harmonica is a reed instrument, you want to use a square wave. Since it sounds pretty breathy,
 /// we also add some noise to it.
 #[derive(Clone)]
-pub struct Harmonica {
-    pub env: EnvelopeADSR,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Harmonica<E = EnvelopeADSR> {
+    pub env: E,
     pub volume: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub noise: NoiseGen,
+    /// The resolution of the saw wave (see `Oscillator::SawAna`), which is picked from the pitch of every note and the
+    /// sample rate if `None`.
+    pub saw_resolution: Option<usize>,
+    pub modulations: Vec<Modulation>,
+    pub tuning: Tuning,
+    pub variation: Variation,
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_sample_rate"))]
+    sample_rate: f64,
+    /// Whether `Instrument::set_reduced_quality` asked for reduced quality, which caps the resolution of the saw wave
+    /// at `REDUCED_SAW_RESOLUTION` without touching `saw_resolution`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    reduced: bool,
 }
 
+/// The highest resolution of the saw wave of `Harmonica` while it is asked to reduce its quality.
+const REDUCED_SAW_RESOLUTION: usize = 10;
+
+/// The pitch (in Hz) of the note that `Harmonica::cost` estimates the cost of, since the resolution of the saw wave
+/// depends on the pitch of every note.
+const HARMONICA_COST_PITCH: f64 = 440.0;
+
 impl Harmonica {
     pub fn new() -> Self {
-        let mut env = EnvelopeADSR::default();
-        env.attack_time = 0.0;
-        env.decay_time = 1.0;
-        env.sustain_amplitude = 0.95;
-        env.release_time = 0.1;
+        let env = EnvelopeADSR::builder()
+            .attack(0.0)
+            .decay(1.0)
+            .sustain(0.95)
+            .release(0.1)
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl<E: Envelope> Harmonica<E> {
+    /// Creates a new harmonica with a custom envelope.
+    pub fn with_envelope(env: E) -> Self {
         let volume = 0.3;
-        Self { env, volume }
+        let noise = NoiseGen::new();
+        Self {
+            env,
+            volume,
+            noise,
+            saw_resolution: None,
+            modulations: Vec::new(),
+            tuning: Tuning::default(),
+            variation: Variation::default(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            reduced: false,
+        }
+    }
+
+    /// Gets the resolution of the saw wave for a saw at `saw_hertz`.
+    fn effective_saw_resolution(&self, saw_hertz: f64) -> usize {
+        let resolution = self
+            .saw_resolution
+            .unwrap_or_else(|| saw_harmonics(saw_hertz, self.sample_rate) + 1);
+        if self.reduced {
+            resolution.min(REDUCED_SAW_RESOLUTION)
+        } else {
+            resolution
+        }
     }
 }
 
-impl Instrument for Harmonica {
+impl<E: Envelope + 'static> Instrument for Harmonica<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
     fn sound(
         &self,
         time: f64,
@@ -189,46 +359,64 @@ impl Instrument for Harmonica {
         note_id: u8,
         note_finished: &mut bool,
     ) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
         let amplitude = self.env.amplitude(time, time_on, time_off);
         if amplitude <= 0.0 {
             *note_finished = true;
         }
+        let saw_hertz = tune(scale(note_id as i32 - 12), tuning);
+        let saw_resolution = self.effective_saw_resolution(saw_hertz);
         let sound =
-            1.00 * osc(
-                time_on - time,
-                scale(note_id as i32 - 12),
-                Oscillator::SawAna(None),
-                Some(LowFrequencyOscillator {
-                    hertz: 5.0,
-                    amplitude: 0.0,
-                }),
+            1.00 * osc_with_modulations(
+                -phase_time,
+                saw_hertz,
+                Oscillator::SawAna(Some(saw_resolution)),
+                &self.modulations,
             ) + 1.00
-                * osc(
-                    time - time_on,
-                    scale(note_id as i32),
+                * osc_with_modulations(
+                    phase_time,
+                    tune(scale(note_id as i32), tuning),
                     Oscillator::Square,
-                    Some(LowFrequencyOscillator {
-                        hertz: 5.0,
-                        amplitude: 0.0,
-                    }),
+                    &self.modulations,
                 )
                 + 0.50
-                    * osc(
-                        time - time_on,
-                        scale(note_id as i32 + 12),
+                    * osc_with_modulations(
+                        phase_time,
+                        tune(scale(note_id as i32 + 12), tuning),
                         Oscillator::Square,
-                        None,
+                        &self.modulations,
                     )
-                + 0.05
-                    * osc(
-                        time - time_on,
-                        scale(note_id as i32 + 24),
-                        Oscillator::Noise,
-                        None,
-                    );
+                + 0.05 * self.noise.sample_for_note(note_id, time_on);
         amplitude * sound * self.volume
     }
 
+    fn prepare(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        self.variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        // The saw is played an octave below the note.
+        self.effective_saw_resolution(HARMONICA_COST_PITCH / 2.0) as f64 + 3.0
+    }
+
+    fn set_reduced_quality(&mut self, reduced: bool) {
+        self.reduced = reduced;
+    }
+
     fn get_name() -> &'static str
     where
         Self: Sized,
@@ -240,30 +428,51 @@ impl Instrument for Harmonica {
 /// A lifetime is added for percussion instruments to ensure that the note is switched off once the `max_life_time`
 /// expires.
 #[derive(Clone)]
-pub struct Drumkick {
-    pub env: EnvelopeADSR,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Drumkick<E = EnvelopeADSR> {
+    pub env: E,
     pub volume: f64,
     pub max_life_time: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub noise: NoiseGen,
+    pub tuning: Tuning,
+    pub variation: Variation,
 }
 
 impl Drumkick {
     pub fn new() -> Self {
-        let mut env = EnvelopeADSR::default();
-        env.attack_time = 0.01;
-        env.decay_time = 0.15;
-        env.sustain_amplitude = 0.0;
-        env.release_time = 0.0;
+        let env = EnvelopeADSR::builder()
+            .attack(0.01)
+            .decay(0.15)
+            .sustain(0.0)
+            .release(0.0)
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl<E: Envelope> Drumkick<E> {
+    /// Creates a new drum kick with a custom envelope.
+    pub fn with_envelope(env: E) -> Self {
         let volume = 1.0;
         let max_life_time = 1.5;
+        let noise = NoiseGen::new();
         Self {
             env,
             volume,
             max_life_time,
+            noise,
+            tuning: Tuning::default(),
+            variation: Variation::default(),
         }
     }
 }
 
-impl Instrument for Drumkick {
+impl<E: Envelope + 'static> Instrument for Drumkick<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
     fn sound(
         &self,
         time: f64,
@@ -272,23 +481,39 @@ impl Instrument for Drumkick {
         note_id: u8,
         note_finished: &mut bool,
     ) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
         let amplitude = self.env.amplitude(time, time_on, time_off);
         if self.max_life_time > 0.0 && time - time_on >= self.max_life_time {
             *note_finished = true;
         }
         let sound =
             0.99 * osc(
-                time - time_on,
-                scale(note_id as i32 - 36),
+                phase_time,
+                tune(scale(note_id as i32 - 36), tuning),
                 Oscillator::Sine,
-                Some(LowFrequencyOscillator {
-                    hertz: 1.0,
-                    amplitude: 1.0,
-                }),
-            ) + 0.01 * osc(time - time_on, 0.0, Oscillator::Noise, None);
+                Some(LowFrequencyOscillator::new(1.0, 1.0, Oscillator::Sine)),
+            ) + 0.01 * self.noise.sample_for_note(note_id, time_on);
         amplitude * sound * self.volume
     }
 
+    fn vary(&mut self, rng: &mut SmallRng) {
+        self.variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        2.0
+    }
+
     fn get_name() -> &'static str
     where
         Self: Sized,
@@ -298,30 +523,51 @@ impl Instrument for Drumkick {
 }
 
 #[derive(Clone)]
-pub struct Drumsnare {
-    pub env: EnvelopeADSR,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Drumsnare<E = EnvelopeADSR> {
+    pub env: E,
     pub volume: f64,
     pub max_life_time: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub noise: NoiseGen,
+    pub tuning: Tuning,
+    pub variation: Variation,
 }
 
 impl Drumsnare {
     pub fn new() -> Self {
-        let mut env = EnvelopeADSR::default();
-        env.attack_time = 0.0;
-        env.decay_time = 0.2;
-        env.sustain_amplitude = 0.0;
-        env.release_time = 0.0;
+        let env = EnvelopeADSR::builder()
+            .attack(0.0)
+            .decay(0.2)
+            .sustain(0.0)
+            .release(0.0)
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl<E: Envelope> Drumsnare<E> {
+    /// Creates a new drum snare with a custom envelope.
+    pub fn with_envelope(env: E) -> Self {
         let volume = 1.0;
         let max_life_time = 1.0;
+        let noise = NoiseGen::new();
         Self {
             env,
             volume,
             max_life_time,
+            noise,
+            tuning: Tuning::default(),
+            variation: Variation::default(),
         }
     }
 }
 
-impl Instrument for Drumsnare {
+impl<E: Envelope + 'static> Instrument for Drumsnare<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
     fn sound(
         &self,
         time: f64,
@@ -330,23 +576,39 @@ impl Instrument for Drumsnare {
         note_id: u8,
         note_finished: &mut bool,
     ) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
         let amplitude = self.env.amplitude(time, time_on, time_off);
         if self.max_life_time > 0.0 && time - time_on >= self.max_life_time {
             *note_finished = true;
         }
         let sound =
             0.5 * osc(
-                time - time_on,
-                scale(note_id as i32 - 24),
+                phase_time,
+                tune(scale(note_id as i32 - 24), tuning),
                 Oscillator::Sine,
-                Some(LowFrequencyOscillator {
-                    hertz: 0.5,
-                    amplitude: 1.0,
-                }),
-            ) + 0.5 * osc(time - time_on, 0.0, Oscillator::Noise, None);
+                Some(LowFrequencyOscillator::new(0.5, 1.0, Oscillator::Sine)),
+            ) + 0.5 * self.noise.sample_for_note(note_id, time_on);
         amplitude * sound * self.volume
     }
 
+    fn vary(&mut self, rng: &mut SmallRng) {
+        self.variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        2.0
+    }
+
     fn get_name() -> &'static str
     where
         Self: Sized,
@@ -356,30 +618,51 @@ impl Instrument for Drumsnare {
 }
 
 #[derive(Clone)]
-pub struct DrumHiHat {
-    pub env: EnvelopeADSR,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DrumHiHat<E = EnvelopeADSR> {
+    pub env: E,
     pub volume: f64,
     pub max_life_time: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub noise: NoiseGen,
+    pub tuning: Tuning,
+    pub variation: Variation,
 }
 
 impl DrumHiHat {
     pub fn new() -> Self {
-        let mut env = EnvelopeADSR::default();
-        env.attack_time = 0.01;
-        env.decay_time = 0.05;
-        env.sustain_amplitude = 0.0;
-        env.release_time = 0.0;
+        let env = EnvelopeADSR::builder()
+            .attack(0.01)
+            .decay(0.05)
+            .sustain(0.0)
+            .release(0.0)
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl<E: Envelope> DrumHiHat<E> {
+    /// Creates a new drum hi-hat with a custom envelope.
+    pub fn with_envelope(env: E) -> Self {
         let volume = 0.5;
         let max_life_time = 1.0;
+        let noise = NoiseGen::new();
         Self {
             env,
             volume,
             max_life_time,
+            noise,
+            tuning: Tuning::default(),
+            variation: Variation::default(),
         }
     }
 }
 
-impl Instrument for DrumHiHat {
+impl<E: Envelope + 'static> Instrument for DrumHiHat<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
     fn sound(
         &self,
         time: f64,
@@ -388,23 +671,39 @@ impl Instrument for DrumHiHat {
         note_id: u8,
         note_finished: &mut bool,
     ) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
         let amplitude = self.env.amplitude(time, time_on, time_off);
         if self.max_life_time > 0.0 && time - time_on >= self.max_life_time {
             *note_finished = true;
         }
         let sound =
             0.1 * osc(
-                time - time_on,
-                scale(note_id as i32 - 12),
+                phase_time,
+                tune(scale(note_id as i32 - 12), tuning),
                 Oscillator::Square,
-                Some(LowFrequencyOscillator {
-                    hertz: 1.5,
-                    amplitude: 1.0,
-                }),
-            ) + 0.9 * osc(time - time_on, 0.0, Oscillator::Noise, None);
+                Some(LowFrequencyOscillator::new(1.5, 1.0, Oscillator::Sine)),
+            ) + 0.9 * self.noise.sample_for_note(note_id, time_on);
         amplitude * sound * self.volume
     }
 
+    fn vary(&mut self, rng: &mut SmallRng) {
+        self.variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        2.0
+    }
+
     fn get_name() -> &'static str
     where
         Self: Sized,
@@ -412,3 +711,1440 @@ impl Instrument for DrumHiHat {
         "Drum HiHat"
     }
 }
+
+/// The archetypal synth voice. Two oscillators, the second of which is detuned from the first to thicken the sound,
+/// are mixed with some noise and passed through a resonant low-pass filter. The filter has its own envelope, which
+/// opens the filter by `filter_env_amount` Hz at its peak, so that notes can start bright and mellow out as they
+/// decay. The cutoff can also be modulated by routing LFOs to `ModulationTarget::Cutoff`. Since the filter keeps its
+/// state between samples, only `process` applies the filter.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SubSynth<E = EnvelopeADSR> {
+    pub env: E,
+    pub filter_env: E,
+    pub volume: f64,
+    pub osc1: Oscillator,
+    pub osc2: Oscillator,
+    /// Detune of the second oscillator (in cents).
+    pub detune: f64,
+    /// Amount of noise mixed in, from 0.0 to 1.0.
+    pub noise_mix: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub noise: NoiseGen,
+    /// Cutoff of the filter (in Hz) when its envelope is closed.
+    pub cutoff: f64,
+    /// Resonance of the filter, from 0.0 to 1.0.
+    pub resonance: f64,
+    pub filter_env_amount: f64,
+    pub modulations: Vec<Modulation>,
+    pub tuning: Tuning,
+    pub variation: Variation,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    filter: LowPassFilter,
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_sample_rate"))]
+    sample_rate: f64,
+}
+
+impl SubSynth {
+    pub fn new() -> Self {
+        let env = EnvelopeADSR::builder()
+            .attack(0.01)
+            .decay(0.3)
+            .sustain(0.7)
+            .release(0.3)
+            .build();
+        let filter_env = EnvelopeADSR::builder()
+            .attack(0.01)
+            .decay(0.4)
+            .sustain(0.2)
+            .release(0.3)
+            .build();
+        Self::with_envelopes(env, filter_env)
+    }
+}
+
+impl Default for SubSynth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Envelope> SubSynth<E> {
+    /// Creates a new subtractive synth with custom amplitude and filter envelopes.
+    pub fn with_envelopes(env: E, filter_env: E) -> Self {
+        let volume = 0.3;
+        let noise = NoiseGen::new();
+        Self {
+            env,
+            filter_env,
+            volume,
+            osc1: Oscillator::SawDig,
+            osc2: Oscillator::SawDig,
+            detune: 7.0,
+            noise_mix: 0.0,
+            noise,
+            cutoff: 800.0,
+            resonance: 0.3,
+            filter_env_amount: 3000.0,
+            modulations: Vec::new(),
+            tuning: Tuning::default(),
+            variation: Variation::default(),
+            filter: LowPassFilter::new(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+        }
+    }
+}
+
+impl<E: Envelope + 'static> Instrument for SubSynth<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+        self.filter_env.retrigger(time, time_on, time_off);
+    }
+
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
+        let amplitude = self.env.amplitude(time, time_on, time_off);
+        if amplitude <= 0.0 {
+            *note_finished = true;
+        }
+        let hertz = tune(scale(note_id as i32), tuning);
+        let oscillators = 0.5
+            * osc_with_modulations(phase_time, hertz, self.osc1.clone(), &self.modulations)
+            + 0.5
+                * osc_with_modulations(
+                    phase_time,
+                    tune(hertz, self.detune),
+                    self.osc2.clone(),
+                    &self.modulations,
+                );
+        let sound = (1.0 - self.noise_mix) * oscillators
+            + self.noise_mix * self.noise.sample_for_note(note_id, time_on);
+        amplitude * sound * self.volume
+    }
+
+    fn prepare(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.filter = LowPassFilter::new();
+    }
+
+    fn process(
+        &mut self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> (f64, f64) {
+        let sound =
+            self.sound_with_velocity(time, time_on, time_off, note_id, velocity, note_finished);
+        let cutoff = self.cutoff
+            + self.filter_env_amount * self.filter_env.amplitude(time, time_on, time_off)
+            + Modulation::sum(&self.modulations, ModulationTarget::Cutoff, time - time_on);
+        let sound = self
+            .filter
+            .process(sound, cutoff, self.resonance, self.sample_rate);
+        (sound, sound)
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        let decay = self
+            .variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+        self.filter_env.scale_decay(decay);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        4.0
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Subtractive Synth"
+    }
+}
+
+/// An electric piano in the style of FM synthesizers. The tone comes from a modulator at the same frequency as the
+/// carrier, which gives the harmonic bark of a struck tine. The modulation index, and with it the brightness of the
+/// tone, fades out as the note rings, and is scaled by how hard the note is played, so that soft notes are mellow
+/// and hard notes bite.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EPiano<E = EnvelopeADSR> {
+    pub env: E,
+    pub volume: f64,
+    /// Modulation index at the start of a note played at full velocity.
+    pub brightness: f64,
+    /// How quickly the brightness fades out (per second).
+    pub brightness_decay: f64,
+    pub modulations: Vec<Modulation>,
+    pub tuning: Tuning,
+    pub variation: Variation,
+}
+
+impl EPiano {
+    pub fn new() -> Self {
+        let env = EnvelopeADSR::builder()
+            .attack(0.002)
+            .decay(3.0)
+            .sustain(0.0)
+            .release(0.3)
+            .decay_curve(EnvelopeCurve::Exponential(3.0))
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl Default for EPiano {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Envelope> EPiano<E> {
+    /// Creates a new electric piano with a custom envelope.
+    pub fn with_envelope(env: E) -> Self {
+        let volume = 0.8;
+        Self {
+            env,
+            volume,
+            brightness: 3.0,
+            brightness_decay: 4.0,
+            modulations: Vec::new(),
+            tuning: Tuning::default(),
+            variation: Variation::default(),
+        }
+    }
+
+    /// Gets the output of the electric piano for a note played at `velocity`, before the velocity is applied to the
+    /// volume.
+    fn tone(&self, time: f64, time_on: f64, note_id: u8, velocity: f64) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
+        let elapsed = time - time_on;
+        let vibrato = Modulation::sum(&self.modulations, ModulationTarget::Pitch, elapsed);
+        let hertz = tune(scale(note_id as i32), tuning);
+        let mod_index = 0.5 + self.brightness * velocity * (-self.brightness_decay * elapsed).exp();
+        let body = fm(phase_time + vibrato / w(hertz), hertz, 1.0, mod_index);
+        // The tine is a short, high ping at the start of the note that also gets louder the harder the note is played.
+        let tine = fm(phase_time, hertz * 14.0, 1.0, 0.5) * velocity * (-20.0 * elapsed).exp();
+        body + 0.15 * tine
+    }
+}
+
+impl<E: Envelope + 'static> Instrument for EPiano<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        self.sound_with_velocity(time, time_on, time_off, note_id, 1.0, note_finished)
+    }
+
+    fn sound_with_velocity(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let amplitude = self.env.amplitude(time, time_on, time_off);
+        if amplitude <= 0.0 {
+            *note_finished = true;
+        }
+        amplitude * self.tone(time, time_on, note_id, velocity) * velocity * self.volume
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        self.variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        4.0
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Electric Piano"
+    }
+}
+
+/// A bass drum in the style of the TR-808. Unlike `Drumkick`, its pitch starts `sweep_ratio` times higher than the
+/// fundamental and quickly drops down to it over roughly `sweep_time` seconds, which gives the classic boom. The
+/// length of the boom is set by the decay of the envelope.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Drum808Kick<E = EnvelopeADSR> {
+    pub env: E,
+    pub volume: f64,
+    pub max_life_time: f64,
+    pub sweep_ratio: f64,
+    pub sweep_time: f64,
+    pub tuning: Tuning,
+    pub variation: Variation,
+}
+
+impl Drum808Kick {
+    pub fn new() -> Self {
+        let env = EnvelopeADSR::builder()
+            .attack(0.001)
+            .decay(0.8)
+            .sustain(0.0)
+            .release(0.0)
+            .decay_curve(EnvelopeCurve::Exponential(4.0))
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl Default for Drum808Kick {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Envelope> Drum808Kick<E> {
+    /// Creates a new 808 kick with a custom envelope.
+    pub fn with_envelope(env: E) -> Self {
+        let volume = 1.0;
+        let max_life_time = 1.5;
+        Self {
+            env,
+            volume,
+            max_life_time,
+            sweep_ratio: 3.0,
+            sweep_time: 0.04,
+            tuning: Tuning::default(),
+            variation: Variation::default(),
+        }
+    }
+}
+
+impl<E: Envelope + 'static> Instrument for Drum808Kick<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
+        let amplitude = self.env.amplitude(time, time_on, time_off);
+        if self.max_life_time > 0.0 && time - time_on >= self.max_life_time {
+            *note_finished = true;
+        }
+        let hertz = tune(scale(note_id as i32 - 36), tuning);
+        let sound = pitch_sweep(phase_time, hertz * self.sweep_ratio, hertz, self.sweep_time);
+        amplitude * sound * self.volume
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        self.variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        1.0
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Drum 808 Kick"
+    }
+}
+
+/// A tom with a pitch that drops slightly after it is hit, like `Drum808Kick`, but higher pitched and with a little
+/// noise for the stick attack. Since toms are tuned, the pitch follows the note that is played.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DrumTom<E = EnvelopeADSR> {
+    pub env: E,
+    pub volume: f64,
+    pub max_life_time: f64,
+    pub sweep_ratio: f64,
+    pub sweep_time: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub noise: NoiseGen,
+    pub tuning: Tuning,
+    pub variation: Variation,
+}
+
+impl DrumTom {
+    pub fn new() -> Self {
+        let env = EnvelopeADSR::builder()
+            .attack(0.001)
+            .decay(0.4)
+            .sustain(0.0)
+            .release(0.0)
+            .decay_curve(EnvelopeCurve::Exponential(3.0))
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl Default for DrumTom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Envelope> DrumTom<E> {
+    /// Creates a new tom with a custom envelope.
+    pub fn with_envelope(env: E) -> Self {
+        let volume = 1.0;
+        let max_life_time = 1.0;
+        let noise = NoiseGen::new();
+        Self {
+            env,
+            volume,
+            max_life_time,
+            sweep_ratio: 1.5,
+            sweep_time: 0.08,
+            noise,
+            tuning: Tuning::default(),
+            variation: Variation::default(),
+        }
+    }
+}
+
+impl<E: Envelope + 'static> Instrument for DrumTom<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
+        let amplitude = self.env.amplitude(time, time_on, time_off);
+        if self.max_life_time > 0.0 && time - time_on >= self.max_life_time {
+            *note_finished = true;
+        }
+        let elapsed = time - time_on;
+        let hertz = tune(scale(note_id as i32 - 24), tuning);
+        let sound = 0.95
+            * pitch_sweep(phase_time, hertz * self.sweep_ratio, hertz, self.sweep_time)
+            + 0.3 * self.noise.sample_for_note(note_id, time_on) * (-60.0 * elapsed).exp();
+        amplitude * sound * self.volume
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        self.variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        2.0
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Drum Tom"
+    }
+}
+
+/// A hand clap, made up of several short bursts of noise a few milliseconds apart (like several hands clapping at
+/// once), with the last burst ringing out for longer.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DrumClap<E = EnvelopeADSR> {
+    pub env: E,
+    pub volume: f64,
+    pub max_life_time: f64,
+    /// Number of bursts of noise, including the last burst that rings out.
+    pub bursts: usize,
+    /// Time between the start of each burst (in seconds).
+    pub burst_spacing: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub noise: NoiseGen,
+    pub variation: Variation,
+}
+
+impl DrumClap {
+    pub fn new() -> Self {
+        let env = EnvelopeADSR::builder()
+            .attack(0.001)
+            .decay(0.25)
+            .sustain(0.0)
+            .release(0.0)
+            .decay_curve(EnvelopeCurve::Exponential(3.0))
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl Default for DrumClap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Envelope> DrumClap<E> {
+    /// Creates a new drum clap with a custom envelope, which shapes the tail of the last burst.
+    pub fn with_envelope(env: E) -> Self {
+        let volume = 0.6;
+        let max_life_time = 1.0;
+        let noise = NoiseGen::new();
+        Self {
+            env,
+            volume,
+            max_life_time,
+            bursts: 4,
+            burst_spacing: 0.01,
+            noise,
+            variation: Variation::default(),
+        }
+    }
+}
+
+impl<E: Envelope + 'static> Instrument for DrumClap<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let elapsed = time - time_on;
+        if self.max_life_time > 0.0 && elapsed >= self.max_life_time {
+            *note_finished = true;
+        }
+        let last_burst = self.bursts.saturating_sub(1) as f64 * self.burst_spacing;
+        let amplitude = if elapsed < last_burst {
+            let since_burst = elapsed % self.burst_spacing;
+            (-since_burst / 0.002).exp()
+        } else {
+            self.env.amplitude(
+                time,
+                time_on + last_burst,
+                time_off.max(time_on + last_burst),
+            )
+        };
+        amplitude * self.noise.sample_for_note(note_id, time_on) * self.volume
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        let (decay, spacing, volume) = self.variation.sample(rng);
+        self.env.scale_decay(decay);
+        // Claps have no pitch, so the variation in pitch loosens or tightens the spacing of the bursts instead.
+        self.burst_spacing *= 2.0_f64.powf(spacing / 1200.0);
+        self.volume *= volume;
+    }
+
+    fn cost(&self) -> f64 {
+        1.0
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Drum Clap"
+    }
+}
+
+/// A crash cymbal. Noise is mixed with a cluster of square waves at inharmonic ratios for the metallic shimmer, and
+/// passed through a high-pass filter so that only the bright part of the sound is kept. Since the filter keeps its
+/// state between samples, only `process` applies the filter.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DrumCrash<E = EnvelopeADSR> {
+    pub env: E,
+    pub volume: f64,
+    pub max_life_time: f64,
+    /// Cutoff of the high-pass filter (in Hz).
+    pub brightness: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub noise: NoiseGen,
+    pub tuning: Tuning,
+    pub variation: Variation,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    filter: LowPassFilter,
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_sample_rate"))]
+    sample_rate: f64,
+}
+
+impl DrumCrash {
+    pub fn new() -> Self {
+        let env = EnvelopeADSR::builder()
+            .attack(0.002)
+            .decay(2.5)
+            .sustain(0.0)
+            .release(0.0)
+            .decay_curve(EnvelopeCurve::Exponential(4.0))
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl Default for DrumCrash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Envelope> DrumCrash<E> {
+    /// Creates a new crash cymbal with a custom envelope.
+    pub fn with_envelope(env: E) -> Self {
+        let volume = 0.5;
+        let max_life_time = 3.0;
+        let noise = NoiseGen::new();
+        Self {
+            env,
+            volume,
+            max_life_time,
+            brightness: 4000.0,
+            noise,
+            tuning: Tuning::default(),
+            variation: Variation::default(),
+            filter: LowPassFilter::new(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+        }
+    }
+}
+
+impl<E: Envelope + 'static> Instrument for DrumCrash<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
+        let amplitude = self.env.amplitude(time, time_on, time_off);
+        if self.max_life_time > 0.0 && time - time_on >= self.max_life_time {
+            *note_finished = true;
+        }
+        let hertz = tune(scale(note_id as i32), tuning);
+        let partials: f64 = [1.0, 1.47, 1.91, 2.53, 3.37, 4.22]
+            .iter()
+            .map(|ratio| osc(phase_time, hertz * ratio, Oscillator::Square, None))
+            .sum();
+        let sound = 0.05 * partials + 0.7 * self.noise.sample_for_note(note_id, time_on);
+        amplitude * sound * self.volume
+    }
+
+    fn prepare(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.filter = LowPassFilter::new();
+    }
+
+    fn process(
+        &mut self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> (f64, f64) {
+        let sound =
+            self.sound_with_velocity(time, time_on, time_off, note_id, velocity, note_finished);
+        // Taking away the low end that the low-pass filter lets through leaves the high end.
+        let sound = sound
+            - self
+                .filter
+                .process(sound, self.brightness, 0.0, self.sample_rate);
+        (sound, sound)
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        self.variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        7.0
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Drum Crash"
+    }
+}
+
+/// A layer of filtered noise for percussion, made up of noise passed through a bank of band-pass filters, each with
+/// its own level. Use `NoiseLayer::matching` to set the bands and the envelope so that the layer matches a recording
+/// of an acoustic drum. Since the filters keep their state between samples, only `process` applies the filters.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NoiseLayer<E = EnvelopeADSR> {
+    pub env: E,
+    pub volume: f64,
+    /// The center frequency (in Hz) and level of every band.
+    pub bands: Vec<(f64, f64)>,
+    /// How narrow every band is, such as 1.4 for bands an octave wide.
+    pub q: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub noise: NoiseGen,
+    pub variation: Variation,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    filters: Vec<BandPassFilter>,
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_sample_rate"))]
+    sample_rate: f64,
+}
+
+impl NoiseLayer {
+    pub fn new() -> Self {
+        let env = EnvelopeADSR::percussive();
+        Self::with_envelope(env)
+    }
+}
+
+impl Default for NoiseLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Envelope> NoiseLayer<E> {
+    /// Creates a new noise layer with a custom envelope, which lets through every octave at the same level.
+    pub fn with_envelope(env: E) -> Self {
+        let volume = 1.0;
+        let noise = NoiseGen::new();
+        Self {
+            env,
+            volume,
+            bands: OCTAVE_BANDS.iter().map(|center| (*center, 1.0)).collect(),
+            q: 1.4,
+            noise,
+            variation: Variation::default(),
+            filters: Vec::new(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+        }
+    }
+}
+
+impl<E: Envelope + 'static> Instrument for NoiseLayer<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let amplitude = self.env.amplitude(time, time_on, time_off);
+        if amplitude <= 0.0 {
+            *note_finished = true;
+        }
+        amplitude * self.noise.sample_for_note(note_id, time_on) * self.volume
+    }
+
+    fn prepare(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.filters = vec![BandPassFilter::new(); self.bands.len()];
+    }
+
+    fn process(
+        &mut self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> (f64, f64) {
+        let sound =
+            self.sound_with_velocity(time, time_on, time_off, note_id, velocity, note_finished);
+        let sound = self
+            .bands
+            .iter()
+            .zip(self.filters.iter_mut())
+            .map(|((center, level), filter)| {
+                level * filter.process(sound, *center, self.q, self.sample_rate)
+            })
+            .sum();
+        (sound, sound)
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        let (decay, _, volume) = self.variation.sample(rng);
+        self.env.scale_decay(decay);
+        self.volume *= volume;
+    }
+
+    fn cost(&self) -> f64 {
+        1.0 + self.bands.len() as f64
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Noise Layer"
+    }
+}
+
+/// A riser (or faller) for transitions. Noise is passed through a resonant low-pass filter, the cutoff of which
+/// sweeps from `start_cutoff` to `end_cutoff` over `sweep_time` seconds, and then stays put until the note is
+/// released. The cutoff moves by the same musical interval every second, so the sweep sounds even all the way
+/// through. Since the filter keeps its state between samples, only `process` applies the filter.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NoiseSweep<E = EnvelopeADSR> {
+    pub env: E,
+    pub volume: f64,
+    /// Cutoff of the filter (in Hz) at the start of the sweep.
+    pub start_cutoff: f64,
+    /// Cutoff of the filter (in Hz) at the end of the sweep.
+    pub end_cutoff: f64,
+    pub sweep_time: f64,
+    /// Resonance of the filter, from 0.0 to 1.0.
+    pub resonance: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub noise: NoiseGen,
+    pub variation: Variation,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    filter: LowPassFilter,
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_sample_rate"))]
+    sample_rate: f64,
+}
+
+impl NoiseSweep {
+    pub fn new() -> Self {
+        let env = EnvelopeADSR::builder()
+            .attack(4.0)
+            .decay(0.0)
+            .sustain(1.0)
+            .release(0.5)
+            .attack_curve(EnvelopeCurve::Logarithmic(2.0))
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl Default for NoiseSweep {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Envelope> NoiseSweep<E> {
+    /// Creates a new noise sweep with a custom envelope.
+    pub fn with_envelope(env: E) -> Self {
+        let volume = 0.5;
+        let noise = NoiseGen::new();
+        Self {
+            env,
+            volume,
+            start_cutoff: 200.0,
+            end_cutoff: 8000.0,
+            sweep_time: 4.0,
+            resonance: 0.5,
+            noise,
+            variation: Variation::default(),
+            filter: LowPassFilter::new(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+        }
+    }
+
+    /// Gets the cutoff of the filter (in Hz) at a given time since the note was played.
+    fn cutoff(&self, elapsed: f64) -> f64 {
+        let progress = (elapsed / self.sweep_time.max(f64::EPSILON)).clamp(0.0, 1.0);
+        self.start_cutoff * (self.end_cutoff / self.start_cutoff).powf(progress)
+    }
+}
+
+impl<E: Envelope + 'static> Instrument for NoiseSweep<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let amplitude = self.env.amplitude(time, time_on, time_off);
+        if amplitude <= 0.0 {
+            *note_finished = true;
+        }
+        amplitude * self.noise.sample_for_note(note_id, time_on) * self.volume
+    }
+
+    fn prepare(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.filter = LowPassFilter::new();
+    }
+
+    fn process(
+        &mut self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> (f64, f64) {
+        let sound =
+            self.sound_with_velocity(time, time_on, time_off, note_id, velocity, note_finished);
+        let cutoff = self.cutoff(time - time_on);
+        let sound = self
+            .filter
+            .process(sound, cutoff, self.resonance, self.sample_rate);
+        (sound, sound)
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        let (decay, _, volume) = self.variation.sample(rng);
+        self.env.scale_decay(decay);
+        self.volume *= volume;
+    }
+
+    fn cost(&self) -> f64 {
+        2.0
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Noise Sweep"
+    }
+}
+
+/// A flute is mostly a pure tone, so we use a sine wave with a touch of its second harmonic, and add breath noise on
+/// top. The attack is slow like a player starting to blow, and a vibrato LFO is routed to the pitch by default.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Flute<E = EnvelopeADSR> {
+    pub env: E,
+    pub volume: f64,
+    /// Amount of breath noise mixed in, from 0.0 to 1.0.
+    pub breath: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub noise: NoiseGen,
+    pub modulations: Vec<Modulation>,
+    pub tuning: Tuning,
+    pub variation: Variation,
+}
+
+impl Flute {
+    pub fn new() -> Self {
+        let env = EnvelopeADSR::builder()
+            .attack(0.15)
+            .decay(0.1)
+            .sustain(0.9)
+            .release(0.15)
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl Default for Flute {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Envelope> Flute<E> {
+    /// Creates a new flute with a custom envelope.
+    pub fn with_envelope(env: E) -> Self {
+        let volume = 0.6;
+        let noise = NoiseGen::new();
+        let vibrato = LowFrequencyOscillator::new(5.0, 0.003, Oscillator::Sine);
+        Self {
+            env,
+            volume,
+            breath: 0.08,
+            noise,
+            modulations: vec![Modulation::new(ModulationTarget::Pitch, vibrato)],
+            tuning: Tuning::default(),
+            variation: Variation::default(),
+        }
+    }
+}
+
+impl<E: Envelope + 'static> Instrument for Flute<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
+        let amplitude = self.env.amplitude(time, time_on, time_off);
+        if amplitude <= 0.0 {
+            *note_finished = true;
+        }
+        let sound =
+            1.00 * osc_with_modulations(
+                phase_time,
+                tune(scale(note_id as i32), tuning),
+                Oscillator::Sine,
+                &self.modulations,
+            ) + 0.10
+                * osc_with_modulations(
+                    phase_time,
+                    tune(scale(note_id as i32 + 12), tuning),
+                    Oscillator::Sine,
+                    &self.modulations,
+                )
+                + self.breath * self.noise.sample_for_note(note_id, time_on);
+        amplitude * sound * self.volume
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        self.variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        2.0
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Flute"
+    }
+}
+
+/// Brass is bright and buzzy, which calls for a saw wave. Instead of a plain saw, we stack its harmonics with a
+/// steeper rolloff, which softens the top end like a filter would, and use a medium attack for the swell of a
+/// player's lips getting going.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Brass<E = EnvelopeADSR> {
+    pub env: E,
+    pub volume: f64,
+    /// Amplitude of every harmonic, starting from the fundamental.
+    pub harmonics: Vec<f64>,
+    pub vibrato: LowFrequencyOscillator,
+    pub tuning: Tuning,
+    pub variation: Variation,
+}
+
+impl Brass {
+    pub fn new() -> Self {
+        let env = EnvelopeADSR::builder()
+            .attack(0.08)
+            .decay(0.2)
+            .sustain(0.8)
+            .release(0.2)
+            .build();
+        Self::with_envelope(env)
+    }
+}
+
+impl Default for Brass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Envelope> Brass<E> {
+    /// Creates a new brass instrument with a custom envelope.
+    pub fn with_envelope(env: E) -> Self {
+        let volume = 0.4;
+        // A saw wave has harmonics that fall off at 1/n, so a rolloff of 1/n^1.5 is a little darker.
+        let harmonics = (1..=16).map(|n| 1.0 / (n as f64).powf(1.5)).collect();
+        Self {
+            env,
+            volume,
+            harmonics,
+            vibrato: LowFrequencyOscillator::default(),
+            tuning: Tuning::default(),
+            variation: Variation::default(),
+        }
+    }
+}
+
+impl<E: Envelope + 'static> Instrument for Brass<E> {
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+        self.env.retrigger(time, time_on, time_off);
+    }
+
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let tuning = self.tuning.cents();
+        let phase_time = self.tuning.phase_time(time, time_on);
+        let amplitude = self.env.amplitude(time, time_on, time_off);
+        if amplitude <= 0.0 {
+            *note_finished = true;
+        }
+        let sound = additive(
+            phase_time,
+            tune(scale(note_id as i32), tuning),
+            &self.harmonics,
+            None,
+            Some(self.vibrato.clone()),
+        );
+        amplitude * sound * self.volume
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        self.variation
+            .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        self.tuning.set_pitch_bend(semitones);
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        self.tuning.set_fine_tune(cents);
+    }
+
+    fn cost(&self) -> f64 {
+        self.harmonics.len() as f64
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Brass"
+    }
+}
+
+/// A percussion instrument of a `GmDrumKit`, along with the note that it is played at.
+#[derive(Clone)]
+pub struct DrumPiece {
+    pub instrument: Box<dyn Instrument>,
+    pub note_id: u8,
+}
+
+impl DrumPiece {
+    /// Creates a new drum piece that plays `instrument` at `note_id`.
+    pub fn new<I>(instrument: I, note_id: u8) -> Self
+    where
+        I: Instrument + 'static,
+    {
+        Self {
+            instrument: Box::new(instrument),
+            note_id,
+        }
+    }
+}
+
+/// A drum kit that picks a percussion instrument for every note according to the General MIDI drum map, such as 36 for
+/// a kick, 38 for a snare, and 42 for a closed hi-hat, so that MIDI drum tracks can be played through a single
+/// instrument. Notes that are not mapped to a drum piece are silent. Pieces can be swapped out through `pieces`.
+#[derive(Clone)]
+pub struct GmDrumKit {
+    pub pieces: BTreeMap<u8, DrumPiece>,
+}
+
+impl GmDrumKit {
+    pub fn new() -> Self {
+        // Drums are tuned to sound right at note 64, which is the note that sequencers play them at.
+        let mut open_hi_hat = DrumHiHat::new();
+        open_hi_hat.env.decay_time = 0.4;
+        let mut pedal_hi_hat = DrumHiHat::new();
+        pedal_hi_hat.env.decay_time = 0.03;
+        pedal_hi_hat.volume *= 0.6;
+        let mut ride = DrumCrash::new();
+        ride.env.decay_time *= 0.5;
+        ride.brightness *= 1.5;
+        ride.volume *= 0.6;
+        let mut splash = DrumCrash::new();
+        splash.env.decay_time *= 0.3;
+        let mut side_stick = DrumTom::new();
+        side_stick.env.decay_time = 0.05;
+        let pieces = [
+            (35, DrumPiece::new(Drum808Kick::new(), 64)),
+            (36, DrumPiece::new(Drumkick::new(), 64)),
+            (37, DrumPiece::new(side_stick, 76)),
+            (38, DrumPiece::new(Drumsnare::new(), 64)),
+            (39, DrumPiece::new(DrumClap::new(), 64)),
+            (40, DrumPiece::new(Drumsnare::new(), 66)),
+            (41, DrumPiece::new(DrumTom::new(), 57)),
+            (42, DrumPiece::new(DrumHiHat::new(), 64)),
+            (43, DrumPiece::new(DrumTom::new(), 59)),
+            (44, DrumPiece::new(pedal_hi_hat, 64)),
+            (45, DrumPiece::new(DrumTom::new(), 61)),
+            (46, DrumPiece::new(open_hi_hat, 64)),
+            (47, DrumPiece::new(DrumTom::new(), 63)),
+            (48, DrumPiece::new(DrumTom::new(), 65)),
+            (49, DrumPiece::new(DrumCrash::new(), 64)),
+            (50, DrumPiece::new(DrumTom::new(), 67)),
+            (51, DrumPiece::new(ride.clone(), 64)),
+            (55, DrumPiece::new(splash, 68)),
+            (57, DrumPiece::new(DrumCrash::new(), 62)),
+            (59, DrumPiece::new(ride, 66)),
+        ];
+        Self {
+            pieces: pieces.into_iter().collect(),
+        }
+    }
+}
+
+impl Default for GmDrumKit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Instrument for GmDrumKit {
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        match self.pieces.get(&note_id) {
+            Some(piece) => {
+                piece
+                    .instrument
+                    .sound(time, time_on, time_off, piece.note_id, note_finished)
+            }
+            None => {
+                *note_finished = true;
+                0.0
+            }
+        }
+    }
+
+    fn sound_with_velocity(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> f64 {
+        match self.pieces.get(&note_id) {
+            Some(piece) => piece.instrument.sound_with_velocity(
+                time,
+                time_on,
+                time_off,
+                piece.note_id,
+                velocity,
+                note_finished,
+            ),
+            None => {
+                *note_finished = true;
+                0.0
+            }
+        }
+    }
+
+    fn sound_stereo(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> (f64, f64) {
+        match self.pieces.get(&note_id) {
+            Some(piece) => piece.instrument.sound_stereo(
+                time,
+                time_on,
+                time_off,
+                piece.note_id,
+                velocity,
+                note_finished,
+            ),
+            None => {
+                *note_finished = true;
+                (0.0, 0.0)
+            }
+        }
+    }
+
+    fn vary(&mut self, rng: &mut SmallRng) {
+        for piece in self.pieces.values_mut() {
+            piece.instrument.vary(rng);
+        }
+    }
+
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, note_id: u8) {
+        if let Some(piece) = self.pieces.get_mut(&note_id) {
+            piece
+                .instrument
+                .retrigger(time, time_on, time_off, piece.note_id);
+        }
+    }
+
+    /// Since the piece that is played is not known ahead of time, this is the cost of the most expensive piece.
+    fn cost(&self) -> f64 {
+        self.pieces
+            .values()
+            .map(|piece| piece.instrument.cost())
+            .fold(0.0, f64::max)
+    }
+
+    fn set_reduced_quality(&mut self, reduced: bool) {
+        for piece in self.pieces.values_mut() {
+            piece.instrument.set_reduced_quality(reduced);
+        }
+    }
+
+    fn set_pitch_bend(&mut self, semitones: f64) {
+        for piece in self.pieces.values_mut() {
+            piece.instrument.set_pitch_bend(semitones);
+        }
+    }
+
+    fn set_fine_tune(&mut self, cents: f64) {
+        for piece in self.pieces.values_mut() {
+            piece.instrument.set_fine_tune(cents);
+        }
+    }
+
+    fn prepare(&mut self, sample_rate: f64) {
+        for piece in self.pieces.values_mut() {
+            piece.instrument.prepare(sample_rate);
+        }
+    }
+
+    fn process(
+        &mut self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> (f64, f64) {
+        match self.pieces.get_mut(&note_id) {
+            Some(piece) => piece.instrument.process(
+                time,
+                time_on,
+                time_off,
+                piece.note_id,
+                velocity,
+                note_finished,
+            ),
+            None => {
+                *note_finished = true;
+                (0.0, 0.0)
+            }
+        }
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "General MIDI Drum Kit"
+    }
+}
@@ -0,0 +1,147 @@
+//! MIDI input through JACK, so that a soundtoys-based app can be played from any JACK MIDI source in a modular setup,
+//! such as a sequencer or a hardware keyboard bridged by `a2jmidid`. Messages are handed to a callback on a thread of
+//! their own rather than played directly, since which instrument a MIDI channel plays is up to the app.
+
+use crate::errors::AudioError;
+use jack::{Client, ClientOptions, ClosureProcessHandler, Control, MidiIn, ProcessScope};
+use rtrb::RingBuffer;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Number of messages that can be waiting for the callback before new messages are dropped.
+const MIDI_QUEUE_CAPACITY: usize = 1024;
+
+/// How often the thread that runs the callback checks for new messages.
+const MIDI_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The process callback of the JACK client, which is boxed so that the client can be named.
+type MidiProcess = Box<dyn FnMut(&Client, &ProcessScope) -> Control + Send>;
+
+/// A MIDI message received from JACK. Channels are numbered from 0 to 15.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum MidiMessage {
+    /// A key was pressed. Note ons with a velocity of 0 are turned into note offs.
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    /// A key was let go.
+    NoteOff { channel: u8, note: u8, velocity: u8 },
+    /// The pitch bend wheel moved, from -1.0 (all the way down) to 1.0 (all the way up).
+    PitchBend { channel: u8, bend: f64 },
+    /// A knob, slider or pedal moved.
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+}
+
+impl MidiMessage {
+    /// Reads a message from its raw bytes, or `None` for messages that are not handled (such as system messages).
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (&status, data) = bytes.split_first()?;
+        let channel = status & 0x0f;
+        match (status & 0xf0, data) {
+            (0x90, &[note, velocity, ..]) if velocity > 0 => Some(Self::NoteOn {
+                channel,
+                note,
+                velocity,
+            }),
+            (0x80 | 0x90, &[note, velocity, ..]) => Some(Self::NoteOff {
+                channel,
+                note,
+                velocity,
+            }),
+            (0xb0, &[controller, value, ..]) => Some(Self::ControlChange {
+                channel,
+                controller,
+                value,
+            }),
+            (0xe0, &[lsb, msb, ..]) => {
+                let value = ((msb as i32) << 7 | lsb as i32) - 8192;
+                Some(Self::PitchBend {
+                    channel,
+                    bend: value as f64 / 8192.0,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A JACK client with a MIDI input port, which hands every message that comes in to a callback. Pair it with
+/// `Player::new_jack` to play instruments from the MIDI of other JACK clients, such as by calling `Player::add_note`
+/// on note ons and `Player::remove_note` on note offs. The client is closed when this is dropped.
+pub struct JackMidiInput {
+    client: Option<jack::AsyncClient<(), ClosureProcessHandler<MidiProcess>>>,
+    port_name: String,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl JackMidiInput {
+    /// Opens a JACK client called `name` with a MIDI input port, which then has to be connected to a MIDI source
+    /// (see `get_port_name`). `callback` is given every message in the order that they came in, on a thread of its
+    /// own, so it may block. The JACK server must be running.
+    pub fn open<F>(name: &str, mut callback: F) -> Result<Self, AudioError>
+    where
+        F: FnMut(MidiMessage) + Send + 'static,
+    {
+        let (client, _) = Client::new(name, ClientOptions::NO_START_SERVER)?;
+        let port = client.register_port("midi_in", MidiIn)?;
+        let port_name = port.name()?;
+        let (mut producer, mut consumer) = RingBuffer::new(MIDI_QUEUE_CAPACITY);
+        // Only parsing and queueing happens on the JACK thread, which must not block.
+        let process: MidiProcess = Box::new(move |_, scope| {
+            for event in port.iter(scope) {
+                if let Some(message) = MidiMessage::parse(event.bytes) {
+                    let _ = producer.push(message);
+                }
+            }
+            Control::Continue
+        });
+        let client = client.activate_async((), ClosureProcessHandler::new(process))?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stopped = Arc::clone(&stopped);
+            thread::spawn(move || {
+                while !stopped.load(Ordering::Relaxed) {
+                    while let Ok(message) = consumer.pop() {
+                        callback(message);
+                    }
+                    thread::sleep(MIDI_POLL_INTERVAL);
+                }
+            })
+        };
+        Ok(Self {
+            client: Some(client),
+            port_name,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    /// Gets the full name of the MIDI input port (such as `"soundtoys:midi_in"`), which other JACK clients and tools
+    /// such as `jack_connect` refer to it by.
+    pub fn get_port_name(&self) -> &str {
+        &self.port_name
+    }
+}
+
+impl Drop for JackMidiInput {
+    fn drop(&mut self) {
+        // The client is closed first, so that no more messages come in.
+        if let Some(client) = self.client.take() {
+            let _ = client.deactivate();
+        }
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
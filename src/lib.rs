@@ -1,7 +1,74 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+// The test harness links `std` even without the `std` feature, which gives `f64` back the methods of `math::Float`.
+#![cfg_attr(all(test, not(feature = "std")), allow(unused_imports, dead_code))]
+
+extern crate alloc;
+
+#[macro_use]
+mod macros;
+
+#[cfg(feature = "broadcast")]
+pub mod broadcast;
+pub mod builder;
+pub mod dsp;
+pub mod effects;
 pub mod errors;
+#[cfg(feature = "serde")]
+pub mod event_log;
+#[cfg(feature = "std")]
+mod fft;
+#[cfg(feature = "fixed")]
+pub mod fixed;
+#[cfg(feature = "flac")]
+mod flac;
+pub mod harmonizer;
+#[cfg(feature = "hydrogen")]
+pub mod hydrogen;
 pub mod instruments;
+#[cfg(all(
+    feature = "jack",
+    any(
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd"
+    )
+))]
+pub mod jack_midi;
+#[cfg(not(feature = "std"))]
+mod math;
+#[cfg(feature = "playback")]
+pub mod media;
+pub mod meter;
+#[cfg(all(
+    feature = "mpris",
+    any(
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd"
+    )
+))]
+pub mod mpris;
 pub mod note;
 pub mod oscillators;
+pub mod parameters;
+pub mod patch;
 pub mod player;
+#[cfg(feature = "serde")]
+pub mod preset;
 pub mod primitives;
+pub mod registry;
+pub mod sampler;
 pub mod sequencer;
+#[cfg(all(feature = "smtc", windows))]
+pub mod smtc;
+pub mod spectrum;
+pub mod testsignals;
+#[cfg(feature = "ogg")]
+mod vorbis;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use rand::rngs::SmallRng;
+}
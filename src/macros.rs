@@ -0,0 +1,140 @@
+//! Macros for cutting down on the boilerplate of writing instruments.
+
+/// Defines an instrument that sums a list of weighted oscillators under an envelope, which is how most of the simpler
+/// built-in instruments work. Each oscillator is given as its weight, followed by the oscillator and the number of
+/// semitones to transpose it by. This generates the struct (with the same fields as `Bell`), `new`, `Default`,
+/// `with_envelope`, and the implementation of `Instrument`.
+///
+/// ```ignore
+/// define_instrument! {
+///     /// An organ with a sub-octave and a fifth.
+///     pub struct Organ: "Organ" {
+///         envelope: EnvelopeADSR::builder().attack(0.01).sustain(1.0).build(),
+///         volume: 0.5,
+///         oscillators: [
+///             1.00 => (Oscillator::Sine, 0),
+///             0.50 => (Oscillator::Sine, -12),
+///             0.25 => (Oscillator::Sine, 7),
+///         ],
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! define_instrument {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident: $display_name:literal {
+            envelope: $envelope:expr,
+            volume: $volume:expr,
+            oscillators: [$($weight:expr => ($oscillator:expr, $transpose:expr)),+ $(,)?] $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone)]
+        $vis struct $name<E = $crate::oscillators::EnvelopeADSR> {
+            pub env: E,
+            pub volume: f64,
+            pub modulations: Vec<$crate::oscillators::Modulation>,
+            pub tuning: $crate::instruments::Tuning,
+            pub variation: $crate::instruments::Variation,
+        }
+
+        impl $name {
+            pub fn new() -> Self {
+                Self::with_envelope($envelope)
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl<E: $crate::oscillators::Envelope> $name<E> {
+            #[doc = concat!("Creates a new ", $display_name, " with a custom envelope.")]
+            pub fn with_envelope(env: E) -> Self {
+                Self {
+                    env,
+                    volume: $volume,
+                    modulations: Vec::new(),
+                    tuning: $crate::instruments::Tuning::default(),
+                    variation: $crate::instruments::Variation::default(),
+                }
+            }
+        }
+
+        impl<E: $crate::oscillators::Envelope + 'static> $crate::instruments::Instrument for $name<E> {
+            fn sound(
+                &self,
+                time: f64,
+                time_on: f64,
+                time_off: f64,
+                note_id: u8,
+                note_finished: &mut bool,
+            ) -> f64 {
+                let tuning = self.tuning.cents();
+                let phase_time = self.tuning.phase_time(time, time_on);
+                let amplitude = self.env.amplitude(time, time_on, time_off);
+                if amplitude <= 0.0 {
+                    *note_finished = true;
+                }
+                let sound = 0.0 $(
+                    + $weight * $crate::oscillators::osc_with_modulations(
+                        phase_time,
+                        $crate::note::tune(
+                            $crate::note::scale(note_id as i32 + $transpose),
+                            tuning,
+                        ),
+                        $oscillator,
+                        &self.modulations,
+                    )
+                )+;
+                amplitude * sound * self.volume
+            }
+
+            fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64, _note_id: u8) {
+                self.env.retrigger(time, time_on, time_off);
+            }
+
+            fn advance(
+                &mut self,
+                time: f64,
+                time_on: f64,
+                time_off: f64,
+                _note_id: u8,
+                _velocity: f64,
+                note_finished: &mut bool,
+            ) {
+                if self.env.amplitude(time, time_on, time_off) <= 0.0 {
+                    *note_finished = true;
+                }
+            }
+
+            fn vary(&mut self, rng: &mut $crate::__private::SmallRng) {
+                self.variation
+                    .apply(rng, &mut self.env, &mut self.tuning, &mut self.volume);
+            }
+
+            fn set_pitch_bend(&mut self, semitones: f64) {
+                self.tuning.set_pitch_bend(semitones);
+            }
+
+            fn set_fine_tune(&mut self, cents: f64) {
+                self.tuning.set_fine_tune(cents);
+            }
+
+            fn cost(&self) -> f64 {
+                let weights: &[f64] = &[$($weight),+];
+                weights.len() as f64
+            }
+
+            fn get_name() -> &'static str
+            where
+                Self: Sized,
+            {
+                $display_name
+            }
+        }
+    };
+}
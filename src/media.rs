@@ -0,0 +1,53 @@
+//! Transport commands (play, pause and stop) mapped onto `Player`, in the shape that media keys send them. The media
+//! controls of the OS call these through `mpris::MediaControls` on Linux (behind the `mpris` feature) and
+//! `smtc::MediaControls` on Windows (behind the `smtc` feature), and apps that listen for media keys some other way
+//! can forward what they receive to `Player::handle_media_command` themselves.
+
+use crate::{errors::AudioError, player::Player};
+
+/// How long playing notes are faded out for when stopping (in seconds), which avoids a click.
+const STOP_FADE_TIME: f64 = 0.01;
+
+/// A command sent by a media key or the media controls of the OS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MediaCommand {
+    Play,
+    Pause,
+    Toggle,
+    Stop,
+}
+
+/// The playback state to report back to the media controls of the OS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+}
+
+impl Player {
+    /// Carries out a command sent by a media key or the media controls of the OS. Play and pause resume and pause
+    /// playback, and stop quickly fades out and removes every note in the queue without pausing, so that new notes
+    /// can still be played.
+    pub fn handle_media_command(&self, command: MediaCommand) -> Result<(), AudioError> {
+        match command {
+            MediaCommand::Play => self.resume(),
+            MediaCommand::Pause => self.pause(),
+            MediaCommand::Toggle if self.is_paused() => self.resume(),
+            MediaCommand::Toggle => self.pause(),
+            MediaCommand::Stop => {
+                self.fade_out(STOP_FADE_TIME);
+                Ok(())
+            }
+        }
+    }
+
+    /// Gets the playback state to report back to the media controls of the OS.
+    pub fn get_playback_state(&self) -> PlaybackState {
+        if self.is_paused() {
+            PlaybackState::Paused
+        } else {
+            PlaybackState::Playing
+        }
+    }
+}
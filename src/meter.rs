@@ -0,0 +1,112 @@
+//! Meters for checking the stereo image of a mix.
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::sampler::Sample;
+
+/// A reading of the stereo image of a signal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StereoField {
+    /// How alike the left and right channels are, ranging from 1.0 when they are identical (mono), through 0.0 when
+    /// they are unrelated, to -1.0 when one is the inverse of the other. Readings below 0.0 mean that parts of the
+    /// signal cancel out when the mix is summed to mono.
+    pub correlation: f64,
+    /// Which side is louder, ranging from -1.0 when only the left channel has signal to 1.0 when only the right
+    /// channel has signal.
+    pub balance: f64,
+}
+
+impl Default for StereoField {
+    fn default() -> Self {
+        Self {
+            correlation: 1.0,
+            balance: 0.0,
+        }
+    }
+}
+
+impl StereoField {
+    /// Reads the stereo image of a whole sample. Mono samples are always fully correlated and centered.
+    pub fn of(sample: &Sample) -> Self {
+        if sample.channels < 2 {
+            return Self::default();
+        }
+        let mut sums = Sums::default();
+        for frame in sample.data.chunks_exact(sample.channels) {
+            sums.add(frame[0], frame[1], 1.0);
+        }
+        sums.field()
+    }
+}
+
+/// Follows the stereo image of a signal over roughly the last `window` seconds, which is what `Player` uses to meter
+/// its output.
+#[derive(Clone, Debug)]
+pub struct StereoMeter {
+    sums: Sums,
+    coefficient: f64,
+}
+
+impl StereoMeter {
+    /// Creates a new meter that averages over roughly `window` seconds, such as 0.3 seconds for a typical
+    /// correlation meter.
+    pub fn new(window: f64, sample_rate: f64) -> Self {
+        Self {
+            sums: Sums::default(),
+            coefficient: (-1.0 / (window * sample_rate).max(1.0)).exp(),
+        }
+    }
+
+    /// Meters the next frame.
+    pub fn process(&mut self, left: f64, right: f64) {
+        self.sums.scale(self.coefficient);
+        self.sums.add(left, right, 1.0 - self.coefficient);
+    }
+
+    /// Gets the current reading of the meter.
+    pub fn get(&self) -> StereoField {
+        self.sums.field()
+    }
+}
+
+/// Running sums of the products of the left and right channels.
+#[derive(Clone, Debug, Default)]
+struct Sums {
+    left: f64,
+    right: f64,
+    cross: f64,
+}
+
+impl Sums {
+    fn scale(&mut self, factor: f64) {
+        self.left *= factor;
+        self.right *= factor;
+        self.cross *= factor;
+    }
+
+    fn add(&mut self, left: f64, right: f64, weight: f64) {
+        self.left += weight * left * left;
+        self.right += weight * right * right;
+        self.cross += weight * left * right;
+    }
+
+    fn field(&self) -> StereoField {
+        // Silence reads as a centered mono signal rather than as noise from rounding errors.
+        const SILENCE: f64 = 1e-12;
+        let correlation = if self.left < SILENCE || self.right < SILENCE {
+            1.0
+        } else {
+            (self.cross / (self.left * self.right).sqrt()).clamp(-1.0, 1.0)
+        };
+        let (left, right) = (self.left.sqrt(), self.right.sqrt());
+        let balance = if left + right < SILENCE {
+            0.0
+        } else {
+            (right - left) / (right + left)
+        };
+        StereoField {
+            correlation,
+            balance,
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! The media controls of Linux desktops, through MPRIS over D-Bus, so that media keys, the sound menu and the
+//! now-playing widgets of the desktop pause and resume a `Player` like any other audio app. Commands are carried out
+//! with `Player::handle_media_command`.
+
+use crate::{
+    errors::AudioError,
+    media::{MediaCommand, PlaybackState},
+    player::Player,
+};
+use std::{collections::HashMap, sync::Arc};
+use zbus::{
+    blocking::{connection, Connection},
+    fdo, interface,
+    object_server::SignalEmitter,
+    zvariant::{ObjectPath, Value},
+};
+
+/// The object that media players are found at.
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+
+/// The interface with the transport and the metadata of a media player.
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// The track that the metadata describes. The player has no playlist, so there is only ever this one.
+const TRACK_ID: &str = "/org/soundtoys/Track";
+
+/// Registers a `Player` with the media controls of the desktop for as long as this is kept. The player shows up under
+/// the name of the app, and pausing, resuming or stopping it from the desktop calls `Player::handle_media_command`.
+pub struct MediaControls {
+    connection: Connection,
+    player: Arc<Player>,
+}
+
+impl MediaControls {
+    /// Registers `player` on the session bus under `name`, which the desktop shows as the name of the app. Several
+    /// apps can share a name, since the bus name is made unique with the ID of the process.
+    pub fn new(player: Arc<Player>, name: &str) -> Result<Self, AudioError> {
+        let connection = connection::Builder::session()?
+            .name(bus_name(name))?
+            .serve_at(
+                OBJECT_PATH,
+                Root {
+                    identity: name.to_string(),
+                },
+            )?
+            .serve_at(
+                OBJECT_PATH,
+                Transport {
+                    player: Arc::clone(&player),
+                    title: None,
+                },
+            )?
+            .build()?;
+        Ok(Self { connection, player })
+    }
+
+    /// Sets the title that the desktop shows as now playing, such as the name of a song or a preset.
+    pub fn set_title(&self, title: &str) -> Result<(), AudioError> {
+        let transport = self
+            .connection
+            .object_server()
+            .interface::<_, Transport>(OBJECT_PATH)?;
+        transport.get_mut().title = Some(title.to_string());
+        self.properties_changed("Metadata", Value::from(metadata(Some(title))))
+    }
+
+    /// Tells the desktop whether the player is playing or paused, which should be called after pausing or resuming
+    /// the player from the app. Commands from the desktop report this themselves.
+    pub fn update(&self) -> Result<(), AudioError> {
+        let status = playback_status(&self.player);
+        self.properties_changed("PlaybackStatus", Value::from(status))
+    }
+
+    /// Emits the signal that tells the desktop that a property of the player has changed.
+    fn properties_changed(&self, property: &str, value: Value<'_>) -> Result<(), AudioError> {
+        let changed = HashMap::from([(property, value)]);
+        self.connection.emit_signal(
+            None::<()>,
+            OBJECT_PATH,
+            "org.freedesktop.DBus.Properties",
+            "PropertiesChanged",
+            &(PLAYER_INTERFACE, changed, Vec::<&str>::new()),
+        )?;
+        Ok(())
+    }
+}
+
+/// Makes a well-known bus name from the name of the app, leaving out the characters that bus names cannot have.
+fn bus_name(name: &str) -> String {
+    let mut element: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .collect();
+    // Elements of bus names cannot be empty or start with a digit.
+    if !element.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        element.insert(0, '_');
+    }
+    format!(
+        "org.mpris.MediaPlayer2.{element}.instance{}",
+        std::process::id()
+    )
+}
+
+/// Gets the playback status of `player` in the words of MPRIS.
+fn playback_status(player: &Player) -> &'static str {
+    match player.get_playback_state() {
+        PlaybackState::Playing => "Playing",
+        PlaybackState::Paused => "Paused",
+    }
+}
+
+/// Builds the metadata of the only track, which has a title once the app has set one.
+fn metadata(title: Option<&str>) -> HashMap<&'static str, Value<'_>> {
+    let mut metadata = HashMap::from([(
+        "mpris:trackid",
+        Value::from(ObjectPath::from_static_str_unchecked(TRACK_ID)),
+    )]);
+    if let Some(title) = title {
+        metadata.insert("xesam:title", Value::from(title));
+    }
+    metadata
+}
+
+/// The root interface of a media player, which describes the app. The app cannot be raised or quit from the desktop.
+struct Root {
+    identity: String,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        self.identity.clone()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// The player interface of a media player, which maps the transport onto `Player`. There are no tracks to skip
+/// between or seek through, so those methods do nothing, as MPRIS asks of players that cannot do them.
+struct Transport {
+    player: Arc<Player>,
+    title: Option<String>,
+}
+
+impl Transport {
+    /// Carries out a command and tells the desktop about the new playback status.
+    async fn command(&self, command: MediaCommand, emitter: SignalEmitter<'_>) -> fdo::Result<()> {
+        self.player
+            .handle_media_command(command)
+            .map_err(|err| fdo::Error::Failed(err.to_string()))?;
+        self.playback_status_changed(&emitter).await?;
+        Ok(())
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Transport {
+    async fn play(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> fdo::Result<()> {
+        self.command(MediaCommand::Play, emitter).await
+    }
+
+    async fn pause(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> fdo::Result<()> {
+        self.command(MediaCommand::Pause, emitter).await
+    }
+
+    async fn play_pause(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        self.command(MediaCommand::Toggle, emitter).await
+    }
+
+    async fn stop(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> fdo::Result<()> {
+        self.command(MediaCommand::Stop, emitter).await
+    }
+
+    fn next(&self) {}
+
+    fn previous(&self) {}
+
+    fn seek(&self, _offset: i64) {}
+
+    fn set_position(&self, _track: ObjectPath<'_>, _position: i64) {}
+
+    fn open_uri(&self, _uri: &str) {}
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &str {
+        playback_status(&self.player)
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<&str, Value<'_>> {
+        metadata(self.title.as_deref())
+    }
+
+    /// The volume is the master volume of the player.
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.player.get_master_volume()
+    }
+
+    #[zbus(property)]
+    fn set_volume(&self, volume: f64) {
+        self.player.set_master_volume(volume.max(0.0));
+    }
+
+    /// The position is the time of the player in microseconds, since there is no track to be partway through.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        (self.player.get_time() * 1e6) as i64
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        false
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
@@ -1,19 +1,85 @@
 //! Utilities for making working with musical notes easier.
 
-use crate::instruments::Instrument;
-use std::any::TypeId;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+#[cfg(feature = "playback")]
+use crate::{instruments::Instrument, player::ChannelId};
+#[cfg(feature = "playback")]
+use alloc::boxed::Box;
+#[cfg(feature = "playback")]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The serial given to the next note that is created.
+#[cfg(feature = "playback")]
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(0);
 
 /// Maps a semitone to its frequency (in Hz).
 pub fn scale(note_id: i32) -> f64 {
     8.0 * 1.0594630943592952645618252949463_f64.powi(note_id)
 }
 
+/// Shifts a frequency (in Hz) by a number of cents, where there are 100 cents in a semitone.
+pub fn tune(hertz: f64, cents: f64) -> f64 {
+    hertz * 2.0_f64.powf(cents / 1200.0)
+}
+
 /// Converts frequency (Hz) to angular velocity.
 pub fn w(hertz: f64) -> f64 {
-    hertz * 2.0 * std::f64::consts::PI
+    hertz * 2.0 * core::f64::consts::PI
+}
+
+/// The mode of a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Major,
+    /// Natural minor.
+    Minor,
+}
+
+impl Mode {
+    /// Gets the semitones of each degree of the scale above its root.
+    pub fn steps(&self) -> [i32; 7] {
+        match self {
+            Self::Major => [0, 2, 4, 5, 7, 9, 11],
+            Self::Minor => [0, 2, 3, 5, 7, 8, 10],
+        }
+    }
+}
+
+/// A musical key, which notes can be locked to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key {
+    /// The pitch class of the root, where 0 is C, 1 is C#, and so on up to 11 for B.
+    pub root: u8,
+    pub mode: Mode,
+}
+
+impl Key {
+    /// Creates a new key.
+    pub fn new(root: u8, mode: Mode) -> Self {
+        Self { root, mode }
+    }
+
+    /// Moves a note by a number of degrees of the scale, such as 2 for a third above, or -4 for a fifth below. Notes
+    /// outside of the scale are moved as if they were the scale note below them, keeping their distance from it.
+    pub fn transpose_diatonic(&self, note_id: i32, degrees: i32) -> i32 {
+        let steps = self.mode.steps();
+        let relative = note_id - self.root as i32;
+        let octave = relative.div_euclid(12);
+        let pitch_class = relative.rem_euclid(12);
+        let degree = steps.iter().rposition(|s| *s <= pitch_class).unwrap_or(0) as i32;
+        let offset = pitch_class - steps[degree as usize];
+        let target = degree + degrees;
+        self.root as i32
+            + (octave + target.div_euclid(7)) * 12
+            + steps[target.rem_euclid(7) as usize]
+            + offset
+    }
 }
 
 /// A basic note.
+#[cfg(feature = "playback")]
+#[derive(Clone)]
 pub(crate) struct Note {
     /// Position in scale.
     pub id: u8,
@@ -23,5 +89,73 @@ pub(crate) struct Note {
     pub off: f64,
     pub active: bool,
     pub channel: Box<dyn Instrument>,
-    pub instrument_id: TypeId,
+    pub channel_id: ChannelId,
+    /// Notes with a lower priority become virtual first when the voice budget is exceeded.
+    pub priority: u8,
+    /// How hard the note was played.
+    pub velocity: f64,
+    /// The gain that the note is multiplied by when it is mixed.
+    pub volume: f64,
+    /// Time the note is scheduled to be deactivated at, which has yet to be reached.
+    pub release_at: Option<f64>,
+    /// Whether the note has yet to be played by the audio thread.
+    pub is_new: bool,
+    /// Whether the note was scheduled to start later than when it was played and has yet to start, in which case it
+    /// plays alongside the note with the same id on the same channel until it starts and replaces it.
+    pub pending: bool,
+    /// Tells the note apart from every other note, including other notes with the same id on the same channel.
+    pub serial: u64,
+}
+
+#[cfg(feature = "playback")]
+impl Note {
+    /// Gets a serial that no note has been given yet.
+    pub fn next_serial() -> u64 {
+        NEXT_SERIAL.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Musical note lengths, expressed relative to the tempo rather than in raw seconds. Tempo-synced effects and LFOs
+/// (such as `Delay::synced` and `BeatRepeat`) take their lengths as note values, and `Transport::duration_of` converts
+/// them at the tempo of the transport.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoteValue {
+    Whole,
+    Half,
+    Quarter,
+    Eighth,
+    Sixteenth,
+    /// A half note lengthened by half of its value.
+    DottedHalf,
+    /// A quarter note lengthened by half of its value.
+    DottedQuarter,
+    /// An eighth note lengthened by half of its value.
+    DottedEighth,
+    /// One of three notes played in the space of two quarter notes.
+    TripletQuarter,
+    /// One of three notes played in the space of two eighth notes.
+    TripletEighth,
+}
+
+impl NoteValue {
+    /// Gets the length of this note value in beats, where a beat is a quarter note.
+    pub fn beats(&self) -> f64 {
+        match self {
+            Self::Whole => 4.0,
+            Self::Half => 2.0,
+            Self::Quarter => 1.0,
+            Self::Eighth => 0.5,
+            Self::Sixteenth => 0.25,
+            Self::DottedHalf => 3.0,
+            Self::DottedQuarter => 1.5,
+            Self::DottedEighth => 0.75,
+            Self::TripletQuarter => 2.0 / 3.0,
+            Self::TripletEighth => 1.0 / 3.0,
+        }
+    }
+
+    /// Converts this note value to seconds at a given tempo (in beats per minute).
+    pub fn as_secs(&self, tempo: f64) -> f64 {
+        self.beats() * 60.0 / tempo
+    }
 }
@@ -1,12 +1,70 @@
 //! A bunch of ready-made utilities to get you started on digital sound synthesis.
 
-use crate::note::w;
-use rand::Rng;
-use std::f64::consts::PI;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::note::{w, NoteValue};
+#[cfg(feature = "std")]
+use crate::primitives::Transport;
+use alloc::{vec, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    f64::consts::PI,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+/// The highest frequency (in Hz) that is worth synthesizing, which is the top of the human hearing range.
+const HEARING_LIMIT: f64 = 20_000.0;
+
+/// The sample rate (in Hz) that `Oscillator::SawAna` assumes when it picks its own resolution, since oscillators are
+/// not told the sample rate.
+const DEFAULT_SAMPLE_RATE: f64 = 44100.0;
+
+/// The most harmonics that `saw_harmonics` picks for saws in the audible range.
+const MAX_SAW_HARMONICS: usize = 1000;
+
+/// The lowest frequency (in Hz) that can be heard as a pitch. Saws below it are used as the shape of a LFO, or are
+/// only heard as a series of clicks.
+const AUDIBLE_PITCH: f64 = 20.0;
+
+/// The most harmonics that `saw_harmonics` picks for saws below `AUDIBLE_PITCH`, which keeps saws that are used as the
+/// shape of a LFO affordable, since the harmonics that a full-range saw would need at those rates cannot be told apart.
+const MAX_LFO_SAW_HARMONICS: usize = 50;
+
+/// The state shared by every unseeded generator when the `entropy` feature is off, and by `Oscillator::Noise`.
+static COUNTER: AtomicU64 = AtomicU64::new(0x853C_49E6_748F_EA9B);
+
+/// Gets the next value of a SplitMix64 sequence over `COUNTER`, which is lock-free and needs no thread-local state.
+fn next_counter() -> u64 {
+    mix(COUNTER
+        .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+        .wrapping_add(0x9E37_79B9_7F4A_7C15))
+}
+
+/// Scrambles the bits of `z` with the output function of SplitMix64.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Creates a generator for anything that is not given a seed, seeded from the entropy of the OS with the `entropy`
+/// feature, or from a counter otherwise.
+pub(crate) fn unseeded_rng() -> SmallRng {
+    #[cfg(feature = "entropy")]
+    {
+        SmallRng::from_entropy()
+    }
+    #[cfg(not(feature = "entropy"))]
+    {
+        SmallRng::seed_from_u64(next_counter())
+    }
+}
 
 #[non_exhaustive]
 /// Represents the various general purpose oscillator types.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Oscillator {
     /// Sine wave
     Sine,
@@ -14,26 +72,322 @@ pub enum Oscillator {
     Square,
     /// Triangle wave
     Triangle,
-    /// Saw wave (analogue, warm, slow) (you can optionally specify the resolution of the wave)
+    /// Saw wave (analogue, warm, slow) (you can optionally specify the resolution of the wave, otherwise it sums as many
+    /// harmonics as fit below the Nyquist frequency of a 44,100Hz sample rate, see `saw_harmonics`)
     SawAna(Option<usize>),
     /// Saw wave (optimised, harsh, fast)
     SawDig,
-    /// Pseudo-random noise
+    /// Two-operator frequency modulation, where a sine modulator running at `mod_ratio` times the carrier frequency
+    /// bends the phase of a sine carrier by up to `mod_index` radians
+    FM { mod_ratio: f64, mod_index: f64 },
+    /// Ring modulation of a sine carrier by a sine modulator running at `mod_ratio` times the carrier frequency
+    RingMod { mod_ratio: f64 },
+    /// Amplitude modulation of a sine carrier by a sine modulator running at `mod_ratio` times the carrier frequency,
+    /// where `depth` ranges from 0.0 (no modulation) to 1.0 (full modulation)
+    AM { mod_ratio: f64, depth: f64 },
+    /// A random value that is held for each cycle, mostly useful as the shape of a LFO, where every LFO draws its
+    /// values from a generator of its own (see `LowFrequencyOscillator::with_seed`). Other oscillators pick the value
+    /// from the number of the cycle, so they all hold the same values
+    SampleAndHold,
+    /// Pseudo-random noise drawn from a counter shared by the whole process, so its output depends on everything
+    /// else that drew from it before (use `NoiseGen::with_seed` for renders that are identical on every run)
     Noise,
 }
 
-/// Carries information about LFO, which can be used to model vibratos, etc.
+/// Carries information about LFO, which can be used to model vibratos, trills, wobble basses, etc.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LowFrequencyOscillator {
     pub hertz: f64,
     pub amplitude: f64,
+    pub shape: Oscillator,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    held: HeldSample,
+    /// The transport whose tempo the LFO follows, if any (see `LowFrequencyOscillator::following`).
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    sync: Option<TempoSync>,
+}
+
+/// Keeps a LFO in step with the tempo of a `Transport`, which can change while the LFO runs.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug)]
+struct TempoSync {
+    transport: Transport,
+    /// The length of a cycle in beats.
+    beats: f64,
+    /// The time that the LFO was last read at and the number of cycles that it had completed by then, so that a
+    /// change of tempo only changes how fast the LFO runs from then on, instead of making it jump.
+    position: Cell<Option<(f64, f64)>>,
+}
+
+#[cfg(feature = "std")]
+impl TempoSync {
+    /// Gets the number of cycles completed by `time`, at the current tempo of the transport.
+    fn cycles(&self, time: f64) -> f64 {
+        let rate = self.transport.get_tempo() / 60.0 / self.beats;
+        let cycles = match self.position.get() {
+            Some((last, cycles)) if time >= last => cycles + (time - last) * rate,
+            _ => time * rate,
+        };
+        self.position.set(Some((time, cycles)));
+        cycles
+    }
 }
 
 impl Default for LowFrequencyOscillator {
     fn default() -> Self {
+        Self::new(0.0, 0.0, Oscillator::Sine)
+    }
+}
+
+/// The value that a LFO shaped like `Oscillator::SampleAndHold` holds for its current cycle, along with the generator
+/// that it draws the value of the next cycle from. The generator is seeded from system entropy (see the `entropy`
+/// feature) the first time it is needed, so that LFOs of other shapes do not pay for it.
+#[derive(Clone, Debug, Default)]
+struct HeldSample {
+    rng: RefCell<Option<SmallRng>>,
+    held: Cell<Option<(i64, f64)>>,
+}
+
+impl HeldSample {
+    /// Gets the value held for `cycle`, drawing a new one whenever the cycle changes.
+    fn sample(&self, cycle: i64) -> f64 {
+        match self.held.get() {
+            Some((held_cycle, value)) if held_cycle == cycle => value,
+            _ => {
+                let value = self
+                    .rng
+                    .borrow_mut()
+                    .get_or_insert_with(unseeded_rng)
+                    .gen_range(-1.0..=1.0);
+                self.held.set(Some((cycle, value)));
+                value
+            }
+        }
+    }
+}
+
+impl LowFrequencyOscillator {
+    /// Creates a new LFO.
+    pub fn new(hertz: f64, amplitude: f64, shape: Oscillator) -> Self {
+        Self {
+            hertz,
+            amplitude,
+            shape,
+            held: HeldSample::default(),
+            #[cfg(feature = "std")]
+            sync: None,
+        }
+    }
+
+    /// Creates a LFO that is synced to the tempo, completing one cycle every `note`, such as `NoteValue::Whole` for a
+    /// cycle every bar of 4/4. Since the time of `Player` follows its `Transport`, the cycles of a synced LFO line up
+    /// with the beats of the transport.
+    pub fn synced(tempo: f64, note: NoteValue, amplitude: f64, shape: Oscillator) -> Self {
+        Self::new(1.0 / note.as_secs(tempo), amplitude, shape)
+    }
+
+    /// Same as `synced`, but follows the tempo of `transport` as it changes (see `Transport::set_tempo`), such as to
+    /// keep a filter sweep in step with a sequencer whose tempo is automated. Changes of tempo only change how fast
+    /// the LFO runs from then on, so the LFO does not jump. `hertz` holds the rate at the tempo when the LFO was
+    /// created, which is the rate that a LFO loaded from a preset keeps, since presets do not save the transport.
+    #[cfg(feature = "std")]
+    pub fn following(
+        transport: &Transport,
+        note: NoteValue,
+        amplitude: f64,
+        shape: Oscillator,
+    ) -> Self {
+        let mut lfo = Self::synced(transport.get_tempo(), note, amplitude, shape);
+        lfo.sync = Some(TempoSync {
+            transport: transport.clone(),
+            beats: note.beats(),
+            position: Cell::new(None),
+        });
+        lfo
+    }
+
+    /// Seeds the generator that a LFO shaped like `Oscillator::SampleAndHold` draws its values from, so that it
+    /// outputs the same values on every run.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.held = HeldSample {
+            rng: RefCell::new(Some(SmallRng::seed_from_u64(seed))),
+            held: Cell::new(None),
+        };
+        self
+    }
+
+    /// Gets the output of the LFO at a given time, which ranges between -`amplitude` and `amplitude`.
+    pub fn value(&self, time: f64) -> f64 {
+        if self.amplitude == 0.0 {
+            return 0.0;
+        }
+        // A LFO that follows a transport runs its shape at 1Hz over the number of cycles that it has completed, which
+        // puts it at the same point of its cycle.
+        #[cfg(feature = "std")]
+        let (time, hertz) = match &self.sync {
+            Some(sync) => (sync.cycles(time), 1.0),
+            None => (time, self.hertz),
+        };
+        #[cfg(not(feature = "std"))]
+        let hertz = self.hertz;
+        if let Oscillator::SampleAndHold = self.shape {
+            self.amplitude * self.held.sample((time * hertz).floor() as i64)
+        } else {
+            self.amplitude * osc(time, hertz, self.shape.clone(), None)
+        }
+    }
+}
+
+/// The parameter that a `Modulation` is routed to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ModulationTarget {
+    /// Modulates the phase of the oscillator (vibrato), in radians.
+    Pitch,
+    /// Modulates the volume of the oscillator (tremolo), where an LFO amplitude of 1.0 silences the oscillator at the
+    /// bottom of every cycle.
+    Amplitude,
+    /// Modulates the cutoff frequency of a filter, in Hz.
+    Cutoff,
+}
+
+/// Routes a LFO to a parameter of an oscillator or instrument.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Modulation {
+    pub target: ModulationTarget,
+    pub lfo: LowFrequencyOscillator,
+}
+
+impl Modulation {
+    /// Creates a new modulation.
+    pub fn new(target: ModulationTarget, lfo: LowFrequencyOscillator) -> Self {
+        Self { target, lfo }
+    }
+
+    /// Sums the output of every modulation in `modulations` that is routed to `target` at a given time. Filters can
+    /// use this to get the amount to move their cutoff frequency by.
+    pub fn sum(modulations: &[Modulation], target: ModulationTarget, time: f64) -> f64 {
+        modulations
+            .iter()
+            .filter(|m| m.target == target)
+            .map(|m| m.lfo.value(time))
+            .sum()
+    }
+}
+
+/// Gets the phase (in radians) of an oscillator at a given time, including any modulation by the LFO.
+fn phase(time: f64, hertz: f64, lfo: Option<LowFrequencyOscillator>) -> f64 {
+    w(hertz) * time + lfo.map_or(0.0, |lfo| lfo.value(time))
+}
+
+/// A pseudo-random noise source. Unlike `Oscillator::Noise`, it owns its generator, which means that it
+/// can be seeded to produce identical output on every run and does not share a counter with every other
+/// noise oscillator in the process.
+#[derive(Clone, Debug)]
+pub struct NoiseGen {
+    rng: RefCell<SmallRng>,
+    seed: u64,
+    /// The note id and the bits of the start time of the note that the generator was last reseeded for by
+    /// `sample_for_note`.
+    note: Cell<Option<(u8, u64)>>,
+}
+
+impl NoiseGen {
+    /// Creates a new noise source seeded from system entropy (see the `entropy` feature).
+    pub fn new() -> Self {
+        Self::with_seed(unseeded_rng().gen())
+    }
+
+    /// Creates a new noise source that always outputs the same sequence for a given seed.
+    pub fn with_seed(seed: u64) -> Self {
         Self {
-            hertz: 0.0,
-            amplitude: 0.0,
+            rng: RefCell::new(SmallRng::seed_from_u64(seed)),
+            seed,
+            note: Cell::new(None),
+        }
+    }
+
+    /// Outputs the next noise sample between -1.0 and 1.0.
+    pub fn sample(&self) -> f64 {
+        self.rng.borrow_mut().gen_range(-1.0..=1.0)
+    }
+
+    /// Same as `sample`, but for the note `note_id` played at `time_on`. The generator is reseeded from its seed, the
+    /// note id and the time whenever the note changes, so that every note of an instrument (which is cloned along with
+    /// its generator for every note) gets noise of its own, while a seeded generator still gives the same noise for
+    /// the same note at the same time on every run.
+    pub fn sample_for_note(&self, note_id: u8, time_on: f64) -> f64 {
+        let note = (note_id, time_on.to_bits());
+        if self.note.get() != Some(note) {
+            self.note.set(Some(note));
+            let seed = self.seed ^ (note_id as u64).rotate_left(56) ^ time_on.to_bits();
+            *self.rng.borrow_mut() = SmallRng::seed_from_u64(seed);
         }
+        self.sample()
+    }
+}
+
+impl Default for NoiseGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keeps the phase of the oscillators of a note continuous while its pitch is bent. Oscillators get their phase from
+/// the time since the note started multiplied by the frequency, so changing the frequency of a sounding note would
+/// make the phase jump and click. Instead, `elapsed` stretches the time it hands to the oscillators whenever the bend
+/// changes, so that the phase at the new frequency picks up where it left off. Every note needs its own, which is the
+/// case for instruments since they are cloned for every note.
+#[derive(Clone, Debug, Default)]
+pub struct BendPhase {
+    semitones: Cell<f64>,
+    offset: Cell<f64>,
+}
+
+impl BendPhase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the time to give to the oscillators of a note started at `time_on` and currently bent by `semitones`, in
+    /// place of `time - time_on`.
+    pub fn elapsed(&self, semitones: f64, time: f64, time_on: f64) -> f64 {
+        let elapsed = time - time_on;
+        let previous = self.semitones.get();
+        if semitones != previous {
+            let ratio = 2f64.powf((previous - semitones) / 12.0);
+            self.offset
+                .set((elapsed + self.offset.get()) * ratio - elapsed);
+            self.semitones.set(semitones);
+        }
+        elapsed + self.offset.get()
+    }
+}
+
+/// The phase of a note only means something while the note plays, so it is saved as nothing and loaded as the state of
+/// a note that has not been bent yet. This lets instruments that derive `Serialize` and `Deserialize` (such as those
+/// made with `define_instrument!`) keep a `BendPhase` without marking it as skipped.
+#[cfg(feature = "serde")]
+impl serde::Serialize for BendPhase {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_none()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for BendPhase {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Option::<serde::de::IgnoredAny>::deserialize(deserializer)?;
+        Ok(Self::default())
     }
 }
 
@@ -45,9 +399,28 @@ pub fn osc(
     osc_type: Oscillator,
     lfo: Option<LowFrequencyOscillator>,
 ) -> f64 {
-    let lfo = lfo.unwrap_or_default();
-    let freq: f64 = w(hertz) * time + lfo.amplitude * (w(lfo.hertz) * time).sin();
+    wave(time, hertz, phase(time, hertz, lfo), osc_type)
+}
+
+/// Like `osc`, but accepts a list of modulations instead of a single LFO that only modulates pitch. Modulations that
+/// target the cutoff of a filter are ignored.
+pub fn osc_with_modulations(
+    time: f64,
+    hertz: f64,
+    osc_type: Oscillator,
+    modulations: &[Modulation],
+) -> f64 {
+    let freq = w(hertz) * time + Modulation::sum(modulations, ModulationTarget::Pitch, time);
+    let tremolo = modulations
+        .iter()
+        .filter(|m| m.target == ModulationTarget::Amplitude)
+        .map(|m| 1.0 - (m.lfo.amplitude - m.lfo.value(time)) / 2.0)
+        .product::<f64>();
+    wave(time, hertz, freq, osc_type) * tremolo
+}
 
+/// Gets the output of an oscillator from its phase (in radians).
+fn wave(time: f64, hertz: f64, freq: f64, osc_type: Oscillator) -> f64 {
     match osc_type {
         Oscillator::Sine => freq.sin(),
         Oscillator::Square => {
@@ -65,7 +438,7 @@ pub fn osc(
         }
         Oscillator::Triangle => freq.sin().asin() * (2.0 / PI),
         Oscillator::SawAna(res) => {
-            let res = res.unwrap_or(50);
+            let res = res.unwrap_or_else(|| saw_harmonics(hertz, DEFAULT_SAMPLE_RATE) + 1);
             (1..res)
                 .into_iter()
                 .map(|n| (n as f64 * freq).sin() / n as f64)
@@ -73,10 +446,191 @@ pub fn osc(
                 * (2.0 / PI)
         }
         Oscillator::SawDig => 2.0 * hertz * (time % (1.0 / hertz)) - 1.0,
-        Oscillator::Noise => rand::thread_rng().gen_range(-1.0..=1.0),
+        Oscillator::FM {
+            mod_ratio,
+            mod_index,
+        } => (freq + mod_index * (w(hertz * mod_ratio) * time).sin()).sin(),
+        Oscillator::RingMod { mod_ratio } => {
+            ring_mod(freq.sin(), (w(hertz * mod_ratio) * time).sin())
+        }
+        Oscillator::AM { mod_ratio, depth } => {
+            amplitude_mod(freq.sin(), (w(hertz * mod_ratio) * time).sin(), depth)
+        }
+        Oscillator::SampleAndHold => {
+            let cycle = (time * hertz).floor() as i64;
+            (mix(cycle as u64) >> 11) as f64 / (1_u64 << 52) as f64 - 1.0
+        }
+        Oscillator::Noise => (next_counter() >> 11) as f64 / (1_u64 << 52) as f64 - 1.0,
     }
 }
 
+/// Gets the number of harmonics that an analogue saw at `hertz` needs to reach up to the Nyquist frequency of
+/// `sample_rate`, or the top of the human hearing range (20,000Hz) if that is lower. Harmonics past that point alias
+/// or cannot be heard, so low notes get many harmonics for a full sound while high notes get few, which saves CPU.
+/// Saws below the range of pitches that can be heard (20Hz), such as the shapes of LFOs, are capped at a few dozen
+/// harmonics. Pass one more than this to `Oscillator::SawAna`, since its resolution leaves out the last harmonic.
+/// The count is picked from the unmodulated pitch, so a saw whose pitch is modulated upwards (such as by vibrato or a
+/// pitch bend) can alias a little at the top of the modulation; give those an explicit lower resolution if needed.
+pub fn saw_harmonics(hertz: f64, sample_rate: f64) -> usize {
+    let limit = (sample_rate / 2.0).min(HEARING_LIMIT);
+    let max = if hertz.abs() < AUDIBLE_PITCH {
+        MAX_LFO_SAW_HARMONICS
+    } else {
+        MAX_SAW_HARMONICS
+    };
+    (limit / hertz.abs()).clamp(1.0, max as f64) as usize
+}
+
+/// Two-operator FM synthesis. A shorthand for calling `osc` with `Oscillator::FM` and no LFO. A `mod_ratio` that
+/// is a whole number gives harmonic timbres such as electric pianos, while other ratios give the inharmonic,
+/// metallic timbres of bells.
+pub fn fm(time: f64, carrier_hz: f64, mod_ratio: f64, mod_index: f64) -> f64 {
+    osc(
+        time,
+        carrier_hz,
+        Oscillator::FM {
+            mod_ratio,
+            mod_index,
+        },
+        None,
+    )
+}
+
+/// A sine wave that sweeps from `start_hz` down (or up) to `end_hz`, with the distance left to go shrinking
+/// exponentially so that most of the sweep is over after `sweep_time` seconds. This is the pitch drop of drum
+/// machine kicks and toms.
+pub fn pitch_sweep(time: f64, start_hz: f64, end_hz: f64, sweep_time: f64) -> f64 {
+    let sweep_time = sweep_time.max(f64::EPSILON);
+    // The phase is the integral of the frequency over time, which keeps the wave continuous while it sweeps.
+    let phase =
+        end_hz * time + (start_hz - end_hz) * sweep_time * (1.0 - (-time / sweep_time).exp());
+    (2.0 * PI * phase).sin()
+}
+
+/// Ring modulation. Multiplies the outputs of two oscillators together, which gives metallic and robotic tones made
+/// up of the sum and difference of their frequencies.
+pub fn ring_mod(a: f64, b: f64) -> f64 {
+    a * b
+}
+
+/// Amplitude modulation. Like `ring_mod`, but the modulator `b` only varies the volume of the carrier `a` between full
+/// and `1.0 - depth`, so that the carrier frequency can still be heard.
+pub fn amplitude_mod(a: f64, b: f64, depth: f64) -> f64 {
+    a * (1.0 - depth + depth * (b + 1.0) / 2.0)
+}
+
+/// Additive synthesis. Sums sine waves at whole-number multiples of `hertz`, where `harmonics[0]` is the amplitude
+/// of the fundamental, `harmonics[1]` the amplitude of the second harmonic, and so on. Each harmonic can optionally be
+/// detuned by the number of cents in the matching position of `detune`. Harmonics above the human hearing range
+/// (20,000Hz) are left out to avoid aliasing. For instance, `Oscillator::SawAna` is roughly equivalent to harmonic
+/// amplitudes of `1/n` scaled by `2/PI`, while organs can be modelled after the drawbar settings of a tonewheel organ.
+pub fn additive(
+    time: f64,
+    hertz: f64,
+    harmonics: &[f64],
+    detune: Option<&[f64]>,
+    lfo: Option<LowFrequencyOscillator>,
+) -> f64 {
+    let freq = phase(time, hertz, lfo);
+    harmonics
+        .iter()
+        .enumerate()
+        .map(|(i, amplitude)| {
+            let cents = detune.and_then(|d| d.get(i)).copied().unwrap_or(0.0);
+            ((i + 1) as f64 * 2.0_f64.powf(cents / 1200.0), amplitude)
+        })
+        .filter(|(multiplier, _)| hertz * multiplier < 20_000.0)
+        .map(|(multiplier, amplitude)| amplitude * (multiplier * freq).sin())
+        .sum()
+}
+
+/// The shape of a stage of an envelope.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EnvelopeCurve {
+    /// Changes at a constant rate.
+    Linear,
+    /// Changes quickly at first and then slows down, like the natural decay of a struck bell or drum. The higher the
+    /// shape factor, the more pronounced the curve.
+    Exponential(f64),
+    /// Changes slowly at first and then speeds up. The higher the shape factor, the more pronounced the curve.
+    Logarithmic(f64),
+}
+
+impl EnvelopeCurve {
+    /// Maps the progress through a stage (from 0.0 to 1.0) to the progress of the amplitude through that stage.
+    pub fn apply(&self, progress: f64) -> f64 {
+        match *self {
+            Self::Exponential(shape) if shape != 0.0 => {
+                (1.0 - (-shape * progress).exp()) / (1.0 - (-shape).exp())
+            }
+            Self::Logarithmic(shape) if shape != 0.0 => {
+                ((shape * progress).exp() - 1.0) / (shape.exp() - 1.0)
+            }
+            _ => progress,
+        }
+    }
+}
+
+/// Shapes the amplitude of a note over its lifetime. Instruments that are generic over an envelope accept any type
+/// that implements this trait.
+pub trait Envelope: Clone + Send {
+    /// Return the amplitude of this envelope for a given time.
+    fn amplitude(&self, time: f64, time_on: f64, time_off: f64) -> f64;
+
+    /// Return the amplitude of this envelope for a given time, for a note played with a given velocity (typically
+    /// from 0.0 to 1.0). By default, the amplitude is scaled by `velocity`.
+    fn amplitude_with_velocity(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        velocity: f64,
+    ) -> f64 {
+        self.amplitude(time, time_on, time_off) * velocity
+    }
+
+    /// Scales the length of the decay of this envelope by `factor`. Does nothing by default.
+    fn scale_decay(&mut self, _factor: f64) {}
+
+    /// Makes the next attack start from the amplitude that the envelope has at `time` (for a note that was activated
+    /// at `time_on` and deactivated at `time_off`) instead of from silence, which is called when a note is played
+    /// again while it is still sounding so that the restart does not click. Does nothing by default.
+    fn retrigger(&mut self, _time: f64, _time_on: f64, _time_off: f64) {}
+}
+
+/// The shortest time (in seconds) that an envelope takes to release, which is long enough to avoid clicks while
+/// still sounding instant.
+const MIN_RELEASE_TIME: f64 = 0.005;
+
+/// Gets the amplitude of an envelope for a given time, by releasing the amplitude given by `held_amplitude` at the
+/// time the note was deactivated down to 0 over `release_time` seconds.
+fn release_amplitude<F>(
+    held_amplitude: F,
+    time: f64,
+    time_on: f64,
+    time_off: f64,
+    release_time: f64,
+    release_curve: EnvelopeCurve,
+) -> f64
+where
+    F: Fn(f64) -> f64,
+{
+    let mut amplitude = if time_on >= time_off {
+        held_amplitude(time - time_on)
+    } else {
+        // The release starts from the actual amplitude at the time the note was deactivated, no matter which stage it
+        // was in, and is stretched to a minimum length so that even instant releases do not click.
+        let release_amplitude = held_amplitude(time_off - time_on);
+        let progress = ((time - time_off) / release_time.max(MIN_RELEASE_TIME)).min(1.0);
+        release_curve.apply(progress) * (-release_amplitude) + release_amplitude
+    };
+    if amplitude < f64::EPSILON {
+        amplitude = 0.0;
+    }
+    amplitude
+}
+
 /// Attack: Initial rise in amplitude.
 ///
 /// Decay: The minute decrease in amplitude from the peak as it approaches the equilibrium amplitude.
@@ -86,13 +640,25 @@ pub fn osc(
 /// Release: The drop-off in amplitude towards the end.
 ///
 /// Certain string instruments when played in pizzicato has no decay and sustain time.
+///
+/// Each stage apart from sustain changes linearly by default, but can be given a different curve. When `looping` is
+/// set, the envelope swells back up from the sustain amplitude to the peak and decays again for as long as the note is
+/// held, instead of sustaining, which is useful for evolving pads and drones.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EnvelopeADSR {
     pub attack_time: f64,
     pub decay_time: f64,
     pub release_time: f64,
     pub sustain_amplitude: f64,
     pub start_amplitude: f64,
+    pub attack_curve: EnvelopeCurve,
+    pub decay_curve: EnvelopeCurve,
+    pub release_curve: EnvelopeCurve,
+    pub looping: bool,
+    /// The amplitude that the attack starts from, which is only above 0 after the envelope is retriggered.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    retrigger_level: f64,
 }
 
 impl Default for EnvelopeADSR {
@@ -103,40 +669,312 @@ impl Default for EnvelopeADSR {
             release_time: 0.2,
             sustain_amplitude: 1.0,
             start_amplitude: 1.0,
+            attack_curve: EnvelopeCurve::Linear,
+            decay_curve: EnvelopeCurve::Linear,
+            release_curve: EnvelopeCurve::Linear,
+            looping: false,
+            retrigger_level: 0.0,
         }
     }
 }
 
+impl Envelope for EnvelopeADSR {
+    fn amplitude(&self, time: f64, time_on: f64, time_off: f64) -> f64 {
+        release_amplitude(
+            |life_time| self.held_amplitude(life_time),
+            time,
+            time_on,
+            time_off,
+            self.release_time,
+            self.release_curve,
+        )
+    }
+
+    /// Scales the peak (`start_amplitude`) and `sustain_amplitude` of the envelope by `velocity`.
+    fn amplitude_with_velocity(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        velocity: f64,
+    ) -> f64 {
+        let env = Self {
+            start_amplitude: self.start_amplitude * velocity,
+            sustain_amplitude: self.sustain_amplitude * velocity,
+            retrigger_level: self.retrigger_level * velocity,
+            ..self.clone()
+        };
+        env.amplitude(time, time_on, time_off)
+    }
+
+    fn scale_decay(&mut self, factor: f64) {
+        self.decay_time *= factor;
+    }
+
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64) {
+        self.retrigger_level = self.amplitude(time, time_on, time_off);
+    }
+}
+
 impl EnvelopeADSR {
-    /// Return the amplitude of this envelope for a given time.
-    pub fn amplitude(&self, time: f64, time_on: f64, time_off: f64) -> f64 {
-        let mut amplitude = if time_on >= time_off {
-            let life_time = time - time_on;
-            if life_time <= self.attack_time {
-                (life_time / self.attack_time) * self.start_amplitude
-            } else if life_time <= (self.attack_time + self.decay_time) {
-                ((life_time - self.attack_time) / self.decay_time)
-                    * (self.sustain_amplitude - self.start_amplitude)
-                    + self.start_amplitude
-            } else {
-                self.sustain_amplitude
+    /// Constructs a new `EnvelopeADSRBuilder`, starting from the default envelope.
+    pub fn builder() -> EnvelopeADSRBuilder {
+        EnvelopeADSRBuilder {
+            env: Self::default(),
+        }
+    }
+
+    /// An envelope for percussive sounds, which hits instantly and decays exponentially with no sustain.
+    pub fn percussive() -> Self {
+        Self::builder()
+            .attack(0.005)
+            .decay(0.3)
+            .sustain(0.0)
+            .release(0.1)
+            .decay_curve(EnvelopeCurve::Exponential(5.0))
+            .build()
+    }
+
+    /// An envelope for pads, which swells in and out slowly.
+    pub fn pad() -> Self {
+        Self::builder()
+            .attack(1.0)
+            .decay(0.5)
+            .sustain(0.8)
+            .release(2.0)
+            .attack_curve(EnvelopeCurve::Logarithmic(2.0))
+            .release_curve(EnvelopeCurve::Exponential(3.0))
+            .build()
+    }
+
+    /// Gets the amplitude of this envelope while the note is held, for a given time since the note was activated.
+    fn held_amplitude(&self, life_time: f64) -> f64 {
+        let cycle_time = self.attack_time + self.decay_time;
+        if self.looping && cycle_time > 0.0 && life_time > cycle_time {
+            // Repeats of the attack start from the sustain amplitude instead of silence.
+            let cycle_life_time = (life_time - cycle_time) % cycle_time;
+            if cycle_life_time <= self.attack_time {
+                return self.attack_curve.apply(cycle_life_time / self.attack_time)
+                    * (self.start_amplitude - self.sustain_amplitude)
+                    + self.sustain_amplitude;
             }
+            return self.held_amplitude_once(cycle_life_time);
+        }
+        self.held_amplitude_once(life_time)
+    }
+
+    /// Same as `held_amplitude`, but without looping.
+    fn held_amplitude_once(&self, life_time: f64) -> f64 {
+        if life_time <= self.attack_time {
+            self.attack_curve.apply(life_time / self.attack_time)
+                * (self.start_amplitude - self.retrigger_level)
+                + self.retrigger_level
+        } else if life_time <= (self.attack_time + self.decay_time) {
+            self.decay_curve
+                .apply((life_time - self.attack_time) / self.decay_time)
+                * (self.sustain_amplitude - self.start_amplitude)
+                + self.start_amplitude
         } else {
-            let life_time = time_off - time_on;
-            let release_amplitude = if life_time <= self.attack_time {
-                (life_time / self.attack_time) * self.start_amplitude
-            } else if life_time <= (self.attack_time + self.decay_time) {
-                ((life_time - self.attack_time) / self.decay_time)
-                    * (self.sustain_amplitude - self.start_amplitude)
-                    + self.start_amplitude
-            } else {
-                self.sustain_amplitude
-            };
-            ((time - time_off) / self.release_time) * (-release_amplitude) + release_amplitude
-        };
-        if amplitude < f64::EPSILON {
-            amplitude = 0.0;
+            self.sustain_amplitude
         }
-        amplitude
+    }
+}
+
+/// Builds an `EnvelopeADSR`.
+#[derive(Clone, Debug)]
+pub struct EnvelopeADSRBuilder {
+    env: EnvelopeADSR,
+}
+
+impl EnvelopeADSRBuilder {
+    /// Sets the attack time (in seconds).
+    pub fn attack(mut self, attack_time: f64) -> Self {
+        self.env.attack_time = attack_time;
+        self
+    }
+
+    /// Sets the decay time (in seconds).
+    pub fn decay(mut self, decay_time: f64) -> Self {
+        self.env.decay_time = decay_time;
+        self
+    }
+
+    /// Sets the sustain amplitude.
+    pub fn sustain(mut self, sustain_amplitude: f64) -> Self {
+        self.env.sustain_amplitude = sustain_amplitude;
+        self
+    }
+
+    /// Sets the release time (in seconds).
+    pub fn release(mut self, release_time: f64) -> Self {
+        self.env.release_time = release_time;
+        self
+    }
+
+    /// Sets the peak amplitude reached at the end of the attack.
+    pub fn peak(mut self, start_amplitude: f64) -> Self {
+        self.env.start_amplitude = start_amplitude;
+        self
+    }
+
+    /// Sets the curve of the attack.
+    pub fn attack_curve(mut self, curve: EnvelopeCurve) -> Self {
+        self.env.attack_curve = curve;
+        self
+    }
+
+    /// Sets the curve of the decay.
+    pub fn decay_curve(mut self, curve: EnvelopeCurve) -> Self {
+        self.env.decay_curve = curve;
+        self
+    }
+
+    /// Sets the curve of the release.
+    pub fn release_curve(mut self, curve: EnvelopeCurve) -> Self {
+        self.env.release_curve = curve;
+        self
+    }
+
+    /// Sets whether the attack and decay loop while the note is held.
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.env.looping = looping;
+        self
+    }
+
+    /// Constructs an `EnvelopeADSR`.
+    pub fn build(self) -> EnvelopeADSR {
+        self.env
+    }
+}
+
+/// A stage of `EnvelopeMulti`, which moves the amplitude from the level of the previous stage to `level` over `time`
+/// seconds.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Breakpoint {
+    pub time: f64,
+    pub level: f64,
+    pub curve: EnvelopeCurve,
+}
+
+impl Breakpoint {
+    /// Creates a new breakpoint.
+    pub fn new(time: f64, level: f64, curve: EnvelopeCurve) -> Self {
+        Self { time, level, curve }
+    }
+}
+
+/// An envelope with any number of stages. While the note is held, the amplitude starts at 0 and moves through each
+/// breakpoint in turn, after which the level of the last breakpoint is sustained. Once the note is released, the
+/// amplitude falls to 0 over `release_time` seconds.
+///
+/// `loop_stages` can be set to the indices of the first and last breakpoints of a loop, which repeats for as long as
+/// the note is held. Repeats of the loop start from the level of its last breakpoint.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnvelopeMulti {
+    pub breakpoints: Vec<Breakpoint>,
+    pub release_time: f64,
+    pub release_curve: EnvelopeCurve,
+    pub loop_stages: Option<(usize, usize)>,
+    /// The amplitude that the first stage starts from, which is only above 0 after the envelope is retriggered.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    retrigger_level: f64,
+}
+
+impl EnvelopeMulti {
+    /// Creates a new envelope from a list of breakpoints.
+    pub fn new(breakpoints: Vec<Breakpoint>, release_time: f64) -> Self {
+        Self {
+            breakpoints,
+            release_time,
+            release_curve: EnvelopeCurve::Linear,
+            loop_stages: None,
+            retrigger_level: 0.0,
+        }
+    }
+
+    /// Creates a DAHDSR envelope, which is an ADSR envelope with a delay before the attack, and a hold at the peak
+    /// amplitude (1.0) before the decay.
+    pub fn dahdsr(
+        delay_time: f64,
+        attack_time: f64,
+        hold_time: f64,
+        decay_time: f64,
+        sustain_amplitude: f64,
+        release_time: f64,
+    ) -> Self {
+        Self::new(
+            vec![
+                Breakpoint::new(delay_time, 0.0, EnvelopeCurve::Linear),
+                Breakpoint::new(attack_time, 1.0, EnvelopeCurve::Linear),
+                Breakpoint::new(hold_time, 1.0, EnvelopeCurve::Linear),
+                Breakpoint::new(decay_time, sustain_amplitude, EnvelopeCurve::Linear),
+            ],
+            release_time,
+        )
+    }
+
+    /// Gets the amplitude of this envelope while the note is held, for a given time since the note was activated.
+    fn held_amplitude(&self, life_time: f64) -> f64 {
+        if let Some((first, last)) = self.loop_stages {
+            if let Some(loop_breakpoints) = self.breakpoints.get(first..=last) {
+                let loop_start: f64 = self.breakpoints[..first].iter().map(|b| b.time).sum();
+                let loop_time: f64 = loop_breakpoints.iter().map(|b| b.time).sum();
+                if loop_time > 0.0 && life_time >= loop_start + loop_time {
+                    return Self::stages_amplitude(
+                        loop_breakpoints,
+                        (life_time - loop_start) % loop_time,
+                        self.breakpoints[last].level,
+                    );
+                }
+            }
+        }
+        Self::stages_amplitude(&self.breakpoints, life_time, self.retrigger_level)
+    }
+
+    /// Gets the amplitude of a list of stages starting at `level`, for a given time since the first stage started.
+    fn stages_amplitude(breakpoints: &[Breakpoint], life_time: f64, mut level: f64) -> f64 {
+        let mut stage_start = 0.0;
+        for breakpoint in breakpoints {
+            let stage_end = stage_start + breakpoint.time;
+            if life_time < stage_end {
+                let progress = (life_time - stage_start) / breakpoint.time;
+                return breakpoint.curve.apply(progress) * (breakpoint.level - level) + level;
+            }
+            stage_start = stage_end;
+            level = breakpoint.level;
+        }
+        level
+    }
+}
+
+impl Envelope for EnvelopeMulti {
+    fn amplitude(&self, time: f64, time_on: f64, time_off: f64) -> f64 {
+        release_amplitude(
+            |life_time| self.held_amplitude(life_time),
+            time,
+            time_on,
+            time_off,
+            self.release_time,
+            self.release_curve,
+        )
+    }
+
+    /// Scales the length of every stage after the loudest breakpoint by `factor`.
+    fn scale_decay(&mut self, factor: f64) {
+        let peak = self
+            .breakpoints
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.level.total_cmp(&b.1.level))
+            .map_or(0, |(i, _)| i);
+        for breakpoint in self.breakpoints.iter_mut().skip(peak + 1) {
+            breakpoint.time *= factor;
+        }
+    }
+
+    fn retrigger(&mut self, time: f64, time_on: f64, time_off: f64) {
+        self.retrigger_level = self.amplitude(time, time_on, time_off);
     }
 }
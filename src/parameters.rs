@@ -0,0 +1,465 @@
+//! Reflection of the tweakable values of instruments and envelopes, so that generic UIs, remote control endpoints, and
+//! controller mappings can expose every value by name without knowing the type that it belongs to.
+
+#[cfg(feature = "std")]
+use crate::effects::{BeatRepeat, ConvolutionReverb};
+use crate::{
+    builder::LayeredInstrument,
+    dsp::{AutoWah, Biquad, EnvelopeFollower, FrequencyShifter},
+    effects::{
+        Bitcrusher, Chorus, Compressor, Delay, Distortion, Flanger, Limiter, Overdrive, Phaser,
+        Tilt, EQ3,
+    },
+    errors::AudioError,
+    instruments::{
+        Bell, Bell8, Brass, Drum808Kick, DrumClap, DrumCrash, DrumHiHat, DrumTom, Drumkick,
+        Drumsnare, EPiano, Flute, Harmonica, NoiseLayer, NoiseSweep, SubSynth,
+    },
+    oscillators::{Envelope, EnvelopeADSR, EnvelopeMulti},
+    sampler::Sampler,
+};
+use alloc::{string::String, string::ToString, vec, vec::Vec};
+
+/// The unit that the value of a parameter is measured in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Unit {
+    /// A plain amount, such as a volume or a mix.
+    Amount,
+    Seconds,
+    Hertz,
+    Cents,
+}
+
+/// Describes a tweakable value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParameterInfo {
+    pub name: String,
+    pub min: f64,
+    pub max: f64,
+    pub unit: Unit,
+}
+
+impl ParameterInfo {
+    /// Describes a new parameter.
+    pub fn new(name: &str, min: f64, max: f64, unit: Unit) -> Self {
+        Self {
+            name: name.to_string(),
+            min,
+            max,
+            unit,
+        }
+    }
+
+    /// Adds `prefix` to the name of the parameter, which is used for the parameters of envelopes within instruments.
+    fn with_prefix(mut self, prefix: &str) -> Self {
+        self.name.insert_str(0, prefix);
+        self
+    }
+}
+
+/// Lists the tweakable values of a type, and gets or sets them by name. The parameters of envelopes within an
+/// instrument are prefixed by the name of the envelope, such as "env.attack".
+pub trait Parameters {
+    /// Lists every parameter.
+    fn parameters(&self) -> Vec<ParameterInfo>;
+
+    /// Gets the value of a parameter, or `None` if there is no parameter with that name.
+    fn get_parameter(&self, name: &str) -> Option<f64>;
+
+    /// Sets the value of a parameter, which is clamped to the range of the parameter. Returns an error if there is no
+    /// parameter with that name.
+    fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), AudioError>;
+}
+
+/// Implements `Parameters` for a type from a list of its parameters, each given as the name of the parameter, the
+/// field that it is stored in, its range, and its unit, followed by the fields holding other types that implement
+/// `Parameters` along with the prefix of their parameters.
+macro_rules! impl_parameters {
+    (
+        impl[$($generics:tt)*] for $ty:ty {
+            $($name:literal => $($field:ident).+: $min:expr, $max:expr, $unit:expr;)*
+            $(nested $prefix:literal => $nested:ident;)*
+        }
+    ) => {
+        impl<$($generics)*> Parameters for $ty {
+            fn parameters(&self) -> Vec<ParameterInfo> {
+                #[allow(unused_mut)]
+                let mut parameters = vec![$(ParameterInfo::new($name, $min, $max, $unit)),*];
+                $(
+                    parameters.extend(
+                        self.$nested
+                            .parameters()
+                            .into_iter()
+                            .map(|p| p.with_prefix($prefix)),
+                    );
+                )*
+                parameters
+            }
+
+            fn get_parameter(&self, name: &str) -> Option<f64> {
+                match name {
+                    $($name => Some(self.$($field).+),)*
+                    _ => {
+                        $(
+                            if let Some(name) = name.strip_prefix($prefix) {
+                                return self.$nested.get_parameter(name);
+                            }
+                        )*
+                        None
+                    }
+                }
+            }
+
+            fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), AudioError> {
+                match name {
+                    $(
+                        $name => {
+                            self.$($field).+ = value.clamp($min, $max);
+                            Ok(())
+                        }
+                    )*
+                    _ => {
+                        $(
+                            if let Some(name) = name.strip_prefix($prefix) {
+                                return self.$nested.set_parameter(name, value);
+                            }
+                        )*
+                        Err(AudioError::UnknownParameter(name.to_string()))
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_parameters! {
+    impl[] for EnvelopeADSR {
+        "attack" => attack_time: 0.0, 10.0, Unit::Seconds;
+        "decay" => decay_time: 0.0, 10.0, Unit::Seconds;
+        "sustain" => sustain_amplitude: 0.0, 1.0, Unit::Amount;
+        "release" => release_time: 0.0, 10.0, Unit::Seconds;
+        "peak" => start_amplitude: 0.0, 1.0, Unit::Amount;
+    }
+}
+
+impl_parameters! {
+    impl[] for EnvelopeMulti {
+        "release" => release_time: 0.0, 10.0, Unit::Seconds;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for Bell<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for Bell8<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for Harmonica<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for Drumkick<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        "max_life_time" => max_life_time: 0.0, 10.0, Unit::Seconds;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for Drumsnare<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        "max_life_time" => max_life_time: 0.0, 10.0, Unit::Seconds;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for DrumHiHat<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        "max_life_time" => max_life_time: 0.0, 10.0, Unit::Seconds;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for SubSynth<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        "detune" => detune: -100.0, 100.0, Unit::Cents;
+        "noise_mix" => noise_mix: 0.0, 1.0, Unit::Amount;
+        "cutoff" => cutoff: 20.0, 20000.0, Unit::Hertz;
+        "resonance" => resonance: 0.0, 1.0, Unit::Amount;
+        "filter_env_amount" => filter_env_amount: 0.0, 20000.0, Unit::Hertz;
+        nested "env." => env;
+        nested "filter_env." => filter_env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for EPiano<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        "brightness" => brightness: 0.0, 10.0, Unit::Amount;
+        "brightness_decay" => brightness_decay: 0.0, 50.0, Unit::Amount;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for Drum808Kick<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        "max_life_time" => max_life_time: 0.0, 10.0, Unit::Seconds;
+        "sweep_ratio" => sweep_ratio: 0.25, 16.0, Unit::Amount;
+        "sweep_time" => sweep_time: 0.0, 1.0, Unit::Seconds;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for DrumTom<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        "max_life_time" => max_life_time: 0.0, 10.0, Unit::Seconds;
+        "sweep_ratio" => sweep_ratio: 0.25, 16.0, Unit::Amount;
+        "sweep_time" => sweep_time: 0.0, 1.0, Unit::Seconds;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for DrumClap<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "max_life_time" => max_life_time: 0.0, 10.0, Unit::Seconds;
+        "burst_spacing" => burst_spacing: 0.001, 0.1, Unit::Seconds;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for DrumCrash<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        "max_life_time" => max_life_time: 0.0, 10.0, Unit::Seconds;
+        "brightness" => brightness: 20.0, 20000.0, Unit::Hertz;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for NoiseLayer<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "q" => q: 0.1, 20.0, Unit::Amount;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for NoiseSweep<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "start_cutoff" => start_cutoff: 20.0, 20000.0, Unit::Hertz;
+        "end_cutoff" => end_cutoff: 20.0, 20000.0, Unit::Hertz;
+        "sweep_time" => sweep_time: 0.0, 60.0, Unit::Seconds;
+        "resonance" => resonance: 0.0, 1.0, Unit::Amount;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for Flute<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        "breath" => breath: 0.0, 1.0, Unit::Amount;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for Brass<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[E: Envelope + Parameters] for LayeredInstrument<E> {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "tuning" => tuning.tuning: -1200.0, 1200.0, Unit::Cents;
+        nested "env." => env;
+    }
+}
+
+impl_parameters! {
+    impl[] for Sampler {
+        "volume" => volume: 0.0, 2.0, Unit::Amount;
+        "pan" => pan: -1.0, 1.0, Unit::Amount;
+        "width" => width: 0.0, 1.0, Unit::Amount;
+    }
+}
+
+impl_parameters! {
+    impl[] for EnvelopeFollower {
+        "attack" => attack: 0.0, 1.0, Unit::Seconds;
+        "release" => release: 0.0, 5.0, Unit::Seconds;
+    }
+}
+
+impl_parameters! {
+    impl[] for Biquad {
+        "cutoff" => cutoff: 10.0, 20000.0, Unit::Hertz;
+        "q" => q: 0.1, 20.0, Unit::Amount;
+        "gain" => gain: -24.0, 24.0, Unit::Amount;
+    }
+}
+
+impl_parameters! {
+    impl[] for FrequencyShifter {
+        "shift" => shift: -5000.0, 5000.0, Unit::Hertz;
+    }
+}
+
+impl_parameters! {
+    impl[] for AutoWah {
+        "min_center" => min_center: 20.0, 20000.0, Unit::Hertz;
+        "max_center" => max_center: 20.0, 20000.0, Unit::Hertz;
+        "q" => q: 0.1, 20.0, Unit::Amount;
+        "sensitivity" => sensitivity: 0.0, 10.0, Unit::Amount;
+        "mix" => mix: 0.0, 1.0, Unit::Amount;
+        nested "follower." => follower;
+    }
+}
+
+impl_parameters! {
+    impl[] for Delay {
+        "time" => time: 0.001, 10.0, Unit::Seconds;
+        "feedback" => feedback: 0.0, 0.999, Unit::Amount;
+        "mix" => mix: 0.0, 1.0, Unit::Amount;
+    }
+}
+
+impl_parameters! {
+    impl[] for Chorus {
+        "rate" => rate: 0.01, 10.0, Unit::Hertz;
+        "depth" => depth: 0.0, 0.05, Unit::Seconds;
+        "delay" => delay: 0.0, 0.05, Unit::Seconds;
+        "feedback" => feedback: 0.0, 0.95, Unit::Amount;
+        "mix" => mix: 0.0, 1.0, Unit::Amount;
+    }
+}
+
+impl_parameters! {
+    impl[] for Flanger {
+        "rate" => rate: 0.01, 10.0, Unit::Hertz;
+        "depth" => depth: 0.0, 0.01, Unit::Seconds;
+        "delay" => delay: 0.0, 0.01, Unit::Seconds;
+        "feedback" => feedback: -0.95, 0.95, Unit::Amount;
+        "mix" => mix: 0.0, 1.0, Unit::Amount;
+    }
+}
+
+impl_parameters! {
+    impl[] for Phaser {
+        "rate" => rate: 0.01, 10.0, Unit::Hertz;
+        "min_frequency" => min_frequency: 20.0, 20000.0, Unit::Hertz;
+        "max_frequency" => max_frequency: 20.0, 20000.0, Unit::Hertz;
+        "feedback" => feedback: 0.0, 0.95, Unit::Amount;
+        "mix" => mix: 0.0, 1.0, Unit::Amount;
+    }
+}
+
+impl_parameters! {
+    impl[] for Limiter {
+        "ceiling" => ceiling: 0.0, 1.0, Unit::Amount;
+        "knee" => knee: 0.0, 24.0, Unit::Amount;
+        "attack" => attack: 0.0, 0.1, Unit::Seconds;
+        "release" => release: 0.001, 2.0, Unit::Seconds;
+    }
+}
+
+impl_parameters! {
+    impl[] for Compressor {
+        "threshold" => threshold: -60.0, 0.0, Unit::Amount;
+        "ratio" => ratio: 1.0, 20.0, Unit::Amount;
+        "knee" => knee: 0.0, 24.0, Unit::Amount;
+        "attack" => attack: 0.0, 0.5, Unit::Seconds;
+        "release" => release: 0.001, 5.0, Unit::Seconds;
+        "makeup" => makeup: 0.0, 24.0, Unit::Amount;
+    }
+}
+
+impl_parameters! {
+    impl[] for EQ3 {
+        "low_frequency" => low_frequency: 20.0, 1000.0, Unit::Hertz;
+        "low_gain" => low_gain: -24.0, 24.0, Unit::Amount;
+        "mid_frequency" => mid_frequency: 100.0, 10000.0, Unit::Hertz;
+        "mid_gain" => mid_gain: -24.0, 24.0, Unit::Amount;
+        "mid_q" => mid_q: 0.1, 10.0, Unit::Amount;
+        "high_frequency" => high_frequency: 1000.0, 20000.0, Unit::Hertz;
+        "high_gain" => high_gain: -24.0, 24.0, Unit::Amount;
+    }
+}
+
+impl_parameters! {
+    impl[] for Tilt {
+        "tilt" => tilt: -12.0, 12.0, Unit::Amount;
+        "frequency" => frequency: 100.0, 10000.0, Unit::Hertz;
+    }
+}
+
+impl_parameters! {
+    impl[] for Distortion {
+        "drive" => drive: 0.01, 50.0, Unit::Amount;
+        "mix" => mix: 0.0, 1.0, Unit::Amount;
+    }
+}
+
+impl_parameters! {
+    impl[] for Overdrive {
+        "drive" => drive: 0.01, 50.0, Unit::Amount;
+        "asymmetry" => asymmetry: 0.0, 1.0, Unit::Amount;
+        "mix" => mix: 0.0, 1.0, Unit::Amount;
+    }
+}
+
+impl_parameters! {
+    impl[] for Bitcrusher {
+        "bits" => bits: 1.0, 24.0, Unit::Amount;
+        "rate" => rate: 100.0, 48000.0, Unit::Hertz;
+        "mix" => mix: 0.0, 1.0, Unit::Amount;
+    }
+}
+
+#[cfg(feature = "std")]
+impl_parameters! {
+    impl[] for BeatRepeat {
+        "beats" => beats: 0.0625, 16.0, Unit::Amount;
+        "quantize" => quantize: 0.0, 4.0, Unit::Amount;
+        "decay" => decay: 0.0, 1.0, Unit::Amount;
+    }
+}
+
+#[cfg(feature = "std")]
+impl_parameters! {
+    impl[] for ConvolutionReverb {
+        "mix" => mix: 0.0, 1.0, Unit::Amount;
+    }
+}
@@ -0,0 +1,77 @@
+//! Utilities for sound design workflows.
+
+/// One of the two slots in `ABSlots`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+/// Holds two versions of a patch (such as an instrument) so that they can be compared by toggling between them
+/// instantly while tweaking one of them.
+#[derive(Clone, Debug)]
+pub struct ABSlots<T> {
+    a: T,
+    b: T,
+    active: Slot,
+}
+
+impl<T: Clone> ABSlots<T> {
+    /// Creates new A/B slots, with both slots starting out with the same patch and slot A active.
+    pub fn new(patch: T) -> Self {
+        Self {
+            a: patch.clone(),
+            b: patch,
+            active: Slot::A,
+        }
+    }
+
+    /// Switches to the other slot.
+    pub fn toggle(&mut self) {
+        self.active = match self.active {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        };
+    }
+
+    /// Switches to a specific slot.
+    pub fn select(&mut self, slot: Slot) {
+        self.active = slot;
+    }
+
+    /// Gets the slot that is currently active.
+    pub fn get_active_slot(&self) -> Slot {
+        self.active
+    }
+
+    /// Gets the patch in the active slot.
+    pub fn active(&self) -> &T {
+        self.get(self.active)
+    }
+
+    /// Gets a mutable reference to the patch in the active slot, for tweaking it.
+    pub fn active_mut(&mut self) -> &mut T {
+        match self.active {
+            Slot::A => &mut self.a,
+            Slot::B => &mut self.b,
+        }
+    }
+
+    /// Gets the patch in a specific slot.
+    pub fn get(&self, slot: Slot) -> &T {
+        match slot {
+            Slot::A => &self.a,
+            Slot::B => &self.b,
+        }
+    }
+
+    /// Replaces the patch in slot B with a copy of the patch in slot A.
+    pub fn copy_a_to_b(&mut self) {
+        self.b = self.a.clone();
+    }
+
+    /// Replaces the patch in slot A with a copy of the patch in slot B.
+    pub fn copy_b_to_a(&mut self) {
+        self.a = self.b.clone();
+    }
+}
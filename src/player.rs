@@ -1,55 +1,427 @@
 //! Audio player that provides a thin wrapper over `SoundMaker`.
 
-use crate::{errors::AudioError, instruments::Instrument, note::Note, primitives::SoundMaker};
-use cpal::traits::{DeviceTrait, HostTrait};
-use std::{
+#[cfg(all(feature = "playback", feature = "serde"))]
+use crate::event_log::{Event, EventLog};
+use crate::instruments::Instrument;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+#[cfg(feature = "playback")]
+use crate::{
+    effects::{Effect, EffectChain, Limiter, Tilt},
+    errors::AudioError,
+    meter::{StereoField, StereoMeter},
+    note::Note,
+    oscillators::{unseeded_rng, LowFrequencyOscillator},
+    primitives::{Parameter, Render, SoundMaker, Transport},
+};
+use alloc::boxed::Box;
+#[cfg(feature = "playback")]
+use alloc::{string::String, string::ToString, vec, vec::Vec};
+#[cfg(feature = "playback")]
+use atomic_float::AtomicF64;
+use core::{
     any::TypeId,
     fmt::Debug,
-    sync::{Arc, Mutex},
+    sync::atomic::{AtomicU64, Ordering},
+};
+#[cfg(feature = "playback")]
+use cpal::{
+    traits::{DeviceTrait, HostTrait},
+    Device, Host, HostId, StreamError, SupportedStreamConfig,
+};
+#[cfg(feature = "playback")]
+use rand::{rngs::SmallRng, SeedableRng};
+#[cfg(feature = "playback")]
+use rtrb::{Consumer, Producer, RingBuffer};
+#[cfg(feature = "playback")]
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
+/// How many commands can be waiting for the audio thread of the player at once.
+#[cfg(feature = "playback")]
+const COMMAND_QUEUE_CAPACITY: usize = 4096;
+
+/// How many notes the player can hold at once. Room for every note is allocated up front so that the audio thread
+/// never has to, and notes past this are stolen (see `Player::set_max_polyphony`).
+#[cfg(feature = "playback")]
+const VOICE_POOL_SIZE: usize = 256;
+
+/// Roughly how long changes to the master volume take to be heard in full (in seconds), which smooths out the steps of
+/// a volume slider.
+#[cfg(feature = "playback")]
+const MASTER_VOLUME_SMOOTHING: f64 = 0.01;
+
+/// How long notes that are stolen take to fade out (in seconds), which is just long enough to avoid a click.
+#[cfg(feature = "playback")]
+const STEAL_FADE_TIME: f64 = 0.005;
+
+/// How much longer than the fade itself (in seconds) stopping the player waits for the audio thread to play the fade,
+/// in case the device has stopped requesting audio.
+#[cfg(feature = "playback")]
+const STOP_TIMEOUT: f64 = 0.5;
+
+/// How long sending commands to the audio thread waits for room in the queue before holding the commands back, in
+/// case the device has stopped requesting audio.
+#[cfg(feature = "playback")]
+const SEND_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// How long the stereo meter of the player averages over (in seconds).
+#[cfg(feature = "playback")]
+const STEREO_METER_WINDOW: f64 = 0.3;
+
+/// A callback that is given every sample of the mix along with the time of the sample.
+#[cfg(feature = "playback")]
+type PostMixCallback = Box<dyn FnMut(f64, &mut f64) + Send>;
+
+/// A callback that is given every frame of the final output, such as to record or broadcast it.
+#[cfg(feature = "playback")]
+type OutputTap = Box<dyn FnMut(&[f64]) + Send>;
+
+/// Modulated parameters, each alongside the LFOs that modulate it.
+#[cfg(feature = "playback")]
+type ParameterModulations = Vec<(Arc<Parameter>, Vec<LowFrequencyOscillator>)>;
+
+/// A change to the notes in the queue of the player, which is sent to the audio thread to be applied there. Notes are
+/// created (and their instruments cloned) before they are sent, so that the audio thread only has to move them in.
+#[cfg(feature = "playback")]
+enum Command {
+    /// Plays a note, or retriggers the note with the same id on the same channel if there is one. This is also how the
+    /// audio thread hands back notes that it is done with.
+    NoteOn(Note),
+    /// Plays a note from a snapshot as it was, alongside any note with the same id on the same channel. The notes are
+    /// always cleared first, so there is room for it.
+    RestoreNote(Note),
+    /// Releases a note at the given time, or as soon as possible if the time has passed.
+    NoteOff {
+        id: u8,
+        channel_id: ChannelId,
+        time: f64,
+    },
+    /// Releases every note at the given time.
+    ReleaseAll(f64),
+    /// Bends the pitch of every note played on a channel.
+    PitchBend {
+        channel_id: ChannelId,
+        semitones: f64,
+    },
+    /// Changes the volume of a note.
+    NoteVolume {
+        id: u8,
+        channel_id: ChannelId,
+        volume: f64,
+    },
+    /// Removes every note.
+    Clear,
+    /// Replaces the chain of effects that the mixed output passes through. The chain that it replaces is handed back
+    /// in the same command.
+    Effects(EffectChain),
+    /// Replaces the limiter, in the same manner as `Effects`.
+    Limiter(Option<Limiter>),
+    /// Clears the tails of every effect.
+    ResetEffects,
+    /// Replaces the routing of every channel, in the same manner as `Effects`.
+    Routing(Box<Routing>),
+    /// Moves the strips of the mixer into a vector with more room, so that strips can be added without allocating.
+    /// The empty vector that they were in is handed back in the same command.
+    ReserveStrips(Vec<ChannelStrip>),
+    /// Adds a strip to the mixer, which always has room for it (see `ReserveStrips`).
+    AddStrip(ChannelStrip),
+    /// Replaces the insert effects of a strip, in the same manner as `Effects`.
+    Inserts { strip: usize, effects: EffectChain },
+    /// Replaces the sends of a strip, as pairs of a bus and a level, in the same manner as `Effects`.
+    Sends {
+        strip: usize,
+        sends: Vec<(usize, f64)>,
+    },
+    /// Moves the buses of the mixer into a vector with more room, in the same manner as `ReserveStrips`.
+    ReserveBuses(Vec<SendBus>),
+    /// Adds a bus to the mixer, which always has room for it (see `ReserveBuses`).
+    AddBus(SendBus),
+    /// Replaces the effects of a bus, in the same manner as `Effects`.
+    BusEffects { bus: usize, effects: EffectChain },
+    /// Sets whether a strip is ducked.
+    Ducked { strip: usize, ducked: bool },
+    /// Sets whether a bus is ducked.
+    BusDucked { bus: usize, ducked: bool },
+    /// Replaces the callback that is given every sample of the mix, in the same manner as `Effects`.
+    PostMixCallback(Option<PostMixCallback>),
+    /// Replaces the callback that is given every frame of the final output, in the same manner as `Effects`.
+    OutputTap(Option<OutputTap>),
+    /// Replaces the parameters whose pending changes are applied at the start of every block, in the same manner as
+    /// `Effects`.
+    Parameters(Vec<Arc<Parameter>>),
+    /// Replaces the LFOs modulating parameters, in the same manner as `Effects`.
+    Modulations(ParameterModulations),
+    /// Changes how many notes are rendered at once.
+    VoiceBudget(usize),
+    /// Changes how the volume of the mix is normalized.
+    Normalization(Normalization),
+    /// Changes how the ducked channels are ducked.
+    Ducking(Ducking),
+}
+
 /// An audio player that provides a thin wrapper over `SoundMaker` to enable users to pass in sound data in real time.
 /// Like `SoundMaker`, the audio will stop playing after you drop `Player`.
+#[cfg(feature = "playback")]
 pub struct Player {
-    notes: Arc<Mutex<Vec<Note>>>,
+    notes: Mutex<Vec<Note>>,
+    queue: Mutex<CommandQueue>,
+    retired: Mutex<Consumer<Command>>,
+    note_count: Arc<AtomicUsize>,
+    held_notes: Mutex<Vec<(u8, ChannelId, &'static str)>>,
+    mixer: Mutex<MixerSettings>,
+    sidechain_level: Arc<AtomicF64>,
+    voice_budget: Arc<AtomicUsize>,
+    max_polyphony: Arc<AtomicUsize>,
+    parameters: Mutex<Vec<Arc<Parameter>>>,
+    parameter_modulations: Mutex<ParameterModulations>,
+    cpu_load: Arc<AtomicF64>,
+    stereo_field: Arc<Mutex<StereoField>>,
+    pitch_bends: Mutex<HashMap<ChannelId, f64>>,
+    auto_reduce: Arc<AtomicBool>,
+    fade_start: Arc<AtomicF64>,
+    fade_time: Arc<AtomicF64>,
+    stopping: Arc<AtomicBool>,
+    drop_fade: Mutex<Option<Duration>>,
+    master_volume: Arc<AtomicF64>,
+    tilt: Arc<AtomicF64>,
+    rng: Mutex<SmallRng>,
+    #[cfg(feature = "serde")]
+    event_log: Mutex<Option<EventLog>>,
+    paused: AtomicBool,
+    latency_compensation: AtomicBool,
     sound_maker: SoundMaker,
 }
 
+/// The sending end of the commands for the audio thread of `Player`, along with the commands that are held back while
+/// the queue is full, such as while playback is paused, since the audio thread will not make room until playback is
+/// resumed.
+/// Commands are held back in the batches that they were sent in, so that a batch is still picked up all at once.
+#[cfg(feature = "playback")]
+struct CommandQueue {
+    producer: Producer<Command>,
+    backlog: VecDeque<Vec<Command>>,
+}
+
+#[cfg(feature = "playback")]
 impl Player {
     /// Creates a new player instance. This spawns an audio thread in the background. Therefore, we are able to add or remove
     /// notes as the audio plays concurrently. You can optionally specify an `amplitude_limit` to avoid blowing
-    /// out your speakers while testing.
+    /// out your speakers while testing, which sets up a `Limiter` with that ceiling (see `set_limiter`). Playback
+    /// moves over to the new default output device whenever it changes, such as when headphones are unplugged, without
+    /// losing the notes that are playing (see `SoundMaker::follow_default_device`).
     pub fn new(amplitude_limit: Option<f64>) -> Result<Self, AudioError> {
-        let notes: Arc<Mutex<Vec<Note>>> = Arc::new(Mutex::new(Vec::new()));
-        let notes_vec_clone = Arc::clone(&notes);
         let host = cpal::default_host();
         let device = host
             .default_output_device()
             .ok_or(AudioError::UnknownDevice)?;
-        let config = device.default_output_config()?;
-        let mut sound_maker = SoundMaker::new(device, config);
-        sound_maker.set_callback(move |time| {
-            let mut notes_lock = notes_vec_clone.lock().unwrap();
-            let mut mixed_output = 0.0;
-            for n in &mut *notes_lock {
-                let mut note_finished = false;
-                let sound = n.channel.sound(time, n.on, n.off, n.id, &mut note_finished);
-                mixed_output += sound;
-                if note_finished {
-                    n.active = false;
-                }
-            }
-            notes_lock.retain(|n| n.active);
-            let mut res = mixed_output * 0.2;
-            if let Some(limit) = amplitude_limit {
-                res = res.min(limit);
-            }
-            res
-        })?;
-        Ok(Self { notes, sound_maker })
+        Self::new_inner(device, None, None, amplitude_limit, None, Some(host.id()))
+    }
+
+    /// Creates a new player instance that plays on a specific output device.
+    pub fn new_with_device(
+        device: Device,
+        amplitude_limit: Option<f64>,
+    ) -> Result<Self, AudioError> {
+        Self::new_inner(device, None, None, amplitude_limit, None, None)
+    }
+
+    /// Creates a new player instance that plays on a specific output device and keeps time with `transport`. Create
+    /// one player for each device with the same `transport` to play on several devices at once.
+    pub fn new_with_transport(
+        device: Device,
+        amplitude_limit: Option<f64>,
+        transport: &Transport,
+    ) -> Result<Self, AudioError> {
+        Self::new_inner(device, None, None, amplitude_limit, Some(transport), None)
+    }
+
+    /// Creates a new player instance that plays through JACK as a client with `channels` output ports (up to 64), so
+    /// that it can be patched into a modular setup like any other JACK client. Route instruments to their own ports
+    /// with `set_output_bus`, such as to give every mixer channel a port of its own. Open a `JackMidiInput` to play
+    /// instruments from JACK MIDI. The JACK server must be running.
+    #[cfg(all(
+        feature = "jack",
+        any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd"
+        )
+    ))]
+    pub fn new_jack(channels: usize, amplitude_limit: Option<f64>) -> Result<Self, AudioError> {
+        let builder = Self::builder().host(HostId::Jack).channels(channels);
+        match amplitude_limit {
+            Some(amplitude_limit) => builder.amplitude_limit(amplitude_limit).build(),
+            None => builder.build(),
+        }
+    }
+
+    /// Constructs a new `PlayerBuilder`, which plays on the default output device of the default host unless told
+    /// otherwise.
+    pub fn builder() -> PlayerBuilder {
+        PlayerBuilder {
+            host: None,
+            device_name: None,
+            sample_rate: None,
+            channels: None,
+            buffer_size: None,
+            amplitude_limit: None,
+            transport: None,
+        }
+    }
+
+    /// Gets the names of the output devices of the default host, which can be given to `PlayerBuilder::device_name`.
+    pub fn list_output_devices() -> Result<Vec<String>, AudioError> {
+        output_device_names(&cpal::default_host())
+    }
+
+    /// Same as `list_output_devices`, but for the output devices of `host`, such as `HostId::Jack` or `HostId::Asio`
+    /// where cpal supports them.
+    pub fn list_host_output_devices(host: HostId) -> Result<Vec<String>, AudioError> {
+        output_device_names(&cpal::host_from_id(host)?)
+    }
+
+    fn new_inner(
+        device: Device,
+        config: Option<SupportedStreamConfig>,
+        buffer_size: Option<u32>,
+        amplitude_limit: Option<f64>,
+        transport: Option<&Transport>,
+        follow_default: Option<HostId>,
+    ) -> Result<Self, AudioError> {
+        let (producer, commands) = RingBuffer::new(COMMAND_QUEUE_CAPACITY);
+        // Notes and everything else that the audio thread is done with are handed back to be dropped elsewhere, since
+        // dropping them would free memory on the audio thread.
+        // Every command hands back at most one thing, and every other note that is handed back was brought in by a
+        // command or was already playing or fading out after being stolen, so the queue back cannot fill up between
+        // two calls to `drop_retired`.
+        let (retire, retired) = RingBuffer::new(COMMAND_QUEUE_CAPACITY + 2 * VOICE_POOL_SIZE);
+        let note_count = Arc::new(AtomicUsize::new(0));
+        let max_polyphony = Arc::new(AtomicUsize::new(usize::MAX));
+        let sidechain_level = Arc::new(AtomicF64::new(0.0));
+        let voice_budget = Arc::new(AtomicUsize::new(usize::MAX));
+        let cpu_load = Arc::new(AtomicF64::new(0.0));
+        let stereo_field = Arc::new(Mutex::new(StereoField::default()));
+        let auto_reduce = Arc::new(AtomicBool::new(false));
+        let fade_start = Arc::new(AtomicF64::new(f64::NAN));
+        let fade_time = Arc::new(AtomicF64::new(0.0));
+        let stopping = Arc::new(AtomicBool::new(false));
+        let master_volume = Arc::new(AtomicF64::new(1.0));
+        let tilt = Arc::new(AtomicF64::new(0.0));
+        let config = match config {
+            Some(config) => config,
+            None => device.default_output_config()?,
+        };
+        let sample_rate = config.sample_rate().0 as f64;
+        let limiter = amplitude_limit.map(|limit| {
+            let mut limiter = Limiter::new(limit);
+            limiter.prepare(sample_rate, 1);
+            limiter
+        });
+        let mut sound_maker = match transport {
+            Some(transport) => SoundMaker::new_with_transport(device, config, transport),
+            None => SoundMaker::new(device, config),
+        };
+        if let Some(frames) = buffer_size {
+            sound_maker.set_buffer_size(frames);
+        }
+        if let Some(host) = follow_default {
+            sound_maker.follow_default_device(host);
+        }
+        let mixer = MixerSettings::new(sound_maker.get_channels());
+        let engine = Engine {
+            notes: Vec::with_capacity(VOICE_POOL_SIZE),
+            stolen: Vec::with_capacity(VOICE_POOL_SIZE),
+            commands,
+            retire,
+            note_count: Arc::clone(&note_count),
+            routing: Box::new(mixer.routing()),
+            mixer: Mixer::default(),
+            normalization: Normalization::default(),
+            ducking: Ducking::default(),
+            sidechain_level: Arc::clone(&sidechain_level),
+            duck_envelope: 0.0,
+            instrument_voices: Vec::with_capacity(VOICE_POOL_SIZE),
+            voice_budget: usize::MAX,
+            rank_voices: false,
+            max_polyphony: Arc::clone(&max_polyphony),
+            parameters: Vec::new(),
+            parameter_modulations: Vec::new(),
+            cpu_load: Arc::clone(&cpu_load),
+            stereo_meter: StereoMeter::new(STEREO_METER_WINDOW, sample_rate),
+            stereo_field: Arc::clone(&stereo_field),
+            post_mix_callback: None,
+            effects: EffectChain::default(),
+            limiter,
+            output_tap: None,
+            auto_reduce: Arc::clone(&auto_reduce),
+            reduced_quality: false,
+            fade_start: Arc::clone(&fade_start),
+            fade_time: Arc::clone(&fade_time),
+            stopping: Arc::clone(&stopping),
+            master_volume: Arc::clone(&master_volume),
+            smoothed_volume: 1.0,
+            volume_smoothing: (-1.0 / (MASTER_VOLUME_SMOOTHING * sample_rate)).exp(),
+            tilt: Arc::clone(&tilt),
+            tilts: (0..sound_maker.get_channels())
+                .map(|_| {
+                    let mut tilt = Tilt::new();
+                    tilt.prepare(sample_rate, 1);
+                    tilt
+                })
+                .collect(),
+            clock: sound_maker.get_clock(),
+            sample_rate,
+            block_start: Instant::now(),
+            block_frames: 0,
+        };
+        sound_maker.set_renderer(engine)?;
+        Ok(Self {
+            // The notes as they were sent to the audio thread, which keeps its own copy. Notes are forgotten once the
+            // audio thread hands them back, so that they can be looked at without ever holding up the audio thread.
+            notes: Mutex::new(Vec::new()),
+            queue: Mutex::new(CommandQueue {
+                producer,
+                backlog: VecDeque::new(),
+            }),
+            retired: Mutex::new(retired),
+            note_count,
+            held_notes: Mutex::new(Vec::new()),
+            mixer: Mutex::new(mixer),
+            sidechain_level,
+            voice_budget,
+            max_polyphony,
+            parameters: Mutex::new(Vec::new()),
+            parameter_modulations: Mutex::new(Vec::new()),
+            cpu_load,
+            stereo_field,
+            pitch_bends: Mutex::new(HashMap::new()),
+            auto_reduce,
+            fade_start,
+            fade_time,
+            stopping,
+            drop_fade: Mutex::new(None),
+            master_volume,
+            tilt,
+            rng: Mutex::new(unseeded_rng()),
+            #[cfg(feature = "serde")]
+            event_log: Mutex::new(None),
+            paused: AtomicBool::new(false),
+            latency_compensation: AtomicBool::new(false),
+            sound_maker,
+        })
     }
 
-    /// Adds a note to the queue.
+    /// Adds a note to the queue. Adding a note that is already in the queue (the same note id on the same channel)
+    /// retriggers it, whether it is still held or ringing out after being released.
     pub fn add_note(&self, voice: Voice) {
         self.add_notes(vec![voice]);
     }
@@ -61,79 +433,2287 @@ impl Player {
 
     /// Adds multiple notes to the queue in bulk.
     pub fn add_notes(&self, voices: Vec<Voice>) {
-        let mut notes = self.notes.lock().unwrap();
-        for voice in voices {
-            let note_found = notes
-                .iter_mut()
-                .find(|n| n.id == voice.note_id && n.instrument_id == voice.instrument_id);
-            if let Some(found_note) = note_found {
-                if found_note.off >= found_note.on {
-                    found_note.on = self.sound_maker.get_time();
-                    found_note.active = true;
-                }
-            } else {
-                let new_note = self.sound_maker.create_note(
-                    voice.note_id,
-                    voice.instrument_id,
-                    dyn_clone::clone_box(&*voice.instrument),
-                );
-                notes.push(new_note);
-            }
+        self.add_notes_at(self.sound_maker.get_time(), voices);
+    }
+
+    /// Adds multiple notes to the queue that start playing at `time` (see `get_time`) instead of immediately, to the
+    /// sample. Notes scheduled in the past start immediately. A note that is scheduled while the same note is playing
+    /// or already scheduled is played as a note of its own, which releases the note before it once it starts, so that
+    /// the note before it rings out until then. With latency compensation on (see `set_latency_compensation`), the
+    /// notes are played early by the output latency so that they are heard at `time`.
+    pub fn schedule_notes(&self, time: f64, voices: Vec<Voice>) {
+        self.add_notes_at(self.scheduled_time(time), voices);
+    }
+
+    /// Releases multiple notes in the queue at `time` (see `get_time`) instead of immediately, in the same manner as
+    /// `schedule_notes`.
+    pub fn schedule_note_releases(&self, time: f64, voices: Vec<Voice>) {
+        self.remove_notes_at(self.scheduled_time(time), voices);
+    }
+
+    /// Sets whether times given to `schedule_notes` and `schedule_note_releases` are moved earlier by the output
+    /// latency of the device (see `get_output_latency`), which is off by default. Turn this on when the times are
+    /// meant to be heard at that moment, such as to line up notes with the frames of a video or with the inputs of a
+    /// rhythm game.
+    pub fn set_latency_compensation(&self, enabled: bool) {
+        self.latency_compensation.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Gets the time (in seconds) between a frame being rendered and it being heard, as reported by the sound driver.
+    pub fn get_output_latency(&self) -> f64 {
+        self.sound_maker.get_output_latency()
+    }
+
+    fn scheduled_time(&self, time: f64) -> f64 {
+        let time = if self.latency_compensation.load(Ordering::Relaxed) {
+            time - self.get_output_latency()
+        } else {
+            time
+        };
+        time.max(self.sound_maker.get_time())
+    }
+
+    fn add_notes_at(&self, time: f64, voices: Vec<Voice>) {
+        let commands = voices
+            .iter()
+            .map(|voice| self.note_on(time, voice))
+            .collect();
+        self.send_all(commands);
+    }
+
+    /// Creates the command that plays `voice` at `time`, and logs it.
+    fn note_on(&self, time: f64, voice: &Voice) -> Command {
+        // The instrument is cloned here even if the note turns out to be retriggered, so that the audio thread
+        // never has to.
+        let mut instrument = dyn_clone::clone_box(&*voice.instrument);
+        instrument.vary(&mut self.rng.lock().unwrap());
+        if let Some(semitones) = self.pitch_bends.lock().unwrap().get(&voice.channel_id) {
+            instrument.set_pitch_bend(*semitones);
         }
+        instrument.prepare(self.sound_maker.get_sample_rate());
+        let mut new_note = self.sound_maker.create_note(
+            voice.note_id,
+            voice.channel_id,
+            instrument,
+            voice.priority,
+            voice.velocity,
+        );
+        new_note.on = time;
+        new_note.volume = voice.volume;
+        let mut held_notes = self.held_notes.lock().unwrap();
+        let held = (voice.note_id, voice.channel_id, voice.instrument_name);
+        if !held_notes.contains(&held) {
+            held_notes.push(held);
+        }
+        #[cfg(feature = "serde")]
+        self.log_note_on(time, voice);
+        Command::NoteOn(new_note)
     }
 
     /// Removes multiple notes from the queue in bulk.
     pub fn remove_notes(&self, voices: Vec<Voice>) {
+        self.remove_notes_at(self.sound_maker.get_time(), voices);
+    }
+
+    fn remove_notes_at(&self, time: f64, voices: Vec<Voice>) {
+        let commands = voices
+            .iter()
+            .map(|voice| self.note_off(time, voice))
+            .collect();
+        self.send_all(commands);
+    }
+
+    /// Creates the command that releases `voice` at `time`, and logs it.
+    fn note_off(&self, time: f64, voice: &Voice) -> Command {
+        self.held_notes
+            .lock()
+            .unwrap()
+            .retain(|(id, channel_id, _)| *id != voice.note_id || *channel_id != voice.channel_id);
+        #[cfg(feature = "serde")]
+        self.log_note_off(time, voice.instrument_name, voice.note_id);
+        Command::NoteOff {
+            id: voice.note_id,
+            channel_id: voice.channel_id,
+            time,
+        }
+    }
+
+    /// Makes a burst of changes to the notes in the queue, such as every change from a tick of a sequencer, which the
+    /// audio thread applies all at once so that no block is played with only some of the changes applied. Every change
+    /// is made at the time that the transaction starts, and the changes are sent when `f` returns. While playback is
+    /// paused, the changes are held back until it is resumed, and are still applied all at once. A transaction with
+    /// more changes than fit in the queue of the audio thread at once (4096) is applied in parts instead.
+    pub fn transaction<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Transaction) -> R,
+    {
+        let mut transaction = Transaction {
+            player: self,
+            time: self.get_time(),
+            commands: Vec::new(),
+        };
+        let result = f(&mut transaction);
+        self.send_all(transaction.commands);
+        result
+    }
+
+    /// Releases every note in the queue, letting them ring out according to their envelopes.
+    pub fn release_all_notes(&self) {
+        let time = self.sound_maker.get_time();
+        self.send(Command::ReleaseAll(time));
+        #[cfg(feature = "serde")]
+        for (id, _, instrument_name) in self.held_notes.lock().unwrap().drain(..) {
+            self.log_note_off(time, instrument_name, id);
+        }
+        #[cfg(not(feature = "serde"))]
+        self.held_notes.lock().unwrap().clear();
+    }
+
+    /// Silences every note in the queue straight away and removes them, without letting them ring out, and clears the
+    /// tails of the effects (see `reset_effects`). Use this as an all-notes-off when notes get stuck.
+    pub fn panic(&self) {
+        #[cfg(feature = "serde")]
+        let time = self.sound_maker.get_time();
+        self.send_all(vec![Command::Clear, Command::ResetEffects]);
+        #[cfg(feature = "serde")]
+        for (id, _, instrument_name) in self.held_notes.lock().unwrap().drain(..) {
+            self.log_note_off(time, instrument_name, id);
+        }
+        #[cfg(not(feature = "serde"))]
+        self.held_notes.lock().unwrap().clear();
+    }
+
+    /// Drops everything that the audio thread has handed back, and forgets the notes among it.
+    fn drop_retired(&self) {
+        let mut retired = self.retired.lock().unwrap();
         let mut notes = self.notes.lock().unwrap();
-        for voice in voices {
-            let note_found = notes
-                .iter_mut()
-                .find(|n| n.id == voice.note_id && n.instrument_id == voice.instrument_id);
-            if let Some(found_note) = note_found {
-                if found_note.off <= found_note.on {
-                    found_note.off = self.sound_maker.get_time();
+        while let Ok(command) = retired.pop() {
+            if let Command::NoteOn(note) = command {
+                notes.retain(|n| n.serial != note.serial);
+            }
+        }
+    }
+
+    /// Sends a command to the audio thread, in the same manner as `send_all`.
+    fn send(&self, command: Command) {
+        self.send_all(vec![command]);
+    }
+
+    /// Sends commands to the audio thread, which picks all of them up at once as long as they fit in the queue,
+    /// waiting a short while (see `SEND_TIMEOUT`) for room in the queue if it is full. Commands that still do not fit,
+    /// or that do not fit while playback is paused, are held back until the next command is sent or playback is
+    /// resumed instead, so that no command is ever lost and sending never blocks for long.
+    fn send_all(&self, commands: Vec<Command>) {
+        self.drop_retired();
+        let now = self.sound_maker.get_time();
+        {
+            let mut notes = self.notes.lock().unwrap();
+            for command in &commands {
+                record_command(&mut notes, command, now);
+            }
+        }
+        let mut queue = self.queue.lock().unwrap();
+        queue.backlog.push_back(commands);
+        self.flush(&mut queue);
+    }
+
+    /// Writes the commands that are held back to the queue in order, waiting up to `SEND_TIMEOUT` for room in the queue
+    /// unless playback is paused, and leaving whatever does not fit held back.
+    fn flush(&self, queue: &mut CommandQueue) {
+        let deadline = Instant::now() + SEND_TIMEOUT;
+        while let Some(commands) = queue.backlog.front_mut() {
+            let n = commands.len().min(COMMAND_QUEUE_CAPACITY);
+            if queue.producer.slots() < n {
+                if self.is_paused() || Instant::now() >= deadline {
+                    return;
                 }
+                self.drop_retired();
+                thread::yield_now();
+                continue;
+            }
+            // Whatever the audio thread hands back is dropped before every write, so that the queue back has room for
+            // everything that the write can hand back.
+            self.drop_retired();
+            if let Ok(chunk) = queue.producer.write_chunk_uninit(n) {
+                chunk.fill_from_iter(commands.drain(..n));
             }
+            if commands.is_empty() {
+                queue.backlog.pop_front();
+            }
+        }
+    }
+
+    /// Sets the log that every note played and released by the player is written to, or stops logging if `None`.
+    /// Events written to the log from then on are timestamped with the time of the player, including events from
+    /// clones of the log that are given to sequencers. Errors writing the log are kept for `EventLog::take_error`.
+    #[cfg(feature = "serde")]
+    pub fn set_event_log(&self, event_log: Option<EventLog>) {
+        if let Some(event_log) = &event_log {
+            event_log.set_clock(self.sound_maker.get_clock());
+        }
+        *self.event_log.lock().unwrap() = event_log;
+    }
+
+    /// Changes the value of `parameter`, and writes the change to the event log under `name` if there is one (see
+    /// `set_event_log`).
+    #[cfg_attr(not(feature = "serde"), allow(unused_variables))]
+    pub fn set_parameter(&self, name: &str, parameter: &Parameter, value: f64) {
+        parameter.set(value);
+        #[cfg(feature = "serde")]
+        self.log(Event::ParameterChange {
+            name: name.to_string(),
+            value,
+        });
+    }
+
+    #[cfg(feature = "serde")]
+    fn log(&self, event: Event) {
+        if let Some(event_log) = &*self.event_log.lock().unwrap() {
+            event_log.queue(event);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn log_at(&self, time: f64, event: Event) {
+        if let Some(event_log) = &*self.event_log.lock().unwrap() {
+            event_log.queue_at(time, event);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn log_note_on(&self, time: f64, voice: &Voice) {
+        self.log_at(
+            time,
+            Event::NoteOn {
+                instrument: voice.instrument_name.to_string(),
+                note_id: voice.note_id,
+                velocity: voice.velocity,
+            },
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    fn log_note_off(&self, time: f64, instrument_name: &str, note_id: u8) {
+        self.log_at(
+            time,
+            Event::NoteOff {
+                instrument: instrument_name.to_string(),
+                note_id,
+            },
+        );
+    }
+
+    /// Pauses playback, freezing every note in the queue where it is until playback is resumed. The time of the player
+    /// stops while paused, so envelopes pick up where they left off instead of jumping ahead.
+    pub fn pause(&self) -> Result<(), AudioError> {
+        self.sound_maker.pause()?;
+        self.paused.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Resumes playback after a call to `pause`, sending the changes that were held back while paused.
+    pub fn resume(&self) -> Result<(), AudioError> {
+        self.sound_maker.resume()?;
+        self.paused.store(false, Ordering::Relaxed);
+        self.flush(&mut self.queue.lock().unwrap());
+        Ok(())
+    }
+
+    /// Checks whether playback is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Gradually lowers the volume of the player to silence over `seconds`, after which every note in the queue is
+    /// removed and the volume is restored so that the player can be used again. A fade of 0.0 seconds or less mutes
+    /// the player straight away.
+    pub fn fade_out(&self, seconds: f64) {
+        self.fade_time.store(seconds, Ordering::Relaxed);
+        self.fade_start
+            .store(self.sound_maker.get_time(), Ordering::Relaxed);
+    }
+
+    /// Fades the whole mix out to silence over `fade`, including the tails of effects, and then stops playback, which
+    /// blocks until the fade has been played. Stopping a player that is paused skips the fade. Use this instead of
+    /// dropping the player to avoid cutting the sound off with a click, or see `set_drop_fade`.
+    pub fn stop(self, fade: Duration) {
+        self.set_drop_fade(Some(fade));
+    }
+
+    /// Sets the fade that is played when the player is dropped, in the same manner as `stop`, or turns it off if
+    /// `None`, which is the default. A few milliseconds is enough to avoid a click.
+    pub fn set_drop_fade(&self, fade: Option<Duration>) {
+        *self.drop_fade.lock().unwrap() = fade;
+    }
+
+    /// Fades the whole mix out to silence over `fade` and waits until the fade has been heard.
+    fn fade_to_silence(&self, fade: Duration) {
+        if self.is_paused() {
+            return;
+        }
+        let seconds = fade.as_secs_f64().max(f64::EPSILON);
+        self.stopping.store(true, Ordering::Relaxed);
+        self.fade_out(seconds);
+        let end = self.sound_maker.get_time() + seconds;
+        let deadline = Instant::now() + Duration::from_secs_f64(seconds + STOP_TIMEOUT);
+        while self.sound_maker.get_time() < end && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
         }
+        thread::sleep(Duration::from_secs_f64(self.get_output_latency()));
+    }
+
+    /// Sets the volume that the whole mix is multiplied by, which is 1.0 by default. Unlike `set_normalization`, this
+    /// is applied after the master effects, and can be changed while audio is playing, such as from a volume slider.
+    /// Changes are smoothed over about 10 ms so that moving the slider does not crackle.
+    pub fn set_master_volume(&self, volume: f64) {
+        self.master_volume.store(volume, Ordering::Relaxed);
+    }
+
+    /// Gets the volume that the whole mix is multiplied by.
+    pub fn get_master_volume(&self) -> f64 {
+        self.master_volume.load(Ordering::Relaxed)
+    }
+
+    /// Tilts the tone of the whole mix between dark and bright (in decibels, see `Tilt`), which is 0.0 by default. Like
+    /// `set_master_volume`, this is applied after the master effects, and can be changed while audio is playing, such
+    /// as from a tone knob.
+    pub fn set_tilt(&self, tilt: f64) {
+        self.tilt.store(tilt, Ordering::Relaxed);
+    }
+
+    /// Gets the tilt set by `set_tilt`.
+    pub fn get_tilt(&self) -> f64 {
+        self.tilt.load(Ordering::Relaxed)
+    }
+
+    /// Routes every note played by instrument `I` to the given output channels of the device (starting from 0),
+    /// such as sending a click track to outputs 3 and 4 of a multi-out audio interface. Output channels that the
+    /// device does not have are ignored. By default, notes are played on every output channel. The left and right
+    /// channels of stereo instruments alternate between the given output channels, unless only one is given.
+    pub fn set_output_bus<I>(&self, outputs: Vec<usize>)
+    where
+        I: Instrument + 'static,
+    {
+        self.set_channel_output_bus(ChannelId::of::<I>(), outputs);
+    }
+
+    /// Same as `set_output_bus`, but for every note played on `channel`.
+    pub fn set_channel_output_bus(&self, channel: ChannelId, outputs: Vec<usize>) {
+        self.update_mixer(|mixer| {
+            mixer.output_buses.insert(channel, outputs);
+            Vec::new()
+        });
+    }
+
+    /// Routes instrument `I` back to every output channel of the device.
+    pub fn clear_output_bus<I>(&self)
+    where
+        I: Instrument + 'static,
+    {
+        self.clear_channel_output_bus(ChannelId::of::<I>());
+    }
+
+    /// Routes `channel` back to every output channel of the device.
+    pub fn clear_channel_output_bus(&self, channel: ChannelId) {
+        self.update_mixer(|mixer| {
+            mixer.output_buses.remove(&channel);
+            Vec::new()
+        });
+    }
+
+    /// Adds instrument `I` to a mixer group such as "drums" or "melodic", creating the group if it does not exist yet,
+    /// so that every note played by the instrument is affected by the volume, mute, and solo of the group. An
+    /// instrument belongs to at most one group, so this moves the instrument out of any group it was previously in.
+    pub fn set_group<I>(&self, group: &str)
+    where
+        I: Instrument + 'static,
+    {
+        self.set_channel_group(ChannelId::of::<I>(), group);
+    }
+
+    /// Same as `set_group`, but for every note played on `channel`.
+    pub fn set_channel_group(&self, channel: ChannelId, group: &str) {
+        self.update_mixer(|mixer| {
+            mixer.groups.entry(group.to_string()).or_default();
+            mixer.members.insert(channel, group.to_string());
+            Vec::new()
+        });
+    }
+
+    /// Removes instrument `I` from its mixer group.
+    pub fn clear_group<I>(&self)
+    where
+        I: Instrument + 'static,
+    {
+        self.clear_channel_group(ChannelId::of::<I>());
+    }
+
+    /// Removes `channel` from its mixer group.
+    pub fn clear_channel_group(&self, channel: ChannelId) {
+        self.update_mixer(|mixer| {
+            mixer.members.remove(&channel);
+            Vec::new()
+        });
+    }
+
+    /// Sets the volume of a mixer group, which is 1.0 by default. Does nothing if the group does not exist.
+    pub fn set_group_volume(&self, group: &str, volume: f64) {
+        self.update_mixer(|mixer| {
+            if let Some(group) = mixer.groups.get_mut(group) {
+                group.volume = volume;
+            }
+            Vec::new()
+        });
+    }
+
+    /// Mutes or unmutes a mixer group. Does nothing if the group does not exist.
+    pub fn set_group_mute(&self, group: &str, mute: bool) {
+        self.update_mixer(|mixer| {
+            if let Some(group) = mixer.groups.get_mut(group) {
+                group.mute = mute;
+            }
+            Vec::new()
+        });
+    }
+
+    /// Solos or unsolos a mixer group. While any group is soloed, only the instruments in soloed groups are heard.
+    /// Does nothing if the group does not exist.
+    pub fn set_group_solo(&self, group: &str, solo: bool) {
+        self.update_mixer(|mixer| {
+            if let Some(group) = mixer.groups.get_mut(group) {
+                group.solo = solo;
+            }
+            Vec::new()
+        });
+    }
+
+    /// Solos a mixer group and unsolos every other group in one go, so that only the given group is heard. Does
+    /// nothing if the group does not exist.
+    pub fn solo_group_exclusive(&self, group: &str) {
+        self.update_mixer(|mixer| {
+            if mixer.groups.contains_key(group) {
+                for (name, g) in mixer.groups.iter_mut() {
+                    g.solo = name == group;
+                }
+            }
+            Vec::new()
+        });
+    }
+
+    /// Unsolos every mixer group.
+    pub fn clear_solo(&self) {
+        self.update_mixer(|mixer| {
+            for group in mixer.groups.values_mut() {
+                group.solo = false;
+            }
+            Vec::new()
+        });
+    }
+
+    /// Gets the names of every mixer group.
+    pub fn get_groups(&self) -> HashSet<String> {
+        self.mixer.lock().unwrap().groups.keys().cloned().collect()
+    }
+
+    /// Sets how the volume of the mix is normalized as more notes are played, which is `Normalization::Fixed(0.2)` by
+    /// default.
+    pub fn set_normalization(&self, normalization: Normalization) {
+        self.send(Command::Normalization(normalization));
+    }
+
+    /// Sets how deep and how fast channels are ducked under audio that plays outside of the player (see
+    /// `set_ducked`), which is `Ducking::default()` by default.
+    pub fn set_ducking(&self, ducking: Ducking) {
+        self.send(Command::Ducking(ducking));
+    }
+
+    /// Sets whether instrument `I` is ducked under audio that plays outside of the player, such as lowering the music
+    /// of a game while dialogue plays. The instrument is ducked as a whole after its insert effects, so what it sends
+    /// to send buses is ducked as well, but the tails of the effects on the buses are not (see `set_bus_ducked`).
+    pub fn set_ducked<I>(&self, ducked: bool)
+    where
+        I: Instrument + 'static,
+    {
+        self.set_channel_ducked(ChannelId::of::<I>(), ducked);
+    }
+
+    /// Same as `set_ducked`, but for every note played on `channel`.
+    pub fn set_channel_ducked(&self, channel: ChannelId, ducked: bool) {
+        self.update_mixer(|mixer| {
+            let mut commands = Vec::new();
+            if ducked || mixer.strips.contains_key(&channel) {
+                let strip = mixer.strip(channel, &mut commands);
+                commands.push(Command::Ducked { strip, ducked });
+            }
+            commands
+        });
+    }
+
+    /// Sets whether a send bus is ducked in the same manner as `set_ducked`, after its effects, so that the tails of
+    /// a reverb or a delay are ducked along with the instruments that feed it. Does nothing if the bus does not exist.
+    pub fn set_bus_ducked(&self, bus: &str, ducked: bool) {
+        self.update_mixer(|mixer| match mixer.buses.get(bus) {
+            Some(&bus) => vec![Command::BusDucked { bus, ducked }],
+            None => Vec::new(),
+        });
+    }
+
+    /// Reports whether audio is playing outside of the player, which fully ducks the ducked channels while `true`.
+    /// This is the same as feeding a sidechain level of 1.0 or 0.0 to `set_sidechain_level`.
+    pub fn set_external_audio(&self, active: bool) {
+        self.set_sidechain_level(if active { 1.0 } else { 0.0 });
+    }
+
+    /// Feeds the level of audio that plays outside of the player (from 0.0 for silence to 1.0), such as the
+    /// envelope of a dialogue track, which ducks the ducked channels in proportion. The level can be fed from any
+    /// thread as often as it changes, and is smoothed by the attack and release of `Ducking`.
+    pub fn set_sidechain_level(&self, level: f64) {
+        self.sidechain_level
+            .store(level.clamp(0.0, 1.0), Ordering::Relaxed);
+    }
+
+    /// Gets the time of the player (in seconds), which is the time that sequencers constructed with `start_at` follow.
+    pub fn get_time(&self) -> f64 {
+        self.sound_maker.get_time()
+    }
+
+    /// Gets the sample rate of the device.
+    pub fn get_sample_rate(&self) -> f64 {
+        self.sound_maker.get_sample_rate()
+    }
+
+    /// Gets the number of output channels of the device.
+    pub fn get_output_channels(&self) -> usize {
+        self.sound_maker.get_channels()
     }
 
     /// Gets the number of notes currently in the queue.
     pub fn get_simultaneous_notes(&self) -> usize {
-        self.notes.lock().unwrap().len()
+        self.note_count.load(Ordering::Relaxed)
     }
-}
 
-/// Signifies a note to be passed into `Player`.
-#[derive(Clone)]
-pub struct Voice {
-    instrument: Box<dyn Instrument>,
-    instrument_id: TypeId,
-    note_id: u8,
-    instrument_name: &'static str,
-}
+    /// Limits the number of notes that are rendered at once, or removes the limit if `None`. Once there are more notes
+    /// in the queue than the budget, the notes with the lowest priority (and the oldest notes among notes with the
+    /// same priority) become virtual. Virtual notes are not heard but keep advancing (see `Instrument::advance`), and
+    /// are revived once there is headroom in the budget again. Virtual notes that are released are discarded.
+    pub fn set_voice_budget(&self, budget: Option<usize>) {
+        let budget = budget.unwrap_or(usize::MAX);
+        self.voice_budget.store(budget, Ordering::Relaxed);
+        self.send(Command::VoiceBudget(budget));
+    }
 
-impl Debug for Voice {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Voice")
-            .field("note_id", &self.note_id)
-            .field("instrument_name", &self.instrument_name)
-            .finish()
+    /// Limits the number of notes in the queue, which is 256 by default and cannot be raised past that. Unlike
+    /// `set_voice_budget`, notes over the limit are stolen rather than made virtual, so the queue never grows past it.
+    /// Notes that have been released are stolen first (starting from the note that was released first), followed by
+    /// the notes with the lowest priority, then the quietest notes, and then the oldest notes. Stolen notes fade out
+    /// over a few milliseconds rather than being cut off, and are not written to the event log as released.
+    pub fn set_max_polyphony(&self, max_polyphony: usize) {
+        self.max_polyphony.store(max_polyphony, Ordering::Relaxed);
     }
-}
 
-impl Voice {
-    /// Create a new `Voice` instance.
-    pub fn new<I>(instrument: I, note_id: u8) -> Self
+    /// Adds a parameter to the player so that changes scheduled with `Parameter::set_at_next_block` take effect at the
+    /// start of the next block that is played.
+    pub fn add_parameter(&self, parameter: Arc<Parameter>) {
+        let mut parameters = self.parameters.lock().unwrap();
+        parameters.push(parameter);
+        self.send(Command::Parameters(parameters.clone()));
+    }
+
+    /// Removes a parameter that was previously added to the player.
+    pub fn remove_parameter(&self, parameter: &Arc<Parameter>) {
+        let mut parameters = self.parameters.lock().unwrap();
+        parameters.retain(|p| !Arc::ptr_eq(p, parameter));
+        self.send(Command::Parameters(parameters.clone()));
+    }
+
+    /// Modulates `parameter` with a LFO, such as to sweep the cutoff of a filter, the feedback of a delay, or the mix of
+    /// a reverb, so that effects can be performed rather than left static. Values of effects are bound to parameters
+    /// with `Automated`. The output of the LFO is added to the value of the parameter, and multiple LFOs on the same
+    /// parameter are summed. Use `LowFrequencyOscillator::following` to keep the LFO in step with the tempo of the
+    /// transport.
+    pub fn modulate_parameter(&self, parameter: &Arc<Parameter>, lfo: LowFrequencyOscillator) {
+        let mut parameter_modulations = self.parameter_modulations.lock().unwrap();
+        match parameter_modulations
+            .iter_mut()
+            .find(|(p, _)| Arc::ptr_eq(p, parameter))
+        {
+            Some((_, lfos)) => lfos.push(lfo),
+            None => parameter_modulations.push((Arc::clone(parameter), vec![lfo])),
+        }
+        self.send(Command::Modulations(parameter_modulations.clone()));
+    }
+
+    /// Removes every LFO modulating `parameter`, returning it to its unmodulated value.
+    pub fn clear_parameter_modulation(&self, parameter: &Arc<Parameter>) {
+        let mut parameter_modulations = self.parameter_modulations.lock().unwrap();
+        parameter_modulations.retain(|(p, _)| !Arc::ptr_eq(p, parameter));
+        self.send(Command::Modulations(parameter_modulations.clone()));
+    }
+
+    /// Gets the sum of the approximate cost of every note in the queue, as reported by `Instrument::cost`.
+    pub fn get_voice_cost(&self) -> f64 {
+        self.drop_retired();
+        self.notes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|n| n.channel.cost())
+            .sum()
+    }
+
+    /// Gets an estimate of how much of the available time the audio thread spends rendering audio, where 1.0 or more
+    /// means that it can no longer keep up and playback starts to stutter.
+    pub fn get_cpu_load(&self) -> f64 {
+        self.cpu_load.load(Ordering::Relaxed)
+    }
+
+    /// Accepts a callback that is given every error reported by the sound driver while audio is playing, such as the
+    /// device being unplugged, so that the application can tell the user. Errors are printed to standard error if no
+    /// callback is set.
+    pub fn on_error<F>(&self, callback: F)
+    where
+        F: FnMut(StreamError) + Send + 'static,
+    {
+        self.sound_maker.set_error_callback(callback);
+    }
+
+    /// Gets a reading of how well playback is keeping up, such as to detect glitching.
+    pub fn stats(&self) -> PlayerStats {
+        PlayerStats {
+            xruns: self.sound_maker.get_xruns(),
+            cpu_load: self.get_cpu_load(),
+            output_latency: self.get_output_latency(),
+            notes: self.get_simultaneous_notes(),
+        }
+    }
+
+    /// Gets an estimate of how much of the available time is left for rendering more notes.
+    pub fn get_cpu_headroom(&self) -> f64 {
+        (1.0 - self.get_cpu_load()).max(0.0)
+    }
+
+    /// Bends the pitch of every note of instrument `I`, including the notes that are already playing, by a number of
+    /// semitones, like the pitch wheel of a controller. Notes played afterwards keep the bend until it is set back to
+    /// 0.0, which overrides any bend given through `Voice::with_pitch_bend`.
+    pub fn set_pitch_bend<I>(&self, semitones: f64)
     where
         I: Instrument + 'static,
     {
-        let instrument_name = I::get_name();
-        let instrument_id = TypeId::of::<I>();
-        Self {
-            instrument: Box::new(instrument),
-            instrument_id,
-            note_id,
-            instrument_name,
+        self.set_channel_pitch_bend(ChannelId::of::<I>(), semitones);
+    }
+
+    /// Same as `set_pitch_bend`, but for every note played on `channel`.
+    pub fn set_channel_pitch_bend(&self, channel: ChannelId, semitones: f64) {
+        let mut pitch_bends = self.pitch_bends.lock().unwrap();
+        if semitones == 0.0 {
+            pitch_bends.remove(&channel);
+        } else {
+            pitch_bends.insert(channel, semitones);
+        }
+        self.send(Command::PitchBend {
+            channel_id: channel,
+            semitones,
+        });
+    }
+
+    /// Sets a callback that is given every sample of the mix, on every output channel, after the notes have been mixed
+    /// but before the effects (see `set_effects`), the fade, and the limiter are applied. This lets custom DSP or test
+    /// signals (such as sine sweeps and calibration tones) be added to the output without giving up on `Player` for
+    /// `SoundMaker`. The callback runs on the audio thread, so it should not block. Replaces any previous callback.
+    pub fn set_post_mix_callback<F>(&self, callback: F)
+    where
+        F: FnMut(f64, &mut f64) + Send + 'static,
+    {
+        self.send(Command::PostMixCallback(Some(Box::new(callback))));
+    }
+
+    /// Removes the callback set by `set_post_mix_callback`.
+    pub fn clear_post_mix_callback(&self) {
+        self.send(Command::PostMixCallback(None));
+    }
+
+    /// Replaces the chain of insert effects that every note played by instrument `I` passes through, in order, such as
+    /// an `EQ3` to thin out a bass. The notes of the instrument are mixed together before the effects, so an effect
+    /// processes the instrument as a whole rather than every note on its own.
+    pub fn set_insert_effects<I>(&self, effects: Vec<Box<dyn Effect>>)
+    where
+        I: Instrument + 'static,
+    {
+        self.set_channel_insert_effects(ChannelId::of::<I>(), effects);
+    }
+
+    /// Same as `set_insert_effects`, but for every note played on `channel`.
+    pub fn set_channel_insert_effects(&self, channel: ChannelId, effects: Vec<Box<dyn Effect>>) {
+        let effects = self.effect_chain(effects);
+        self.update_mixer(|mixer| {
+            let mut commands = Vec::new();
+            let strip = mixer.strip(channel, &mut commands);
+            commands.push(Command::Inserts { strip, effects });
+            commands
+        });
+    }
+
+    /// Removes every insert effect of instrument `I`.
+    pub fn clear_insert_effects<I>(&self)
+    where
+        I: Instrument + 'static,
+    {
+        self.set_insert_effects::<I>(Vec::new());
+    }
+
+    /// Replaces the chain of effects of a send bus such as "reverb", creating the bus if it does not exist yet. Every
+    /// instrument can send some of its output to the bus (see `set_send`), so that a single effect is shared by
+    /// several instruments. The bus should usually only output the effect, such as a `ConvolutionReverb` with a `mix`
+    /// of 1.0, since the dry signal of the instruments is already in the mix.
+    pub fn set_bus_effects(&self, bus: &str, effects: Vec<Box<dyn Effect>>) {
+        let effects = self.effect_chain(effects);
+        self.update_mixer(|mixer| {
+            let mut commands = Vec::new();
+            if let Some(&bus) = mixer.buses.get(bus) {
+                commands.push(Command::BusEffects { bus, effects });
+                return commands;
+            }
+            let index = match mixer.free_buses.pop() {
+                Some(index) => {
+                    commands.push(Command::BusEffects {
+                        bus: index,
+                        effects,
+                    });
+                    index
+                }
+                None => {
+                    if mixer.bus_count == mixer.bus_capacity {
+                        mixer.bus_capacity = (mixer.bus_capacity * 2).max(MIXER_RESERVE);
+                        commands.push(Command::ReserveBuses(Vec::with_capacity(
+                            mixer.bus_capacity,
+                        )));
+                    }
+                    commands.push(Command::AddBus(SendBus::new(effects, mixer.channels)));
+                    mixer.bus_count += 1;
+                    mixer.bus_count - 1
+                }
+            };
+            mixer.buses.insert(bus.to_string(), index);
+            mixer.resolve_sends(bus, &mut commands);
+            commands
+        });
+    }
+
+    /// Removes a send bus along with its effects. Sends to the bus are kept, but are silent until the bus is created
+    /// again.
+    pub fn remove_bus(&self, bus: &str) {
+        self.update_mixer(|mixer| {
+            let mut commands = Vec::new();
+            if let Some(index) = mixer.buses.remove(bus) {
+                mixer.free_buses.push(index);
+                commands.push(Command::BusEffects {
+                    bus: index,
+                    effects: EffectChain::default(),
+                });
+                commands.push(Command::BusDucked {
+                    bus: index,
+                    ducked: false,
+                });
+                mixer.resolve_sends(bus, &mut commands);
+            }
+            commands
+        });
+    }
+
+    /// Gets the names of every send bus.
+    pub fn get_buses(&self) -> HashSet<String> {
+        self.mixer.lock().unwrap().buses.keys().cloned().collect()
+    }
+
+    /// Sends instrument `I` to a send bus at `level`, from 0.0 (nothing) to 1.0 (its full output), after its insert
+    /// effects and its mixer group.
+    pub fn set_send<I>(&self, bus: &str, level: f64)
+    where
+        I: Instrument + 'static,
+    {
+        self.set_channel_send(ChannelId::of::<I>(), bus, level);
+    }
+
+    /// Same as `set_send`, but for every note played on `channel`.
+    pub fn set_channel_send(&self, channel: ChannelId, bus: &str, level: f64) {
+        self.update_mixer(|mixer| {
+            let mut commands = Vec::new();
+            mixer.strip(channel, &mut commands);
+            if let Some(strip) = mixer.strips.get_mut(&channel) {
+                strip.sends.insert(bus.to_string(), level);
+            }
+            mixer.resolve_sends(bus, &mut commands);
+            commands
+        });
+    }
+
+    /// Stops sending instrument `I` to a send bus.
+    pub fn clear_send<I>(&self, bus: &str)
+    where
+        I: Instrument + 'static,
+    {
+        self.clear_channel_send(ChannelId::of::<I>(), bus);
+    }
+
+    /// Stops sending `channel` to a send bus.
+    pub fn clear_channel_send(&self, channel: ChannelId, bus: &str) {
+        self.update_mixer(|mixer| {
+            let mut commands = Vec::new();
+            if let Some(strip) = mixer.strips.get_mut(&channel) {
+                if strip.sends.remove(bus).is_some() {
+                    commands.push(Command::Sends {
+                        strip: strip.index,
+                        sends: mixer.sends(channel),
+                    });
+                }
+            }
+            commands
+        });
+    }
+
+    /// Replaces the chain of effects that the mixed output passes through, in order. The effects are applied after the
+    /// insert effects and send buses, the callback set by `set_post_mix_callback`, and before the fade and the
+    /// limiter. Add a `Compressor` to even out busy sequences.
+    pub fn set_effects(&self, effects: Vec<Box<dyn Effect>>) {
+        self.send(Command::Effects(self.effect_chain(effects)));
+    }
+
+    /// Changes the settings of the mixer with `f`, and sends the commands that it returns to the audio thread along
+    /// with the routing that results from the new settings, all at once. The settings stay locked until the commands
+    /// are sent, so that changes from several threads reach the audio thread in the order that they are made.
+    fn update_mixer<F>(&self, f: F)
+    where
+        F: FnOnce(&mut MixerSettings) -> Vec<Command>,
+    {
+        let mut mixer = self.mixer.lock().unwrap();
+        let mut commands = f(&mut mixer);
+        commands.push(Command::Routing(Box::new(mixer.routing())));
+        self.send_all(commands);
+    }
+
+    /// Sets up a chain of effects for every output channel, ready to be handed to the audio thread.
+    fn effect_chain(&self, effects: Vec<Box<dyn Effect>>) -> EffectChain {
+        EffectChain::new(effects, self.get_output_channels(), self.get_sample_rate())
+    }
+
+    /// Removes every effect from the output.
+    pub fn clear_effects(&self) {
+        self.set_effects(Vec::new());
+    }
+
+    /// Clears the tails of the effects and the limiter (see `Effect::reset`), such as the echoes of a delay, without
+    /// changing their settings. This is useful when jumping to a different part of a song.
+    pub fn reset_effects(&self) {
+        self.send(Command::ResetEffects);
+    }
+
+    /// Replaces the limiter that the output passes through last, after the fade, or removes it if `None`. This
+    /// replaces the limiter set up by the `amplitude_limit` that the player was created with.
+    pub fn set_limiter(&self, mut limiter: Option<Limiter>) {
+        if let Some(limiter) = &mut limiter {
+            limiter.prepare(self.get_sample_rate(), 1);
+        }
+        self.send(Command::Limiter(limiter));
+    }
+
+    /// Sets a callback that is given every frame of the final output, after the limiter, such as
+    /// `HttpBroadcast::tap` or `IcecastSource::tap` (behind the `broadcast` feature) to broadcast the output. The callback runs on the audio
+    /// thread, so it should not block. Replaces any previous callback.
+    pub fn set_output_tap<F>(&self, tap: F)
+    where
+        F: FnMut(&[f64]) + Send + 'static,
+    {
+        self.send(Command::OutputTap(Some(Box::new(tap))));
+    }
+
+    /// Removes the callback set by `set_output_tap`.
+    pub fn clear_output_tap(&self) {
+        self.send(Command::OutputTap(None));
+    }
+
+    /// Gets the stereo image of the output over roughly the last 300 ms, so that widened patches can be checked for
+    /// mono compatibility. Only the first two output channels are metered.
+    pub fn get_stereo_field(&self) -> StereoField {
+        *self.stereo_field.lock().unwrap()
+    }
+
+    /// When enabled, instruments are asked to reduce their quality through `Instrument::set_reduced_quality` when
+    /// the audio thread nears overload, and to restore it once the load drops again.
+    pub fn set_auto_reduce(&self, enabled: bool) {
+        self.auto_reduce.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Seeds the generator that varies the parameters of instruments every time a note is played (see
+    /// `Instrument::vary`), so that the same variations are played on every run.
+    pub fn set_variation_seed(&self, seed: u64) {
+        *self.rng.lock().unwrap() = SmallRng::seed_from_u64(seed);
+    }
+
+    /// Captures the state of the player, which includes the notes in the queue along with how far along they are,
+    /// their instrument parameters, and the output routing of the player. The notes are captured as they were sent to
+    /// the audio thread, so state that instruments build up while they play (such as the memory of a filter) is left
+    /// out, but the audio thread is never held up.
+    pub fn snapshot(&self) -> PlayerSnapshot {
+        self.drop_retired();
+        let time = self.sound_maker.get_time();
+        let mut notes = self.notes.lock().unwrap();
+        start_pending_notes(&mut notes, time);
+        PlayerSnapshot {
+            time,
+            notes: notes.clone(),
+            output_buses: self.mixer.lock().unwrap().output_buses.clone(),
+            voice_budget: self.voice_budget.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Restores the player to a previously captured state, replacing every note in the queue. Notes continue from where
+    /// they were when the snapshot was taken, no matter how much time has passed since then. The audio thread picks up
+    /// the whole state at once, so no block is played with only some of it restored.
+    pub fn restore(&self, snapshot: &PlayerSnapshot) {
+        let offset = self.sound_maker.get_time() - snapshot.time;
+        let notes = snapshot.notes.iter().take(VOICE_POOL_SIZE).map(|note| {
+            let mut note = note.clone();
+            note.serial = Note::next_serial();
+            note.on += offset;
+            note.off += offset;
+            if let Some(release) = &mut note.release_at {
+                *release += offset;
+            }
+            Command::RestoreNote(note)
+        });
+        self.voice_budget
+            .store(snapshot.voice_budget, Ordering::Relaxed);
+        self.update_mixer(|mixer| {
+            mixer.output_buses = snapshot.output_buses.clone();
+            std::iter::once(Command::Clear)
+                .chain(notes)
+                .chain(std::iter::once(Command::VoiceBudget(snapshot.voice_budget)))
+                .collect()
+        });
+    }
+
+    /// Gets the number of notes in the queue that are currently virtual.
+    pub fn get_virtual_notes(&self) -> usize {
+        self.get_simultaneous_notes()
+            .saturating_sub(self.voice_budget.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(feature = "playback")]
+impl Drop for Player {
+    fn drop(&mut self) {
+        if let Some(fade) = *self.drop_fade.lock().unwrap() {
+            self.fade_to_silence(fade);
+        }
+    }
+}
+
+/// A reading of how well `Player` is keeping up (see `Player::stats`).
+#[cfg(feature = "playback")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayerStats {
+    /// The number of times that the sound driver has run out of audio to play, which is heard as a glitch (see
+    /// `SoundMaker::get_xruns`).
+    pub xruns: usize,
+    /// The same as `Player::get_cpu_load`.
+    pub cpu_load: f64,
+    /// The same as `Player::get_output_latency`.
+    pub output_latency: f64,
+    /// The number of notes in the queue.
+    pub notes: usize,
+}
+
+/// A burst of changes to the notes of `Player` that the audio thread applies all at once (see `Player::transaction`).
+/// Changes are applied in the order that they are made.
+#[cfg(feature = "playback")]
+pub struct Transaction<'a> {
+    player: &'a Player,
+    time: f64,
+    commands: Vec<Command>,
+}
+
+#[cfg(feature = "playback")]
+impl Transaction<'_> {
+    /// Adds a note to the queue, in the same manner as `Player::add_note`.
+    pub fn add(&mut self, voice: Voice) {
+        let command = self.player.note_on(self.time, &voice);
+        self.commands.push(command);
+    }
+
+    /// Releases a note in the queue, in the same manner as `Player::remove_note`.
+    pub fn remove(&mut self, voice: Voice) {
+        let command = self.player.note_off(self.time, &voice);
+        self.commands.push(command);
+    }
+
+    /// Changes the volume of a note in the queue while it plays (see `Voice::with_volume`).
+    pub fn set_volume(&mut self, voice: &Voice, volume: f64) {
+        self.commands.push(Command::NoteVolume {
+            id: voice.note_id,
+            channel_id: voice.channel_id,
+            volume,
+        });
+    }
+}
+
+/// Builds a `Player`, choosing the host and the output device that it plays through.
+#[cfg(feature = "playback")]
+#[derive(Clone, Debug)]
+pub struct PlayerBuilder {
+    host: Option<HostId>,
+    device_name: Option<String>,
+    sample_rate: Option<u32>,
+    channels: Option<usize>,
+    buffer_size: Option<u32>,
+    amplitude_limit: Option<f64>,
+    transport: Option<Transport>,
+}
+
+#[cfg(feature = "playback")]
+impl PlayerBuilder {
+    /// Sets the host (the audio API of the OS, such as ALSA, JACK, WASAPI, or ASIO) that the player plays through.
+    /// See `cpal::available_hosts` for the hosts that can be used on this system.
+    pub fn host(mut self, host: HostId) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    /// Sets the name of the output device that the player plays on, as listed by `Player::list_output_devices` (or
+    /// `Player::list_host_output_devices` for other hosts). Without this, the player plays on the default output device and follows
+    /// it when it changes (see `Player::new`).
+    pub fn device_name(mut self, name: &str) -> Self {
+        self.device_name = Some(name.to_string());
+        self
+    }
+
+    /// Sets the sample rate (in Hz) to open the device with, instead of its default configuration. The nearest sample
+    /// rate that the device supports is used if it does not support this one (see `SoundMaker::negotiate_config`).
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// Sets the number of output channels to open the device with, instead of its default configuration.
+    pub fn channels(mut self, channels: usize) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    /// Sets how many frames the sound driver requests at a time (see `SoundMaker::set_buffer_size`), such as 256 for
+    /// a low latency on systems whose default buffers are audibly late.
+    pub fn buffer_size(mut self, frames: u32) -> Self {
+        self.buffer_size = Some(frames);
+        self
+    }
+
+    /// Sets up a `Limiter` with this ceiling, in the same manner as `Player::new`.
+    pub fn amplitude_limit(mut self, amplitude_limit: f64) -> Self {
+        self.amplitude_limit = Some(amplitude_limit);
+        self
+    }
+
+    /// Keeps time with `transport`, in the same manner as `Player::new_with_transport`.
+    pub fn transport(mut self, transport: &Transport) -> Self {
+        self.transport = Some(transport.clone());
+        self
+    }
+
+    /// Constructs a `Player`. Returns an error if the host is not available, if there is no output device with the
+    /// given name, or if the device does not support the given number of channels.
+    pub fn build(self) -> Result<Player, AudioError> {
+        let host = match self.host {
+            Some(host) => cpal::host_from_id(host)?,
+            None => cpal::default_host(),
+        };
+        let device = match &self.device_name {
+            Some(name) => host
+                .output_devices()?
+                .find(|device| device.name().is_ok_and(|n| n == *name))
+                .ok_or_else(|| AudioError::UnknownDeviceName(name.clone()))?,
+            None => host
+                .default_output_device()
+                .ok_or(AudioError::UnknownDevice)?,
+        };
+        let config = match (self.sample_rate, self.channels) {
+            (None, None) => None,
+            (sample_rate, channels) => Some(SoundMaker::negotiate_config(
+                &device,
+                sample_rate,
+                channels,
+            )?),
+        };
+        Player::new_inner(
+            device,
+            config,
+            self.buffer_size,
+            self.amplitude_limit,
+            self.transport.as_ref(),
+            self.device_name.is_none().then(|| host.id()),
+        )
+    }
+}
+
+/// Gets the names of the output devices of `host`, leaving out devices whose name cannot be read.
+#[cfg(feature = "playback")]
+fn output_device_names(host: &Host) -> Result<Vec<String>, AudioError> {
+    Ok(host
+        .output_devices()?
+        .filter_map(|device| device.name().ok())
+        .collect())
+}
+
+/// Everything that the audio thread of `Player` renders with. The audio thread owns this, and the control thread only
+/// changes the notes by sending commands (see `Command`), so that the audio thread never has to wait on it.
+#[cfg(feature = "playback")]
+struct Engine {
+    notes: Vec<Note>,
+    stolen: Vec<(Note, f64)>,
+    commands: Consumer<Command>,
+    retire: Producer<Command>,
+    note_count: Arc<AtomicUsize>,
+    routing: Box<Routing>,
+    mixer: Mixer,
+    normalization: Normalization,
+    ducking: Ducking,
+    sidechain_level: Arc<AtomicF64>,
+    duck_envelope: f64,
+    instrument_voices: Vec<(ChannelId, usize)>,
+    voice_budget: usize,
+    rank_voices: bool,
+    max_polyphony: Arc<AtomicUsize>,
+    parameters: Vec<Arc<Parameter>>,
+    parameter_modulations: ParameterModulations,
+    cpu_load: Arc<AtomicF64>,
+    stereo_meter: StereoMeter,
+    stereo_field: Arc<Mutex<StereoField>>,
+    post_mix_callback: Option<PostMixCallback>,
+    effects: EffectChain,
+    limiter: Option<Limiter>,
+    output_tap: Option<OutputTap>,
+    auto_reduce: Arc<AtomicBool>,
+    reduced_quality: bool,
+    fade_start: Arc<AtomicF64>,
+    fade_time: Arc<AtomicF64>,
+    stopping: Arc<AtomicBool>,
+    master_volume: Arc<AtomicF64>,
+    smoothed_volume: f64,
+    volume_smoothing: f64,
+    tilt: Arc<AtomicF64>,
+    tilts: Vec<Tilt>,
+    clock: Arc<AtomicF64>,
+    sample_rate: f64,
+    block_start: Instant,
+    block_frames: usize,
+}
+
+#[cfg(feature = "playback")]
+impl Engine {
+    /// Applies a command sent by the control thread at `now`.
+    fn apply_command(&mut self, command: Command, now: f64) {
+        match command {
+            Command::NoteOn(note) => {
+                let retriggered = retriggered_note(&self.notes, &note, now).is_some();
+                if !retriggered && self.notes.len() == self.notes.capacity() {
+                    self.steal_notes(self.notes.len() - 1, now);
+                }
+                if let Some(note) = note_on(&mut self.notes, note, now) {
+                    self.retire(Command::NoteOn(note));
+                }
+                self.rank_voices = true;
+            }
+            Command::RestoreNote(note) => {
+                if self.notes.len() < self.notes.capacity() {
+                    self.notes.push(note);
+                    self.rank_voices = true;
+                } else {
+                    self.retire(Command::NoteOn(note));
+                }
+            }
+            Command::Clear => {
+                while let Some(note) = self.notes.pop() {
+                    self.retire(Command::NoteOn(note));
+                }
+                while let Some((note, _)) = self.stolen.pop() {
+                    self.retire(Command::NoteOn(note));
+                }
+            }
+            Command::Effects(mut effects) => {
+                std::mem::swap(&mut self.effects, &mut effects);
+                self.retire(Command::Effects(effects));
+            }
+            Command::Limiter(mut limiter) => {
+                std::mem::swap(&mut self.limiter, &mut limiter);
+                self.retire(Command::Limiter(limiter));
+            }
+            Command::ResetEffects => {
+                self.mixer.reset();
+                self.effects.reset();
+                if let Some(limiter) = &mut self.limiter {
+                    limiter.reset();
+                }
+            }
+            Command::Routing(mut routing) => {
+                std::mem::swap(&mut self.routing, &mut routing);
+                self.retire(Command::Routing(routing));
+            }
+            Command::ReserveStrips(mut strips) => {
+                strips.append(&mut self.mixer.strips);
+                std::mem::swap(&mut self.mixer.strips, &mut strips);
+                self.retire(Command::ReserveStrips(strips));
+            }
+            Command::AddStrip(strip) => {
+                if self.mixer.strips.len() < self.mixer.strips.capacity() {
+                    self.mixer.strips.push(strip);
+                } else {
+                    self.retire(Command::AddStrip(strip));
+                }
+            }
+            Command::Inserts { strip, mut effects } => {
+                if let Some(strip) = self.mixer.strips.get_mut(strip) {
+                    std::mem::swap(&mut strip.inserts, &mut effects);
+                }
+                self.retire(Command::Inserts { strip, effects });
+            }
+            Command::Sends { strip, mut sends } => {
+                if let Some(strip) = self.mixer.strips.get_mut(strip) {
+                    std::mem::swap(&mut strip.sends, &mut sends);
+                }
+                self.retire(Command::Sends { strip, sends });
+            }
+            Command::ReserveBuses(mut buses) => {
+                buses.append(&mut self.mixer.buses);
+                std::mem::swap(&mut self.mixer.buses, &mut buses);
+                self.retire(Command::ReserveBuses(buses));
+            }
+            Command::AddBus(bus) => {
+                if self.mixer.buses.len() < self.mixer.buses.capacity() {
+                    self.mixer.buses.push(bus);
+                } else {
+                    self.retire(Command::AddBus(bus));
+                }
+            }
+            Command::BusEffects { bus, mut effects } => {
+                if let Some(bus) = self.mixer.buses.get_mut(bus) {
+                    std::mem::swap(&mut bus.effects, &mut effects);
+                }
+                self.retire(Command::BusEffects { bus, effects });
+            }
+            Command::Ducked { strip, ducked } => {
+                if let Some(strip) = self.mixer.strips.get_mut(strip) {
+                    strip.ducked = ducked;
+                }
+            }
+            Command::BusDucked { bus, ducked } => {
+                if let Some(bus) = self.mixer.buses.get_mut(bus) {
+                    bus.ducked = ducked;
+                }
+            }
+            Command::PostMixCallback(mut callback) => {
+                std::mem::swap(&mut self.post_mix_callback, &mut callback);
+                self.retire(Command::PostMixCallback(callback));
+            }
+            Command::OutputTap(mut tap) => {
+                std::mem::swap(&mut self.output_tap, &mut tap);
+                self.retire(Command::OutputTap(tap));
+            }
+            Command::Parameters(mut parameters) => {
+                std::mem::swap(&mut self.parameters, &mut parameters);
+                self.retire(Command::Parameters(parameters));
+            }
+            Command::Modulations(mut modulations) => {
+                std::mem::swap(&mut self.parameter_modulations, &mut modulations);
+                // Parameters that are no longer modulated return to their unmodulated value.
+                for (parameter, _) in &modulations {
+                    if !self
+                        .parameter_modulations
+                        .iter()
+                        .any(|(p, _)| Arc::ptr_eq(p, parameter))
+                    {
+                        parameter.set_modulation(0.0);
+                    }
+                }
+                self.retire(Command::Modulations(modulations));
+            }
+            Command::VoiceBudget(voice_budget) => {
+                self.voice_budget = voice_budget;
+                self.rank_voices = true;
+            }
+            Command::Normalization(normalization) => self.normalization = normalization,
+            Command::Ducking(ducking) => self.ducking = ducking,
+            command => record_command(&mut self.notes, &command, now),
+        }
+    }
+
+    /// Steals notes at `now` until there are no more than `max_polyphony`. Stolen notes fade out before they are
+    /// removed, unless too many notes are already fading out.
+    fn steal_notes(&mut self, max_polyphony: usize, now: f64) {
+        while self.notes.len() > max_polyphony {
+            let Some(i) = note_to_steal(&self.notes) else {
+                break;
+            };
+            let note = self.notes.remove(i);
+            if self.stolen.len() < self.stolen.capacity() {
+                self.stolen.push((note, now));
+            } else {
+                self.retire(Command::NoteOn(note));
+            }
+        }
+    }
+
+    /// Hands something that the audio thread is done with back to be dropped on another thread. The queue back is
+    /// sized so that it never fills up (see `Player::new_inner`).
+    fn retire(&mut self, command: Command) {
+        let retired = self.retire.push(command);
+        debug_assert!(retired.is_ok(), "the queue of retired commands is full");
+    }
+}
+
+#[cfg(feature = "playback")]
+impl Render for Engine {
+    fn start_block(&mut self, frames: usize) {
+        self.block_start = Instant::now();
+        self.block_frames = frames;
+        for parameter in &self.parameters {
+            parameter.apply_pending();
+        }
+        // Modulations are applied once per block, which is plenty for LFOs.
+        let time = self.clock.load(Ordering::Relaxed);
+        for (parameter, lfos) in &self.parameter_modulations {
+            parameter.set_modulation(lfos.iter().map(|lfo| lfo.value(time)).sum());
+        }
+    }
+
+    fn render(&mut self, time: f64, frame: &mut [f64]) {
+        let sample_rate = self.sample_rate;
+        if !self.commands.is_empty() {
+            while let Ok(command) = self.commands.pop() {
+                self.apply_command(command, time);
+            }
+            self.steal_notes(self.max_polyphony.load(Ordering::Relaxed), time);
+        }
+        let normalization = self.normalization;
+        let ducking = self.ducking;
+        let sidechain_level = self.sidechain_level.load(Ordering::Relaxed);
+        self.duck_envelope = ducking.follow(self.duck_envelope, sidechain_level, sample_rate);
+        let duck_gain = ducking.gain(self.duck_envelope);
+        let voice_budget = self.voice_budget;
+        // Notes are only ranked when they are added or retriggered, or when the budget changes, since removing notes
+        // keeps the rest in order.
+        if self.rank_voices && self.notes.len() > voice_budget {
+            self.notes
+                .sort_unstable_by(|a, b| b.priority.cmp(&a.priority).then(b.on.total_cmp(&a.on)));
+            self.rank_voices = false;
+        }
+        let voices = self.notes.len().min(voice_budget);
+        self.instrument_voices.clear();
+        if normalization == Normalization::PerInstrument {
+            count_instrument_voices(&mut self.instrument_voices, &self.notes[..voices]);
+        }
+        let fade_start = self.fade_start.load(Ordering::Relaxed);
+        let gain = if fade_start.is_nan() {
+            1.0
+        } else {
+            let fade_time = self.fade_time.load(Ordering::Relaxed);
+            let progress = if fade_time > 0.0 {
+                (time - fade_start) / fade_time
+            } else {
+                1.0
+            };
+            // A player that is being stopped stays silent, so that the tails of effects are not let back in.
+            if progress >= 1.0 && !self.stopping.load(Ordering::Relaxed) {
+                for n in self.notes.iter_mut() {
+                    n.active = false;
+                }
+                self.fade_start.store(f64::NAN, Ordering::Relaxed);
+            }
+            (1.0 - progress).clamp(0.0, 1.0)
+        };
+        let master_volume = self.master_volume.load(Ordering::Relaxed);
+        self.smoothed_volume =
+            master_volume + (self.smoothed_volume - master_volume) * self.volume_smoothing;
+        let gain = gain * self.smoothed_volume;
+        let cpu_load = self.cpu_load.load(Ordering::Relaxed);
+        let reduce = if !self.auto_reduce.load(Ordering::Relaxed) {
+            false
+        } else if cpu_load > 0.8 {
+            true
+        } else if cpu_load < 0.5 {
+            false
+        } else {
+            self.reduced_quality
+        };
+        let reduce_changed = self.reduced_quality != reduce;
+        self.reduced_quality = reduce;
+        start_pending_notes(&mut self.notes, time);
+        for (i, n) in self.notes.iter_mut().enumerate() {
+            if reduce_changed || (reduce && n.is_new) {
+                n.channel.set_reduced_quality(reduce);
+            }
+            n.is_new = false;
+            if let Some(release) = n.release_at {
+                if time >= release {
+                    n.off = release;
+                    n.release_at = None;
+                }
+            }
+            if n.on > time {
+                // Scheduled notes are held back until their time comes.
+                continue;
+            }
+            let mut note_finished = false;
+            if i >= voice_budget {
+                // Virtual notes are not heard, but are still advanced so that they pick up where they should be once
+                // revived, and so that one-shots still finish. The tail of a released note would not be heard anyway.
+                if n.off > n.on {
+                    n.active = false;
+                } else {
+                    n.channel
+                        .advance(time, n.on, n.off, n.id, n.velocity, &mut note_finished);
+                    if note_finished {
+                        n.active = false;
+                    }
+                }
+                continue;
+            }
+            let (left, right) =
+                n.channel
+                    .process(time, n.on, n.off, n.id, n.velocity, &mut note_finished);
+            let same_instrument = instrument_voices(&self.instrument_voices, &n.channel_id);
+            let voice_gain = n.volume * normalization.gain(voices, same_instrument);
+            mix_note(
+                frame,
+                &self.routing,
+                &mut self.mixer.strips,
+                &n.channel_id,
+                left * voice_gain,
+                right * voice_gain,
+            );
+            if note_finished {
+                n.active = false;
+            }
+        }
+        // Stolen notes fade out quickly instead of being cut off, which would click.
+        let mut i = 0;
+        while i < self.stolen.len() {
+            let (n, stolen_at) = &mut self.stolen[i];
+            let fade = 1.0 - (time - *stolen_at) / STEAL_FADE_TIME;
+            let mut note_finished = n.on > time || fade <= 0.0;
+            if !note_finished {
+                let (left, right) =
+                    n.channel
+                        .process(time, n.on, n.off, n.id, n.velocity, &mut note_finished);
+                let same_instrument = instrument_voices(&self.instrument_voices, &n.channel_id);
+                let voice_gain = n.volume * normalization.gain(voices, same_instrument) * fade;
+                mix_note(
+                    frame,
+                    &self.routing,
+                    &mut self.mixer.strips,
+                    &n.channel_id,
+                    left * voice_gain,
+                    right * voice_gain,
+                );
+            }
+            if note_finished {
+                let (note, _) = self.stolen.swap_remove(i);
+                self.retire(Command::NoteOn(note));
+            } else {
+                i += 1;
+            }
+        }
+        let mut i = 0;
+        while i < self.notes.len() {
+            if self.notes[i].active {
+                i += 1;
+            } else {
+                let note = self.notes.remove(i);
+                self.retire(Command::NoteOn(note));
+            }
+        }
+        self.note_count.store(self.notes.len(), Ordering::Relaxed);
+        self.mixer.process(frame, duck_gain);
+        if let Some(callback) = &mut self.post_mix_callback {
+            for sample in frame.iter_mut() {
+                callback(time, sample);
+            }
+        }
+        self.effects.process(frame);
+        let tilt = self.tilt.load(Ordering::Relaxed);
+        for (sample, tilt_filter) in frame.iter_mut().zip(self.tilts.iter_mut()) {
+            tilt_filter.tilt = tilt;
+            *sample = tilt_filter.process(*sample) * gain;
+        }
+        if let Some(limiter) = &mut self.limiter {
+            limiter.process_frame(frame);
+        }
+        if let Some(tap) = &mut self.output_tap {
+            tap(frame);
+        }
+        match frame {
+            [left, right, ..] => self.stereo_meter.process(*left, *right),
+            [mono] => self.stereo_meter.process(*mono, *mono),
+            [] => {}
+        }
+    }
+
+    fn end_block(&mut self) {
+        if let Ok(mut stereo_field) = self.stereo_field.try_lock() {
+            *stereo_field = self.stereo_meter.get();
+        }
+        // The load is the time taken to render the block relative to the time that the block plays for, smoothed out
+        // over roughly a thousand frames.
+        let duration = self.block_frames as f64 / self.sample_rate;
+        if duration > 0.0 {
+            let load = self.block_start.elapsed().as_secs_f64() / duration;
+            let smoothing = 0.999f64.powi(self.block_frames as i32);
+            let cpu_load = self.cpu_load.load(Ordering::Relaxed);
+            self.cpu_load.store(
+                cpu_load * smoothing + load * (1.0 - smoothing),
+                Ordering::Relaxed,
+            );
+        }
+    }
+}
+
+/// Finds the note in `notes` that `note` retriggers at `now`, which is the note with the same id on the same channel
+/// that has already started. Notes that start later than `now` never retrigger anything, and are played as a note of
+/// their own instead (see `start_pending_notes`).
+#[cfg(feature = "playback")]
+fn retriggered_note(notes: &[Note], note: &Note, now: f64) -> Option<usize> {
+    if note.on > now {
+        return None;
+    }
+    notes
+        .iter()
+        .position(|n| n.id == note.id && n.channel_id == note.channel_id && !n.pending)
+}
+
+/// Plays `note`, or retriggers the note in `notes` that it replaces at `now` if there is one (see
+/// `retriggered_note`), in which case `note` is handed back.
+#[cfg(feature = "playback")]
+fn note_on(notes: &mut Vec<Note>, mut note: Note, now: f64) -> Option<Note> {
+    match retriggered_note(notes, &note, now) {
+        Some(i) => {
+            let found_note = &mut notes[i];
+            found_note
+                .channel
+                .retrigger(note.on, found_note.on, found_note.off, found_note.id);
+            found_note.on = note.on;
+            found_note.release_at = None;
+            found_note.active = true;
+            found_note.priority = note.priority;
+            found_note.velocity = note.velocity;
+            found_note.volume = note.volume;
+            Some(note)
+        }
+        None => {
+            note.pending = note.on > now;
+            notes.push(note);
+            None
+        }
+    }
+}
+
+/// Starts the notes in `notes` that were scheduled to start by `time`, which releases the notes with the same id on
+/// the same channel that were playing before them at the moment they start.
+#[cfg(feature = "playback")]
+fn start_pending_notes(notes: &mut [Note], time: f64) {
+    for i in 0..notes.len() {
+        if !notes[i].pending || notes[i].on > time {
+            continue;
+        }
+        notes[i].pending = false;
+        let (id, channel_id, serial, on) = (
+            notes[i].id,
+            notes[i].channel_id,
+            notes[i].serial,
+            notes[i].on,
+        );
+        for n in notes.iter_mut() {
+            if n.id == id && n.channel_id == channel_id && n.serial != serial && !n.pending {
+                release_note(n, on, on);
+            }
+        }
+    }
+}
+
+/// Applies a command to `notes` at `now`, which is how the control thread of `Player` keeps track of the notes that
+/// it has sent, and how the audio thread applies every command that only changes notes that are already playing.
+#[cfg(feature = "playback")]
+fn record_command(notes: &mut Vec<Note>, command: &Command, now: f64) {
+    match command {
+        Command::NoteOn(note) => {
+            note_on(notes, note.clone(), now);
+        }
+        Command::RestoreNote(note) => notes.push(note.clone()),
+        Command::NoteOff {
+            id,
+            channel_id,
+            time,
+        } => {
+            // Every note with the id on the channel that has started by `time` is released, which leaves notes that are
+            // scheduled to start later held.
+            for note in notes.iter_mut() {
+                if note.id == *id && note.channel_id == *channel_id && note.on <= *time {
+                    release_note(note, *time, now);
+                }
+            }
+        }
+        Command::ReleaseAll(time) => {
+            for note in notes.iter_mut() {
+                release_note(note, *time, now);
+            }
+        }
+        Command::PitchBend {
+            channel_id,
+            semitones,
+        } => {
+            for note in notes.iter_mut() {
+                if note.channel_id == *channel_id {
+                    note.channel.set_pitch_bend(*semitones);
+                }
+            }
+        }
+        Command::NoteVolume {
+            id,
+            channel_id,
+            volume,
+        } => {
+            for note in notes.iter_mut() {
+                if note.id == *id && note.channel_id == *channel_id {
+                    note.volume = *volume;
+                }
+            }
+        }
+        Command::Clear => notes.clear(),
+        _ => {}
+    }
+}
+
+/// Releases a held note at `time`, or once `time` is reached if it is later than `now`. Notes that are released straight
+/// away can be retriggered by the commands that follow.
+#[cfg(feature = "playback")]
+fn release_note(note: &mut Note, time: f64, now: f64) {
+    if note.off > note.on {
+        return;
+    }
+    let time = note.release_at.map_or(time, |release| release.min(time));
+    if time <= now {
+        note.off = time;
+        note.release_at = None;
+    } else {
+        note.release_at = Some(time);
+    }
+}
+
+/// Picks the note that `Player` steals once its maximum polyphony is exceeded.
+#[cfg(feature = "playback")]
+fn note_to_steal(notes: &[Note]) -> Option<usize> {
+    let released = |n: &Note| n.off > n.on;
+    notes
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| match (released(a), released(b)) {
+            (true, true) => a.off.total_cmp(&b.off),
+            (true, false) => std::cmp::Ordering::Less,
+            (false, true) => std::cmp::Ordering::Greater,
+            (false, false) => a
+                .priority
+                .cmp(&b.priority)
+                .then((a.velocity * a.volume).total_cmp(&(b.velocity * b.volume)))
+                .then(a.on.total_cmp(&b.on)),
+        })
+        .map(|(i, _)| i)
+}
+
+/// Strategies for keeping the volume of the mix in check as more notes are played at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalization {
+    /// Scales every note by a fixed gain, which leaves headroom for a fixed number of notes at the cost of making
+    /// single notes quieter than they need to be.
+    Fixed(f64),
+    /// Scales every note by 1/sqrt(n), where n is the number of notes being played, so that the loudness of the mix
+    /// stays roughly the same no matter how many notes are played.
+    ActiveVoices,
+    /// Scales every note by 1/sqrt(n), where n is the number of notes being played by the same instrument, so that
+    /// each instrument keeps roughly the same loudness and their relative levels can be staged with mixer groups.
+    PerInstrument,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Self::Fixed(0.2)
+    }
+}
+
+impl Normalization {
+    /// Gets the gain applied to a single note, given the number of notes being played and the number of notes being
+    /// played by the same instrument.
+    pub fn gain(&self, voices: usize, instrument_voices: usize) -> f64 {
+        match self {
+            Self::Fixed(gain) => *gain,
+            Self::ActiveVoices => 1.0 / (voices.max(1) as f64).sqrt(),
+            Self::PerInstrument => 1.0 / (instrument_voices.max(1) as f64).sqrt(),
+        }
+    }
+}
+
+/// How `Player` ducks channels under audio that plays outside of it (see `Player::set_ducked`).
+#[cfg(feature = "playback")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ducking {
+    /// How much the volume is lowered at a sidechain level of 1.0, from 0.0 (not at all) to 1.0 (silenced).
+    pub depth: f64,
+    /// How long the ducking takes to follow a rise in the sidechain level (in seconds).
+    pub attack: f64,
+    /// How long the ducking takes to follow a fall in the sidechain level (in seconds).
+    pub release: f64,
+}
+
+#[cfg(feature = "playback")]
+impl Default for Ducking {
+    /// Lowers the volume by about 10 dB, coming in quickly and letting go slowly so that the music does not pump
+    /// between words.
+    fn default() -> Self {
+        Self {
+            depth: 0.7,
+            attack: 0.05,
+            release: 0.5,
+        }
+    }
+}
+
+#[cfg(feature = "playback")]
+impl Ducking {
+    /// Moves `envelope` a frame closer to `level`.
+    fn follow(&self, envelope: f64, level: f64, sample_rate: f64) -> f64 {
+        let time = if level > envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        let coefficient = (-1.0 / (time.max(1e-6) * sample_rate)).exp();
+        level + (envelope - level) * coefficient
+    }
+
+    /// Gets the gain of a ducked channel for a smoothed sidechain level.
+    fn gain(&self, envelope: f64) -> f64 {
+        1.0 - self.depth.clamp(0.0, 1.0) * envelope
+    }
+}
+
+/// Counts the number of notes played by each instrument into `counts`.
+#[cfg(feature = "playback")]
+fn count_instrument_voices<'a, I>(counts: &mut Vec<(ChannelId, usize)>, notes: I)
+where
+    I: IntoIterator<Item = &'a Note>,
+{
+    for note in notes {
+        match counts.iter_mut().find(|(id, _)| *id == note.channel_id) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((note.channel_id, 1)),
+        }
+    }
+}
+
+/// Gets the number of notes played by an instrument, as counted by `count_instrument_voices`.
+#[cfg(feature = "playback")]
+fn instrument_voices(counts: &[(ChannelId, usize)], channel_id: &ChannelId) -> usize {
+    counts
+        .iter()
+        .find(|(id, _)| id == channel_id)
+        .map_or(1, |(_, count)| *count)
+}
+
+/// Mixes a frame of a note played on `channel_id` into `frame` according to its route, which goes through the strip
+/// of the channel first if it has one.
+#[cfg(feature = "playback")]
+fn mix_note(
+    frame: &mut [f64],
+    routing: &Routing,
+    strips: &mut [ChannelStrip],
+    channel_id: &ChannelId,
+    left: f64,
+    right: f64,
+) {
+    let route = routing.get(channel_id);
+    let (left, right) = (left * route.gain, right * route.gain);
+    let target = match route.strip.and_then(|strip| strips.get_mut(strip)) {
+        Some(strip) => &mut strip.buffer[..],
+        None => frame,
+    };
+    match &route.outputs {
+        Some(outputs) => mix_stereo(target, outputs.iter().copied(), left, right),
+        None => mix_stereo(target, 0..target.len(), left, right),
+    }
+}
+
+/// Adds a stereo sound to the given output channels of a frame, alternating between the left and right channels.
+/// The sound is mixed down to mono if there is only one output channel.
+#[cfg(feature = "playback")]
+fn mix_stereo<I>(frame: &mut [f64], outputs: I, left: f64, right: f64)
+where
+    I: ExactSizeIterator<Item = usize>,
+{
+    if outputs.len() == 1 {
+        for output in outputs {
+            if let Some(sample) = frame.get_mut(output) {
+                *sample += (left + right) / 2.0;
+            }
+        }
+        return;
+    }
+    for (i, output) in outputs.enumerate() {
+        if let Some(sample) = frame.get_mut(output) {
+            *sample += if i % 2 == 0 { left } else { right };
+        }
+    }
+}
+
+/// How many strips or buses the mixer of `Player` makes room for at first, and at least whenever it runs out of room.
+#[cfg(feature = "playback")]
+const MIXER_RESERVE: usize = 8;
+
+/// The settings of the mixer of `Player` as the control thread keeps them, from which the routing of every channel
+/// and the strips and buses of the audio thread are built. Strips and buses are referred to by their index on the
+/// audio thread, so that it never has to look anything up by name.
+#[cfg(feature = "playback")]
+struct MixerSettings {
+    channels: usize,
+    output_buses: HashMap<ChannelId, Vec<usize>>,
+    groups: HashMap<String, MixerGroup>,
+    members: HashMap<ChannelId, String>,
+    strips: HashMap<ChannelId, StripSettings>,
+    strip_capacity: usize,
+    buses: HashMap<String, usize>,
+    free_buses: Vec<usize>,
+    bus_count: usize,
+    bus_capacity: usize,
+}
+
+/// The index of the strip of a channel on the audio thread, along with the levels of its sends by bus name.
+#[cfg(feature = "playback")]
+struct StripSettings {
+    index: usize,
+    sends: HashMap<String, f64>,
+}
+
+#[cfg(feature = "playback")]
+impl MixerSettings {
+    fn new(channels: usize) -> Self {
+        Self {
+            channels,
+            output_buses: HashMap::new(),
+            groups: HashMap::new(),
+            members: HashMap::new(),
+            strips: HashMap::new(),
+            strip_capacity: 0,
+            buses: HashMap::new(),
+            free_buses: Vec::new(),
+            bus_count: 0,
+            bus_capacity: 0,
+        }
+    }
+
+    /// Gets the gain applied to notes played on a channel according to the group it belongs to.
+    fn gain(&self, channel_id: &ChannelId) -> f64 {
+        let any_solo = self.groups.values().any(|g| g.solo);
+        let group = self
+            .members
+            .get(channel_id)
+            .and_then(|name| self.groups.get(name));
+        match group {
+            Some(group) if group.mute || (any_solo && !group.solo) => 0.0,
+            Some(group) => group.volume,
+            None if any_solo => 0.0,
+            None => 1.0,
+        }
+    }
+
+    /// Works out the routing of every channel from the settings.
+    fn routing(&self) -> Routing {
+        let channels = self
+            .output_buses
+            .keys()
+            .chain(self.members.keys())
+            .chain(self.strips.keys());
+        let mut routing = Routing {
+            channels: HashMap::new(),
+            default: ChannelRoute {
+                gain: if self.groups.values().any(|g| g.solo) {
+                    0.0
+                } else {
+                    1.0
+                },
+                outputs: None,
+                strip: None,
+            },
+        };
+        for channel_id in channels {
+            routing
+                .channels
+                .entry(*channel_id)
+                .or_insert_with(|| ChannelRoute {
+                    gain: self.gain(channel_id),
+                    outputs: self.output_buses.get(channel_id).cloned(),
+                    strip: self.strips.get(channel_id).map(|strip| strip.index),
+                });
+        }
+        routing
+    }
+
+    /// Gets the index of the strip of `channel_id`, adding the commands that create the strip to `commands` if it
+    /// does not have one yet.
+    fn strip(&mut self, channel_id: ChannelId, commands: &mut Vec<Command>) -> usize {
+        if let Some(strip) = self.strips.get(&channel_id) {
+            return strip.index;
+        }
+        let index = self.strips.len();
+        if index == self.strip_capacity {
+            self.strip_capacity = (self.strip_capacity * 2).max(MIXER_RESERVE);
+            commands.push(Command::ReserveStrips(Vec::with_capacity(
+                self.strip_capacity,
+            )));
+        }
+        commands.push(Command::AddStrip(ChannelStrip::new(self.channels)));
+        self.strips.insert(
+            channel_id,
+            StripSettings {
+                index,
+                sends: HashMap::new(),
+            },
+        );
+        index
+    }
+
+    /// Gets the sends of the strip of `channel_id` as pairs of a bus index and a level, leaving out sends to buses
+    /// that do not exist.
+    fn sends(&self, channel_id: ChannelId) -> Vec<(usize, f64)> {
+        self.strips
+            .get(&channel_id)
+            .into_iter()
+            .flat_map(|strip| &strip.sends)
+            .filter_map(|(bus, level)| Some((*self.buses.get(bus)?, *level)))
+            .collect()
+    }
+
+    /// Adds the commands that resend the sends of every strip that sends to `bus` to `commands`, such as after the
+    /// bus is created or removed.
+    fn resolve_sends(&self, bus: &str, commands: &mut Vec<Command>) {
+        for (channel_id, strip) in &self.strips {
+            if strip.sends.contains_key(bus) {
+                commands.push(Command::Sends {
+                    strip: strip.index,
+                    sends: self.sends(*channel_id),
+                });
+            }
+        }
+    }
+}
+
+/// How the notes of every channel are mixed, as worked out by `MixerSettings::routing`.
+#[cfg(feature = "playback")]
+struct Routing {
+    channels: HashMap<ChannelId, ChannelRoute>,
+    default: ChannelRoute,
+}
+
+#[cfg(feature = "playback")]
+impl Routing {
+    /// Gets the route of a channel.
+    fn get(&self, channel_id: &ChannelId) -> &ChannelRoute {
+        self.channels.get(channel_id).unwrap_or(&self.default)
+    }
+}
+
+/// How the notes of a channel are mixed: the gain of its mixer group, the output channels that it plays on (every
+/// output channel if `None`), and the strip that it is mixed on first.
+#[cfg(feature = "playback")]
+struct ChannelRoute {
+    gain: f64,
+    outputs: Option<Vec<usize>>,
+    strip: Option<usize>,
+}
+
+/// The strips of every channel with insert effects or sends in `Player`, and the send buses that they feed, as the
+/// audio thread keeps them.
+#[cfg(feature = "playback")]
+#[derive(Default)]
+struct Mixer {
+    strips: Vec<ChannelStrip>,
+    buses: Vec<SendBus>,
+}
+
+#[cfg(feature = "playback")]
+impl Mixer {
+    /// Passes every strip through its insert effects and into its sends, then every bus through its effects, and adds
+    /// them all to the frame. Strips and buses are processed even when they are silent, so that tails ring out. Ducked
+    /// strips and buses are multiplied by `duck_gain` after their effects.
+    fn process(&mut self, frame: &mut [f64], duck_gain: f64) {
+        for bus in self.buses.iter_mut() {
+            bus.buffer.fill(0.0);
+        }
+        for strip in self.strips.iter_mut() {
+            strip.inserts.process(&mut strip.buffer);
+            if strip.ducked {
+                for sample in strip.buffer.iter_mut() {
+                    *sample *= duck_gain;
+                }
+            }
+            for &(bus, level) in &strip.sends {
+                if let Some(bus) = self.buses.get_mut(bus) {
+                    for (sent, sample) in bus.buffer.iter_mut().zip(&strip.buffer) {
+                        *sent += sample * level;
+                    }
+                }
+            }
+            for (output, sample) in frame.iter_mut().zip(strip.buffer.iter_mut()) {
+                *output += *sample;
+                *sample = 0.0;
+            }
+        }
+        for bus in self.buses.iter_mut() {
+            bus.effects.process(&mut bus.buffer);
+            if bus.ducked {
+                for sample in bus.buffer.iter_mut() {
+                    *sample *= duck_gain;
+                }
+            }
+            for (output, sample) in frame.iter_mut().zip(&bus.buffer) {
+                *output += sample;
+            }
+        }
+    }
+
+    /// Resets the effects of every strip and bus.
+    fn reset(&mut self) {
+        for strip in self.strips.iter_mut() {
+            strip.inserts.reset();
+        }
+        for bus in self.buses.iter_mut() {
+            bus.effects.reset();
+        }
+    }
+}
+
+/// The insert effects and sends of a single channel, whether it is ducked, and the frame that its notes are mixed into.
+#[cfg(feature = "playback")]
+struct ChannelStrip {
+    inserts: EffectChain,
+    sends: Vec<(usize, f64)>,
+    ducked: bool,
+    buffer: Vec<f64>,
+}
+
+#[cfg(feature = "playback")]
+impl ChannelStrip {
+    fn new(channels: usize) -> Self {
+        Self {
+            inserts: EffectChain::default(),
+            sends: Vec::new(),
+            ducked: false,
+            buffer: vec![0.0; channels],
+        }
+    }
+}
+
+/// A bus that channels send to, whether it is ducked, and the frame that the sends are mixed into.
+#[cfg(feature = "playback")]
+struct SendBus {
+    effects: EffectChain,
+    ducked: bool,
+    buffer: Vec<f64>,
+}
+
+#[cfg(feature = "playback")]
+impl SendBus {
+    fn new(effects: EffectChain, channels: usize) -> Self {
+        Self {
+            effects,
+            ducked: false,
+            buffer: vec![0.0; channels],
+        }
+    }
+}
+
+#[cfg(feature = "playback")]
+struct MixerGroup {
+    volume: f64,
+    mute: bool,
+    solo: bool,
+}
+
+#[cfg(feature = "playback")]
+impl Default for MixerGroup {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            mute: false,
+            solo: false,
+        }
+    }
+}
+
+/// A saved state of `Player`, which can be restored at any time.
+#[cfg(feature = "playback")]
+#[derive(Clone)]
+pub struct PlayerSnapshot {
+    time: f64,
+    notes: Vec<Note>,
+    output_buses: HashMap<ChannelId, Vec<usize>>,
+    voice_budget: usize,
+}
+
+#[cfg(feature = "playback")]
+impl PlayerSnapshot {
+    /// Gets the time of the player when this snapshot was taken.
+    pub fn get_time(&self) -> f64 {
+        self.time
+    }
+
+    /// Gets the number of notes that were in the queue when this snapshot was taken.
+    pub fn get_simultaneous_notes(&self) -> usize {
+        self.notes.len()
+    }
+}
+
+/// The next id given out by `ChannelId::unique`.
+static NEXT_CHANNEL_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies the channel of `Player` that a note is mixed on, which is what output buses, mixer groups, insert
+/// effects, sends, and pitch bends apply to. By default, every instrument type has its own channel (see
+/// `ChannelId::of`), so two differently configured `Bell`s share one channel. Play them on separate channels with
+/// `Voice::on_channel` to mix them independently, and to let both play the same note at once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChannelId(ChannelKey);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum ChannelKey {
+    Instrument(TypeId),
+    Custom(u64),
+    Unique(u64),
+}
+
+impl ChannelId {
+    /// Gets the default channel of instrument `I`.
+    pub fn of<I>() -> Self
+    where
+        I: Instrument + 'static,
+    {
+        Self(ChannelKey::Instrument(TypeId::of::<I>()))
+    }
+
+    /// Gets a channel with a user-supplied id, such as the index of a part in a song. Channels created with the same
+    /// id are the same channel.
+    pub fn new(id: u64) -> Self {
+        Self(ChannelKey::Custom(id))
+    }
+
+    /// Creates a channel that is different from every other channel.
+    pub fn unique() -> Self {
+        Self(ChannelKey::Unique(
+            NEXT_CHANNEL_ID.fetch_add(1, Ordering::Relaxed),
+        ))
+    }
+}
+
+/// Signifies a note to be passed into `Player`.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "playback"), allow(dead_code))]
+pub struct Voice {
+    instrument: Box<dyn Instrument>,
+    channel_id: ChannelId,
+    note_id: u8,
+    instrument_name: &'static str,
+    priority: u8,
+    velocity: f64,
+    volume: f64,
+}
+
+impl Debug for Voice {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Voice")
+            .field("note_id", &self.note_id)
+            .field("instrument_name", &self.instrument_name)
+            .field("priority", &self.priority)
+            .field("velocity", &self.velocity)
+            .field("volume", &self.volume)
+            .finish()
+    }
+}
+
+impl Voice {
+    /// Create a new `Voice` instance. The note is played at full velocity unless set otherwise with `with_velocity`.
+    pub fn new<I>(instrument: I, note_id: u8) -> Self
+    where
+        I: Instrument + 'static,
+    {
+        Self {
+            channel_id: instrument.get_channel().unwrap_or_else(ChannelId::of::<I>),
+            instrument: Box::new(instrument),
+            note_id,
+            instrument_name: I::get_name(),
+            priority: 0,
+            velocity: 1.0,
+            volume: 1.0,
+        }
+    }
+
+    /// Sets the priority of a `Voice`, which is 0 by default. Voices with a higher priority are kept over voices with
+    /// a lower priority once the voice budget of `Player` is exceeded.
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets how hard the note of a `Voice` is played (from 0.0 to 1.0), such as to accent certain drum hits, which is
+    /// 1.0 by default.
+    pub fn with_velocity(mut self, velocity: f64) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    /// Sets the gain that the note of a `Voice` is multiplied by when it is mixed, which is 1.0 by default. Unlike
+    /// `with_velocity`, this does not change how the instrument plays the note, only how loud it is in the mix.
+    pub fn with_volume(mut self, volume: f64) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    /// Bends the pitch of the note of a `Voice` by a number of semitones (see `Instrument::set_pitch_bend`).
+    pub fn with_pitch_bend(mut self, semitones: f64) -> Self {
+        self.instrument.set_pitch_bend(semitones);
+        self
+    }
+
+    /// Detunes the note of a `Voice` by a number of cents (see `Instrument::set_fine_tune`), such as to layer the
+    /// same note slightly out of tune.
+    pub fn with_fine_tune(mut self, cents: f64) -> Self {
+        self.instrument.set_fine_tune(cents);
+        self
+    }
+
+    /// Plays the note of a `Voice` on `channel` instead of the default channel of its instrument, so that it can be
+    /// mixed separately from other instances of the same instrument (see `ChannelId`).
+    pub fn on_channel(mut self, channel: ChannelId) -> Self {
+        self.channel_id = channel;
+        self
+    }
+
+    /// Gets the channel that the note of a `Voice` is played on.
+    pub fn get_channel(&self) -> ChannelId {
+        self.channel_id
+    }
+
+    /// Gets the note id of a `Voice`.
+    pub fn get_note_id(&self) -> u8 {
+        self.note_id
+    }
+
+    /// Creates a copy of a `Voice` that plays a different note.
+    pub fn with_note_id(&self, note_id: u8) -> Self {
+        Self {
+            note_id,
+            ..self.clone()
         }
     }
 
@@ -150,9 +2730,12 @@ impl Voice {
     ) -> Self {
         Self {
             instrument,
-            instrument_id,
+            channel_id: ChannelId(ChannelKey::Instrument(instrument_id)),
             note_id,
             instrument_name,
+            priority: 0,
+            velocity: 1.0,
+            volume: 1.0,
         }
     }
 }
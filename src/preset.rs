@@ -0,0 +1,55 @@
+//! Saving and loading tweaked instruments and envelopes as presets.
+
+use crate::errors::AudioError;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fs, path::Path};
+
+/// Saves and loads values as preset files, which is implemented for everything that can be serialized, such as the
+/// built-in instruments and envelopes. Presets are written as TOML if the path ends in `.toml`, and as JSON otherwise.
+pub trait Preset: Sized {
+    /// Writes the preset to a file at `path`, replacing the file if it already exists.
+    fn save<P>(&self, path: P) -> Result<(), AudioError>
+    where
+        P: AsRef<Path>;
+
+    /// Reads a preset back from a file at `path`.
+    fn load<P>(path: P) -> Result<Self, AudioError>
+    where
+        P: AsRef<Path>;
+}
+
+impl<T> Preset for T
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn save<P>(&self, path: P) -> Result<(), AudioError>
+    where
+        P: AsRef<Path>,
+    {
+        let contents = if is_toml(path.as_ref()) {
+            toml::to_string_pretty(self)?
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    fn load<P>(path: P) -> Result<Self, AudioError>
+    where
+        P: AsRef<Path>,
+    {
+        let contents = fs::read_to_string(path.as_ref())?;
+        if is_toml(path.as_ref()) {
+            Ok(toml::from_str(&contents)?)
+        } else {
+            Ok(serde_json::from_str(&contents)?)
+        }
+    }
+}
+
+/// Checks if a preset at `path` should be written as TOML.
+fn is_toml(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"))
+}
@@ -1,23 +1,536 @@
 //! Sound synthesis primitives that you can't live without.
 
-use crate::{errors::AudioError, instruments::Instrument, note::Note};
+#[cfg(feature = "std")]
+use crate::note::NoteValue;
+#[cfg(feature = "playback")]
+use crate::{errors::AudioError, instruments::Instrument, note::Note, player::ChannelId};
+#[cfg(feature = "playback")]
+use alloc::{boxed::Box, format, vec};
 use atomic_float::AtomicF64;
+use core::sync::atomic::Ordering;
+#[cfg(feature = "playback")]
 use cpal::{
-    traits::{DeviceTrait, StreamTrait},
-    Device, FromSample, SizedSample, Stream, StreamConfig, SupportedStreamConfig,
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    BackendSpecificError, BufferSize, Device, FromSample, HostId, PauseStreamError,
+    PlayStreamError, SampleRate, SizedSample, Stream, StreamConfig, StreamError, StreamInstant,
+    SupportedBufferSize, SupportedStreamConfig,
 };
+#[cfg(feature = "playback")]
 use std::{
-    any::TypeId,
-    sync::{atomic::Ordering, Arc},
+    sync::{
+        atomic::AtomicBool,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
 };
+#[cfg(feature = "std")]
+use std::{
+    sync::{
+        atomic::{fence, AtomicUsize},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+/// A callback that is given every error reported by the sound driver while audio is playing.
+#[cfg(feature = "playback")]
+type ErrorCallback = Box<dyn FnMut(StreamError) + Send>;
+
+/// How often the thread that owns the stream of a `SoundMaker` checks whether the device has been lost or the default
+/// output device has changed.
+#[cfg(feature = "playback")]
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Renders the audio of a `SoundMaker` on the audio thread, which owns it. The renderer is kept when the stream is
+/// rebuilt on another device, so that it carries on where it left off. `Player` implements this directly, so that
+/// everything that it renders with is owned by the audio thread alone.
+#[cfg(feature = "playback")]
+pub(crate) trait Render: Send {
+    /// Called at the start of every block of `frames` frames that is requested by the sound driver.
+    fn start_block(&mut self, frames: usize);
+
+    /// Fills in a zeroed frame with one sample per output channel, given the time of the frame.
+    fn render(&mut self, time: f64, frame: &mut [f64]);
+
+    /// Called once every frame of a block has been rendered. Does nothing by default.
+    fn end_block(&mut self) {}
+}
+
+/// The callbacks given to `SoundMaker::set_multichannel_callback_with_block`.
+#[cfg(feature = "playback")]
+struct Callbacks<F, B> {
+    frame: F,
+    block: B,
+}
+
+#[cfg(feature = "playback")]
+impl<F, B> Render for Callbacks<F, B>
+where
+    F: Fn(f64, &mut [f64]) + Send,
+    B: FnMut() + Send,
+{
+    fn start_block(&mut self, _frames: usize) {
+        (self.block)();
+    }
+
+    fn render(&mut self, time: f64, frame: &mut [f64]) {
+        (self.frame)(time, frame);
+    }
+}
+
+/// Passes an error that happened while audio was playing to the error callback, or prints it if no callback is set.
+#[cfg(feature = "playback")]
+fn report_error(error_callback: &Mutex<Option<ErrorCallback>>, err: StreamError) {
+    match &mut *error_callback.lock().unwrap() {
+        Some(callback) => callback(err),
+        None => eprintln!("Error while playing output sound stream: {}", err),
+    }
+}
+
+/// A request from a `SoundMaker` to the thread that owns its stream, along with where to send the result.
+#[cfg(feature = "playback")]
+enum StreamRequest {
+    Pause(Sender<Result<(), PauseStreamError>>),
+    Play(Sender<Result<(), PlayStreamError>>),
+}
+
+/// The thread that owns the stream of a `SoundMaker`. Streams cannot be sent between threads on every platform, so a
+/// stream is built, played, paused and rebuilt on this thread alone, which lets it be rebuilt when the device changes
+/// without anything else having to step in. The stream is stopped when this is dropped.
+#[cfg(feature = "playback")]
+struct StreamThread {
+    requests: Option<Sender<StreamRequest>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(feature = "playback")]
+impl StreamThread {
+    /// Sends a request to the thread and waits for the result.
+    fn request<T>(
+        &self,
+        request: impl FnOnce(Sender<T>) -> StreamRequest,
+    ) -> Result<T, AudioError> {
+        let (reply, result) = mpsc::channel();
+        self.requests
+            .as_ref()
+            .and_then(|requests| requests.send(request(reply)).ok())
+            .ok_or(AudioError::StreamThreadStopped)?;
+        result.recv().map_err(|_| AudioError::StreamThreadStopped)
+    }
+}
+
+#[cfg(feature = "playback")]
+impl Drop for StreamThread {
+    fn drop(&mut self) {
+        // Closing the channel tells the thread to stop.
+        self.requests = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Everything that the thread that owns the stream of a `SoundMaker` needs to build the stream.
+#[cfg(feature = "playback")]
+struct StreamContext {
+    renderer: Arc<Mutex<Box<dyn Render>>>,
+    buffer_size: Option<u32>,
+    tick: Arc<AtomicF64>,
+    latency: Arc<AtomicF64>,
+    paused: Arc<AtomicBool>,
+    pause_count: Arc<AtomicUsize>,
+    xruns: Arc<AtomicUsize>,
+    error_callback: Arc<Mutex<Option<ErrorCallback>>>,
+    device_lost: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "playback")]
+impl StreamContext {
+    /// Builds and plays the stream, reporting whether that worked through `ready`, and then serves requests until the
+    /// `SoundMaker` is dropped. In between requests, the stream is rebuilt when the device is lost, or when the
+    /// default output device of `follow_default` changes. The rebuilt stream has to play at the same sample rate and
+    /// number of channels, since everything that renders into it was set up for them, so a device that cannot is
+    /// reported through the error callback and tried again later.
+    fn run(
+        self,
+        mut device: Device,
+        config: SupportedStreamConfig,
+        follow_default: Option<HostId>,
+        requests: Receiver<StreamRequest>,
+        ready: Sender<Result<(), AudioError>>,
+    ) {
+        let mut stream = match self.build(&device, &config) {
+            Ok(stream) => {
+                let _ = ready.send(Ok(()));
+                Some(stream)
+            }
+            Err(err) => {
+                let _ = ready.send(Err(err));
+                return;
+            }
+        };
+        let host = follow_default.and_then(|host| cpal::host_from_id(host).ok());
+        let mut device_name = device.name().ok();
+        let mut failing = false;
+        loop {
+            match requests.recv_timeout(DEVICE_POLL_INTERVAL) {
+                Ok(StreamRequest::Pause(reply)) => {
+                    let _ = reply.send(stream.as_ref().map_or(Ok(()), |s| s.pause()));
+                }
+                Ok(StreamRequest::Play(reply)) => {
+                    let _ = reply.send(stream.as_ref().map_or(Ok(()), |s| s.play()));
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let default = host.as_ref().and_then(|host| host.default_output_device());
+                    let default_name = default.as_ref().and_then(|d| d.name().ok());
+                    let changed = default_name.is_some() && default_name != device_name;
+                    let lost = self.device_lost.load(Ordering::Relaxed) || stream.is_none();
+                    if !changed && !lost {
+                        continue;
+                    }
+                    if let Some(default) = default {
+                        device = default;
+                        device_name = default_name;
+                    }
+                    // The old stream is dropped first, in case the new stream is on the same device.
+                    drop(stream.take());
+                    let rebuilt = SoundMaker::negotiate_config(
+                        &device,
+                        Some(config.sample_rate().0),
+                        Some(config.channels() as usize),
+                    )
+                    .and_then(|negotiated| {
+                        if negotiated.sample_rate() != config.sample_rate()
+                            || negotiated.channels() != config.channels()
+                        {
+                            return Err(AudioError::UnsupportedConfig {
+                                sample_rate: config.sample_rate().0,
+                                channels: config.channels() as usize,
+                            });
+                        }
+                        self.build(&device, &negotiated)
+                    });
+                    // A device that cannot be opened yet is tried again after the next interval, but only the first
+                    // failure in a row is reported.
+                    match rebuilt {
+                        Ok(rebuilt) => {
+                            stream = Some(rebuilt);
+                            failing = false;
+                        }
+                        Err(err) if !failing => {
+                            self.report(StreamError::BackendSpecific {
+                                err: BackendSpecificError {
+                                    description: format!(
+                                        "cannot reopen the output device: {}",
+                                        err
+                                    ),
+                                },
+                            });
+                            failing = true;
+                        }
+                        Err(_) => {}
+                    }
+                    self.device_lost.store(false, Ordering::Relaxed);
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    /// Passes an error to the error callback, such as when the stream could not be rebuilt.
+    fn report(&self, err: StreamError) {
+        report_error(&self.error_callback, err);
+    }
+
+    /// Builds a stream on `device` that renders audio through the renderer, playing it unless playback is paused. If
+    /// the device will not take the requested block size, the driver is left to pick one instead.
+    fn build(&self, device: &Device, config: &SupportedStreamConfig) -> Result<Stream, AudioError> {
+        let stream = match self.make(device, config, self.buffer_size) {
+            // Devices do not always take the block sizes that they claim to support.
+            Err(_) if self.buffer_size.is_some() => self.make(device, config, None),
+            result => result,
+        }?;
+        if !self.paused.load(Ordering::Relaxed) {
+            stream.play()?;
+        }
+        Ok(stream)
+    }
+
+    /// Builds a stream in the sample format of `config`, with blocks of `buffer_size` frames if given.
+    fn make(
+        &self,
+        device: &Device,
+        config: &SupportedStreamConfig,
+        buffer_size: Option<u32>,
+    ) -> Result<Stream, AudioError> {
+        match config.sample_format() {
+            cpal::SampleFormat::I8 => self.stream_make::<i8>(device, config, buffer_size),
+            cpal::SampleFormat::I16 => self.stream_make::<i16>(device, config, buffer_size),
+            cpal::SampleFormat::I32 => self.stream_make::<i32>(device, config, buffer_size),
+            cpal::SampleFormat::I64 => self.stream_make::<i64>(device, config, buffer_size),
+            cpal::SampleFormat::U8 => self.stream_make::<u8>(device, config, buffer_size),
+            cpal::SampleFormat::U16 => self.stream_make::<u16>(device, config, buffer_size),
+            cpal::SampleFormat::U32 => self.stream_make::<u32>(device, config, buffer_size),
+            cpal::SampleFormat::U64 => self.stream_make::<u64>(device, config, buffer_size),
+            cpal::SampleFormat::F32 => self.stream_make::<f32>(device, config, buffer_size),
+            cpal::SampleFormat::F64 => self.stream_make::<f64>(device, config, buffer_size),
+            _ => unreachable!(),
+        }
+    }
+
+    fn stream_make<T>(
+        &self,
+        device: &Device,
+        config: &SupportedStreamConfig,
+        buffer_size: Option<u32>,
+    ) -> Result<Stream, AudioError>
+    where
+        T: SizedSample + FromSample<f64>,
+    {
+        let mut config: StreamConfig = config.clone().into();
+        if let Some(frames) = buffer_size {
+            config.buffer_size = BufferSize::Fixed(frames);
+        }
+        let sample_rate = config.sample_rate.0 as f64;
+        let time_step = 1.0 / sample_rate;
+        let renderer = Arc::clone(&self.renderer);
+        let tick = Arc::clone(&self.tick);
+        let latency = Arc::clone(&self.latency);
+        let paused = Arc::clone(&self.paused);
+        let pause_count = Arc::clone(&self.pause_count);
+        let xruns = Arc::clone(&self.xruns);
+        let error_callback = Arc::clone(&self.error_callback);
+        let device_lost = Arc::clone(&self.device_lost);
+        let nchannels = config.channels as usize;
+        let mut frame_buffer = vec![0.0; nchannels];
+        // The time that the previous block finishes playing, along with the number of pauses at that point, since the
+        // gap left by a pause is not an underrun.
+        let mut expected_playback: Option<(StreamInstant, usize)> = None;
+        let err_fn = move |err| {
+            if let StreamError::DeviceNotAvailable = err {
+                device_lost.store(true, Ordering::Relaxed);
+            }
+            report_error(&error_callback, err);
+        };
+        let stream = device.build_output_stream(
+            &config,
+            move |output: &mut [T], info: &cpal::OutputCallbackInfo| {
+                let timestamp = info.timestamp();
+                if let Some(delay) = timestamp.playback.duration_since(&timestamp.callback) {
+                    latency.store(delay.as_secs_f64(), Ordering::Relaxed);
+                }
+                let block = Duration::from_secs_f64((output.len() / nchannels) as f64 * time_step);
+                let pauses = pause_count.load(Ordering::Relaxed);
+                if let Some((expected, expected_pauses)) = expected_playback {
+                    // A block that starts playing well after the previous one finished means that the driver played
+                    // silence in between.
+                    let gap = timestamp.playback.duration_since(&expected);
+                    if pauses == expected_pauses && gap.is_some_and(|gap| gap > block / 2) {
+                        xruns.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                expected_playback = timestamp.playback.add(block).map(|end| (end, pauses));
+                if paused.load(Ordering::Relaxed) {
+                    output.fill(T::EQUILIBRIUM);
+                    return;
+                }
+                // The lock is only ever contended for the moment that a rebuilt stream takes over.
+                let mut renderer = renderer.lock().unwrap();
+                renderer.start_block(output.len() / nchannels);
+                for frame in output.chunks_mut(nchannels) {
+                    frame_buffer.fill(0.0);
+                    renderer.render(tick.load(Ordering::Relaxed), &mut frame_buffer);
+                    for (sample, value) in frame.iter_mut().zip(&frame_buffer) {
+                        *sample = T::from_sample(*value);
+                    }
+                    tick.fetch_add(time_step, Ordering::Relaxed);
+                }
+                renderer.end_block();
+            },
+            err_fn,
+            None,
+        )?;
+        Ok(stream)
+    }
+}
+
+/// A shared time origin for running several `SoundMaker`s at once, such as a main mix on the speakers and a cue
+/// mix on the headphones. Each `SoundMaker` still keeps time with its own device, so clocks of different devices
+/// may slowly drift apart over long sessions. The transport also keeps the tempo, which clones of it share, so that
+/// tempo-synced effects such as `BeatRepeat` follow it.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug)]
+pub struct Transport {
+    start: Instant,
+    tempo: Arc<TempoState>,
+}
+
+/// The tempo of a `Transport`, which can be read from the audio thread without locking.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct TempoState {
+    /// Odd while the tempo is being changed, so that readers can tell when they read halfway through a change.
+    sequence: AtomicUsize,
+    tempo: AtomicF64,
+    /// The beat that the transport would have started on had it always played at `tempo`, which keeps the beat from
+    /// jumping when the tempo changes.
+    offset: AtomicF64,
+    /// Held while the tempo is being changed, so that only one thread changes it at a time.
+    writer: Mutex<()>,
+}
+
+#[cfg(feature = "std")]
+impl Transport {
+    /// Creates a new transport that starts counting from when this method is called, at 120 beats per minute.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            tempo: Arc::new(TempoState {
+                sequence: AtomicUsize::new(0),
+                tempo: AtomicF64::new(120.0),
+                offset: AtomicF64::new(0.0),
+                writer: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Gets the wall time elapsed since the transport was created.
+    pub fn get_time(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    /// Changes the tempo (in beats per minute), which counting beats carries on from the current beat at. Set this
+    /// along with the tempo of a sequencer to keep tempo-synced effects in step with it.
+    pub fn set_tempo(&self, tempo: f64) {
+        let _writer = self.tempo.writer.lock().unwrap();
+        let (_, beat) = self.get_tempo_and_beat();
+        let sequence = self.tempo.sequence.load(Ordering::Relaxed);
+        self.tempo.sequence.store(sequence + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.tempo.tempo.store(tempo, Ordering::Relaxed);
+        self.tempo
+            .offset
+            .store(beat - self.get_time() * tempo / 60.0, Ordering::Relaxed);
+        self.tempo.sequence.store(sequence + 2, Ordering::Release);
+    }
+
+    /// Gets the tempo (in beats per minute).
+    pub fn get_tempo(&self) -> f64 {
+        self.get_tempo_and_beat().0
+    }
+
+    /// Gets the length (in seconds) of `note` at the current tempo.
+    pub fn duration_of(&self, note: NoteValue) -> f64 {
+        note.as_secs(self.get_tempo())
+    }
 
+    /// Gets the number of beats elapsed since the transport was created, which includes the fraction of the current
+    /// beat.
+    pub fn get_beat(&self) -> f64 {
+        self.get_tempo_and_beat().1
+    }
+
+    /// Gets the tempo and the current beat, read together so that a change of tempo cannot land in between.
+    pub(crate) fn get_tempo_and_beat(&self) -> (f64, f64) {
+        loop {
+            let sequence = self.tempo.sequence.load(Ordering::Acquire);
+            let tempo = self.tempo.tempo.load(Ordering::Relaxed);
+            let offset = self.tempo.offset.load(Ordering::Relaxed);
+            fence(Ordering::Acquire);
+            if sequence.is_multiple_of(2) && self.tempo.sequence.load(Ordering::Relaxed) == sequence
+            {
+                return (tempo, self.get_time() * tempo / 60.0 + offset);
+            }
+            std::hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for Transport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A value that is shared between the control thread and the audio thread, such as the volume or cutoff of an
+/// instrument. Changes can either take effect immediately, or be scheduled to take effect at the start of the next
+/// block by `Player` or at the next step by `PercussionSequencer`, so that automated changes do not land halfway
+/// through a block and cause clicks. Parameters can also be modulated by LFOs with `Player::modulate_parameter`,
+/// such as to sweep the cutoff of a filter or the feedback of a delay.
+#[derive(Debug)]
+pub struct Parameter {
+    value: AtomicF64,
+    pending: AtomicF64,
+    modulation: AtomicF64,
+}
+
+impl Parameter {
+    /// Creates a new parameter with an initial value.
+    pub fn new(value: f64) -> Self {
+        Self {
+            value: AtomicF64::new(value),
+            pending: AtomicF64::new(f64::NAN),
+            modulation: AtomicF64::new(0.0),
+        }
+    }
+
+    /// Gets the current value of the parameter, including any modulation.
+    pub fn get(&self) -> f64 {
+        self.get_base() + self.modulation.load(Ordering::Relaxed)
+    }
+
+    /// Gets the value of the parameter without any modulation, which is the value that was last set.
+    pub fn get_base(&self) -> f64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    /// Changes the value of the parameter immediately.
+    pub fn set(&self, value: f64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    /// Changes the value of the parameter at the start of the next block. The parameter has to be added to the
+    /// `Player` with `Player::add_parameter` for this to take effect. Only the latest scheduled value is kept.
+    pub fn set_at_next_block(&self, value: f64) {
+        self.pending.store(value, Ordering::Relaxed);
+    }
+
+    /// Sets the amount that the value of the parameter is currently moved by its modulations.
+    #[cfg(feature = "playback")]
+    pub(crate) fn set_modulation(&self, modulation: f64) {
+        self.modulation.store(modulation, Ordering::Relaxed);
+    }
+
+    /// Applies the value scheduled by `set_at_next_block`, if any.
+    #[cfg(feature = "playback")]
+    pub(crate) fn apply_pending(&self) {
+        let pending = self.pending.swap(f64::NAN, Ordering::Relaxed);
+        if !pending.is_nan() {
+            self.set(pending);
+        }
+    }
+}
+
+#[cfg(feature = "playback")]
 pub struct SoundMaker {
     device: Device,
     config: SupportedStreamConfig,
     tick: Arc<AtomicF64>,
-    stream: Option<Stream>,
+    latency: Arc<AtomicF64>,
+    paused: Arc<AtomicBool>,
+    pause_count: Arc<AtomicUsize>,
+    xruns: Arc<AtomicUsize>,
+    error_callback: Arc<Mutex<Option<ErrorCallback>>>,
+    device_lost: Arc<AtomicBool>,
+    follow_default: Option<HostId>,
+    buffer_size: Option<u32>,
+    stream: Option<StreamThread>,
+    transport: Option<Transport>,
 }
 
+#[cfg(feature = "playback")]
 impl SoundMaker {
     /// Computers do not produce sound waves smoothly. The greater the sample rate, the more precise and
     /// smoother the amplitude (y-axis) of the wave. The human hearing range is between 20Hz and 20,000Hz.
@@ -36,15 +549,131 @@ impl SoundMaker {
             device,
             config,
             tick: Arc::new(AtomicF64::new(0.0)),
+            latency: Arc::new(AtomicF64::new(0.0)),
+            paused: Arc::new(AtomicBool::new(false)),
+            pause_count: Arc::new(AtomicUsize::new(0)),
+            xruns: Arc::new(AtomicUsize::new(0)),
+            error_callback: Arc::new(Mutex::new(None)),
+            device_lost: Arc::new(AtomicBool::new(false)),
+            follow_default: None,
+            buffer_size: None,
+            stream: None,
+            transport: None,
+        }
+    }
+
+    /// Same as `new`, but the time of this `SoundMaker` follows `transport` instead of starting from 0, so that
+    /// multiple `SoundMaker`s on different devices can be kept in time with each other.
+    pub fn new_with_transport(
+        device: Device,
+        config: SupportedStreamConfig,
+        transport: &Transport,
+    ) -> Self {
+        Self {
+            device,
+            config,
+            tick: Arc::new(AtomicF64::new(transport.get_time())),
+            latency: Arc::new(AtomicF64::new(0.0)),
+            paused: Arc::new(AtomicBool::new(false)),
+            pause_count: Arc::new(AtomicUsize::new(0)),
+            xruns: Arc::new(AtomicUsize::new(0)),
+            error_callback: Arc::new(Mutex::new(None)),
+            device_lost: Arc::new(AtomicBool::new(false)),
+            follow_default: None,
+            buffer_size: None,
             stream: None,
+            transport: Some(transport.clone()),
         }
     }
 
+    /// Picks the output configuration of `device` that is closest to the requested sample rate (in Hz) and number of
+    /// channels, falling back on the default configuration of the device for whatever is not requested. When the
+    /// device does not support the requested sample rate, the nearest sample rate that it supports is used instead.
+    /// Returns an error if the device does not support the requested number of channels at all.
+    pub fn negotiate_config(
+        device: &Device,
+        sample_rate: Option<u32>,
+        channels: Option<usize>,
+    ) -> Result<SupportedStreamConfig, AudioError> {
+        let default = device.default_output_config()?;
+        let sample_rate = sample_rate.unwrap_or(default.sample_rate().0);
+        let channels = channels.unwrap_or(default.channels() as usize);
+        if default.sample_rate().0 == sample_rate && default.channels() as usize == channels {
+            return Ok(default);
+        }
+        device
+            .supported_output_configs()?
+            .filter(|config| config.channels() as usize == channels)
+            .map(|config| {
+                let rate =
+                    sample_rate.clamp(config.min_sample_rate().0, config.max_sample_rate().0);
+                config.with_sample_rate(SampleRate(rate))
+            })
+            .min_by_key(|config| {
+                (
+                    config.sample_rate().0.abs_diff(sample_rate),
+                    config.sample_format() != default.sample_format(),
+                )
+            })
+            .ok_or(AudioError::UnsupportedChannels(channels))
+    }
+
+    /// Requests blocks of `frames` frames from the sound driver, which takes effect the next time a callback is set.
+    /// Smaller blocks lower the latency at the cost of more frequent interrupts (see `new`). The request is clamped
+    /// to the block sizes that the device supports, and the driver picks the block size if this is never called, or
+    /// if the device will not take the requested size after all.
+    pub fn set_buffer_size(&mut self, frames: u32) {
+        self.buffer_size = Some(match self.config.buffer_size() {
+            // Some backends report a range that is the wrong way round, which cannot be clamped to.
+            SupportedBufferSize::Range { min, max } if min <= max => frames.clamp(*min, *max),
+            _ => frames,
+        });
+    }
+
+    /// Moves playback over to the default output device of `host` whenever the default device changes, such as when
+    /// headphones are plugged in or out, or when the device that is playing is lost, which takes effect the next time a
+    /// callback is set. The callback and the time carry on where they left off on the new device, which has to play at
+    /// the same sample rate and number of channels, or else the error callback is told and playback stays silent
+    /// until a device that can comes along. Without this, a lost device is reopened once it comes back. Devices are
+    /// told apart by name, since cpal has no stable identifier for them, so switching between two devices with the
+    /// same name is not noticed, and a device that is renamed is taken for a new one.
+    pub fn follow_default_device(&mut self, host: HostId) {
+        self.follow_default = Some(host);
+    }
+
     /// Gets the current CPU time starting from when this struct is first initialized.
     pub fn get_time(&self) -> f64 {
         self.tick.load(Ordering::Relaxed)
     }
 
+    /// Gets the time (in seconds) between a frame being rendered and it being heard, as last reported by the sound
+    /// driver. This is 0.0 until the first block of frames has been requested, or if the driver does not report it.
+    pub fn get_output_latency(&self) -> f64 {
+        self.latency.load(Ordering::Relaxed)
+    }
+
+    /// Gets the number of times that the sound driver has run out of audio to play (an underrun, or xrun) since the
+    /// first callback was set, which is heard as a glitch. Underruns are detected from gaps in the timestamps that the
+    /// driver reports, so drivers that do not report accurate timestamps may miss some or report a few too many.
+    pub fn get_xruns(&self) -> usize {
+        self.xruns.load(Ordering::Relaxed)
+    }
+
+    /// Accepts a callback that is given every error reported by the sound driver while audio is playing, such as the
+    /// device being unplugged, along with a device that could not be reopened afterwards (see `follow_default_device`),
+    /// which takes effect straight away. Errors are printed to standard error if no callback is set.
+    pub fn set_error_callback<F>(&self, f: F)
+    where
+        F: FnMut(StreamError) + Send + 'static,
+    {
+        *self.error_callback.lock().unwrap() = Some(Box::new(f));
+    }
+
+    /// Gets the clock that `get_time` reads from, so that it can be read without holding on to this struct.
+    pub(crate) fn get_clock(&self) -> Arc<AtomicF64> {
+        Arc::clone(&self.tick)
+    }
+
     /// Accepts a callback that provides the CPU time and returns the frequency (in Hz). The sound can
     /// be manipulated at any time through the use of atomics or mutexes. This also spawns a
     /// thread that plays audio in the background, but stops playing when `SoundMaker` is dropped.
@@ -52,59 +681,135 @@ impl SoundMaker {
     where
         F: Fn(f64) -> f64 + Send + 'static,
     {
-        let stream = match self.config.sample_format() {
-            cpal::SampleFormat::I8 => self.stream_make::<F, i8>(f),
-            cpal::SampleFormat::I16 => self.stream_make::<F, i16>(f),
-            cpal::SampleFormat::I32 => self.stream_make::<F, i32>(f),
-            cpal::SampleFormat::I64 => self.stream_make::<F, i64>(f),
-            cpal::SampleFormat::U8 => self.stream_make::<F, u8>(f),
-            cpal::SampleFormat::U16 => self.stream_make::<F, u16>(f),
-            cpal::SampleFormat::U32 => self.stream_make::<F, u32>(f),
-            cpal::SampleFormat::U64 => self.stream_make::<F, u64>(f),
-            cpal::SampleFormat::F32 => self.stream_make::<F, f32>(f),
-            cpal::SampleFormat::F64 => self.stream_make::<F, f64>(f),
-            _ => unreachable!(),
-        }?;
-        stream.play()?;
-        self.stream = Some(stream);
-        Ok(())
+        self.set_multichannel_callback(move |time, frame| frame.fill(f(time)))
     }
 
-    fn stream_make<F, T>(&self, f: F) -> Result<Stream, AudioError>
+    /// Like `set_callback`, but instead of returning a single value that is copied to every output channel, the
+    /// callback is provided a zeroed frame with one sample per output channel of the device to fill in.
+    pub fn set_multichannel_callback<F>(&mut self, f: F) -> Result<(), AudioError>
     where
-        F: Fn(f64) -> f64 + Send + 'static,
-        T: SizedSample + FromSample<f64>,
+        F: Fn(f64, &mut [f64]) + Send + 'static,
     {
-        let config: StreamConfig = self.config.clone().into();
-        let sample_rate = config.sample_rate.0 as f64;
-        let time_step = 1.0 / sample_rate;
-        let tick = Arc::clone(&self.tick);
-        let nchannels = config.channels as usize;
-        let err_fn = |err| eprintln!("Error building output sound stream: {}", err);
-        let stream = self.device.build_output_stream(
-            &config,
-            move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
-                for frame in output.chunks_mut(nchannels) {
-                    let s = f(tick.load(Ordering::Relaxed));
-                    let value = T::from_sample(s);
-                    for sample in frame.iter_mut() {
-                        *sample = value;
-                    }
-                    tick.fetch_add(time_step, Ordering::Relaxed);
+        self.set_multichannel_callback_with_block(f, || {})
+    }
+
+    /// Like `set_multichannel_callback`, but also calls `on_block` at the start of every block of frames that is
+    /// requested by the sound driver. Changes made in `on_block` never land halfway through a block.
+    pub fn set_multichannel_callback_with_block<F, B>(
+        &mut self,
+        f: F,
+        on_block: B,
+    ) -> Result<(), AudioError>
+    where
+        F: Fn(f64, &mut [f64]) + Send + 'static,
+        B: FnMut() + Send + 'static,
+    {
+        self.set_renderer(Callbacks {
+            frame: f,
+            block: on_block,
+        })
+    }
+
+    /// Renders the audio of this `SoundMaker` with `renderer` on a new audio thread, in the same manner as the callbacks
+    /// given to `set_multichannel_callback_with_block`.
+    pub(crate) fn set_renderer<R>(&mut self, renderer: R) -> Result<(), AudioError>
+    where
+        R: Render + 'static,
+    {
+        if let Some(transport) = &self.transport {
+            self.tick.store(transport.get_time(), Ordering::Relaxed);
+        }
+        // The previous stream is stopped first, so that the device is never opened twice.
+        self.stream = None;
+        let context = StreamContext {
+            renderer: Arc::new(Mutex::new(Box::new(renderer))),
+            buffer_size: self.buffer_size,
+            tick: Arc::clone(&self.tick),
+            latency: Arc::clone(&self.latency),
+            paused: Arc::clone(&self.paused),
+            pause_count: Arc::clone(&self.pause_count),
+            xruns: Arc::clone(&self.xruns),
+            error_callback: Arc::clone(&self.error_callback),
+            device_lost: Arc::clone(&self.device_lost),
+        };
+        self.device_lost.store(false, Ordering::Relaxed);
+        let (requests, receiver) = mpsc::channel();
+        let (ready, ready_receiver) = mpsc::channel();
+        let device = self.device.clone();
+        let config = self.config.clone();
+        let follow_default = self.follow_default;
+        let thread = thread::spawn(move || {
+            context.run(device, config, follow_default, receiver, ready);
+        });
+        let stream = StreamThread {
+            requests: Some(requests),
+            thread: Some(thread),
+        };
+        match ready_receiver.recv() {
+            Ok(Ok(())) => {
+                self.stream = Some(stream);
+                Ok(())
+            }
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(AudioError::StreamThreadStopped),
+        }
+    }
+
+    /// Pauses the audio thread, which also stops the time of this `SoundMaker` until playback is resumed, so that
+    /// nothing that follows the time jumps ahead on resume. The time stops straight away even if the sound driver
+    /// requests a few more blocks before the stream is paused. On platforms that do not support pausing a stream, the
+    /// stream keeps running but plays silence instead.
+    pub fn pause(&self) -> Result<(), AudioError> {
+        self.paused.store(true, Ordering::Relaxed);
+        self.pause_count.fetch_add(1, Ordering::Relaxed);
+        let result = self.request_pause();
+        if result.is_err() {
+            // The stream is still playing, so the time has to keep going as well.
+            self.paused.store(false, Ordering::Relaxed);
+            self.pause_count.fetch_sub(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn request_pause(&self) -> Result<(), AudioError> {
+        if let Some(stream) = &self.stream {
+            match stream.request(StreamRequest::Pause)? {
+                Err(PauseStreamError::DeviceNotAvailable) => {
+                    return Err(PauseStreamError::DeviceNotAvailable.into())
                 }
-            },
-            err_fn,
-            None,
-        )?;
-        Ok(stream)
+                Err(PauseStreamError::BackendSpecific { .. }) | Ok(()) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Resumes the audio thread after a call to `pause`.
+    pub fn resume(&self) -> Result<(), AudioError> {
+        if let Some(stream) = &self.stream {
+            stream.request(StreamRequest::Play)??;
+        }
+        self.paused.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Gets the sample rate of the device (in Hz).
+    pub fn get_sample_rate(&self) -> f64 {
+        self.config.sample_rate().0 as f64
+    }
+
+    /// Gets the number of output channels of the device.
+    pub fn get_channels(&self) -> usize {
+        self.config.channels() as usize
     }
 
     /// Get a note instance with the current wall time data when this method is called.
     pub(crate) fn create_note(
         &self,
         note_id: u8,
-        instrument_id: TypeId,
+        channel_id: ChannelId,
         instrument: Box<dyn Instrument>,
+        priority: u8,
+        velocity: f64,
     ) -> Note {
         Note {
             id: note_id,
@@ -112,7 +817,14 @@ impl SoundMaker {
             off: 0.0,
             active: true,
             channel: instrument,
-            instrument_id,
+            channel_id,
+            priority,
+            velocity,
+            volume: 1.0,
+            release_at: None,
+            is_new: true,
+            pending: false,
+            serial: Note::next_serial(),
         }
     }
 }
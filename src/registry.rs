@@ -0,0 +1,175 @@
+//! Registries of instruments and effects that can be looked up by name at runtime, for songs that are loaded from config
+//! files and UIs that let users pick an instrument or effect from a list. Other crates can publish their own
+//! instruments and effects by implementing `Instrument` or `Effect`, which lets them be registered alongside the
+//! built-in ones.
+
+use crate::{
+    dsp::{Biquad, BiquadType, FrequencyShifter},
+    effects::{
+        Bitcrusher, Chorus, Compressor, Delay, Distortion, Effect, Flanger, Limiter, Overdrive,
+        Phaser, Tilt, EQ3,
+    },
+    errors::AudioError,
+    instruments::{
+        Bell, Bell8, Brass, Drum808Kick, DrumClap, DrumCrash, DrumHiHat, DrumTom, Drumkick,
+        Drumsnare, EPiano, Flute, GmDrumKit, Harmonica, Instrument, NoiseLayer, NoiseSweep,
+        SubSynth,
+    },
+    player::Voice,
+};
+use alloc::{boxed::Box, collections::BTreeMap, string::ToString, vec::Vec};
+use core::any::TypeId;
+
+/// Maps the names of instruments (see `Instrument::get_name`) to an instance of each instrument, which is copied
+/// whenever the instrument is instantiated.
+#[derive(Clone, Default)]
+pub struct InstrumentRegistry {
+    instruments: BTreeMap<&'static str, (Box<dyn Instrument>, TypeId)>,
+}
+
+impl InstrumentRegistry {
+    /// Creates a new registry with no instruments.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new registry with every built-in instrument, using their default settings.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register(Bell::new());
+        registry.register(Bell8::new());
+        registry.register(Harmonica::new());
+        registry.register(Drumkick::new());
+        registry.register(Drumsnare::new());
+        registry.register(DrumHiHat::new());
+        registry.register(SubSynth::new());
+        registry.register(EPiano::new());
+        registry.register(Drum808Kick::new());
+        registry.register(DrumTom::new());
+        registry.register(DrumClap::new());
+        registry.register(DrumCrash::new());
+        registry.register(NoiseLayer::new());
+        registry.register(NoiseSweep::new());
+        registry.register(Flute::new());
+        registry.register(Brass::new());
+        registry.register(GmDrumKit::new());
+        registry
+    }
+
+    /// Registers an instrument under its name, replacing any instrument that was registered under the same name.
+    pub fn register<I>(&mut self, instrument: I)
+    where
+        I: Instrument + 'static,
+    {
+        self.instruments
+            .insert(I::get_name(), (Box::new(instrument), TypeId::of::<I>()));
+    }
+
+    /// Removes the instrument registered under `name`, returning whether there was one.
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.instruments.remove(name).is_some()
+    }
+
+    /// Gets the names of every registered instrument in alphabetical order.
+    pub fn get_names(&self) -> Vec<&'static str> {
+        let mut names: Vec<_> = self.instruments.keys().copied().collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Checks if an instrument is registered under `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.instruments.contains_key(name)
+    }
+
+    /// Creates a copy of the instrument registered under `name`.
+    pub fn instantiate(&self, name: &str) -> Result<Box<dyn Instrument>, AudioError> {
+        self.instruments
+            .get(name)
+            .map(|(instrument, _)| dyn_clone::clone_box(&**instrument))
+            .ok_or_else(|| AudioError::UnknownInstrument(name.to_string()))
+    }
+
+    /// Creates a `Voice` that plays a note on the instrument registered under `name`, which behaves the same as a
+    /// `Voice` created from the instrument itself.
+    pub fn voice(&self, name: &str, note_id: u8) -> Result<Voice, AudioError> {
+        let (instrument_name, (instrument, instrument_id)) =
+            self.instruments
+                .get_key_value(name)
+                .ok_or_else(|| AudioError::UnknownInstrument(name.to_string()))?;
+        Ok(Voice::new_inner(
+            dyn_clone::clone_box(&**instrument),
+            *instrument_id,
+            note_id,
+            instrument_name,
+        ))
+    }
+}
+
+/// Maps the names of effects (see `Effect::get_name`) to an instance of each effect, which is copied whenever the
+/// effect is instantiated, in the same manner as `InstrumentRegistry`.
+#[derive(Clone, Default)]
+pub struct EffectRegistry {
+    effects: BTreeMap<&'static str, Box<dyn Effect>>,
+}
+
+impl EffectRegistry {
+    /// Creates a new registry with no effects.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new registry with every built-in effect, using their default settings. `ConvolutionReverb` is left
+    /// out since it needs an impulse response, and `BeatRepeat` since it needs a transport and is played through its
+    /// control.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register(Delay::from_millis(375.0));
+        registry.register(Chorus::new());
+        registry.register(Flanger::new());
+        registry.register(Phaser::new());
+        registry.register(Limiter::new(1.0));
+        registry.register(Compressor::new(-18.0));
+        registry.register(EQ3::new());
+        registry.register(Tilt::new());
+        registry.register(Distortion::new(4.0));
+        registry.register(Overdrive::new(4.0));
+        registry.register(Bitcrusher::new(8.0, 11025.0));
+        registry.register(Biquad::new(BiquadType::LowPass, 1000.0, 0.707));
+        registry.register(FrequencyShifter::new());
+        registry
+    }
+
+    /// Registers an effect under its name, replacing any effect that was registered under the same name.
+    pub fn register<E>(&mut self, effect: E)
+    where
+        E: Effect + 'static,
+    {
+        self.effects.insert(E::get_name(), Box::new(effect));
+    }
+
+    /// Removes the effect registered under `name`, returning whether there was one.
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.effects.remove(name).is_some()
+    }
+
+    /// Gets the names of every registered effect in alphabetical order.
+    pub fn get_names(&self) -> Vec<&'static str> {
+        let mut names: Vec<_> = self.effects.keys().copied().collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Checks if an effect is registered under `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.effects.contains_key(name)
+    }
+
+    /// Creates a copy of the effect registered under `name`, which can be passed to `Player::set_effects`.
+    pub fn instantiate(&self, name: &str) -> Result<Box<dyn Effect>, AudioError> {
+        self.effects
+            .get(name)
+            .map(|effect| dyn_clone::clone_box(&**effect))
+            .ok_or_else(|| AudioError::UnknownEffect(name.to_string()))
+    }
+}
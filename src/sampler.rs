@@ -0,0 +1,253 @@
+//! Sample playback, and rendering instruments offline into samples.
+
+#[cfg(any(feature = "wav", feature = "flac", feature = "ogg"))]
+use crate::errors::AudioError;
+use crate::instruments::Instrument;
+use alloc::{sync::Arc, vec::Vec};
+#[cfg(feature = "wav")]
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+#[cfg(any(feature = "wav", feature = "flac", feature = "ogg"))]
+use std::path::Path;
+
+/// A mono or stereo recording of a sound. Stereo recordings are stored with the samples of the left and right
+/// channels interleaved.
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub data: Vec<f64>,
+    pub sample_rate: f64,
+    pub channels: usize,
+}
+
+impl Sample {
+    /// Loads a sample from a WAV file. Files with more than two channels only have their first two channels kept.
+    #[cfg(feature = "wav")]
+    pub fn from_wav<P>(path: P) -> Result<Self, AudioError>
+    where
+        P: AsRef<Path>,
+    {
+        let reader = WavReader::open(path)?;
+        let spec = reader.spec();
+        let file_channels = spec.channels as usize;
+        let samples: Vec<f64> = match spec.sample_format {
+            SampleFormat::Float => reader
+                .into_samples::<f32>()
+                .map(|s| s.map(f64::from))
+                .collect::<Result<_, _>>()?,
+            SampleFormat::Int => {
+                let max = (1_i64 << (spec.bits_per_sample - 1)) as f64;
+                reader
+                    .into_samples::<i32>()
+                    .map(|s| s.map(|s| s as f64 / max))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+        let channels = file_channels.min(2);
+        let data = samples
+            .chunks(file_channels)
+            .flat_map(|frame| frame.iter().take(channels).copied())
+            .collect();
+        Ok(Self {
+            data,
+            sample_rate: spec.sample_rate as f64,
+            channels,
+        })
+    }
+
+    /// Saves the sample to a WAV file as 32-bit floating point samples.
+    #[cfg(feature = "wav")]
+    pub fn to_wav<P>(&self, path: P) -> Result<(), AudioError>
+    where
+        P: AsRef<Path>,
+    {
+        let spec = WavSpec {
+            channels: self.channels as u16,
+            sample_rate: self.sample_rate as u32,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = WavWriter::create(path, spec)?;
+        for sample in &self.data {
+            writer.write_sample(*sample as f32)?;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+
+    /// Saves the sample to a FLAC file as 24-bit samples, which is lossless and around half the size of a WAV file.
+    /// Samples are clamped between -1.0 and 1.0.
+    #[cfg(feature = "flac")]
+    pub fn to_flac<P>(&self, path: P) -> Result<(), AudioError>
+    where
+        P: AsRef<Path>,
+    {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        crate::flac::encode(file, self, 24)?;
+        Ok(())
+    }
+
+    /// Saves the sample to an Ogg Vorbis file, which is lossy but a fraction of the size of a FLAC file, for sharing
+    /// loops and captures. The encoder keeps the noise it adds about 30 dB below the loudest part of every band.
+    #[cfg(feature = "ogg")]
+    pub fn to_ogg<P>(&self, path: P) -> Result<(), AudioError>
+    where
+        P: AsRef<Path>,
+    {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        crate::vorbis::encode(file, self)?;
+        Ok(())
+    }
+
+    /// Gets the length of the sample (in seconds).
+    pub fn get_length(&self) -> f64 {
+        self.get_frames() as f64 / self.sample_rate
+    }
+
+    fn get_frames(&self) -> usize {
+        self.data.len() / self.channels
+    }
+
+    /// Gets the amplitude of a channel of the sample at a given time (in seconds), interpolating between recorded
+    /// values. Silence is returned outside of the recording.
+    fn channel_amplitude(&self, time: f64, channel: usize) -> f64 {
+        if time < 0.0 {
+            return 0.0;
+        }
+        let position = time * self.sample_rate;
+        let index = position as usize;
+        let Some(current) = self.data.get(index * self.channels + channel) else {
+            return 0.0;
+        };
+        let next = self
+            .data
+            .get((index + 1) * self.channels + channel)
+            .copied()
+            .unwrap_or(0.0);
+        let fraction = position - index as f64;
+        current + (next - current) * fraction
+    }
+
+    /// Gets the amplitude of the sample at a given time (in seconds), interpolating between recorded values.
+    /// Stereo samples are mixed down to mono. Silence is returned outside of the recording.
+    pub fn amplitude(&self, time: f64) -> f64 {
+        let (left, right) = self.amplitude_stereo(time);
+        (left + right) / 2.0
+    }
+
+    /// Gets the amplitude of the left and right channels of the sample at a given time (in seconds). Both channels of
+    /// a mono sample are the same.
+    pub fn amplitude_stereo(&self, time: f64) -> (f64, f64) {
+        let left = self.channel_amplitude(time, 0);
+        if self.channels < 2 {
+            (left, left)
+        } else {
+            (left, self.channel_amplitude(time, 1))
+        }
+    }
+}
+
+/// Renders a single note of `instrument` offline into a sample. The note is released after `hold_time` seconds,
+/// or held indefinitely if it is `None`. Rendering stops once the instrument reports that the note has finished,
+/// or after `max_length` seconds, whichever comes first. Stereo instruments are mixed down to mono.
+pub fn bounce(
+    instrument: &dyn Instrument,
+    note_id: u8,
+    hold_time: Option<f64>,
+    sample_rate: f64,
+    max_length: f64,
+) -> Sample {
+    let max_samples = (max_length * sample_rate) as usize;
+    let mut instrument = dyn_clone::clone_box(instrument);
+    instrument.prepare(sample_rate);
+    let mut data = Vec::new();
+    for i in 0..max_samples {
+        let time = i as f64 / sample_rate;
+        // The note is held (released before it was played) until the hold time is up.
+        let time_off = match hold_time {
+            Some(hold_time) if time >= hold_time => hold_time,
+            _ => 0.0,
+        };
+        let mut note_finished = false;
+        let (left, right) =
+            instrument.process(time, 0.0, time_off, note_id, 1.0, &mut note_finished);
+        data.push((left + right) / 2.0);
+        // The amplitude of most envelopes is 0 when a note is first played, which should not end the render.
+        if note_finished && i > 0 {
+            break;
+        }
+    }
+    Sample {
+        data,
+        sample_rate,
+        channels: 1,
+    }
+}
+
+/// Plays back a sample once every time a note is played, regardless of which note it is. Releasing the note does not
+/// cut the sample short. Stereo samples keep their stereo image, which can be narrowed with `width` (from 0.0 for
+/// mono to 1.0 for the original image) and moved with `pan` (from -1.0 for hard left to 1.0 for hard right).
+#[derive(Clone)]
+pub struct Sampler {
+    pub sample: Arc<Sample>,
+    pub volume: f64,
+    pub pan: f64,
+    pub width: f64,
+}
+
+impl Sampler {
+    pub fn new(sample: Sample) -> Self {
+        Self {
+            sample: Arc::new(sample),
+            volume: 1.0,
+            pan: 0.0,
+            width: 1.0,
+        }
+    }
+}
+
+impl Instrument for Sampler {
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        _time_off: f64,
+        _note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        let life_time = time - time_on;
+        if life_time >= self.sample.get_length() {
+            *note_finished = true;
+        }
+        self.sample.amplitude(life_time) * self.volume
+    }
+
+    fn sound_stereo(
+        &self,
+        time: f64,
+        time_on: f64,
+        _time_off: f64,
+        _note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> (f64, f64) {
+        let life_time = time - time_on;
+        if life_time >= self.sample.get_length() {
+            *note_finished = true;
+        }
+        let (left, right) = self.sample.amplitude_stereo(life_time);
+        let mid = (left + right) / 2.0;
+        let side = (left - right) / 2.0 * self.width;
+        let left_gain = (1.0 - self.pan).min(1.0);
+        let right_gain = (1.0 + self.pan).min(1.0);
+        (
+            (mid + side) * left_gain * self.volume * velocity,
+            (mid - side) * right_gain * self.volume * velocity,
+        )
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Sampler"
+    }
+}
@@ -1,21 +1,183 @@
 //! A percussion instrument sequencer that outputs looped drum beats to be played at any given time.
 
-use crate::{instruments::Instrument, player::Voice};
-use std::{
+#[cfg(feature = "serde")]
+use crate::event_log::{Event, EventLog};
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::{
+    errors::AudioError,
+    instruments::Instrument,
+    oscillators::unseeded_rng,
+    player::{ChannelId, Voice},
+    primitives::Parameter,
+    sampler::{bounce, Sample, Sampler},
+};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use core::{
     any::TypeId,
-    collections::HashMap,
     fmt::Debug,
-    hash::{Hash, Hasher},
-    time::Instant,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    rngs::SmallRng,
+    Rng, SeedableRng,
 };
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// The tracks of a sequencer.
+type Tracks = BTreeMap<TrackId, Track>;
+
+/// The next id to give to a track, which is shared by every sequencer so that tracks of different sections of a
+/// `PatternChain` never share an id.
+static NEXT_TRACK_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Identifies a track of a sequencer, which stays the same when the instrument of the track is swapped (see
+/// `PercussionSequencer::swap_instrument`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TrackId(usize);
+
+impl TrackId {
+    fn next() -> Self {
+        Self(NEXT_TRACK_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// A track of a sequencer, with the instrument that plays it, its pattern, how it is humanized, the channel of
+/// `Player` that it plays on, and the indices of the layers that it is in.
+#[derive(Clone, Debug)]
+struct Track {
+    instrument: InstrumentObj,
+    notes: Vec<PercussiveState>,
+    humanize: Option<Humanize>,
+    channel: ChannelId,
+    layers: Vec<usize>,
+}
+
+impl Track {
+    fn new(instrument: InstrumentObj, notes: Vec<PercussiveState>) -> Self {
+        Self {
+            instrument,
+            notes,
+            humanize: None,
+            channel: ChannelId::unique(),
+            layers: Vec::new(),
+        }
+    }
+}
+
+/// How the hits of a track are pushed off the grid to make a sequence feel less mechanical. Start from one of the
+/// presets, such as `Humanize::LOOSE` or `Humanize::MPC_SWING_58`, and apply it with
+/// `PercussionSequencerBuilder::humanize_track`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Humanize {
+    /// Where the second step of every pair of steps lands, as a fraction of the pair, like the swing setting of a drum
+    /// machine. 0.5 is straight, and about 0.67 is a triplet shuffle.
+    pub swing: f64,
+    /// How late a hit can land at random, as a fraction of a step.
+    pub timing: f64,
+    /// How much softer a hit can be played at random, from 0.0 (always full velocity) to 1.0.
+    pub velocity: f64,
+}
+
+impl Humanize {
+    /// Plays exactly on the grid at full velocity.
+    pub const STRAIGHT: Self = Self {
+        swing: 0.5,
+        timing: 0.0,
+        velocity: 0.0,
+    };
+    /// A session drummer on a good day.
+    pub const TIGHT: Self = Self {
+        swing: 0.5,
+        timing: 0.02,
+        velocity: 0.05,
+    };
+    /// A relaxed feel with noticeably uneven hits.
+    pub const LOOSE: Self = Self {
+        swing: 0.52,
+        timing: 0.08,
+        velocity: 0.15,
+    };
+    /// Hits land all over the place, at wildly different velocities.
+    pub const DRUNK_DRUMMER: Self = Self {
+        swing: 0.55,
+        timing: 0.3,
+        velocity: 0.5,
+    };
+    /// The subtle swing settings of an MPC drum machine.
+    pub const MPC_SWING_54: Self = Self::swing(0.54);
+    pub const MPC_SWING_58: Self = Self::swing(0.58);
+    /// The heavier swing settings of an MPC drum machine, which are a staple of hip hop.
+    pub const MPC_SWING_62: Self = Self::swing(0.62);
+    pub const MPC_SWING_66: Self = Self::swing(0.66);
+
+    /// Swings every second step by `swing` (see `Humanize::swing`), without any randomness.
+    pub const fn swing(swing: f64) -> Self {
+        Self {
+            swing,
+            timing: 0.0,
+            velocity: 0.0,
+        }
+    }
+
+    /// Picks how late to play the hit at `step` (in seconds) and how hard to play it.
+    fn sample(&self, step: usize, step_time: f64, rng: &mut SmallRng) -> (f64, f64) {
+        let swing = if step % 2 == 1 {
+            (self.swing - 0.5) * 2.0 * step_time
+        } else {
+            0.0
+        };
+        let delay = swing + rng.gen_range(0.0..=self.timing.abs()) * step_time;
+        let velocity = 1.0 - rng.gen_range(0.0..=self.velocity.clamp(0.0, 1.0));
+        (delay.max(0.0), velocity)
+    }
+}
+
+impl Default for Humanize {
+    fn default() -> Self {
+        Self::STRAIGHT
+    }
+}
 
-/// Builds a `PercussionSequencer`.
+/// The fade of a set of tracks of a `PercussionSequencer` that fade in and out together, such as the layers of an
+/// adaptive game soundtrack that come in as the action picks up (see `PercussionSequencerBuilder::add_layer`). The
+/// tracks keep the indices of the layers that they are in, so that they take their layers along into other sections
+/// of a `PatternChain`.
+#[derive(Clone, Debug)]
+struct Layer {
+    volume: f64,
+    target: f64,
+    /// How much the volume moves towards the target on every step.
+    fade_step: f64,
+    /// The target and fade length (in bars) that take over at the start of the next bar.
+    cue: Option<(f64, f64)>,
+}
+
+impl Layer {
+    fn new(active: bool) -> Self {
+        let volume = if active { 1.0 } else { 0.0 };
+        Self {
+            volume,
+            target: volume,
+            fade_step: 0.0,
+            cue: None,
+        }
+    }
+}
+
+/// Builds a `PercussionSequencer`. Every pattern has to have one step for every sub-beat of every beat (`beats` ×
+/// `sub_beats` steps), which is checked when the sequencer is started. Patterns given as arrays have `BEATS` steps,
+/// while patterns given as vectors can have any number of steps, which is useful for time signatures that are only
+/// known at runtime.
 #[derive(Clone)]
 pub struct PercussionSequencerBuilder<const BEATS: usize> {
     beats: u32,
     sub_beats: u32,
     tempo: f64,
-    channels: HashMap<InstrumentObj, [PercussiveState; BEATS]>,
+    channels: Tracks,
+    layers: Vec<Layer>,
 }
 
 impl<const BEATS: usize> PercussionSequencerBuilder<BEATS> {
@@ -25,7 +187,8 @@ impl<const BEATS: usize> PercussionSequencerBuilder<BEATS> {
             beats,
             sub_beats,
             tempo,
-            channels: HashMap::new(),
+            channels: BTreeMap::new(),
+            layers: Vec::new(),
         }
     }
 
@@ -35,7 +198,8 @@ impl<const BEATS: usize> PercussionSequencerBuilder<BEATS> {
             beats: 4,
             sub_beats: 4,
             tempo,
-            channels: HashMap::new(),
+            channels: BTreeMap::new(),
+            layers: Vec::new(),
         }
     }
 
@@ -44,39 +208,227 @@ impl<const BEATS: usize> PercussionSequencerBuilder<BEATS> {
             beats: 4,
             sub_beats: 4,
             tempo: 120.0,
-            channels: HashMap::new(),
+            channels: BTreeMap::new(),
+            layers: Vec::new(),
         }
     }
 
-    /// Adds a track to the sequencer.
-    pub fn add_track<I>(&mut self, instrument: I, notes: [PercussiveState; BEATS])
+    /// Adds a track to the sequencer, returning its id. Several tracks can be played by the same instrument, and each
+    /// plays on a channel of its own (see `get_track_channel`), so that their hits do not cut each other off.
+    pub fn add_track<I>(&mut self, instrument: I, notes: [PercussiveState; BEATS]) -> TrackId
+    where
+        I: Instrument + 'static,
+    {
+        self.add_track_vec(instrument, notes.to_vec())
+    }
+
+    /// Adds a track to the sequencer with a pattern of any length, returning its id.
+    pub fn add_track_vec<I>(&mut self, instrument: I, notes: Vec<PercussiveState>) -> TrackId
+    where
+        I: Instrument + 'static,
+    {
+        let id = TrackId::next();
+        self.channels
+            .insert(id, Track::new(InstrumentObj::new(instrument), notes));
+        id
+    }
+
+    /// Gets the number of steps that every pattern needs to have.
+    pub fn get_steps(&self) -> usize {
+        self.beats as usize * self.sub_beats as usize
+    }
+
+    /// Adds a track that has already been type-erased.
+    #[cfg(feature = "hydrogen")]
+    pub(crate) fn add_track_obj(
+        &mut self,
+        instrument: InstrumentObj,
+        notes: [PercussiveState; BEATS],
+    ) -> TrackId {
+        let id = TrackId::next();
+        self.channels
+            .insert(id, Track::new(instrument, notes.to_vec()));
+        id
+    }
+
+    /// Gets the tracks played by instrument `I`.
+    fn tracks_of<I>(&mut self) -> impl Iterator<Item = &mut Track>
     where
         I: Instrument + 'static,
     {
         let instrument_id = TypeId::of::<I>();
-        let instrument = Box::new(instrument);
-        self.channels.insert(
-            InstrumentObj {
-                instrument,
-                instrument_id,
-                instrument_name: I::get_name(),
-            },
-            notes,
-        );
+        self.channels
+            .values_mut()
+            .filter(move |t| t.instrument.instrument_id == instrument_id)
+    }
+
+    /// Freezes every track played by instrument `I` by rendering its pattern offline into a sample of one bar at
+    /// `sample_rate`, and replacing the instrument with a `Sampler` that plays the bar back once at the start of every
+    /// bar. This saves CPU for complex instruments at the cost of losing any changes made to the instrument or the
+    /// pattern afterwards. The tails of hits that ring past the end of the bar are wrapped around to its start, as
+    /// they would be while the pattern loops, so the first bar also carries them. Hits longer than `max_length`
+    /// seconds are cut short. Swing is rendered into the bar, but the random part of humanization is dropped, and the
+    /// bar is rendered at the tempo of the builder, so it does not follow later changes of tempo. Returns `false` if
+    /// there is no track played by `I`.
+    pub fn freeze_track<I>(&mut self, sample_rate: f64, max_length: f64) -> bool
+    where
+        I: Instrument + 'static,
+    {
+        let step_time = (60.0 / self.tempo) / self.sub_beats as f64;
+        let mut found = false;
+        for track in self.tracks_of::<I>() {
+            let hit = bounce(
+                &*track.instrument.instrument,
+                64,
+                None,
+                sample_rate,
+                max_length,
+            );
+            let swing = track.humanize.map_or(0.5, |humanize| humanize.swing);
+            let bar = render_bar(&hit, &track.notes, step_time, swing);
+            track.instrument.instrument = Box::new(Sampler::new(bar));
+            for (step, state) in track.notes.iter_mut().enumerate() {
+                *state = if step == 0 {
+                    PercussiveState::Beat
+                } else {
+                    PercussiveState::Rest
+                };
+            }
+            track.humanize = None;
+            found = true;
+        }
+        found
+    }
+
+    /// Humanizes every track played by instrument `I`, such as with `Humanize::LOOSE`. Returns `false` if there is no
+    /// track played by `I`.
+    pub fn humanize_track<I>(&mut self, humanize: Humanize) -> bool
+    where
+        I: Instrument + 'static,
+    {
+        let mut found = false;
+        for track in self.tracks_of::<I>() {
+            track.humanize = Some(humanize);
+            found = true;
+        }
+        found
+    }
+
+    /// Humanizes a single track, which stays humanized when its instrument is swapped. Returns `false` if there is no
+    /// such track.
+    pub fn humanize_track_id(&mut self, track: TrackId, humanize: Humanize) -> bool {
+        match self.channels.get_mut(&track) {
+            Some(track) => {
+                track.humanize = Some(humanize);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Plays a track on `channel` of `Player` instead of the channel of its own that every track gets when it is
+    /// added, such as to mix several tracks together (see `ChannelId`). The track stays on `channel` when its
+    /// instrument is swapped. Returns `false` if there is no such track.
+    pub fn set_track_channel(&mut self, track: TrackId, channel: ChannelId) -> bool {
+        match self.channels.get_mut(&track) {
+            Some(track) => {
+                track.channel = channel;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gets the channel of `Player` that a track plays on, which is a channel of its own (see `ChannelId::unique`)
+    /// unless it was set with `set_track_channel`. Since tracks do not share the channel of their instrument type,
+    /// this is the channel to pass to the mixing methods of `Player`. Returns `None` if there is no such track.
+    pub fn get_track_channel(&self, track: TrackId) -> Option<ChannelId> {
+        self.channels.get(&track).map(|track| track.channel)
+    }
+
+    /// Groups tracks into a layer that can be faded in and out while the sequencer plays (see
+    /// `PercussionSequencer::cue_layer`), which starts out playing if `active` is `true` and silent otherwise. Tracks
+    /// that are in several layers are only heard as loud as their quietest layer allows. Returns the index of the
+    /// layer, counting from 0 in the order that layers are added. In a `PatternChain`, layers are matched across
+    /// sections and fills by their index, so that the tracks of layer 1 of every section fade together.
+    pub fn add_layer(&mut self, tracks: &[TrackId], active: bool) -> usize {
+        let layer = self.layers.len();
+        for track in tracks {
+            if let Some(track) = self.channels.get_mut(track) {
+                track.layers.push(layer);
+            }
+        }
+        self.layers.push(Layer::new(active));
+        layer
+    }
+
+    /// Humanizes every track that has been added so far.
+    pub fn humanize_all(&mut self, humanize: Humanize) {
+        for track in self.channels.values_mut() {
+            track.humanize = Some(humanize);
+        }
     }
 
     /// Constructs a `PercussionSequencer`. The internal clock of the `PercussionSequencer` will start counting down as soon as this method
-    /// is called.
-    pub fn start(self) -> PercussionSequencer<BEATS> {
-        PercussionSequencer {
+    /// is called, and the first step is played on the first call to `update`. Returns an error if any pattern does not
+    /// have `get_steps` steps.
+    #[cfg(feature = "std")]
+    pub fn start(self) -> Result<PercussionSequencer<BEATS>, AudioError> {
+        self.start_at(0.0)
+    }
+
+    /// Constructs a `PercussionSequencer` that follows the time of `Player` (see `Player::get_time`) instead of its
+    /// own clock, and plays its first step at `time`. Use `PercussionSequencer::update_at` to get the `Voice`s to play.
+    /// Sequencers that are started at the same time stay in time with each other. Returns an error if any pattern does
+    /// not have `get_steps` steps.
+    pub fn start_at(self, time: f64) -> Result<PercussionSequencer<BEATS>, AudioError> {
+        check_steps(&self.channels, self.get_steps())?;
+        Ok(PercussionSequencer {
             beat_time: (60.0 / self.tempo) / self.sub_beats as f64,
             current_beat: 0,
-            total_beats: self.sub_beats as usize * self.beats as usize,
-            accumulate: 0.0,
-            previous: Instant::now(),
+            total_beats: self.get_steps(),
+            next_step: time,
+            #[cfg(feature = "std")]
+            start: Instant::now(),
             channels: self.channels,
+            sub_beats: self.sub_beats,
+            pending_tempo: None,
+            pending_parameters: Vec::new(),
+            current_bar: 0,
+            stop_at_bar: None,
+            pending_swaps: Vec::new(),
+            pending_hits: Vec::new(),
+            layers: self.layers,
+            rng: unseeded_rng(),
+        })
+    }
+}
+
+/// Renders a hit at every beat of a pattern into a sample of one bar, with steps of `step_time` seconds and every
+/// second step swung by `swing` (see `Humanize::swing`). Anything that rings past the end of the bar is wrapped
+/// around to its start.
+fn render_bar(hit: &Sample, notes: &[PercussiveState], step_time: f64, swing: f64) -> Sample {
+    let frames = ((notes.len() as f64 * step_time * hit.sample_rate).round() as usize).max(1);
+    let mut data = vec![0.0; frames];
+    for (step, state) in notes.iter().enumerate() {
+        if *state != PercussiveState::Beat {
+            continue;
+        }
+        let delay = if step % 2 == 1 {
+            ((swing - 0.5) * 2.0 * step_time).max(0.0)
+        } else {
+            0.0
+        };
+        let start = ((step as f64 * step_time + delay) * hit.sample_rate).round() as usize;
+        for (i, x) in hit.data.iter().enumerate() {
+            data[(start + i) % frames] += x;
         }
     }
+    Sample {
+        data,
+        sample_rate: hit.sample_rate,
+        channels: 1,
+    }
 }
 
 /// A percussion instrument sequencer that outputs looped drum beats to be played at any given time, designed to be used in conjunction with `Player`.
@@ -85,66 +437,588 @@ pub struct PercussionSequencer<const BEATS: usize> {
     beat_time: f64,
     current_beat: usize,
     total_beats: usize,
-    accumulate: f64,
-    previous: Instant,
-    channels: HashMap<InstrumentObj, [PercussiveState; BEATS]>,
+    next_step: f64,
+    #[cfg(feature = "std")]
+    start: Instant,
+    channels: Tracks,
+    sub_beats: u32,
+    pending_tempo: Option<f64>,
+    pending_parameters: Vec<(Arc<Parameter>, f64)>,
+    current_bar: usize,
+    stop_at_bar: Option<usize>,
+    pending_swaps: Vec<(TrackId, InstrumentObj)>,
+    pending_hits: Vec<(f64, Voice)>,
+    layers: Vec<Layer>,
+    rng: SmallRng,
 }
 
 impl<const N: usize> PercussionSequencer<N> {
+    /// Changes the tempo of the sequencer. The new tempo takes effect from the next step onwards, so that the step
+    /// that is currently playing keeps its length.
+    pub fn set_tempo(&mut self, tempo: f64) {
+        self.pending_tempo = Some(tempo);
+    }
+
+    /// Changes the value of `parameter` exactly when the next step is played, so that automated changes land in time
+    /// with the music.
+    pub fn set_parameter_at_next_step(&mut self, parameter: &Arc<Parameter>, value: f64) {
+        self.pending_parameters.push((Arc::clone(parameter), value));
+    }
+
+    /// Stops the sequencer once `bar` has finished playing, where a bar is one loop of the pattern and the first bar
+    /// is bar 0. Use `get_current_bar` to stop at the end of the bar that is currently playing.
+    pub fn stop_at(&mut self, bar: usize) {
+        self.stop_at_bar = Some(bar);
+    }
+
+    /// Replaces the instrument that plays `track` from the start of the next bar, such as to swap one snare for another
+    /// at a section change. The pattern and humanization of the track are kept. Returns `false` if there is no such
+    /// track.
+    pub fn swap_instrument<I>(&mut self, track: TrackId, instrument: I) -> bool
+    where
+        I: Instrument + 'static,
+    {
+        self.swap_instrument_obj(track, InstrumentObj::new(instrument))
+    }
+
+    /// Same as `swap_instrument`, but for an instrument that has already been type-erased.
+    fn swap_instrument_obj(&mut self, track: TrackId, instrument: InstrumentObj) -> bool {
+        if !self.channels.contains_key(&track) {
+            return false;
+        }
+        self.pending_swaps.push((track, instrument));
+        true
+    }
+
+    /// Fades a layer (see `PercussionSequencerBuilder::add_layer`) in if `active` is `true`, or out otherwise, over
+    /// `fade_bars` bars from the start of the next bar. A fade of 0.0 bars switches the layer straight away at the
+    /// start of the next bar. Cueing a layer again before the next bar replaces the earlier cue. Returns `false` if
+    /// there is no such layer.
+    pub fn cue_layer(&mut self, layer: usize, active: bool, fade_bars: f64) -> bool {
+        let Some(layer) = self.layers.get_mut(layer) else {
+            return false;
+        };
+        layer.cue = Some((if active { 1.0 } else { 0.0 }, fade_bars));
+        true
+    }
+
+    /// Cues the first `intensity` layers in, and the rest of the layers out, in the same manner as `cue_layer`. This is
+    /// the usual way of driving the layers from the state of a game, with layers added from the calmest to the most
+    /// intense.
+    pub fn cue_intensity(&mut self, intensity: usize, fade_bars: f64) {
+        for layer in 0..self.layers.len() {
+            self.cue_layer(layer, layer < intensity, fade_bars);
+        }
+    }
+
+    /// Gets the current volume of a layer, from 0.0 when it is silent to 1.0 when it is fully faded in. Returns `None`
+    /// if there is no such layer.
+    pub fn get_layer_volume(&self, layer: usize) -> Option<f64> {
+        self.layers.get(layer).map(|l| l.volume)
+    }
+
+    /// Gets how loud `track` is played according to the layers that it is in.
+    fn track_volume(&self, track: &Track) -> f64 {
+        track
+            .layers
+            .iter()
+            .filter_map(|&layer| self.layers.get(layer))
+            .map(|l| l.volume)
+            .fold(1.0, f64::min)
+    }
+
+    /// Moves every layer along its fade by a step, taking up cues at the start of a bar.
+    fn advance_layers(&mut self) {
+        let steps = self.total_beats as f64;
+        for layer in &mut self.layers {
+            if self.current_beat == 0 {
+                if let Some((target, fade_bars)) = layer.cue.take() {
+                    layer.target = target;
+                    layer.fade_step = (target - layer.volume).abs() / (fade_bars * steps).max(1.0);
+                    if fade_bars <= 0.0 {
+                        layer.volume = target;
+                    }
+                }
+            }
+            if layer.volume < layer.target {
+                layer.volume = (layer.volume + layer.fade_step).min(layer.target);
+            } else {
+                layer.volume = (layer.volume - layer.fade_step).max(layer.target);
+            }
+        }
+    }
+
+    /// Gets the bar that is currently playing.
+    pub fn get_current_bar(&self) -> usize {
+        self.current_bar
+    }
+
+    /// Checks whether the sequencer has stopped after reaching the bar given to `stop_at`.
+    pub fn is_stopped(&self) -> bool {
+        self.stop_at_bar.is_some_and(|bar| self.current_bar > bar)
+    }
+
+    /// Gets the time that `step` (counting from 0) is next played at, which is a time of `Player` for sequencers
+    /// constructed with `start_at`, and the time since `start` was called otherwise. This is exact for the step
+    /// that is about to be played, while steps further ahead assume that the tempo does not change until then. Returns
+    /// `None` if the pattern does not have `step`.
+    pub fn get_next_step_time(&self, step: usize) -> Option<f64> {
+        if step >= self.total_beats {
+            return None;
+        }
+        let ahead = (step + self.total_beats - self.current_beat) % self.total_beats;
+        let beat_time = match self.pending_tempo {
+            Some(tempo) => (60.0 / tempo) / self.sub_beats as f64,
+            None => self.beat_time,
+        };
+        Some(self.next_step + ahead as f64 * beat_time)
+    }
+
+    /// Gets the time that `step` was last played at, in the same manner as `get_next_step_time`. Steps that have not
+    /// been played yet are counted back from the first step at the current tempo.
+    pub fn get_previous_step_time(&self, step: usize) -> Option<f64> {
+        if step >= self.total_beats {
+            return None;
+        }
+        let behind = (self.current_beat + self.total_beats - step - 1) % self.total_beats + 1;
+        Some(self.next_step - behind as f64 * self.beat_time)
+    }
+
+    /// Gets how far off the grid of the sequencer a hit at `time` is (in milliseconds), measured from the nearest step
+    /// of the grid, whether the step has a beat or not. The error is positive for late hits and negative for early
+    /// hits. For sequencers that follow `Player`, pass the time the player was heard at (`Player::get_time` minus
+    /// `Player::get_output_latency`) to judge what the player actually heard.
+    pub fn get_hit_error(&self, time: f64) -> f64 {
+        let offset = time - self.next_step;
+        let beat_time = match self.pending_tempo {
+            Some(tempo) if offset >= 0.0 => (60.0 / tempo) / self.sub_beats as f64,
+            _ => self.beat_time,
+        };
+        (offset - (offset / beat_time).round() * beat_time) * 1000.0
+    }
+
+    /// Same as `get_hit_error`, but measured from the nearest time that `step` is played at, either the last time or
+    /// the next time. Returns `None` if the pattern does not have `step`.
+    pub fn get_step_hit_error(&self, time: f64, step: usize) -> Option<f64> {
+        let previous = time - self.get_previous_step_time(step)?;
+        let next = time - self.get_next_step_time(step)?;
+        let error = if previous.abs() < next.abs() {
+            previous
+        } else {
+            next
+        };
+        Some(error * 1000.0)
+    }
+
+    /// Same as `get_hit_error`, but measured from the nearest beat of `track`, such as to judge the hits of a player
+    /// against the notes of a chart. Returns `None` if there is no such track, or if the track has no beats.
+    pub fn get_track_hit_error(&self, time: f64, track: TrackId) -> Option<f64> {
+        self.channels
+            .get(&track)?
+            .notes
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| **state == PercussiveState::Beat)
+            .filter_map(|(step, _)| self.get_step_hit_error(time, step))
+            .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+    }
+
     /// Outputs a vector of `Voice`s to be played by `Player` at a given time. It accounts for any previous calls
     /// to update and outputs new `Voice`s that has not been played yet.
     /// Since this is a toneless percussion sequencer, all `Voice`s this method outputs have its `note_id` set to 64.
+    #[cfg(feature = "std")]
     pub fn update(&mut self) -> Vec<Voice> {
-        let elapsed_time = self.previous.elapsed().as_secs_f64();
-        self.previous = Instant::now();
+        self.update_at(self.start.elapsed().as_secs_f64())
+    }
+
+    /// Same as `update`, but for sequencers constructed with `start_at`, which follow the time of `Player` given as
+    /// `time` rather than their own clock.
+    pub fn update_at(&mut self, time: f64) -> Vec<Voice> {
+        self.update_inner(time, |_| {})
+    }
+
+    /// Same as `update_at`, but calls `on_loop` every time the pattern wraps back to its first step, before that step
+    /// is played.
+    fn update_inner<F>(&mut self, time: f64, mut on_loop: F) -> Vec<Voice>
+    where
+        F: FnMut(&mut Self),
+    {
         let mut result = Vec::new();
-        self.accumulate += elapsed_time;
-        while self.accumulate >= self.beat_time {
-            self.accumulate -= self.beat_time;
-            self.current_beat += 1;
-            if self.current_beat >= self.total_beats {
-                self.current_beat = 0;
+        while time >= self.next_step && !self.is_stopped() {
+            if let Some(tempo) = self.pending_tempo.take() {
+                self.beat_time = (60.0 / tempo) / self.sub_beats as f64;
+            }
+            for (parameter, value) in self.pending_parameters.drain(..) {
+                parameter.set(value);
+            }
+            if self.current_beat == 0 {
+                for (track, instrument) in self.pending_swaps.drain(..) {
+                    if let Some(track) = self.channels.get_mut(&track) {
+                        track.instrument = instrument;
+                    }
+                }
             }
-            for channel in &self.channels {
-                if channel.1[self.current_beat] == PercussiveState::Beat {
+            self.advance_layers();
+            for track in self.channels.values() {
+                if track.notes[self.current_beat] == PercussiveState::Beat {
+                    let volume = self.track_volume(track);
+                    if volume <= 0.0 {
+                        continue;
+                    }
                     let voice = Voice::new_inner(
-                        dyn_clone::clone_box(&*channel.0.instrument),
-                        channel.0.instrument_id,
+                        dyn_clone::clone_box(&*track.instrument.instrument),
+                        track.instrument.instrument_id,
                         64,
-                        channel.0.instrument_name,
+                        track.instrument.instrument_name,
                     );
-                    result.push(voice);
+                    let voice = voice.on_channel(track.channel).with_volume(volume);
+                    match track.humanize {
+                        Some(humanize) => {
+                            let (delay, velocity) =
+                                humanize.sample(self.current_beat, self.beat_time, &mut self.rng);
+                            self.pending_hits
+                                .push((self.next_step + delay, voice.with_velocity(velocity)));
+                        }
+                        None => result.push(voice),
+                    }
+                }
+            }
+            self.next_step += self.beat_time;
+            self.current_beat += 1;
+            if self.current_beat >= self.total_beats {
+                self.current_beat = 0;
+                self.current_bar += 1;
+                if self.is_stopped() {
+                    break;
                 }
+                on_loop(self);
+            }
+        }
+        // Humanized hits are held back until they are due.
+        let mut i = 0;
+        while i < self.pending_hits.len() {
+            if time >= self.pending_hits[i].0 {
+                result.push(self.pending_hits.swap_remove(i).1);
+            } else {
+                i += 1;
             }
         }
         result
     }
 }
 
+/// Builds a `PatternChain`.
 #[derive(Clone)]
-struct InstrumentObj {
-    instrument: Box<dyn Instrument>,
-    instrument_id: TypeId,
-    instrument_name: &'static str,
+pub struct PatternChainBuilder<const BEATS: usize> {
+    sections: Vec<PercussionSequencerBuilder<BEATS>>,
+    transitions: Vec<Vec<(usize, f64)>>,
+    jump_every: Vec<usize>,
+    fills: Vec<(usize, usize, PercussionSequencerBuilder<BEATS>)>,
+    seed: Option<u64>,
 }
 
-impl Debug for InstrumentObj {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.instrument_name)
+impl<const BEATS: usize> PatternChainBuilder<BEATS> {
+    /// Constructs a new `PatternChainBuilder`. The first section is always played first, and its tempo and time
+    /// signature are used for the whole chain.
+    pub fn new(first_section: PercussionSequencerBuilder<BEATS>) -> Self {
+        Self {
+            sections: vec![first_section],
+            transitions: vec![Vec::new()],
+            jump_every: vec![1],
+            fills: Vec::new(),
+            seed: None,
+        }
+    }
+
+    /// Adds a section to the chain and returns its index. Only the tracks of the section, how they are humanized, and
+    /// the layers that they are in are used. Tracks played by an instrument that is already humanized by an earlier
+    /// section keep that humanization, and layers are matched to the layers of the other sections by their index.
+    pub fn add_section(&mut self, section: PercussionSequencerBuilder<BEATS>) -> usize {
+        self.sections.push(section);
+        self.transitions.push(Vec::new());
+        self.jump_every.push(1);
+        self.sections.len() - 1
+    }
+
+    /// Adds a possible transition from one section to another. When a section finishes, the next section is
+    /// picked at random, with each transition out of it being picked in proportion to its `weight`. A section
+    /// without any transitions keeps looping.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either section index is out of bounds.
+    pub fn add_transition(&mut self, from: usize, to: usize, weight: f64) {
+        assert!(to < self.sections.len(), "section index out of bounds");
+        self.transitions[from].push((to, weight));
+    }
+
+    /// Only lets the chain leave `section` every `bars` bars, counting from when the section started, so that moving
+    /// to another section (whether picked at random or cued with `PatternChain::cue_section`) waits for the end of a
+    /// musical phrase. By default, a section can be left at the end of any bar.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the section index is out of bounds.
+    pub fn set_jump_points(&mut self, section: usize, bars: usize) {
+        assert!(section < self.sections.len(), "section index out of bounds");
+        self.jump_every[section] = bars.max(1);
+    }
+
+    /// Adds a fill that plays for one bar whenever the chain moves from one section to another, such as a drum roll
+    /// leading into a chorus. Only the tracks of the fill, how they are humanized, and the layers that they are in are
+    /// used, in the same manner as `add_section`. Replaces any earlier fill between the same sections.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either section index is out of bounds.
+    pub fn add_fill(&mut self, from: usize, to: usize, fill: PercussionSequencerBuilder<BEATS>) {
+        assert!(
+            from < self.sections.len() && to < self.sections.len(),
+            "section index out of bounds"
+        );
+        self.fills.retain(|(f, t, _)| (*f, *t) != (from, to));
+        self.fills.push((from, to, fill));
+    }
+
+    /// Seeds the generator that picks transitions so that the same arrangement is played on every run.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Constructs a `PatternChain`. Like `PercussionSequencerBuilder::start`, the internal clock starts counting
+    /// down as soon as this method is called. Returns an error if any pattern of any section does not have as many
+    /// steps as the first section needs.
+    #[cfg(feature = "std")]
+    pub fn start(self) -> Result<PatternChain<BEATS>, AudioError> {
+        self.start_at(0.0)
+    }
+
+    /// Constructs a `PatternChain` that follows the time of `Player` and plays its first step at `time`, in the same
+    /// manner as `PercussionSequencerBuilder::start_at`.
+    pub fn start_at(self, time: f64) -> Result<PatternChain<BEATS>, AudioError> {
+        let rng = match self.seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            None => unseeded_rng(),
+        };
+        let mut sections = self.sections.into_iter();
+        let mut sequencer = sections.next().unwrap().start_at(time)?;
+        let sections: Vec<_> = sections.collect();
+        // Layers are matched by index, so sections and fills with more layers than the first section add their own.
+        for builder in sections
+            .iter()
+            .chain(self.fills.iter().map(|(_, _, fill)| fill))
+        {
+            let known = sequencer.layers.len();
+            sequencer
+                .layers
+                .extend(builder.layers.iter().skip(known).cloned());
+        }
+        let expected = sequencer.total_beats;
+        let mut humanize: BTreeMap<TypeId, Humanize> = BTreeMap::new();
+        let sections: Vec<Tracks> = core::iter::once(sequencer.channels.clone())
+            .chain(sections.into_iter().map(|s| s.channels))
+            .map(|mut channels| {
+                for track in channels.values() {
+                    if let Some(h) = track.humanize {
+                        humanize.entry(track.instrument.instrument_id).or_insert(h);
+                    }
+                }
+                for track in channels.values_mut() {
+                    track.humanize = track
+                        .humanize
+                        .or_else(|| humanize.get(&track.instrument.instrument_id).copied());
+                }
+                channels
+            })
+            .collect();
+        for channels in &sections {
+            check_steps(channels, expected)?;
+        }
+        let mut fills = BTreeMap::new();
+        for (from, to, fill) in self.fills {
+            check_steps(&fill.channels, expected)?;
+            fills.insert((from, to), fill.channels);
+        }
+        Ok(PatternChain {
+            sequencer,
+            sections,
+            transitions: self.transitions,
+            jump_every: self.jump_every,
+            fills,
+            current_section: 0,
+            bars_in_section: 0,
+            cued_section: None,
+            fill_target: None,
+            rng,
+            #[cfg(feature = "serde")]
+            event_log: None,
+        })
+    }
+}
+
+/// Plays a chain of percussion patterns, moving between them at random like a Markov chain so that long-running
+/// music keeps varying without a fixed arrangement. Sections can also be cued from the state of a game with
+/// `cue_section`, which moves to them at the next jump point.
+#[derive(Clone)]
+pub struct PatternChain<const BEATS: usize> {
+    sequencer: PercussionSequencer<BEATS>,
+    sections: Vec<Tracks>,
+    transitions: Vec<Vec<(usize, f64)>>,
+    jump_every: Vec<usize>,
+    fills: BTreeMap<(usize, usize), Tracks>,
+    current_section: usize,
+    bars_in_section: usize,
+    cued_section: Option<usize>,
+    /// The section that the fill that is currently playing leads into.
+    fill_target: Option<usize>,
+    rng: SmallRng,
+    #[cfg(feature = "serde")]
+    event_log: Option<EventLog>,
+}
+
+impl<const N: usize> PatternChain<N> {
+    /// Outputs a vector of `Voice`s to be played by `Player` at a given time, in the same manner as
+    /// `PercussionSequencer::update`.
+    #[cfg(feature = "std")]
+    pub fn update(&mut self) -> Vec<Voice> {
+        self.update_at(self.sequencer.start.elapsed().as_secs_f64())
+    }
+
+    /// Same as `update`, but for chains constructed with `start_at`, in the same manner as
+    /// `PercussionSequencer::update_at`.
+    pub fn update_at(&mut self, time: f64) -> Vec<Voice> {
+        let Self {
+            sequencer,
+            sections,
+            transitions,
+            jump_every,
+            fills,
+            current_section,
+            bars_in_section,
+            cued_section,
+            fill_target,
+            rng,
+            #[cfg(feature = "serde")]
+            event_log,
+        } = self;
+        sequencer.update_inner(time, |sequencer| {
+            let next = match fill_target.take() {
+                Some(target) => target,
+                None => {
+                    *bars_in_section += 1;
+                    if *bars_in_section % jump_every[*current_section] != 0 {
+                        return;
+                    }
+                    let next = match cued_section.take() {
+                        Some(section) if section == *current_section => return,
+                        Some(section) => section,
+                        None => {
+                            let choices = &transitions[*current_section];
+                            let Ok(dist) = WeightedIndex::new(choices.iter().map(|c| c.1)) else {
+                                return;
+                            };
+                            choices[dist.sample(rng)].0
+                        }
+                    };
+                    if let Some(fill) = fills.get(&(*current_section, next)) {
+                        sequencer.channels = fill.clone();
+                        *fill_target = Some(next);
+                        return;
+                    }
+                    next
+                }
+            };
+            *current_section = next;
+            *bars_in_section = 0;
+            sequencer.channels = sections[next].clone();
+            #[cfg(feature = "serde")]
+            if let Some(event_log) = event_log {
+                event_log.queue(Event::PatternSwitch { section: next });
+            }
+        })
+    }
+
+    /// Moves the chain to `section` at the next jump point of the current section (see
+    /// `PatternChainBuilder::set_jump_points`), playing the fill between the two sections first if there is one. The
+    /// cue takes the place of the transition that would have been picked at random, and replaces any earlier cue that
+    /// has yet to be taken. Returns `false` if there is no such section.
+    pub fn cue_section(&mut self, section: usize) -> bool {
+        if section >= self.sections.len() {
+            return false;
+        }
+        self.cued_section = Some(section);
+        true
+    }
+
+    /// Checks whether a fill between two sections is currently playing.
+    pub fn is_playing_fill(&self) -> bool {
+        self.fill_target.is_some()
+    }
+
+    /// Replaces the instrument that plays `track` from the start of the next bar, in whichever section the track
+    /// belongs to, in the same manner as `PercussionSequencer::swap_instrument`. Returns `false` if there is no such
+    /// track.
+    pub fn swap_instrument<I>(&mut self, track: TrackId, instrument: I) -> bool
+    where
+        I: Instrument + 'static,
+    {
+        let Some(section) = self.sections.iter_mut().find_map(|s| s.get_mut(&track)) else {
+            return false;
+        };
+        let instrument = InstrumentObj::new(instrument);
+        section.instrument = instrument.clone();
+        self.sequencer.swap_instrument_obj(track, instrument);
+        true
+    }
+
+    /// Sets the log that every switch between sections is written to, or stops logging if `None`.
+    #[cfg(feature = "serde")]
+    pub fn set_event_log(&mut self, event_log: Option<EventLog>) {
+        self.event_log = event_log;
+    }
+
+    /// Gets the index of the section that is currently playing, which is the section that is being left while a fill
+    /// plays.
+    pub fn get_current_section(&self) -> usize {
+        self.current_section
     }
 }
 
-impl PartialEq for InstrumentObj {
-    fn eq(&self, other: &Self) -> bool {
-        self.instrument_id == other.instrument_id
+/// Checks that every pattern in `channels` has `expected` steps.
+fn check_steps(channels: &Tracks, expected: usize) -> Result<(), AudioError> {
+    match channels.values().find(|t| t.notes.len() != expected) {
+        Some(track) => Err(AudioError::PatternLength {
+            expected,
+            found: track.notes.len(),
+        }),
+        None => Ok(()),
     }
 }
 
-impl Eq for InstrumentObj {}
+#[derive(Clone)]
+pub(crate) struct InstrumentObj {
+    instrument: Box<dyn Instrument>,
+    instrument_id: TypeId,
+    instrument_name: &'static str,
+}
+
+impl InstrumentObj {
+    pub(crate) fn new<I>(instrument: I) -> Self
+    where
+        I: Instrument + 'static,
+    {
+        Self {
+            instrument: Box::new(instrument),
+            instrument_id: TypeId::of::<I>(),
+            instrument_name: I::get_name(),
+        }
+    }
+}
 
-impl Hash for InstrumentObj {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.instrument_id.hash(state);
+impl Debug for InstrumentObj {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.instrument_name)
     }
 }
 
@@ -155,3 +1029,35 @@ pub enum PercussiveState {
 }
 
 impl Eq for PercussiveState {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruments::Drumkick;
+
+    #[test]
+    fn first_step_plays_at_start_time() {
+        let mut pattern = [PercussiveState::Rest; 16];
+        pattern[0] = PercussiveState::Beat;
+        let mut builder = PercussionSequencerBuilder::<16>::default();
+        builder.add_track(Drumkick::new(), pattern);
+        let mut sequencer = builder.start_at(0.0).unwrap();
+        let voices = sequencer.update_at(0.0);
+        assert_eq!(voices.len(), 1);
+        assert_eq!(voices[0].get_instrument_name(), "Drum Kick");
+        assert!(sequencer.update_at(0.0).is_empty());
+    }
+
+    #[test]
+    fn patterns_of_the_wrong_length_are_rejected() {
+        let mut builder = PercussionSequencerBuilder::<16>::default();
+        builder.add_track_vec(Drumkick::new(), vec![PercussiveState::Beat; 12]);
+        assert!(matches!(
+            builder.start_at(0.0),
+            Err(AudioError::PatternLength {
+                expected: 16,
+                found: 12
+            })
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+//! The system media transport controls of Windows, so that media keys, the volume flyout and the lock screen pause and
+//! resume a `Player` like any other audio app. Commands are carried out with `Player::handle_media_command`.
+
+use crate::{
+    errors::AudioError,
+    media::{MediaCommand, PlaybackState},
+    player::Player,
+};
+use std::sync::Arc;
+use windows::{
+    core::{Ref, HSTRING},
+    Foundation::TypedEventHandler,
+    Media::{
+        MediaPlaybackStatus, MediaPlaybackType, Playback::MediaPlayer,
+        SystemMediaTransportControls, SystemMediaTransportControlsButton,
+        SystemMediaTransportControlsButtonPressedEventArgs,
+    },
+};
+
+/// The handler of the buttons of the controls.
+type ButtonHandler = TypedEventHandler<
+    SystemMediaTransportControls,
+    SystemMediaTransportControlsButtonPressedEventArgs,
+>;
+
+/// Registers a `Player` with the media controls of Windows for as long as this is kept. Pressing play, pause or stop
+/// in the controls calls `Player::handle_media_command`.
+pub struct MediaControls {
+    /// A media player that is never given anything to play. Apps without a window of their own get their controls
+    /// through one.
+    _media_player: MediaPlayer,
+    controls: SystemMediaTransportControls,
+    /// The token that removes the handler of the buttons.
+    button_pressed: i64,
+    player: Arc<Player>,
+}
+
+impl MediaControls {
+    /// Registers `player` with the controls under `name`, which Windows uses as the ID of the media of the app.
+    pub fn new(player: Arc<Player>, name: &str) -> Result<Self, AudioError> {
+        let media_player = MediaPlayer::new()?;
+        // The media player would otherwise answer the buttons itself.
+        media_player.CommandManager()?.SetIsEnabled(false)?;
+        let controls = media_player.SystemMediaTransportControls()?;
+        controls.SetIsEnabled(true)?;
+        controls.SetIsPlayEnabled(true)?;
+        controls.SetIsPauseEnabled(true)?;
+        controls.SetIsStopEnabled(true)?;
+        let handler = {
+            let player = Arc::clone(&player);
+            ButtonHandler::new(
+                move |controls: Ref<SystemMediaTransportControls>,
+                      args: Ref<SystemMediaTransportControlsButtonPressedEventArgs>| {
+                    let command = match args.ok()?.Button()? {
+                        SystemMediaTransportControlsButton::Play => MediaCommand::Play,
+                        SystemMediaTransportControlsButton::Pause => MediaCommand::Pause,
+                        SystemMediaTransportControlsButton::Stop => MediaCommand::Stop,
+                        _ => return Ok(()),
+                    };
+                    // Windows has nowhere to show an error, and the status that is reported below shows whether the
+                    // command took effect.
+                    let _ = player.handle_media_command(command);
+                    controls.ok()?.SetPlaybackStatus(playback_status(&player))
+                },
+            )
+        };
+        let button_pressed = controls.ButtonPressed(&handler)?;
+        let display = controls.DisplayUpdater()?;
+        display.SetType(MediaPlaybackType::Music)?;
+        display.SetAppMediaId(&HSTRING::from(name))?;
+        display.Update()?;
+        let media_controls = Self {
+            _media_player: media_player,
+            controls,
+            button_pressed,
+            player,
+        };
+        media_controls.update()?;
+        Ok(media_controls)
+    }
+
+    /// Sets the title that Windows shows as now playing, such as the name of a song or a preset.
+    pub fn set_title(&self, title: &str) -> Result<(), AudioError> {
+        let display = self.controls.DisplayUpdater()?;
+        display.MusicProperties()?.SetTitle(&HSTRING::from(title))?;
+        display.Update()?;
+        Ok(())
+    }
+
+    /// Tells Windows whether the player is playing or paused, which should be called after pausing or resuming the
+    /// player from the app. Buttons pressed in the controls report this themselves.
+    pub fn update(&self) -> Result<(), AudioError> {
+        self.controls
+            .SetPlaybackStatus(playback_status(&self.player))?;
+        Ok(())
+    }
+}
+
+impl Drop for MediaControls {
+    fn drop(&mut self) {
+        let _ = self.controls.RemoveButtonPressed(self.button_pressed);
+        let _ = self.controls.SetIsEnabled(false);
+    }
+}
+
+/// Gets the playback status of `player` in the terms of the controls.
+fn playback_status(player: &Player) -> MediaPlaybackStatus {
+    match player.get_playback_state() {
+        PlaybackState::Playing => MediaPlaybackStatus::Playing,
+        PlaybackState::Paused => MediaPlaybackStatus::Paused,
+    }
+}
@@ -0,0 +1,298 @@
+//! Analysis of recordings, used to recreate the timbre of acoustic percussion with synthesized noise instead of
+//! shipping samples, to find where the hits of a recording are, and to find the tempo and key of imported loops.
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::{
+    instruments::{NoiseLayer, OCTAVE_BANDS},
+    note::{Key, Mode},
+    oscillators::{EnvelopeADSR, EnvelopeCurve},
+    sampler::Sample,
+};
+use alloc::{vec, vec::Vec};
+use core::f64::consts::PI;
+
+/// Number of frames at the start of a recording that are analyzed for its spectrum.
+const ANALYSIS_FRAMES: usize = 4096;
+
+/// Length of the windows that the loudness of a recording is measured over (in seconds).
+const ENVELOPE_WINDOW: f64 = 0.005;
+
+/// Shortest time between two transients (in seconds), which keeps the decay of a hit from counting as more hits.
+const MIN_TRANSIENT_GAP: f64 = 0.05;
+
+/// The range of tempos (in beats per minute) that `estimate_tempo` picks from.
+const MIN_TEMPO: f64 = 70.0;
+const MAX_TEMPO: f64 = 180.0;
+
+/// Length of the frames that the chroma of a recording is measured over (in seconds), which is long enough to tell
+/// apart neighbouring semitones in the lowest octave that is measured.
+const CHROMA_FRAME: f64 = 0.25;
+
+/// The range of notes (as MIDI note numbers, from C2 to B6) that the chroma of a recording is measured over.
+const CHROMA_NOTES: core::ops::Range<i32> = 36..96;
+
+/// How strongly every degree of a major and a minor key is heard in tonal music, as found by Krumhansl and Kessler.
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+/// Number of windows on either side of a window that its rise in loudness is compared against.
+const TRANSIENT_NEIGHBORHOOD: usize = 10;
+
+/// Gets the level of every band of a recording, where a band centered on `center` spans from `center / sqrt(2)` to
+/// `center * sqrt(2)`. Only the start of the recording, where most of the energy of a drum hit is, is analyzed. The
+/// levels are scaled so that the loudest band has a level of 1.0.
+pub fn band_levels(sample: &Sample, centers: &[f64]) -> Vec<f64> {
+    let mono = mono_frames(sample);
+    let frames = &mono[..mono.len().min(ANALYSIS_FRAMES)];
+    let n = frames.len();
+    if n == 0 {
+        return vec![0.0; centers.len()];
+    }
+    // A plain DFT with a Hann window, which is fast enough for a one-off analysis of a few thousand frames.
+    let windowed: Vec<f64> = frames
+        .iter()
+        .enumerate()
+        .map(|(i, x)| x * 0.5 * (1.0 - (2.0 * PI * i as f64 / n as f64).cos()))
+        .collect();
+    let power: Vec<f64> = (0..n / 2)
+        .map(|bin| {
+            let (re, im) = windowed
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(re, im), (i, x)| {
+                    let angle = 2.0 * PI * bin as f64 * i as f64 / n as f64;
+                    (re + x * angle.cos(), im - x * angle.sin())
+                });
+            re * re + im * im
+        })
+        .collect();
+    let bin_hz = sample.sample_rate / n as f64;
+    let levels: Vec<f64> = centers
+        .iter()
+        .map(|center| {
+            let low = center / 2.0_f64.sqrt();
+            let high = center * 2.0_f64.sqrt();
+            let (sum, count) = power
+                .iter()
+                .enumerate()
+                .filter(|(bin, _)| (low..high).contains(&(*bin as f64 * bin_hz)))
+                .fold((0.0, 0), |(sum, count), (_, p)| (sum + p, count + 1));
+            if count == 0 {
+                0.0
+            } else {
+                (sum / count as f64).sqrt()
+            }
+        })
+        .collect();
+    let loudest = levels.iter().copied().fold(0.0, f64::max);
+    if loudest > 0.0 {
+        levels.iter().map(|l| l / loudest).collect()
+    } else {
+        levels
+    }
+}
+
+/// Fits a percussive envelope to the loudness of a recording over time. The attack lasts until the recording is at
+/// its loudest, and the decay lasts until it has faded by 60 dB.
+pub fn fit_envelope(sample: &Sample) -> EnvelopeADSR {
+    let mono = mono_frames(sample);
+    let window = ((ENVELOPE_WINDOW * sample.sample_rate) as usize).max(1);
+    let loudness: Vec<f64> = mono
+        .chunks(window)
+        .map(|chunk| (chunk.iter().map(|x| x * x).sum::<f64>() / chunk.len() as f64).sqrt())
+        .collect();
+    let (peak, peak_loudness) = loudness.iter().copied().enumerate().fold(
+        (0, 0.0),
+        |best, (i, l)| if l > best.1 { (i, l) } else { best },
+    );
+    let end = loudness[peak..]
+        .iter()
+        .position(|l| *l < peak_loudness * 0.001)
+        .map_or(loudness.len(), |i| peak + i);
+    let window_time = window as f64 / sample.sample_rate;
+    EnvelopeADSR::builder()
+        .attack(peak as f64 * window_time)
+        .decay((end - peak) as f64 * window_time)
+        .sustain(0.0)
+        .release(0.0)
+        // A shape of ln(1000) follows a natural exponential decay that fades by 60 dB over the decay.
+        .decay_curve(EnvelopeCurve::Exponential(1000.0_f64.ln()))
+        .build()
+}
+
+/// Finds the transients (the starts of hits and notes) of a recording by looking for sudden rises in loudness, and
+/// returns their times in seconds. The `sensitivity` ranges from 0.0, which only finds hard hits, to 1.0, which also
+/// finds soft ones. This can be used to slice a recording into hits, or to line a recording up with the grid.
+pub fn detect_transients(sample: &Sample, sensitivity: f64) -> Vec<f64> {
+    let (rise, window_time) = loudness_rise(sample);
+    // A window has to rise by this many more decibels than its neighbours do on average.
+    let threshold = 3.0 + 15.0 * (1.0 - sensitivity.clamp(0.0, 1.0));
+    let mut transients: Vec<f64> = Vec::new();
+    for (i, r) in rise.iter().enumerate() {
+        let start = i.saturating_sub(TRANSIENT_NEIGHBORHOOD);
+        let end = (i + TRANSIENT_NEIGHBORHOOD + 1).min(rise.len());
+        let neighbours = &rise[start..end];
+        let mean = neighbours.iter().sum::<f64>() / neighbours.len() as f64;
+        let is_peak = neighbours.iter().all(|n| n <= r);
+        let time = i as f64 * window_time;
+        let far_enough = transients
+            .last()
+            .is_none_or(|last| time - last >= MIN_TRANSIENT_GAP);
+        if is_peak && *r > mean + threshold && far_enough {
+            transients.push(time);
+        }
+    }
+    transients
+}
+
+/// Estimates the tempo (in beats per minute) of a loop, between 70 and 180 BPM, by finding the beat length that the
+/// rises in loudness repeat at most strongly. The tempo is then nudged so that the loop spans a whole number of beats,
+/// which makes the loop line up with a sequencer playing at that tempo. Returns `None` if the loop has no clear beat.
+pub fn estimate_tempo(sample: &Sample) -> Option<f64> {
+    let (rise, window_time) = loudness_rise(sample);
+    let mean = rise.iter().sum::<f64>() / rise.len().max(1) as f64;
+    let centered: Vec<f64> = rise.iter().map(|r| r - mean).collect();
+    let lag_of = |bpm: f64| (60.0 / bpm / window_time).round() as usize;
+    let (min_lag, max_lag) = (lag_of(MAX_TEMPO).max(1), lag_of(MIN_TEMPO));
+    if max_lag >= centered.len() {
+        return None;
+    }
+    let (lag, strength) = (min_lag..=max_lag)
+        .map(|lag| {
+            let correlation = centered[lag..]
+                .iter()
+                .zip(&centered)
+                .map(|(a, b)| a * b)
+                .sum::<f64>()
+                / (centered.len() - lag) as f64;
+            (lag, correlation)
+        })
+        .fold(
+            (0, 0.0),
+            |best, (lag, c)| if c > best.1 { (lag, c) } else { best },
+        );
+    if strength <= 0.0 {
+        return None;
+    }
+    let tempo = 60.0 / (lag as f64 * window_time);
+    let length = sample.data.len() as f64 / sample.channels.max(1) as f64 / sample.sample_rate;
+    let beats = (length * tempo / 60.0).round();
+    if beats >= 1.0 {
+        let snapped = beats * 60.0 / length;
+        // Only snap if the loop is close to a whole number of beats, since it might not be trimmed to the beat.
+        if (snapped / tempo - 1.0).abs() < 0.03 {
+            return Some(snapped);
+        }
+    }
+    Some(tempo)
+}
+
+/// Gets how much energy a recording has in each pitch class, where index 0 is C, 1 is C#, and so on up to 11 for B.
+/// Every octave from C2 to B6 is folded together, and the result is scaled so that the strongest pitch class has a
+/// level of 1.0. Frequencies are measured against A4 = 440 Hz.
+pub fn chroma(sample: &Sample) -> [f64; 12] {
+    let mono = mono_frames(sample);
+    let frame = ((CHROMA_FRAME * sample.sample_rate) as usize).max(1);
+    let window: Vec<f64> = (0..frame)
+        .map(|i| 0.5 * (1.0 - (2.0 * PI * i as f64 / frame as f64).cos()))
+        .collect();
+    let mut chroma = [0.0; 12];
+    for frames in mono.chunks_exact(frame) {
+        for note in CHROMA_NOTES {
+            // The Goertzel algorithm, which measures a single frequency far more cheaply than a full DFT.
+            let hertz = 440.0 * 2.0_f64.powf((note - 69) as f64 / 12.0);
+            let coefficient = 2.0 * (2.0 * PI * hertz / sample.sample_rate).cos();
+            let (previous, before) = frames
+                .iter()
+                .zip(&window)
+                .fold((0.0, 0.0), |(previous, before), (x, w)| {
+                    (x * w + coefficient * previous - before, previous)
+                });
+            let power = previous * previous + before * before - coefficient * previous * before;
+            chroma[note.rem_euclid(12) as usize] += power.sqrt();
+        }
+    }
+    let strongest = chroma.iter().copied().fold(0.0, f64::max);
+    if strongest > 0.0 {
+        chroma.iter_mut().for_each(|c| *c /= strongest);
+    }
+    chroma
+}
+
+/// Estimates the key of a recording by comparing its `chroma` against how strongly every degree is heard in every
+/// major and minor key. Returns `None` if the recording is too short or too quiet to measure. Relative keys share all
+/// their notes, so a loop that only plays a few of them may be given the relative major or minor of its real key.
+pub fn estimate_key(sample: &Sample) -> Option<Key> {
+    let chroma = chroma(sample);
+    if chroma.iter().all(|c| *c == 0.0) {
+        return None;
+    }
+    let correlation = |profile: &[f64; 12], root: usize| {
+        let mean_profile = profile.iter().sum::<f64>() / 12.0;
+        let mean_chroma = chroma.iter().sum::<f64>() / 12.0;
+        let (mut product, mut profile_power, mut chroma_power) = (0.0, 0.0, 0.0);
+        for (degree, weight) in profile.iter().enumerate() {
+            let p = weight - mean_profile;
+            let c = chroma[(root + degree) % 12] - mean_chroma;
+            product += p * c;
+            profile_power += p * p;
+            chroma_power += c * c;
+        }
+        product / (profile_power * chroma_power).sqrt().max(f64::EPSILON)
+    };
+    [(Mode::Major, &MAJOR_PROFILE), (Mode::Minor, &MINOR_PROFILE)]
+        .into_iter()
+        .flat_map(|(mode, profile)| {
+            (0..12).map(move |root| (Key::new(root as u8, mode), correlation(profile, root)))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(key, _)| key)
+}
+
+impl NoiseLayer {
+    /// Creates a noise layer that matches the spectrum and envelope of a recording of a drum hit, with one band for
+    /// every octave.
+    pub fn matching(sample: &Sample) -> Self {
+        let mut layer = Self::with_envelope(fit_envelope(sample));
+        layer.bands = OCTAVE_BANDS
+            .iter()
+            .copied()
+            .zip(band_levels(sample, &OCTAVE_BANDS))
+            .collect();
+        layer
+    }
+}
+
+/// Measures how much the loudness of a recording rises (in decibels) from every short window to the next, which spikes
+/// at the start of every hit. Also returns the length of the windows (in seconds).
+fn loudness_rise(sample: &Sample) -> (Vec<f64>, f64) {
+    // Loudness is measured in decibels, so that a soft hit after silence stands out as much as a loud one.
+    const SILENCE: f64 = 1e-6;
+    let mono = mono_frames(sample);
+    let window = ((ENVELOPE_WINDOW * sample.sample_rate) as usize).max(1);
+    let loudness: Vec<f64> = mono
+        .chunks(window)
+        .map(|chunk| {
+            let rms = (chunk.iter().map(|x| x * x).sum::<f64>() / chunk.len() as f64).sqrt();
+            20.0 * rms.max(SILENCE).log10()
+        })
+        .collect();
+    let rise = core::iter::once(0.0)
+        .chain(loudness.windows(2).map(|w| (w[1] - w[0]).max(0.0)))
+        .collect();
+    (rise, window as f64 / sample.sample_rate)
+}
+
+/// Mixes a recording down to mono.
+fn mono_frames(sample: &Sample) -> Vec<f64> {
+    sample
+        .data
+        .chunks(sample.channels.max(1))
+        .map(|frame| frame.iter().sum::<f64>() / frame.len() as f64)
+        .collect()
+}
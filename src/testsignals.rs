@@ -0,0 +1,176 @@
+//! Test signals for measuring filters and effects, and for calibrating playback chains. Every signal can be played
+//! through `Player` as a `Voice` of `TestSignal`, or rendered offline into a `Sample` with `TestSignal::render`.
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::{
+    instruments::Instrument, note::w, oscillators::NoiseGen, player::Voice, sampler::Sample,
+};
+
+/// The shape of a `TestSignal`.
+#[non_exhaustive]
+#[derive(Clone, Debug)]
+pub enum Signal {
+    /// A sine wave that sweeps exponentially from `start_hz` to `end_hz` over `sweep_time` seconds, so that every
+    /// octave gets the same amount of time, after which it holds at `end_hz`.
+    SineSweep {
+        start_hz: f64,
+        end_hz: f64,
+        sweep_time: f64,
+    },
+    /// Noise with equal energy in every octave, which sounds balanced to the ear and is the usual signal for
+    /// measuring the response of a room or speaker.
+    PinkNoise,
+    /// A single-sample click `hertz` times a second, which shows the impulse response of whatever it passes through.
+    ImpulseTrain { hertz: f64 },
+    /// Silence that steps up to a constant `level` after `delay` seconds, which shows how quickly a filter or meter
+    /// settles.
+    DcStep { level: f64, delay: f64 },
+}
+
+/// Plays a test signal for as long as the note is held, ignoring the note that is played. Signals are played at full
+/// scale by default, so turn the `volume` down before sending them to speakers.
+#[derive(Clone)]
+pub struct TestSignal {
+    pub signal: Signal,
+    pub volume: f64,
+    noise: NoiseGen,
+    pink: [f64; 3],
+    sample_rate: f64,
+}
+
+impl TestSignal {
+    /// Creates a new test signal.
+    pub fn new(signal: Signal) -> Self {
+        Self {
+            signal,
+            volume: 1.0,
+            noise: NoiseGen::new(),
+            pink: [0.0; 3],
+            sample_rate: 44100.0,
+        }
+    }
+
+    /// Creates a `Voice` that plays the signal until it is removed from `Player`.
+    pub fn voice(self) -> Voice {
+        Voice::new(self, 64)
+    }
+
+    /// Renders `duration` seconds of the signal into a mono sample at `sample_rate`.
+    pub fn render(&self, duration: f64, sample_rate: f64) -> Sample {
+        let mut signal = self.clone();
+        signal.prepare(sample_rate);
+        let frames = (duration * sample_rate) as usize;
+        let data = (0..frames)
+            .map(|i| {
+                let time = i as f64 / sample_rate;
+                signal.process(time, 0.0, 0.0, 64, 1.0, &mut false).0
+            })
+            .collect();
+        Sample {
+            data,
+            sample_rate,
+            channels: 1,
+        }
+    }
+
+    /// Gets the signal at `time` seconds after it started. Pink noise is the only signal with state.
+    fn value(&mut self, time: f64) -> f64 {
+        match self.signal {
+            Signal::SineSweep {
+                start_hz,
+                end_hz,
+                sweep_time,
+            } => {
+                let ratio = (end_hz / start_hz).ln();
+                // The phase is the integral of the exponentially rising frequency.
+                let phase = if sweep_time <= 0.0 || ratio == 0.0 {
+                    w(end_hz) * time
+                } else if time < sweep_time {
+                    w(start_hz) * sweep_time / ratio * ((time / sweep_time * ratio).exp() - 1.0)
+                } else {
+                    w(start_hz) * sweep_time / ratio * (ratio.exp() - 1.0)
+                        + w(end_hz) * (time - sweep_time)
+                };
+                phase.sin()
+            }
+            Signal::PinkNoise => {
+                // Paul Kellet's economy filter, which is within 0.05 dB of pink above 9.2 Hz at 44.1 kHz.
+                let white = self.noise.sample();
+                self.pink[0] = 0.99765 * self.pink[0] + white * 0.0990460;
+                self.pink[1] = 0.96300 * self.pink[1] + white * 0.2965164;
+                self.pink[2] = 0.57000 * self.pink[2] + white * 1.0526913;
+                (self.pink[0] + self.pink[1] + self.pink[2] + white * 0.1848) * 0.25
+            }
+            Signal::ImpulseTrain { hertz } => {
+                let period = (self.sample_rate / hertz).round().max(1.0) as u64;
+                let frame = (time * self.sample_rate).round() as u64;
+                if frame.is_multiple_of(period) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Signal::DcStep { level, delay } => {
+                if time >= delay {
+                    level
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+impl Instrument for TestSignal {
+    fn sound(
+        &self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        note_id: u8,
+        note_finished: &mut bool,
+    ) -> f64 {
+        // Pink noise needs the filter state that `process` keeps, so it falls back to white noise here. Every other
+        // signal is stateless, so it is rendered from a copy.
+        if let Signal::PinkNoise = self.signal {
+            if time_off > time_on {
+                *note_finished = true;
+            }
+            return self.noise.sample() * self.volume;
+        }
+        let mut signal = self.clone();
+        signal
+            .process(time, time_on, time_off, note_id, 1.0, note_finished)
+            .0
+    }
+
+    fn prepare(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.pink = [0.0; 3];
+    }
+
+    fn process(
+        &mut self,
+        time: f64,
+        time_on: f64,
+        time_off: f64,
+        _note_id: u8,
+        velocity: f64,
+        note_finished: &mut bool,
+    ) -> (f64, f64) {
+        if time_off > time_on {
+            *note_finished = true;
+            return (0.0, 0.0);
+        }
+        let sound = self.value(time - time_on) * self.volume * velocity;
+        (sound, sound)
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Test Signal"
+    }
+}
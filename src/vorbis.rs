@@ -0,0 +1,993 @@
+//! A small Ogg Vorbis encoder, which codes every block at a single size, with a floor that follows the loudest part of
+//! the spectrum and a residue whose codebooks are fitted to the sample being encoded. This is well short of the
+//! quality per bit of the reference encoder, which models masking and switches to short blocks on transients, but
+//! gives files that every player opens without pulling in a native library. Live streams, whose headers go out before
+//! any of the signal is known, use codebooks fitted to rough odds of every entry instead (see `StreamEncoder`).
+
+use crate::{
+    fft::{fft, Complex},
+    sampler::Sample,
+};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    f64::consts::PI,
+    io::{self, Write},
+};
+
+/// Number of frames in every block, as a power of two. This is the long block size of the reference encoder at most
+/// sample rates.
+const BLOCK_EXPONENT: u32 = 11;
+
+/// Number of frames in every block.
+const BLOCK_SIZE: usize = 1 << BLOCK_EXPONENT;
+
+/// Number of frames that every block moves on by, which is also the number of bins of its spectrum.
+const HALF_BLOCK: usize = BLOCK_SIZE / 2;
+
+/// The bins of the posts of the floor after the first two, which sit at the lowest and the highest bin. The posts are
+/// spaced evenly in pitch, except at the bottom where the bins are too far apart for that.
+const FLOOR_POSTS: [usize; 30] = [
+    2, 4, 6, 8, 11, 14, 18, 22, 27, 33, 40, 48, 57, 68, 80, 95, 112, 132, 155, 182, 213, 249, 291,
+    340, 397, 463, 540, 630, 735, 857,
+];
+
+/// Number of posts in every partition of the floor, which all use the same class.
+const FLOOR_PARTITION: usize = 3;
+
+/// The floor is stored in steps of this many entries of the table of decibels, which gives 128 levels of about 1.1 dB.
+const FLOOR_MULTIPLIER: usize = 2;
+
+/// Number of levels of the floor.
+const FLOOR_RANGE: usize = 256 / FLOOR_MULTIPLIER;
+
+/// The quietest amplitude in the table of decibels that floors are read from, which is the first entry of the table.
+const FLOOR_MIN: f64 = 1.064_986_3e-7;
+
+/// How far (in dB) the floor sits below the loudest bin near each post. Residues are quantized in steps of the floor,
+/// so this sets the signal-to-noise ratio of every part of the spectrum.
+const FLOOR_OFFSET: f64 = 30.0;
+
+/// The lowest that the floor goes, which is about -120 dB below a full-scale sine. Anything quieter is left out.
+const FLOOR_LIMIT: f64 = 1e-6;
+
+/// Number of bins in every partition of the residue.
+const RESIDUE_PARTITION: usize = 32;
+
+/// Number of partitions of the residue in every block.
+const RESIDUE_PARTITIONS: usize = HALF_BLOCK / RESIDUE_PARTITION;
+
+/// Number of classes that partitions of the residue are sorted into, by their loudest bin (see `classify`).
+const CLASSIFICATIONS: usize = 4;
+
+/// Number of partitions whose classes are coded by every entry of the class book.
+const CLASSWORDS: usize = 2;
+
+/// The largest residue (in steps of the floor) that can be coded, which is the sum of the largest values of the
+/// coarse and fine books.
+const MAX_RESIDUE: i32 = 15 * 17 + 8;
+
+/// The longest codeword that a codebook can have.
+const MAX_CODEWORD_LENGTH: u8 = 24;
+
+/// The serial number of the logical stream, which only has to tell it apart from other streams in the same file.
+const SERIAL: u32 = 0x736F_756E;
+
+/// Pages are closed once they hold this many bytes, which is about the size that the reference encoder aims for.
+const PAGE_SIZE: usize = 4096;
+
+/// How much less likely every step away from zero makes an entry of a residue book or a class book, for the codebooks
+/// of live streams.
+const STREAM_ODDS: f64 = 0.6;
+
+/// The codebook that codes the posts of the floor.
+const FLOOR_BOOK: usize = 0;
+
+/// The codebook that codes the classes of the partitions of the residue.
+const CLASS_BOOK: usize = 1;
+
+/// The codebooks that each class uses in every pass of the residue, where class 0 is silent. The loudest class takes
+/// a coarse pass and then a fine one.
+const RESIDUE_PASSES: [[Option<usize>; CLASSIFICATIONS]; 2] = [
+    [None, Some(2), Some(3), Some(4)],
+    [None, None, None, Some(5)],
+];
+
+/// Encodes a sample as an Ogg Vorbis stream.
+pub(crate) fn encode<W>(writer: W, sample: &Sample) -> io::Result<()>
+where
+    W: Write,
+{
+    let encoder = Encoder::new();
+    let channels = sample.channels.max(1);
+    let frames = sample.data.len() / channels;
+    // Every block is centred half a block after the one before it, and the first one is centred on the first frame.
+    let blocks = frames.div_ceil(HALF_BLOCK) + 1;
+    let signals = |block: usize| -> Vec<Vec<f64>> {
+        (0..channels)
+            .map(|channel| {
+                (0..BLOCK_SIZE)
+                    .map(|i| {
+                        (block * HALF_BLOCK + i)
+                            .checked_sub(HALF_BLOCK)
+                            .filter(|frame| *frame < frames)
+                            .map_or(0.0, |frame| sample.data[frame * channels + channel])
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+    let mut books = codebooks();
+    // The codebooks are fitted to the sample, so the sample is analysed once to count the entries that it uses and
+    // again to code it.
+    for block in 0..blocks {
+        for symbol in encoder.block(&signals(block), &books) {
+            if let Symbol::Entry { book, entry } = symbol {
+                books[book].counts[entry] += 1;
+            }
+        }
+    }
+    books.iter_mut().for_each(Codebook::fit);
+    let mut ogg = OggWriter::new(writer);
+    ogg.headers(&headers(sample.sample_rate, channels, &books))?;
+    for block in 0..blocks {
+        let packet = audio_packet(encoder.block(&signals(block), &books), &books);
+        // The first block only primes the overlap, and every block after it finishes another half block.
+        let granule = (block * HALF_BLOCK).min(frames);
+        ogg.packet(&packet, granule as u64)?;
+        // Some decoders count samples from the granule position of the page that the first audio packet is on, so it
+        // is given a page of its own.
+        if block == 0 {
+            ogg.flush(false)?;
+        }
+    }
+    ogg.flush(true)?;
+    ogg.writer.flush()
+}
+
+/// Encodes a live signal as it comes in, half a block at a time. The codebooks are fitted to rough odds of every entry
+/// rather than to the signal, since the headers that carry them are sent before any of it, which costs some bits.
+pub(crate) struct StreamEncoder {
+    encoder: Encoder,
+    books: Vec<Codebook>,
+    headers: [Vec<u8>; 3],
+    /// The block of every channel that is being filled, whose first half is the end of the block before it.
+    signals: Vec<Vec<f64>>,
+    /// Number of frames in the second half of the block that is being filled.
+    filled: usize,
+    /// Number of audio packets that have been encoded.
+    packets: u64,
+}
+
+impl StreamEncoder {
+    pub(crate) fn new(sample_rate: f64, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let books = stream_codebooks();
+        Self {
+            encoder: Encoder::new(),
+            headers: headers(sample_rate, channels, &books),
+            books,
+            signals: vec![vec![0.0; BLOCK_SIZE]; channels],
+            filled: 0,
+            packets: 0,
+        }
+    }
+
+    /// Gets the three header packets, which every decoder needs before the audio packets.
+    pub(crate) fn headers(&self) -> &[Vec<u8>; 3] {
+        &self.headers
+    }
+
+    /// Adds interleaved frames to the stream, calling `packet` with every audio packet that they finish along with
+    /// its granule position. Channels that are missing from the end of the last frame are silent.
+    pub(crate) fn push<F>(&mut self, samples: &[f32], mut packet: F)
+    where
+        F: FnMut(Vec<u8>, u64),
+    {
+        let channels = self.signals.len();
+        for frame in samples.chunks(channels) {
+            for (channel, signal) in self.signals.iter_mut().enumerate() {
+                signal[HALF_BLOCK + self.filled] =
+                    frame.get(channel).copied().unwrap_or(0.0) as f64;
+            }
+            self.filled += 1;
+            if self.filled == HALF_BLOCK {
+                let symbols = self.encoder.block(&self.signals, &self.books);
+                // As in `encode`, the first packet only primes the overlap.
+                packet(
+                    audio_packet(symbols, &self.books),
+                    self.packets * HALF_BLOCK as u64,
+                );
+                self.packets += 1;
+                self.filled = 0;
+                for signal in &mut self.signals {
+                    signal.copy_within(HALF_BLOCK.., 0);
+                }
+            }
+        }
+    }
+}
+
+/// Something that is written to an audio packet, which is either raw bits or an entry of a codebook.
+enum Symbol {
+    Bits { value: u32, count: u32 },
+    Entry { book: usize, entry: usize },
+}
+
+/// The spectrum of one channel of a block, coded as a floor and a residue.
+struct Channel {
+    /// The first two posts of the floor, followed by the values that code the rest of the posts.
+    floor: Vec<usize>,
+    /// The spectrum divided by the floor and rounded.
+    residue: Vec<i32>,
+    /// The class of every partition of the residue.
+    classes: Vec<usize>,
+}
+
+/// The parts of the encoder that stay the same for every block.
+struct Encoder {
+    transform: Transform,
+    floor: Floor,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Self {
+            transform: Transform::new(),
+            floor: Floor::new(),
+        }
+    }
+
+    /// Analyses the signal of every channel of a block and gets the symbols of its audio packet, apart from the packet
+    /// type.
+    fn block(&self, signals: &[Vec<f64>], books: &[Codebook]) -> Vec<Symbol> {
+        let channels: Vec<Option<Channel>> =
+            signals.iter().map(|signal| self.channel(signal)).collect();
+        let mut symbols = Vec::new();
+        for channel in &channels {
+            let Some(channel) = channel else {
+                // The floor is unused, which silences the channel.
+                symbols.push(Symbol::Bits { value: 0, count: 1 });
+                continue;
+            };
+            symbols.push(Symbol::Bits { value: 1, count: 1 });
+            for value in &channel.floor[..2] {
+                symbols.push(Symbol::Bits {
+                    value: *value as u32,
+                    count: ilog(FLOOR_RANGE as u32 - 1),
+                });
+            }
+            for value in &channel.floor[2..] {
+                symbols.push(Symbol::Entry {
+                    book: FLOOR_BOOK,
+                    entry: *value,
+                });
+            }
+        }
+        // The residue is only coded for the channels whose floor is used.
+        let coded: Vec<&Channel> = channels.iter().flatten().collect();
+        if coded.is_empty() {
+            return symbols;
+        }
+        let mut remaining: Vec<Vec<i32>> = coded
+            .iter()
+            .map(|channel| channel.residue.clone())
+            .collect();
+        for (pass, classes) in RESIDUE_PASSES.iter().enumerate() {
+            for group in (0..RESIDUE_PARTITIONS).step_by(CLASSWORDS) {
+                if pass == 0 {
+                    for channel in &coded {
+                        let entry = channel.classes[group..group + CLASSWORDS]
+                            .iter()
+                            .fold(0, |entry, class| entry * CLASSIFICATIONS + class);
+                        symbols.push(Symbol::Entry {
+                            book: CLASS_BOOK,
+                            entry,
+                        });
+                    }
+                }
+                for partition in group..group + CLASSWORDS {
+                    for (channel, remaining) in coded.iter().zip(&mut remaining) {
+                        let Some(book) = classes[channel.classes[partition]] else {
+                            continue;
+                        };
+                        let start = partition * RESIDUE_PARTITION;
+                        for vector in remaining[start..start + RESIDUE_PARTITION]
+                            .chunks_mut(books[book].dimensions)
+                        {
+                            symbols.push(Symbol::Entry {
+                                book,
+                                entry: books[book].quantize(vector),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        symbols
+    }
+
+    /// Codes one channel of a block, or gets `None` if all of it rounds to silence.
+    fn channel(&self, signal: &[f64]) -> Option<Channel> {
+        let spectrum = self.transform.forward(signal);
+        let (floor, curve) = self.floor.fit(&spectrum);
+        let residue: Vec<i32> = spectrum
+            .iter()
+            .zip(&curve)
+            .map(|(x, floor)| ((x / floor).round() as i32).clamp(-MAX_RESIDUE, MAX_RESIDUE))
+            .collect();
+        if residue.iter().all(|x| *x == 0) {
+            return None;
+        }
+        let classes = residue.chunks(RESIDUE_PARTITION).map(classify).collect();
+        Some(Channel {
+            floor,
+            residue,
+            classes,
+        })
+    }
+}
+
+/// Writes the symbols of a block into an audio packet.
+fn audio_packet(symbols: Vec<Symbol>, books: &[Codebook]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    // An audio packet, with the only mode taking no bits.
+    bits.write(0, 1);
+    for symbol in symbols {
+        match symbol {
+            Symbol::Bits { value, count } => bits.write(value, count),
+            Symbol::Entry { book, entry } => books[book].write(&mut bits, entry),
+        }
+    }
+    bits.into_bytes()
+}
+
+/// Sorts a partition of the residue into a class by its loudest bin, so that quiet partitions are coded with small
+/// codebooks and silent ones are not coded at all.
+fn classify(partition: &[i32]) -> usize {
+    match partition.iter().map(|x| x.abs()).max().unwrap_or(0) {
+        0 => 0,
+        1 => 1,
+        2..=4 => 2,
+        _ => 3,
+    }
+}
+
+/// The window and twiddle factors of the MDCT of a block.
+struct Transform {
+    window: Vec<f64>,
+    before: Vec<Complex>,
+    after: Vec<Complex>,
+}
+
+impl Transform {
+    fn new() -> Self {
+        let n = BLOCK_SIZE as f64;
+        let rotation = |angle: f64| Complex::new(angle.cos(), angle.sin());
+        Self {
+            // The power-complementary window of Vorbis, which lets overlapping blocks add back up to the signal.
+            window: (0..BLOCK_SIZE)
+                .map(|i| (PI / 2.0 * ((i as f64 + 0.5) / n * PI).sin().powi(2)).sin())
+                .collect(),
+            before: (0..BLOCK_SIZE)
+                .map(|i| rotation(-PI * i as f64 / n))
+                .collect(),
+            after: (0..HALF_BLOCK)
+                .map(|k| rotation(-PI * (0.5 + n / 4.0) * (2 * k + 1) as f64 / n))
+                .collect(),
+        }
+    }
+
+    /// Windows a block and transforms it into its spectrum, scaled so that the decoder gets the signal back.
+    fn forward(&self, signal: &[f64]) -> Vec<f64> {
+        let mut buffer: Vec<Complex> = signal
+            .iter()
+            .zip(&self.window)
+            .zip(&self.before)
+            .map(|((x, w), twiddle)| Complex::new(x * w * twiddle.re, x * w * twiddle.im))
+            .collect();
+        fft(&mut buffer, false);
+        let scale = 2.0 / HALF_BLOCK as f64;
+        buffer
+            .iter()
+            .zip(&self.after)
+            .map(|(x, twiddle)| x.mul(*twiddle).re * scale)
+            .collect()
+    }
+}
+
+/// The posts of the floor, in the order that they are coded.
+struct Floor {
+    posts: Vec<usize>,
+    /// The posts coded before each post that sit closest to it on either side, which it is predicted from.
+    neighbours: Vec<(usize, usize)>,
+    /// The posts in order of their bins.
+    order: Vec<usize>,
+    /// The bins that the level of each post is taken from, which reach to the posts on either side of it.
+    regions: Vec<(usize, usize)>,
+}
+
+impl Floor {
+    fn new() -> Self {
+        let posts: Vec<usize> = [0, HALF_BLOCK].into_iter().chain(FLOOR_POSTS).collect();
+        let neighbours = (0..posts.len())
+            .map(|i| {
+                let before = || (0..i).filter(|j| posts[*j] < posts[i]);
+                let after = || (0..i).filter(|j| posts[*j] > posts[i]);
+                (
+                    before().max_by_key(|j| posts[*j]).unwrap_or(0),
+                    after().min_by_key(|j| posts[*j]).unwrap_or(0),
+                )
+            })
+            .collect();
+        let mut order: Vec<usize> = (0..posts.len()).collect();
+        order.sort_by_key(|i| posts[*i]);
+        let mut regions = vec![(0, 0); posts.len()];
+        for (rank, post) in order.iter().enumerate() {
+            let low = posts[order[rank.saturating_sub(1)]];
+            let high = posts[order[(rank + 1).min(order.len() - 1)]];
+            regions[*post] = (low, high.min(HALF_BLOCK - 1));
+        }
+        Self {
+            posts,
+            neighbours,
+            order,
+            regions,
+        }
+    }
+
+    /// Fits the floor under `spectrum`, and gets the values that code it along with the curve that the decoder
+    /// builds from them.
+    fn fit(&self, spectrum: &[f64]) -> (Vec<usize>, Vec<f64>) {
+        let gain = 10.0_f64.powf(-FLOOR_OFFSET / 20.0);
+        let levels: Vec<usize> = self
+            .regions
+            .iter()
+            .map(|(low, high)| {
+                let loudest = spectrum[*low..=*high]
+                    .iter()
+                    .fold(0.0_f64, |max, x| max.max(x.abs()));
+                let amplitude = (loudest * gain).max(FLOOR_LIMIT);
+                // Rounds down, so that the floor never sits above the level that was asked for.
+                let level = (amplitude / FLOOR_MIN).ln() / decibel_step() / FLOOR_MULTIPLIER as f64;
+                (level.max(0.0) as usize).min(FLOOR_RANGE - 1)
+            })
+            .collect();
+        let mut values = vec![levels[0], levels[1]];
+        let mut used = vec![false; self.posts.len()];
+        used[0] = true;
+        used[1] = true;
+        for i in 2..self.posts.len() {
+            let (low, high) = self.neighbours[i];
+            let predicted = render_point(
+                self.posts[low],
+                levels[low],
+                self.posts[high],
+                levels[high],
+                self.posts[i],
+            ) as isize;
+            let high_room = FLOOR_RANGE as isize - predicted;
+            let low_room = predicted;
+            let room = 2 * high_room.min(low_room);
+            let difference = levels[i] as isize - predicted;
+            // The inverse of how the decoder folds the values, where small differences alternate in sign and larger
+            // ones count from the edge of the room.
+            let value = if difference == 0 {
+                0
+            } else if difference > 0 && 2 * difference < room {
+                2 * difference
+            } else if difference < 0 && -2 * difference - 1 < room {
+                -2 * difference - 1
+            } else if high_room > low_room {
+                difference + low_room
+            } else {
+                high_room - 1 - difference
+            };
+            if value != 0 {
+                used[low] = true;
+                used[high] = true;
+                used[i] = true;
+            }
+            values.push(value as usize);
+        }
+        let mut curve = vec![0; HALF_BLOCK];
+        let mut last = (0, levels[0] * FLOOR_MULTIPLIER);
+        for post in self.order.iter().skip(1).filter(|post| used[**post]) {
+            let next = (self.posts[*post], levels[*post] * FLOOR_MULTIPLIER);
+            render_line(last, next, &mut curve);
+            last = next;
+        }
+        let curve = curve.into_iter().map(decibels).collect();
+        (values, curve)
+    }
+}
+
+/// The step between neighbouring entries of the table of decibels, in nepers.
+fn decibel_step() -> f64 {
+    (1.0 / FLOOR_MIN).ln() / 255.0
+}
+
+/// Reads an entry of the table of decibels that the floor is drawn from, which goes from `FLOOR_MIN` to 1.0.
+fn decibels(index: usize) -> f64 {
+    FLOOR_MIN * (index as f64 * decibel_step()).exp()
+}
+
+/// Predicts the level of a post at `x` from the line between two other posts, rounding in the same way as the decoder.
+fn render_point(x0: usize, y0: usize, x1: usize, y1: usize, x: usize) -> usize {
+    let dy = y1 as isize - y0 as isize;
+    let offset = (dy.abs() * (x - x0) as isize) / (x1 - x0) as isize;
+    if dy < 0 {
+        (y0 as isize - offset) as usize
+    } else {
+        (y0 as isize + offset) as usize
+    }
+}
+
+/// Draws the line between two posts onto the curve of the floor, in the same way as the decoder.
+fn render_line((x0, y0): (usize, usize), (x1, y1): (usize, usize), curve: &mut [usize]) {
+    let dy = y1 as isize - y0 as isize;
+    let adx = (x1 - x0) as isize;
+    let base = dy / adx;
+    let step = if dy < 0 { base - 1 } else { base + 1 };
+    let ady = dy.abs() - base.abs() * adx;
+    let mut y = y0 as isize;
+    let mut error = 0;
+    curve[x0] = y0;
+    for point in curve.iter_mut().take(x1).skip(x0 + 1) {
+        error += ady;
+        if error >= adx {
+            error -= adx;
+            y += step;
+        } else {
+            y += base;
+        }
+        *point = y as usize;
+    }
+}
+
+/// A codebook, with the vectors that its entries stand for and the number of times each entry is used.
+struct Codebook {
+    dimensions: usize,
+    /// The smallest value, the step between values, and the number of values of each dimension of the vectors, for
+    /// the books of the residue.
+    lookup: Option<(i32, i32, usize)>,
+    counts: Vec<u64>,
+    lengths: Vec<u8>,
+    codewords: Vec<u32>,
+}
+
+impl Codebook {
+    /// Creates a codebook whose entries stand for scalars rather than vectors, such as the classes of the residue.
+    fn scalar(dimensions: usize, entries: usize) -> Self {
+        Self {
+            dimensions,
+            lookup: None,
+            counts: vec![0; entries],
+            lengths: Vec::new(),
+            codewords: Vec::new(),
+        }
+    }
+
+    /// Creates a codebook of every vector of `dimensions` values from `minimum` in steps of `delta`.
+    fn vectors(dimensions: usize, minimum: i32, delta: i32, values: usize) -> Self {
+        Self {
+            lookup: Some((minimum, delta, values)),
+            ..Self::scalar(dimensions, values.pow(dimensions as u32))
+        }
+    }
+
+    /// Gets the entry whose vector is closest to `vector`, and leaves what it does not cover in `vector` for the next
+    /// pass of the residue.
+    fn quantize(&self, vector: &mut [i32]) -> usize {
+        let (minimum, delta, values) = self.lookup.unwrap();
+        let mut entry = 0;
+        for value in vector.iter_mut().rev() {
+            let index = ((*value - minimum) as f64 / delta as f64)
+                .round()
+                .clamp(0.0, (values - 1) as f64) as i32;
+            *value -= index * delta + minimum;
+            entry = entry * values + index as usize;
+        }
+        entry
+    }
+
+    /// Gives the entries Huffman codes for the number of times that they were used. Every entry gets a codeword,
+    /// since the decoder needs a complete code.
+    fn fit(&mut self) {
+        let mut weights: Vec<u64> = self.counts.iter().map(|count| count + 1).collect();
+        self.lengths = loop {
+            let lengths = huffman_lengths(&weights);
+            if lengths.iter().all(|length| *length <= MAX_CODEWORD_LENGTH) {
+                break lengths;
+            }
+            // Evens out the weights until the rarest entries fit.
+            weights
+                .iter_mut()
+                .for_each(|weight| *weight = *weight / 2 + 1);
+        };
+        self.codewords = codewords(&self.lengths);
+    }
+
+    /// Writes the codeword of `entry`, which the decoder reads starting from its most significant bit.
+    fn write(&self, bits: &mut BitWriter, entry: usize) {
+        let (codeword, length) = (self.codewords[entry], self.lengths[entry]);
+        for bit in (0..length).rev() {
+            bits.write((codeword >> bit) & 1, 1);
+        }
+    }
+
+    /// Writes the codebook to the setup header.
+    fn write_setup(&self, bits: &mut BitWriter) {
+        bits.write(0x56_4342, 24);
+        bits.write(self.dimensions as u32, 16);
+        bits.write(self.lengths.len() as u32, 24);
+        // The lengths are listed in full rather than ordered or sparse.
+        bits.write(0, 1);
+        bits.write(0, 1);
+        for length in &self.lengths {
+            bits.write(*length as u32 - 1, 5);
+        }
+        match self.lookup {
+            None => bits.write(0, 4),
+            Some((minimum, delta, values)) => {
+                bits.write(1, 4);
+                bits.write(pack_float(minimum), 32);
+                bits.write(pack_float(delta), 32);
+                let value_bits = ilog(values as u32 - 1);
+                bits.write(value_bits - 1, 4);
+                bits.write(0, 1);
+                for value in 0..values {
+                    bits.write(value as u32, value_bits);
+                }
+            }
+        }
+    }
+}
+
+/// Creates the codebooks of the floor and the residue, which are fitted to the sample before they are used.
+fn codebooks() -> Vec<Codebook> {
+    vec![
+        Codebook::scalar(1, FLOOR_RANGE),
+        Codebook::scalar(CLASSWORDS, CLASSIFICATIONS.pow(CLASSWORDS as u32)),
+        Codebook::vectors(4, -1, 1, 3),
+        Codebook::vectors(2, -4, 1, 9),
+        Codebook::vectors(2, -15 * 17, 17, 31),
+        Codebook::vectors(2, -8, 1, 17),
+    ]
+}
+
+/// Creates codebooks fitted to rough odds of every entry, where entries nearer to zero are more likely. The posts of the
+/// floor are most often close to where they are predicted, which is coded by the smallest values.
+fn stream_codebooks() -> Vec<Codebook> {
+    let mut books = codebooks();
+    for (index, book) in books.iter_mut().enumerate() {
+        let (minimum, delta, values) = book.lookup.unwrap_or((0, 1, CLASSIFICATIONS));
+        for (entry, count) in book.counts.iter_mut().enumerate() {
+            let odds = if index == FLOOR_BOOK {
+                1.0 / (entry + 1) as f64
+            } else {
+                (0..book.dimensions)
+                    .map(|dimension| {
+                        let index = entry / values.pow(dimension as u32) % values;
+                        let steps = (minimum + index as i32 * delta).abs() / delta;
+                        STREAM_ODDS.powi(steps)
+                    })
+                    .product()
+            };
+            *count = (odds * 1e6) as u64;
+        }
+        book.fit();
+    }
+    books
+}
+
+/// Works out the length of the Huffman code of every entry from its weight.
+fn huffman_lengths(weights: &[u64]) -> Vec<u8> {
+    let mut parents = vec![usize::MAX; 2 * weights.len() - 1];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = weights
+        .iter()
+        .enumerate()
+        .map(|(entry, weight)| Reverse((*weight, entry)))
+        .collect();
+    let mut next = weights.len();
+    while let (Some(Reverse((a, first))), Some(Reverse((b, second)))) = (heap.pop(), heap.pop()) {
+        parents[first] = next;
+        parents[second] = next;
+        heap.push(Reverse((a + b, next)));
+        next += 1;
+    }
+    (0..weights.len())
+        .map(|entry| {
+            let mut length = 0;
+            let mut node = entry;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                length += 1;
+            }
+            length
+        })
+        .collect()
+}
+
+/// Assigns codewords to entries from their lengths, in the same way as the decoder, where every entry takes the
+/// lowest codeword of its length that is still free.
+fn codewords(lengths: &[u8]) -> Vec<u32> {
+    let mut marker = [0_u64; 33];
+    lengths
+        .iter()
+        .map(|length| {
+            let length = *length as usize;
+            let mut entry = marker[length];
+            let codeword = entry as u32;
+            for j in (1..=length).rev() {
+                if marker[j] & 1 != 0 {
+                    marker[j] = if j == 1 {
+                        marker[1] + 1
+                    } else {
+                        marker[j - 1] << 1
+                    };
+                    break;
+                }
+                marker[j] += 1;
+            }
+            for j in length + 1..33 {
+                if marker[j] >> 1 != entry {
+                    break;
+                }
+                entry = marker[j];
+                marker[j] = marker[j - 1] << 1;
+            }
+            codeword
+        })
+        .collect()
+}
+
+/// Packs an integer into the floating point format of codebooks, with a 21-bit mantissa and an exponent biased by 788.
+fn pack_float(value: i32) -> u32 {
+    let sign = if value < 0 { 1 << 31 } else { 0 };
+    sign | (788 << 21) | value.unsigned_abs()
+}
+
+/// Gets the number of bits needed to hold `value`.
+fn ilog(value: u32) -> u32 {
+    u32::BITS - value.leading_zeros()
+}
+
+/// Builds the three header packets of a stream.
+fn headers(sample_rate: f64, channels: usize, books: &[Codebook]) -> [Vec<u8>; 3] {
+    [
+        identification_header(sample_rate, channels),
+        comment_header(),
+        setup_header(books),
+    ]
+}
+
+/// Builds the identification header, which gives the channels, the sample rate and the block sizes.
+fn identification_header(sample_rate: f64, channels: usize) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    bits.write_header(1);
+    bits.write(0, 32);
+    bits.write(channels as u32, 8);
+    bits.write(sample_rate as u32, 32);
+    // The maximum, nominal and minimum bitrates are left unset.
+    bits.write(0, 32);
+    bits.write(0, 32);
+    bits.write(0, 32);
+    // Short and long blocks are the same size, since only one is used.
+    bits.write(BLOCK_EXPONENT, 4);
+    bits.write(BLOCK_EXPONENT, 4);
+    bits.write(1, 1);
+    bits.into_bytes()
+}
+
+/// Builds the comment header, with the name of the encoder and no comments.
+fn comment_header() -> Vec<u8> {
+    let vendor = concat!("soundtoys ", env!("CARGO_PKG_VERSION"));
+    let mut bits = BitWriter::default();
+    bits.write_header(3);
+    bits.write(vendor.len() as u32, 32);
+    for byte in vendor.bytes() {
+        bits.write(byte as u32, 8);
+    }
+    bits.write(0, 32);
+    bits.write(1, 1);
+    bits.into_bytes()
+}
+
+/// Builds the setup header, which has the codebooks along with the single floor, residue, mapping and mode that every
+/// block uses.
+fn setup_header(books: &[Codebook]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    bits.write_header(5);
+    bits.write(books.len() as u32 - 1, 8);
+    for book in books {
+        book.write_setup(&mut bits);
+    }
+    // A single placeholder for the time domain transforms, which are unused in Vorbis I.
+    bits.write(0, 6);
+    bits.write(0, 16);
+    // A floor of type 1, with every partition of posts in the same class.
+    bits.write(0, 6);
+    bits.write(1, 16);
+    let partitions = FLOOR_POSTS.len() / FLOOR_PARTITION;
+    bits.write(partitions as u32, 5);
+    for _ in 0..partitions {
+        bits.write(0, 4);
+    }
+    bits.write(FLOOR_PARTITION as u32 - 1, 3);
+    bits.write(0, 2);
+    bits.write(FLOOR_BOOK as u32 + 1, 8);
+    bits.write(FLOOR_MULTIPLIER as u32 - 1, 2);
+    let range_bits = ilog(HALF_BLOCK as u32 - 1);
+    bits.write(range_bits, 4);
+    for post in FLOOR_POSTS {
+        bits.write(post as u32, range_bits);
+    }
+    // A residue of type 1, which codes every channel on its own.
+    bits.write(0, 6);
+    bits.write(1, 16);
+    bits.write(0, 24);
+    bits.write(HALF_BLOCK as u32, 24);
+    bits.write(RESIDUE_PARTITION as u32 - 1, 24);
+    bits.write(CLASSIFICATIONS as u32 - 1, 6);
+    bits.write(CLASS_BOOK as u32, 8);
+    for class in 0..CLASSIFICATIONS {
+        let cascade = RESIDUE_PASSES
+            .iter()
+            .enumerate()
+            .fold(0, |cascade, (pass, books)| {
+                cascade | (books[class].is_some() as u32) << pass
+            });
+        bits.write(cascade, 3);
+        bits.write(0, 1);
+    }
+    for class in 0..CLASSIFICATIONS {
+        for book in RESIDUE_PASSES.iter().filter_map(|books| books[class]) {
+            bits.write(book as u32, 8);
+        }
+    }
+    // A mapping with a single submap and no coupling between channels.
+    bits.write(0, 6);
+    bits.write(0, 16);
+    bits.write(0, 1);
+    bits.write(0, 1);
+    bits.write(0, 2);
+    bits.write(0, 8);
+    bits.write(0, 8);
+    bits.write(0, 8);
+    // A single mode.
+    bits.write(0, 6);
+    bits.write(0, 1);
+    bits.write(0, 16);
+    bits.write(0, 16);
+    bits.write(0, 8);
+    bits.write(1, 1);
+    bits.into_bytes()
+}
+
+/// Writes values least significant bit first, which is how Vorbis packs its packets.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    used: u32,
+}
+
+impl BitWriter {
+    /// Writes the lowest `count` bits of `value`.
+    fn write(&mut self, value: u32, count: u32) {
+        for bit in 0..count {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            *self.bytes.last_mut().unwrap() |= (((value >> bit) & 1) as u8) << self.used;
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    /// Writes the type of a header packet, followed by the name of the codec.
+    fn write_header(&mut self, kind: u32) {
+        self.write(kind, 8);
+        for byte in b"vorbis" {
+            self.write(*byte as u32, 8);
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Splits packets into Ogg pages.
+pub(crate) struct OggWriter<W> {
+    pub(crate) writer: W,
+    sequence: u32,
+    lacing: Vec<u8>,
+    data: Vec<u8>,
+    /// The granule position of the last packet that ends on the page, if any.
+    granule: Option<u64>,
+    /// Whether the page starts partway through a packet.
+    continued: bool,
+}
+
+impl<W> OggWriter<W>
+where
+    W: Write,
+{
+    pub(crate) fn new(writer: W) -> Self {
+        Self {
+            writer,
+            sequence: 0,
+            lacing: Vec::new(),
+            data: Vec::new(),
+            granule: None,
+            continued: false,
+        }
+    }
+
+    /// Writes the header packets, with the identification header on a page of its own as the first page of the stream.
+    pub(crate) fn headers(&mut self, headers: &[Vec<u8>; 3]) -> io::Result<()> {
+        self.packet(&headers[0], 0)?;
+        self.flush(false)?;
+        self.packet(&headers[1], 0)?;
+        self.packet(&headers[2], 0)?;
+        self.flush(false)
+    }
+
+    /// Adds a packet, which is split into segments of up to 255 bytes, where a shorter segment ends the packet.
+    pub(crate) fn packet(&mut self, packet: &[u8], granule: u64) -> io::Result<()> {
+        if self.data.len() >= PAGE_SIZE {
+            self.flush(false)?;
+        }
+        let mut start = 0;
+        loop {
+            if self.lacing.len() == 255 {
+                self.flush(false)?;
+                self.continued = start > 0;
+            }
+            let end = (start + 255).min(packet.len());
+            self.lacing.push((end - start) as u8);
+            self.data.extend_from_slice(&packet[start..end]);
+            if end - start < 255 {
+                break;
+            }
+            start = end;
+        }
+        self.granule = Some(granule);
+        Ok(())
+    }
+
+    /// Writes out the page that has been built up, which is the last page of the stream if `last` is set.
+    pub(crate) fn flush(&mut self, last: bool) -> io::Result<()> {
+        let mut page = Vec::with_capacity(27 + self.lacing.len() + self.data.len());
+        page.extend_from_slice(b"OggS");
+        page.push(0);
+        page.push(self.continued as u8 | ((self.sequence == 0) as u8) << 1 | (last as u8) << 2);
+        page.extend_from_slice(&self.granule.unwrap_or(u64::MAX).to_le_bytes());
+        page.extend_from_slice(&SERIAL.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(self.lacing.len() as u8);
+        page.append(&mut self.lacing);
+        page.append(&mut self.data);
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        self.writer.write_all(&page)?;
+        self.sequence += 1;
+        self.granule = None;
+        self.continued = false;
+        Ok(())
+    }
+}
+
+/// The CRC-32 that protects an Ogg page, with the polynomial 0x04C11DB7.
+fn crc32(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u32) << 24), |crc, _| {
+            if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            }
+        })
+    })
+}